@@ -1,20 +1,30 @@
-use clap::Parser;
+use std::time::Duration;
 
+use clap::{ArgGroup, Parser};
+use image::ImageFormat;
+
+use crate::constants::PIC_MIN_COMPLEXITY;
 use crate::{
-    CoordinateSystem, DEFAULT_COORDINATE_SYSTEM, DEFAULT_IMAGE_HEIGHT, DEFAULT_IMAGE_WIDTH,
-    DEFAULT_PICTURES_PATH,
+    BlendMode, Color, ColorSpace, ContactSheet, CoordinateSystem, DownscaleFilter, LoopMode,
+    SimdBackend, Subsample, Symmetry, TimeRange, DEFAULT_BACKGROUND, DEFAULT_BLEND_MODE,
+    DEFAULT_COLOR_SPACE, DEFAULT_COORDINATE_SYSTEM, DEFAULT_DEBOUNCE_MS, DEFAULT_DOWNSCALE_FILTER,
+    DEFAULT_GAMMA, DEFAULT_GRID_COLS, DEFAULT_GRID_ROWS, DEFAULT_IMAGE_HEIGHT, DEFAULT_IMAGE_WIDTH,
+    DEFAULT_JPEG_QUALITY, DEFAULT_LOOP_MODE, DEFAULT_MUTATION_RATE, DEFAULT_PICTURES_PATH,
+    DEFAULT_SIMD_BACKEND, DEFAULT_SMOOTH_EDGES, DEFAULT_THUMB_QUALITY, DEFAULT_THUMB_SIZE,
+    DEFAULT_TIME_RANGE, EXEC_UI_GRID_CELLS_MAX, MAX_IMAGE_DIMENSION,
 };
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(group(ArgGroup::new("source").args(["input", "compose"])))]
 pub struct Args {
     #[clap(short, long, value_parser, default_value = DEFAULT_PICTURES_PATH, help="The path to images that can be loaded via the Pic- operation")]
     pub pictures_path: String,
 
-    #[clap(short, long, value_parser, default_value_t = DEFAULT_IMAGE_WIDTH, help="The width of the generated image")]
+    #[clap(short, long, value_parser = parse_width, default_value_t = DEFAULT_IMAGE_WIDTH, help="The width of the generated image, capped to 16384")]
     pub width: u32,
 
-    #[clap(long, value_parser, default_value_t = DEFAULT_IMAGE_HEIGHT, help="The height of the generated image")]
+    #[clap(long, value_parser = parse_dimension, default_value_t = DEFAULT_IMAGE_HEIGHT, help="The height of the generated image, capped to 16384")]
     pub height: u32,
 
     #[clap(
@@ -22,10 +32,52 @@ pub struct Args {
         long,
         value_parser,
         default_value_t = 0.0,
-        help = "set the T variable (ms)"
+        help = "The moment (ms) of the animation to render, or the duration (ms) of a gif or webp"
     )]
     pub time: f32,
 
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = DEFAULT_TIME_RANGE,
+        allow_hyphen_values = true,
+        help = "The values (min:max) the T variable sweeps through during an animation"
+    )]
+    pub time_range: TimeRange,
+
+    #[clap(
+        long = "loop",
+        value_parser,
+        default_value_t = DEFAULT_LOOP_MODE,
+        help = "How an animation is played back"
+    )]
+    pub loop_mode: LoopMode,
+
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with("time_range"),
+        help = "Make an animation loop seamlessly: every T is read as TSIN and T sweeps through 0:2"
+    )]
+    pub loop_seamless_time: bool,
+
+    #[clap(
+        long,
+        visible_alias = "oversample-time",
+        value_parser = clap::value_parser!(u32).range(1..=16),
+        default_value_t = 1,
+        help = "Render every frame of an animation as the average of N moments until the next frame, to blur fast motion"
+    )]
+    pub motion_blur: u32,
+
+    #[clap(
+        long,
+        value_parser,
+        requires("input"),
+        help = "Write a COLSxROWS grid of evenly spaced moments of the animation instead of one image"
+    )]
+    pub contact_sheet: Option<ContactSheet>,
+
     #[clap(
         short,
         long,
@@ -38,11 +90,101 @@ pub struct Args {
         short,
         long,
         value_parser,
-        requires("input"),
-        help = "image file to write to"
+        requires("source"),
+        help = "image file to write to; Use '-' to write to stdout, this needs a --format."
     )]
     pub output: Option<String>,
 
+    #[clap(
+        short,
+        long,
+        value_parser = parse_image_format,
+        requires("source"),
+        help = "Image format to write (png, jpeg, webp, gif, ...), overrides the output file's extension"
+    )]
+    pub format: Option<ImageFormat>,
+
+    #[clap(
+        long,
+        requires("input"),
+        help = "Only parse the input and print its normalized expression, without rendering"
+    )]
+    pub check: bool,
+
+    #[clap(
+        long,
+        requires("input"),
+        help = "Print the depth of every channel, the number of nodes and the operations used by the input"
+    )]
+    pub stats: bool,
+
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all(["input", "compose", "batch"]),
+        help = "Print the expression that is embedded in a png that was rendered by evolution"
+    )]
+    pub extract_metadata: Option<String>,
+
+    #[clap(
+        short,
+        long,
+        conflicts_with("verbose"),
+        help = "Print only the errors and the output that is asked for, like --check and --stats"
+    )]
+    pub quiet: bool,
+
+    #[clap(short, long, help = "Print how long the renders take as well")]
+    pub verbose: bool,
+
+    #[clap(
+        long,
+        help = "Negate the colors of the rendered image, the alpha stays as it is"
+    )]
+    pub invert: bool,
+
+    #[clap(
+        long,
+        value_parser = parse_gamma,
+        default_value_t = DEFAULT_GAMMA,
+        help = "Brighten (above 1) or darken (below 1) the midtones of the rendered image"
+    )]
+    pub gamma: f32,
+
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = DEFAULT_COLOR_SPACE,
+        help = "Whether the rendered values are sRGB colors or linear light that is sRGB encoded before it is quantized"
+    )]
+    pub color_space: ColorSpace,
+
+    #[clap(
+        long,
+        value_parser = parse_smooth_edges,
+        default_value_t = DEFAULT_SMOOTH_EDGES,
+        help = "Blur the sharp edges of the rendered image, from 0 (off) to 1 (the most)"
+    )]
+    pub smooth_edges: f32,
+
+    #[clap(
+        long,
+        value_parser,
+        num_args = 2..,
+        conflicts_with_all(["batch", "load_population"]),
+        help = "Render the sexpr files and blend them into one image, the first one is the bottom layer"
+    )]
+    pub compose: Option<Vec<String>>,
+
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = DEFAULT_BLEND_MODE,
+        requires("compose"),
+        help = "How the layers of --compose are blended"
+    )]
+    pub blend: BlendMode,
+
     #[clap(
         short,
         long,
@@ -52,6 +194,746 @@ pub struct Args {
     )]
     pub copy_path: Option<String>,
 
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = DEFAULT_DEBOUNCE_MS,
+        requires("copy_path"),
+        help = "Changes to the input within this many ms are rendered once"
+    )]
+    pub debounce_ms: u64,
+
     #[clap(short='s', long, value_parser, default_value_t = DEFAULT_COORDINATE_SYSTEM, help="The Coordinate system to use")]
     pub coordinate_system: CoordinateSystem,
+
+    #[clap(
+        long,
+        visible_alias = "repeat",
+        value_parser,
+        requires("source"),
+        help = "Fold the image into N (1 to 64) mirrored wedges around the center, like a kaleidoscope"
+    )]
+    pub symmetry: Option<Symmetry>,
+
+    #[clap(
+        long,
+        value_parser,
+        requires("source"),
+        help = "Render these comma separated channels (R, G, B, H, S, V) at half the resolution and interpolate them, which is faster"
+    )]
+    pub subsample: Option<Subsample>,
+
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..=16),
+        default_value_t = 1,
+        help = "Render N x N samples per pixel to smooth the edges of the image"
+    )]
+    pub supersample: u32,
+
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = DEFAULT_DOWNSCALE_FILTER,
+        help = "How the samples of --supersample are scaled down to the pixels of the image"
+    )]
+    pub downscale_filter: DownscaleFilter,
+
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..=4),
+        default_value_t = DEFAULT_THUMB_QUALITY,
+        help = "Render the Ui thumbnails at N times their size and scale them down, 1 is the fastest"
+    )]
+    pub thumb_quality: u32,
+
+    #[clap(
+        long,
+        value_parser = parse_grid_size,
+        default_value_t = DEFAULT_GRID_COLS,
+        help = "The number of thumbnails next to each other in the Ui"
+    )]
+    pub grid_cols: usize,
+
+    #[clap(
+        long,
+        value_parser = parse_grid_size,
+        default_value_t = DEFAULT_GRID_ROWS,
+        help = "The number of thumbnails above each other in the Ui"
+    )]
+    pub grid_rows: usize,
+
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u32).range(16..=1024),
+        default_value_t = DEFAULT_THUMB_SIZE,
+        help = "The largest width of a Ui thumbnail, they shrink to fit the window"
+    )]
+    pub thumb_size: u32,
+
+    #[clap(
+        long,
+        value_parser = parse_mutation_rate,
+        default_value_t = DEFAULT_MUTATION_RATE,
+        help = "The chance, from 0 to 1, that a node changes when the Ui mutates or breeds a pic"
+    )]
+    pub mutation_rate: f32,
+
+    #[clap(
+        long,
+        value_parser = parse_max_render_time,
+        help = "Abort a render that takes longer than this many ms, a thumbnail of the Ui then stays empty [default: no limit]"
+    )]
+    pub max_render_time: Option<Duration>,
+
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = DEFAULT_SIMD_BACKEND,
+        help = "The instruction set to render with, auto takes the widest one the cpu supports"
+    )]
+    pub simd: SimdBackend,
+
+    #[clap(
+        long,
+        requires("input"),
+        help = "Render the input with scalar and with --simd, report how much they differ and which operations cause it"
+    )]
+    pub verify_simd: bool,
+
+    #[clap(
+        long,
+        conflicts_with_all(["input", "compose", "batch", "extract_metadata"]),
+        help = "Time the renders of a fixed set of expressions with every instruction set the cpu supports, or only with --simd, in megapixels per second"
+    )]
+    pub benchmark: bool,
+
+    #[clap(
+        long,
+        value_parser,
+        help = "Seed for the random generator, to reproduce the same population"
+    )]
+    pub seed: Option<u64>,
+
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = PIC_MIN_COMPLEXITY,
+        help = "Generate random pics again until their channels have at least this many nodes"
+    )]
+    pub min_complexity: usize,
+
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with("input"),
+        help = "Palette file, GIMP .gpl or one hex color per line, with the colors of the random gradient pics"
+    )]
+    pub palette: Option<String>,
+
+    #[clap(
+        long,
+        value_parser = parse_bit_depth,
+        default_value_t = 8,
+        help = "Bits per channel (8 or 16), 16 bits is only used for png and tiff files"
+    )]
+    pub bit_depth: u8,
+
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        default_value_t = DEFAULT_JPEG_QUALITY,
+        help = "Quality (1-100) of jpeg files"
+    )]
+    pub jpeg_quality: u8,
+
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = DEFAULT_BACKGROUND,
+        help = "The color (#rrggbb) behind the transparent pixels in image formats without alpha, like jpeg"
+    )]
+    pub background: Color,
+
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with("input"),
+        help = "Population file, saved with the 's' key, to fill the grid with"
+    )]
+    pub load_population: Option<String>,
+
+    #[clap(
+        long,
+        value_parser,
+        requires("out_dir"),
+        conflicts_with_all(["input", "load_population"]),
+        help = "Render N random images without the UI, reproducible with --seed"
+    )]
+    pub batch: Option<usize>,
+
+    #[clap(
+        long,
+        value_parser,
+        requires("batch"),
+        help = "Directory to write the png and sexpr files of --batch to"
+    )]
+    pub out_dir: Option<String>,
+}
+
+/// The columns or rows of the Ui grid, their product is checked when the grid is laid out
+fn parse_grid_size(s: &str) -> Result<usize, String> {
+    let size: usize = s
+        .parse()
+        .map_err(|e| format!("{} is not a valid number of thumbnails. {}", s, e))?;
+    if size == 0 || size > EXEC_UI_GRID_CELLS_MAX {
+        return Err(format!(
+            "The grid takes 1 to {} thumbnails, not {}",
+            EXEC_UI_GRID_CELLS_MAX, s
+        ));
+    }
+    Ok(size)
+}
+
+fn parse_bit_depth(s: &str) -> Result<u8, String> {
+    match s {
+        "8" => Ok(8),
+        "16" => Ok(16),
+        _ => Err(format!("{} is not a supported bit depth, use 8 or 16", s)),
+    }
+}
+
+fn parse_gamma(s: &str) -> Result<f32, String> {
+    let gamma: f32 = s
+        .parse()
+        .map_err(|e| format!("{} is not a valid gamma. {}", s, e))?;
+    if !gamma.is_finite() || gamma <= 0.0 {
+        return Err(format!("The gamma must be a number above 0, not {}", s));
+    }
+    Ok(gamma)
+}
+
+fn parse_smooth_edges(s: &str) -> Result<f32, String> {
+    let strength: f32 = s
+        .parse()
+        .map_err(|e| format!("{} is not a valid edge smoothing. {}", s, e))?;
+    if !(0.0..=1.0).contains(&strength) {
+        return Err(format!(
+            "The edge smoothing must be a number from 0 to 1, not {}",
+            s
+        ));
+    }
+    Ok(strength)
+}
+
+fn parse_mutation_rate(s: &str) -> Result<f32, String> {
+    let rate: f32 = s
+        .parse()
+        .map_err(|e| format!("{} is not a valid mutation rate. {}", s, e))?;
+    if !(0.0..=1.0).contains(&rate) {
+        return Err(format!(
+            "The mutation rate must be a number from 0 to 1, not {}",
+            s
+        ));
+    }
+    Ok(rate)
+}
+
+fn parse_max_render_time(s: &str) -> Result<Duration, String> {
+    let ms: u64 = s
+        .parse()
+        .map_err(|e| format!("{} is not a valid render time. {}", s, e))?;
+    if ms == 0 {
+        return Err("The render time must be at least 1 ms".to_string());
+    }
+    Ok(Duration::from_millis(ms))
+}
+
+fn parse_image_format(s: &str) -> Result<ImageFormat, String> {
+    match s.to_lowercase().as_str() {
+        "png" => Ok(ImageFormat::Png),
+        "jpg" | "jpeg" => Ok(ImageFormat::Jpeg),
+        "webp" => Ok(ImageFormat::WebP),
+        "gif" => Ok(ImageFormat::Gif),
+        "bmp" => Ok(ImageFormat::Bmp),
+        "ico" => Ok(ImageFormat::Ico),
+        "pnm" => Ok(ImageFormat::Pnm),
+        "tif" | "tiff" => Ok(ImageFormat::Tiff),
+        "tga" => Ok(ImageFormat::Tga),
+        "dds" => Ok(ImageFormat::Dds),
+        "hdr" => Ok(ImageFormat::Hdr),
+        "farb" | "farbfeld" => Ok(ImageFormat::Farbfeld),
+        "avif" => Ok(ImageFormat::Avif),
+        _ => Err(format!(
+            "{} is not a supported image format, use png, jpeg, webp, gif, bmp, ico, pnm, tiff, tga, dds, hdr, farbfeld or avif",
+            s
+        )),
+    }
+}
+
+fn parse_dimension(s: &str) -> Result<u32, String> {
+    let dimension: u32 = s
+        .parse()
+        .map_err(|e| format!("{} is not a valid dimension. {}", s, e))?;
+    if dimension == 0 {
+        return Err("The width and height must be at least 1 pixel".to_string());
+    }
+    Ok(dimension.min(MAX_IMAGE_DIMENSION))
+}
+
+// the X of neighbouring pixels is 1 / (width - 1) apart, a row needs 2 pixels
+fn parse_width(s: &str) -> Result<u32, String> {
+    let width = parse_dimension(s)?;
+    if width < 2 {
+        return Err("The width must be at least 2 pixels".to_string());
+    }
+    Ok(width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dimension() {
+        assert_eq!(parse_dimension("640"), Ok(640));
+        assert_eq!(parse_dimension("1"), Ok(1));
+        assert_eq!(parse_dimension("100000"), Ok(MAX_IMAGE_DIMENSION));
+        assert!(parse_dimension("0").is_err());
+        assert!(parse_dimension("-5").is_err());
+        assert!(parse_dimension("wide").is_err());
+        assert_eq!(parse_width("2"), Ok(2));
+        assert_eq!(
+            parse_width("1"),
+            Err("The width must be at least 2 pixels".to_string())
+        );
+        assert!(parse_width("0").is_err());
+    }
+
+    #[test]
+    fn test_args_dimensions() {
+        let args =
+            Args::try_parse_from(["evolution", "--width", "640", "--height", "480"]).unwrap();
+        assert_eq!((args.width, args.height), (640, 480));
+        assert!(Args::try_parse_from(["evolution", "--width", "0"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--width", "1"]).is_err());
+        assert_eq!(
+            Args::try_parse_from(["evolution", "--height", "1"])
+                .unwrap()
+                .height,
+            1
+        );
+        assert!(Args::try_parse_from(["evolution", "--height", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_args_time_range() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.time_range, DEFAULT_TIME_RANGE);
+        let args = Args::try_parse_from(["evolution", "--time-range", "-0.5:2"]).unwrap();
+        assert_eq!(args.time_range, TimeRange::new(-0.5, 2.0));
+        assert!(Args::try_parse_from(["evolution", "--time-range", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_args_contact_sheet() {
+        assert_eq!(
+            Args::try_parse_from(["evolution"]).unwrap().contact_sheet,
+            None
+        );
+        let args = Args::try_parse_from(["evolution", "--contact-sheet", "4x3", "-i", "in.sexpr"])
+            .unwrap();
+        assert_eq!(args.contact_sheet, Some(ContactSheet::new(4, 3).unwrap()));
+        assert!(Args::try_parse_from(["evolution", "--contact-sheet", "4x3"]).is_err());
+        assert!(
+            Args::try_parse_from(["evolution", "--contact-sheet", "0x3", "-i", "in.sexpr"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_args_compose() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.compose, None);
+        assert_eq!(args.blend, DEFAULT_BLEND_MODE);
+        let args = Args::try_parse_from([
+            "evolution",
+            "--compose",
+            "a.sexpr",
+            "b.sexpr",
+            "--blend",
+            "multiply",
+            "-o",
+            "out.png",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.compose,
+            Some(vec!["a.sexpr".to_string(), "b.sexpr".to_string()])
+        );
+        assert_eq!(args.blend, BlendMode::Multiply);
+        // one layer is nothing to blend
+        assert!(Args::try_parse_from(["evolution", "--compose", "a.sexpr"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--blend", "add"]).is_err());
+        assert!(Args::try_parse_from([
+            "evolution",
+            "--compose",
+            "a.sexpr",
+            "b.sexpr",
+            "-i",
+            "c.sexpr"
+        ])
+        .is_err());
+        assert!(Args::try_parse_from(["evolution", "-o", "out.png"]).is_err());
+    }
+
+    #[test]
+    fn test_args_invert() {
+        assert!(!Args::try_parse_from(["evolution"]).unwrap().invert);
+        let args = Args::try_parse_from(["evolution", "--invert", "-i", "in.sexpr"]).unwrap();
+        assert!(args.invert);
+    }
+
+    #[test]
+    fn test_args_quiet_verbose() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert!(!args.quiet && !args.verbose);
+        assert!(Args::try_parse_from(["evolution", "-q"]).unwrap().quiet);
+        assert!(
+            Args::try_parse_from(["evolution", "--verbose"])
+                .unwrap()
+                .verbose
+        );
+        assert!(Args::try_parse_from(["evolution", "--quiet", "--verbose"]).is_err());
+    }
+
+    #[test]
+    fn test_args_extract_metadata() {
+        assert_eq!(
+            Args::try_parse_from(["evolution"])
+                .unwrap()
+                .extract_metadata,
+            None
+        );
+        let args = Args::try_parse_from(["evolution", "--extract-metadata", "out.png"]).unwrap();
+        assert_eq!(args.extract_metadata, Some("out.png".to_string()));
+        assert!(Args::try_parse_from([
+            "evolution",
+            "--extract-metadata",
+            "out.png",
+            "-i",
+            "in.sexpr"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_args_benchmark() {
+        assert!(!Args::try_parse_from(["evolution"]).unwrap().benchmark);
+        let args = Args::try_parse_from(["evolution", "--benchmark", "--simd", "scalar"]).unwrap();
+        assert!(args.benchmark);
+        assert_eq!(args.simd, SimdBackend::Scalar);
+        assert!(Args::try_parse_from(["evolution", "--benchmark", "-i", "in.sexpr"]).is_err());
+    }
+
+    #[test]
+    fn test_args_downscale_filter() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(
+            (args.supersample, args.downscale_filter),
+            (1, DEFAULT_DOWNSCALE_FILTER)
+        );
+        let args = Args::try_parse_from([
+            "evolution",
+            "--supersample",
+            "4",
+            "--downscale-filter",
+            "lanczos3",
+        ])
+        .unwrap();
+        assert_eq!(
+            (args.supersample, args.downscale_filter),
+            (4, DownscaleFilter::Lanczos3)
+        );
+        assert!(Args::try_parse_from(["evolution", "--supersample", "0"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--downscale-filter", "bicubic"]).is_err());
+    }
+
+    #[test]
+    fn test_args_gamma() {
+        assert_eq!(
+            Args::try_parse_from(["evolution"]).unwrap().gamma,
+            DEFAULT_GAMMA
+        );
+        let args = Args::try_parse_from(["evolution", "--gamma", "2.2"]).unwrap();
+        assert_eq!(args.gamma, 2.2);
+        assert!(Args::try_parse_from(["evolution", "--gamma", "0"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--gamma", "-1"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--gamma", "inf"]).is_err());
+    }
+
+    #[test]
+    fn test_args_color_space() {
+        assert_eq!(
+            Args::try_parse_from(["evolution"]).unwrap().color_space,
+            ColorSpace::Srgb
+        );
+        let args = Args::try_parse_from(["evolution", "--color-space", "linear"]).unwrap();
+        assert_eq!(args.color_space, ColorSpace::Linear);
+        assert!(Args::try_parse_from(["evolution", "--color-space", "rec2020"]).is_err());
+    }
+
+    #[test]
+    fn test_args_smooth_edges() {
+        assert_eq!(
+            Args::try_parse_from(["evolution"]).unwrap().smooth_edges,
+            DEFAULT_SMOOTH_EDGES
+        );
+        let args = Args::try_parse_from(["evolution", "--smooth-edges", "0.5"]).unwrap();
+        assert_eq!(args.smooth_edges, 0.5);
+        assert!(Args::try_parse_from(["evolution", "--smooth-edges", "-0.1"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--smooth-edges", "1.5"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--smooth-edges", "NaN"]).is_err());
+    }
+
+    #[test]
+    fn test_args_grid() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.grid_cols, DEFAULT_GRID_COLS);
+        assert_eq!(args.grid_rows, DEFAULT_GRID_ROWS);
+        assert_eq!(args.thumb_size, DEFAULT_THUMB_SIZE);
+        let args = Args::try_parse_from([
+            "evolution",
+            "--grid-cols",
+            "20",
+            "--grid-rows",
+            "12",
+            "--thumb-size",
+            "200",
+        ])
+        .unwrap();
+        assert_eq!(
+            (args.grid_cols, args.grid_rows, args.thumb_size),
+            (20, 12, 200)
+        );
+        assert!(Args::try_parse_from(["evolution", "--grid-cols", "0"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--grid-rows", "100000"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--thumb-size", "8"]).is_err());
+    }
+
+    #[test]
+    fn test_args_check() {
+        let args = Args::try_parse_from(["evolution", "--check", "-i", "-"]).unwrap();
+        assert!(args.check);
+        assert!(!Args::try_parse_from(["evolution"]).unwrap().check);
+        assert!(Args::try_parse_from(["evolution", "--check"]).is_err());
+    }
+
+    #[test]
+    fn test_args_stats() {
+        let args = Args::try_parse_from(["evolution", "--stats", "-i", "in.sexpr"]).unwrap();
+        assert!(args.stats);
+        assert!(!Args::try_parse_from(["evolution"]).unwrap().stats);
+        assert!(Args::try_parse_from(["evolution", "--stats"]).is_err());
+    }
+
+    #[test]
+    fn test_args_debounce_ms() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.debounce_ms, DEFAULT_DEBOUNCE_MS);
+        let args = Args::try_parse_from([
+            "evolution",
+            "-i",
+            "in.sexpr",
+            "-c",
+            "copies",
+            "--debounce-ms",
+            "500",
+        ])
+        .unwrap();
+        assert_eq!(args.debounce_ms, 500);
+        assert!(Args::try_parse_from(["evolution", "--debounce-ms", "500"]).is_err());
+    }
+
+    #[test]
+    fn test_args_batch() {
+        let args =
+            Args::try_parse_from(["evolution", "--batch", "3", "--out-dir", "some/dir"]).unwrap();
+        assert_eq!(args.batch, Some(3));
+        assert_eq!(args.out_dir, Some("some/dir".to_string()));
+        assert!(Args::try_parse_from(["evolution", "--batch", "3"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--out-dir", "some/dir"]).is_err());
+        assert!(Args::try_parse_from([
+            "evolution",
+            "--batch",
+            "3",
+            "--out-dir",
+            "some/dir",
+            "-i",
+            "in.sexpr"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_args_min_complexity() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.min_complexity, PIC_MIN_COMPLEXITY);
+        let args = Args::try_parse_from(["evolution", "--min-complexity", "12"]).unwrap();
+        assert_eq!(args.min_complexity, 12);
+        assert!(Args::try_parse_from(["evolution", "--min-complexity", "-1"]).is_err());
+    }
+
+    #[test]
+    fn test_args_palette() {
+        assert_eq!(Args::try_parse_from(["evolution"]).unwrap().palette, None);
+        let args = Args::try_parse_from(["evolution", "--palette", "fire.gpl"]).unwrap();
+        assert_eq!(args.palette, Some("fire.gpl".to_string()));
+        assert!(
+            Args::try_parse_from(["evolution", "--palette", "fire.gpl", "-i", "in.sexpr"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_args_simd() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.simd, SimdBackend::Auto);
+        let args = Args::try_parse_from(["evolution", "--simd", "scalar"]).unwrap();
+        assert_eq!(args.simd, SimdBackend::Scalar);
+        assert!(Args::try_parse_from(["evolution", "--simd", "neon"]).is_err());
+    }
+
+    #[test]
+    fn test_args_loop_mode() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.loop_mode, LoopMode::Forward);
+        let args = Args::try_parse_from(["evolution", "--loop", "pingpong"]).unwrap();
+        assert_eq!(args.loop_mode, LoopMode::PingPong);
+        assert!(Args::try_parse_from(["evolution", "--loop", "backward"]).is_err());
+    }
+
+    #[test]
+    fn test_args_coordinate_system() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.coordinate_system, DEFAULT_COORDINATE_SYSTEM);
+        let args = Args::try_parse_from(["evolution", "-s", "aspect"]).unwrap();
+        assert_eq!(args.coordinate_system, CoordinateSystem::AspectCorrect);
+        assert!(Args::try_parse_from(["evolution", "-s", "mercator"]).is_err());
+    }
+
+    #[test]
+    fn test_args_loop_seamless_time() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert!(!args.loop_seamless_time);
+        let args = Args::try_parse_from(["evolution", "--loop-seamless-time"]).unwrap();
+        assert!(args.loop_seamless_time);
+        assert!(
+            Args::try_parse_from(["evolution", "--loop-seamless-time", "--time-range", "0:4"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_args_motion_blur() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.motion_blur, 1);
+        let args = Args::try_parse_from(["evolution", "--motion-blur", "4"]).unwrap();
+        assert_eq!(args.motion_blur, 4);
+        let args = Args::try_parse_from(["evolution", "--oversample-time", "8"]).unwrap();
+        assert_eq!(args.motion_blur, 8);
+        assert!(Args::try_parse_from(["evolution", "--motion-blur", "0"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--motion-blur", "17"]).is_err());
+    }
+
+    #[test]
+    fn test_args_symmetry() {
+        let args = Args::try_parse_from(["evolution", "-i", "in.sexpr"]).unwrap();
+        assert_eq!(args.symmetry, None);
+        let args =
+            Args::try_parse_from(["evolution", "-i", "in.sexpr", "--symmetry", "6"]).unwrap();
+        assert_eq!(args.symmetry, Some(Symmetry::new(6).unwrap()));
+        let args = Args::try_parse_from(["evolution", "-i", "in.sexpr", "--repeat", "3"]).unwrap();
+        assert_eq!(args.symmetry, Some(Symmetry::new(3).unwrap()));
+        assert!(Args::try_parse_from(["evolution", "-i", "in.sexpr", "--symmetry", "0"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "-i", "in.sexpr", "--symmetry", "65"]).is_err());
+        // the Ui does not render with a symmetry
+        assert!(Args::try_parse_from(["evolution", "--symmetry", "4"]).is_err());
+    }
+
+    #[test]
+    fn test_args_subsample() {
+        let args = Args::try_parse_from(["evolution", "-i", "in.sexpr"]).unwrap();
+        assert_eq!(args.subsample, None);
+        let args =
+            Args::try_parse_from(["evolution", "-i", "in.sexpr", "--subsample", "h,s"]).unwrap();
+        assert_eq!(args.subsample, Some("H,S".parse().unwrap()));
+        assert!(
+            Args::try_parse_from(["evolution", "-i", "in.sexpr", "--subsample", "H,A"]).is_err()
+        );
+        // the Ui renders every channel at full resolution
+        assert!(Args::try_parse_from(["evolution", "--subsample", "H"]).is_err());
+    }
+
+    #[test]
+    fn test_args_mutation_rate() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.mutation_rate, DEFAULT_MUTATION_RATE);
+        let args = Args::try_parse_from(["evolution", "--mutation-rate", "0.35"]).unwrap();
+        assert_eq!(args.mutation_rate, 0.35);
+        assert!(Args::try_parse_from(["evolution", "--mutation-rate", "1.5"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--mutation-rate", "-0.1"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--mutation-rate", "often"]).is_err());
+    }
+
+    #[test]
+    fn test_args_max_render_time() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.max_render_time, None);
+        let args = Args::try_parse_from(["evolution", "--max-render-time", "1500"]).unwrap();
+        assert_eq!(args.max_render_time, Some(Duration::from_millis(1500)));
+        assert!(Args::try_parse_from(["evolution", "--max-render-time", "0"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--max-render-time", "-5"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--max-render-time", "1.5s"]).is_err());
+    }
+
+    #[test]
+    fn test_args_jpeg_quality() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.jpeg_quality, DEFAULT_JPEG_QUALITY);
+        let args = Args::try_parse_from(["evolution", "--jpeg-quality", "100"]).unwrap();
+        assert_eq!(args.jpeg_quality, 100);
+        assert!(Args::try_parse_from(["evolution", "--jpeg-quality", "0"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "--jpeg-quality", "101"]).is_err());
+    }
+
+    #[test]
+    fn test_args_background() {
+        let args = Args::try_parse_from(["evolution"]).unwrap();
+        assert_eq!(args.background, DEFAULT_BACKGROUND);
+        let args = Args::try_parse_from(["evolution", "--background", "#00ffff"]).unwrap();
+        assert_eq!(args.background, Color::CYAN);
+        assert!(Args::try_parse_from(["evolution", "--background", "cyan"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_image_format() {
+        assert_eq!(parse_image_format("png"), Ok(ImageFormat::Png));
+        assert_eq!(parse_image_format("JPG"), Ok(ImageFormat::Jpeg));
+        assert_eq!(parse_image_format("jpeg"), Ok(ImageFormat::Jpeg));
+        assert_eq!(parse_image_format("webp"), Ok(ImageFormat::WebP));
+        assert_eq!(parse_image_format("tif"), Ok(ImageFormat::Tiff));
+        assert!(parse_image_format("mp4").is_err());
+        assert!(parse_image_format("").is_err());
+    }
+
+    #[test]
+    fn test_args_format() {
+        let args =
+            Args::try_parse_from(["evolution", "-i", "in.sexpr", "--format", "jpeg"]).unwrap();
+        assert_eq!(args.format, Some(ImageFormat::Jpeg));
+        let args = Args::try_parse_from(["evolution", "-i", "in.sexpr"]).unwrap();
+        assert_eq!(args.format, None);
+        assert!(Args::try_parse_from(["evolution", "--format", "png"]).is_err());
+        assert!(Args::try_parse_from(["evolution", "-i", "in.sexpr", "--format", "mp4"]).is_err());
+    }
 }
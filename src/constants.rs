@@ -1,26 +1,86 @@
+use crate::pic::blend::BlendMode;
+use crate::pic::colorspace::ColorSpace;
 use crate::pic::coordinatesystem::CoordinateSystem;
+use crate::pic::downscalefilter::DownscaleFilter;
+use crate::pic::loopmode::LoopMode;
+use crate::pic::renderoptions::RenderOptions;
+use crate::pic::simdbackend::SimdBackend;
+use crate::pic::timerange::TimeRange;
+use crate::pic::viewport::Viewport;
 
 pub const DEFAULT_IMAGE_WIDTH: u32 = 1920;
 pub const DEFAULT_IMAGE_HEIGHT: u32 = 1080;
+pub const MAX_IMAGE_DIMENSION: u32 = 16384; // larger widths and heights are capped to this
 pub const DEFAULT_COORDINATE_SYSTEM: CoordinateSystem = CoordinateSystem::Polar;
+pub const DEFAULT_TIME_RANGE: TimeRange = TimeRange::new(-1.0, 1.0); // the values T sweeps through
+pub const SEAMLESS_TIME_RANGE: TimeRange = TimeRange::new(0.0, 2.0); // TSIN and TCOS go round once
+pub const DEFAULT_VIEWPORT: Viewport = Viewport::new(-1.0, -1.0, 1.0, 1.0); // X and Y of the whole image
+pub const DEFAULT_RENDER_OPTIONS: RenderOptions = RenderOptions::new(); // no symmetry, deadline or blur
+pub const DEFAULT_LOOP_MODE: LoopMode = LoopMode::Forward;
+pub const DEFAULT_BLEND_MODE: BlendMode = BlendMode::Over;
+pub const DEFAULT_DOWNSCALE_FILTER: DownscaleFilter = DownscaleFilter::Box;
+pub const DEFAULT_COLOR_SPACE: ColorSpace = ColorSpace::Srgb; // the rendered values are the colors
+pub const DEFAULT_SIMD_BACKEND: SimdBackend = SimdBackend::Auto;
+pub const MAX_CONTACT_SHEET_CELLS: u32 = 256; // renders of the picture for one contact sheet
+pub const MAX_SYMMETRY: u32 = 64; // wedges that --symmetry folds the plane into
+pub const SIMD_VERIFY_SIZE: u32 = 64; // width and height of the renders that --verify-simd compares
+pub const SIMD_DIVERGENCE_THRESHOLD: u8 = 1; // larger differences between backends are reported
+pub const BENCHMARK_SIZE: u32 = 256; // width and height of the renders that --benchmark times
+pub const BENCHMARK_ROUNDS: u32 = 3; // renders of every expression per backend, the fastest counts
 
 pub const PIC_RANDOM_TREE_MIN: usize = 1;
 pub const PIC_RANDOM_TREE_MAX: usize = 40;
+pub const PIC_RANDOM_TREE_MAX_DEPTH: usize = 12; // levels, the leaves included
+pub const PIC_RANDOM_TREE_MAX_NODES: usize = 160; // operations and leaves
+pub const PIC_MIN_COMPLEXITY: usize = 0; // nodes of all the channels together, 0 accepts every pic
+pub const PIC_COMPLEXITY_ATTEMPTS: usize = 20; // pics generated before the most complex one is taken
+pub const PIC_UNSEEN_ATTEMPTS: usize = 10; // pics generated before a duplicate is accepted
+pub const PIC_MAX_STACK_DEPTH: usize = 256; // values on the stack of a channel, deeper sexprs are refused
+
+// threaded renders of at most this many rows of channels of at least this many instructions
+// evaluate every channel on tasks of its own, below that the tasks per row are too few
+pub const PIC_CHANNEL_SPLIT_MAX_ROWS: u32 = 32;
+pub const PIC_CHANNEL_SPLIT_MIN_INSTRUCTIONS: usize = 24;
+
+pub const PIC_FBM_OCTAVES_MAX: usize = 8; // the octaves of FBM for an octaves child of 1.0
+pub const PIC_FBM_OCTAVES_DEFAULT: f32 = 0.375; // 3 octaves, for FBM written without octaves
+pub const PIC_DERIVATIVE_STEP: f32 = 1.0 / 256.0; // DX and DY compare the child this far apart, twice
+
+pub const PIC_MUTATE_CONSTANT_DELTA: f32 = 0.1;
+pub const PIC_MUTATE_TREE_MAX: usize = 4;
+pub const PIC_MUTATE_SWAP_ATTEMPTS: usize = 10;
 
 pub const PIC_GRADIENT_STOP_CHANCE: usize = 5; // 1 in 5
 pub const PIC_GRADIENT_COUNT_MAX: usize = 10;
 pub const PIC_GRADIENT_COUNT_MIN: usize = 2;
 pub const PIC_GRADIENT_SIZE: usize = 512;
 
+pub const POPULATION_SEPARATOR: &'static str = "---"; // a line between the pics in a population file
+
 #[cfg(feature = "ui")]
 pub mod exec {
+    use crate::pic::color::Color;
+
     pub const EXEC_NAME: &'static str = "Evolution";
-    pub const EXEC_UI_THUMB_ROWS: usize = 15;
-    pub const EXEC_UI_THUMB_COLS: usize = 14;
-    pub const EXEC_UI_THUMB_WIDTH: u32 = 128;
-    pub const EXEC_UI_THUMB_HEIGHT: u32 = 72;
+    pub const EXEC_UI_GRID_CELLS_MAX: usize = 512; // thumbnails in the grid of the Ui
+    pub const EXEC_UI_PLACEHOLDER_GRAY: u8 = 64;
+    pub const EXEC_UI_PIN_BORDER: u32 = 3; // pixels around a pinned thumbnail
+    pub const EXEC_UI_PIN_COLOR: [u8; 4] = [255, 196, 0, 255];
+    pub const EXEC_UI_HISTORY_SIZE: usize = 10; // populations that can be undone
+    pub const EXEC_UI_ZOOM_LEVEL_MIN: i32 = -3; // 1/8 of the window resolution, scaled up
+    pub const EXEC_UI_ZOOM_LEVEL_MAX: i32 = 2; // 4 x 4 times the samples per pixel
+    pub const DEFAULT_GRID_COLS: usize = 14;
+    pub const DEFAULT_GRID_ROWS: usize = 15;
+    pub const DEFAULT_THUMB_SIZE: u32 = 128; // the width of a thumbnail, at most
+    pub const DEFAULT_THUMB_QUALITY: u32 = 2; // thumbnails are rendered at twice their size
+    pub const DEFAULT_MUTATION_RATE: f32 = 0.1; // the chance of every node to mutate
     pub const DEFAULT_PICTURES_PATH: &'static str = "pictures";
     pub const DEFAULT_FILE_OUT: &'static str = "out.png";
     pub const DEFAULT_FPS: u16 = 15;
+    pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+    pub const DEFAULT_GAMMA: f32 = 1.0; // leaves the colors as they are rendered
+    pub const DEFAULT_BACKGROUND: Color = Color::WHITE; // behind transparent pixels in a jpeg
+    pub const DEFAULT_SMOOTH_EDGES: f32 = 0.0; // leaves the edges as they are rendered
+    pub const DEFAULT_DEBOUNCE_MS: u64 = 150; // file events closer together are one change
     pub const DEFAULT_VIDEO_DURATION: f32 = 5000.0; //milliseconds
 }
@@ -4,12 +4,17 @@ pub mod args;
 pub mod constants;
 pub mod parser;
 pub mod pic;
+pub mod verbosity;
 pub mod vm;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use std::fs::read_dir;
+use std::fs::{create_dir_all, read_dir, write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
 #[cfg(feature = "ui")]
 use std::env::var;
@@ -17,22 +22,62 @@ use std::env::var;
 #[cfg(feature = "ui")]
 pub use args::Args;
 
-pub use constants::{DEFAULT_COORDINATE_SYSTEM, DEFAULT_IMAGE_HEIGHT, DEFAULT_IMAGE_WIDTH};
+pub use constants::{
+    BENCHMARK_ROUNDS, BENCHMARK_SIZE, DEFAULT_BLEND_MODE, DEFAULT_COLOR_SPACE,
+    DEFAULT_COORDINATE_SYSTEM, DEFAULT_DOWNSCALE_FILTER, DEFAULT_IMAGE_HEIGHT, DEFAULT_IMAGE_WIDTH,
+    DEFAULT_LOOP_MODE, DEFAULT_RENDER_OPTIONS, DEFAULT_SIMD_BACKEND, DEFAULT_TIME_RANGE,
+    DEFAULT_VIEWPORT, MAX_CONTACT_SHEET_CELLS, MAX_IMAGE_DIMENSION, SEAMLESS_TIME_RANGE,
+    SIMD_VERIFY_SIZE,
+};
 
 #[cfg(feature = "ui")]
 pub use constants::exec::{
-    DEFAULT_FILE_OUT, DEFAULT_FPS, DEFAULT_PICTURES_PATH, DEFAULT_VIDEO_DURATION, EXEC_NAME,
-    EXEC_UI_THUMB_COLS, EXEC_UI_THUMB_HEIGHT, EXEC_UI_THUMB_ROWS, EXEC_UI_THUMB_WIDTH,
+    DEFAULT_BACKGROUND, DEFAULT_DEBOUNCE_MS, DEFAULT_FILE_OUT, DEFAULT_FPS, DEFAULT_GAMMA,
+    DEFAULT_GRID_COLS, DEFAULT_GRID_ROWS, DEFAULT_JPEG_QUALITY, DEFAULT_MUTATION_RATE,
+    DEFAULT_PICTURES_PATH, DEFAULT_SMOOTH_EDGES, DEFAULT_THUMB_QUALITY, DEFAULT_THUMB_SIZE,
+    DEFAULT_VIDEO_DURATION, EXEC_NAME, EXEC_UI_GRID_CELLS_MAX, EXEC_UI_HISTORY_SIZE,
+    EXEC_UI_PIN_BORDER, EXEC_UI_PIN_COLOR, EXEC_UI_PLACEHOLDER_GRAY, EXEC_UI_ZOOM_LEVEL_MAX,
+    EXEC_UI_ZOOM_LEVEL_MIN,
 };
 #[cfg(feature = "ui")]
 pub mod ui;
 
+pub use parser::aptnode::APTNode;
 pub use parser::lexer::lisp_to_pic;
+pub use parser::parseerror::ParseError;
 pub use pic::actual_picture::ActualPicture;
+pub use pic::benchmark::{run_benchmark, BenchmarkRun};
+pub use pic::blend::{blend_rgba8, flatten_rgba8_over, BlendMode};
+pub use pic::color::Color;
+pub use pic::colorspace::ColorSpace;
+pub use pic::contactsheet::ContactSheet;
 pub use pic::coordinatesystem::CoordinateSystem;
+pub use pic::data::Channel;
+pub use pic::downscalefilter::DownscaleFilter;
+pub use pic::edges::smooth_edges_rgba8;
+pub use pic::generation::{GenerationConfig, OperatorWeights};
+pub use pic::loopmode::LoopMode;
+pub use pic::metadata::{
+    encode_png16_with_metadata, encode_png_with_metadata, read_png_metadata, RenderMetadata,
+};
+pub use pic::palette::{load_palette, parse_palette};
 pub use pic::pic::{
-    pic_get_rgba8_runtime_select, pic_get_video_runtime_select, pic_simplify_runtime_select, Pic,
+    pic_get_rgba16_runtime_select, pic_get_rgba16_viewport_runtime_select,
+    pic_get_rgba8_runtime_select, pic_get_rgba8_tiled_runtime_select,
+    pic_get_rgba8_viewport_runtime_select, pic_get_video_runtime_select,
+    pic_simplify_runtime_select, Pic,
 };
+pub use pic::population::{lisp_to_population, population_to_lisp};
+pub use pic::renderoptions::RenderOptions;
+pub use pic::simdbackend::{select_simd_backend, selected_simd_backend, SimdBackend};
+pub use pic::simdcheck::{verify_simd, SimdDivergence};
+pub use pic::stats::PicStats;
+pub use pic::subsample::Subsample;
+pub use pic::symmetry::Symmetry;
+pub use pic::timerange::TimeRange;
+pub use pic::viewport::Viewport;
+pub use verbosity::{debug, info, report, set_verbosity, warn, Verbosity};
+pub use vm::cache::StackMachineCache;
 
 #[cfg(feature = "ui")]
 pub fn get_picture_path(args: &Args) -> PathBuf {
@@ -45,17 +90,57 @@ pub fn get_picture_path(args: &Args) -> PathBuf {
     path_buf
 }
 
+/// How the random pics are generated, with the colors of --palette when it is given
+#[cfg(feature = "ui")]
+pub fn get_generation_config(args: &Args) -> Result<GenerationConfig, String> {
+    let palette = match &args.palette {
+        Some(file_name) => Some(load_palette(Path::new(file_name))?),
+        None => None,
+    };
+    Ok(GenerationConfig {
+        min_complexity: args.min_complexity,
+        palette,
+        ..GenerationConfig::default()
+    })
+}
+
+/// Load every image in `pic_path` by its file name, other files are skipped
 pub fn load_pictures(pic_path: &Path) -> Result<HashMap<String, ActualPicture>, String> {
+    if !pic_path.exists() {
+        return Err(format!(
+            "The pictures folder {} does not exist, create it or choose another one with --pictures-path",
+            pic_path.display()
+        ));
+    }
+    if !pic_path.is_dir() {
+        return Err(format!(
+            "The pictures folder {} is not a folder",
+            pic_path.display()
+        ));
+    }
     let mut pictures = HashMap::new();
+    let files = read_dir(pic_path).map_err(|e| {
+        format!(
+            "Cannot read the pictures folder {}. {}",
+            pic_path.display(),
+            e
+        )
+    })?;
     //todo rayon par_iter
-    for file in read_dir(pic_path).expect(&format!("Cannot read path {:?}", pic_path)) {
-        let short_file_name = file
-            .as_ref()
-            .unwrap()
-            .file_name()
-            .into_string()
-            .expect("Cannot convert file's name ");
-        let path = file.as_ref().unwrap().path();
+    for file in files {
+        let file = file.map_err(|e| {
+            format!(
+                "Cannot read the pictures folder {}. {}",
+                pic_path.display(),
+                e
+            )
+        })?;
+        // a name that is not valid UTF-8 cannot be written in a sexpr
+        let short_file_name = match file.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let path = file.path();
         let full_file_name = path.to_string_lossy();
         if let Ok(pic) = ActualPicture::new_via_file(&full_file_name.to_owned()) {
             pictures.insert(short_file_name, pic);
@@ -64,6 +149,87 @@ pub fn load_pictures(pic_path: &Path) -> Result<HashMap<String, ActualPicture>,
     Ok(pictures)
 }
 
+/// Like load_pictures, but a pictures folder that does not exist has no pictures. Random pics
+/// do not use the Pic- operation then, so the app runs without a pictures folder.
+pub fn load_optional_pictures(pic_path: &Path) -> Result<HashMap<String, ActualPicture>, String> {
+    if !pic_path.exists() {
+        return Ok(HashMap::new());
+    }
+    load_pictures(pic_path)
+}
+
+/// Render an expression without a window, the pictures it refers to are loaded from `pictures_dir`
+pub fn render_sexpr_to_rgba8(
+    sexpr: &str,
+    coord: CoordinateSystem,
+    pictures_dir: &Path,
+    w: u32,
+    h: u32,
+    t: f32,
+) -> Result<Vec<u8>, String> {
+    if w < 2 || h < 1 {
+        return Err(format!("Cannot render an image of {}x{} pixels", w, h));
+    }
+    let pictures = Arc::new(load_pictures(pictures_dir)?);
+    let mut pic = lisp_to_pic(sexpr.to_string(), coord)?;
+    pic.resolve_pictures(&pictures)?;
+    Ok(pic_get_rgba8_runtime_select(
+        &pic, true, pictures, w, h, t, 1,
+    ))
+}
+
+/// The size, the moment and the samples per pixel of the pics of render_batch
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatchRender {
+    pub w: u32,
+    pub h: u32,
+    pub t: f32,
+    pub supersample: u32,
+}
+
+/// Render `count` random pics that are reproducible with `seed`, every pic is written to
+/// `out_dir` as a png and as a sexpr file, named after the seed and a counter
+pub fn render_batch(
+    count: usize,
+    out_dir: &Path,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    config: &GenerationConfig,
+    seed: u64,
+    render: BatchRender,
+) -> Result<Vec<PathBuf>, String> {
+    let BatchRender {
+        w,
+        h,
+        t,
+        supersample,
+    } = render;
+    create_dir_all(out_dir).map_err(|e| format!("Cannot create {}. {}", out_dir.display(), e))?;
+    let mut rng = StdRng::seed_from_u64(seed);
+    // the order of a HashMap differs between runs, the seed should pick the same pictures
+    let mut pic_names: Vec<&String> = pictures.keys().collect();
+    pic_names.sort();
+    let mut written = Vec::with_capacity(count * 2);
+    let mut seen = HashSet::new();
+    for i in 0..count {
+        let pic = Pic::unseen(&mut seen, || Pic::new(&mut rng, &pic_names, config));
+        let name = format!("{}_{:04}", seed, i);
+
+        let sexpr_file = out_dir.join(format!("{}.sexpr", name));
+        write(&sexpr_file, pic.to_lisp())
+            .map_err(|e| format!("Cannot write {}. {}", sexpr_file.display(), e))?;
+        written.push(sexpr_file);
+
+        let png_file = out_dir.join(format!("{}.png", name));
+        let rgba8 =
+            pic_get_rgba8_runtime_select(&pic, true, pictures.clone(), w, h, t, supersample);
+        let metadata = RenderMetadata::new(&pic, w, h, t, Some(seed));
+        let png = encode_png_with_metadata(&rgba8[0..], w, h, &metadata)?;
+        write(&png_file, png).map_err(|e| format!("Cannot write {}. {}", png_file.display(), e))?;
+        written.push(png_file);
+    }
+    Ok(written)
+}
+
 pub fn keep_aspect_ratio(output: (u32, u32), thumb: (u32, u32)) -> (u32, u32) {
     // todo make this function signature type generic
     let (ow, oh) = output;
@@ -104,6 +270,141 @@ mod tests {
         assert_eq!(keep_aspect_ratio((1000, 600), (128, 32)), (128, 76));
     }
 
+    #[test]
+    fn test_render_sexpr_to_rgba8() {
+        let sexpr = "( RGB CARTESIAN ( PIC-eye.jpg X Y ) ( Y ) ( * X T ) )";
+        let rgba8 = render_sexpr_to_rgba8(
+            sexpr,
+            DEFAULT_COORDINATE_SYSTEM,
+            Path::new("pictures"),
+            64,
+            48,
+            0.5,
+        )
+        .unwrap();
+        assert_eq!(rgba8.len(), 64 * 48 * 4);
+
+        assert!(render_sexpr_to_rgba8(
+            "( RGB ( X ) )",
+            DEFAULT_COORDINATE_SYSTEM,
+            Path::new("pictures"),
+            64,
+            48,
+            0.0
+        )
+        .is_err());
+        let err = render_sexpr_to_rgba8(
+            "( MONO ( PIC-moon.jpg X Y ) )",
+            DEFAULT_COORDINATE_SYSTEM,
+            Path::new("pictures"),
+            64,
+            48,
+            0.0,
+        )
+        .err()
+        .unwrap();
+        assert!(
+            err.starts_with("The picture moon.jpg is missing"),
+            "{}",
+            err
+        );
+        assert!(render_sexpr_to_rgba8(
+            sexpr,
+            DEFAULT_COORDINATE_SYSTEM,
+            Path::new("does/not/exist"),
+            64,
+            48,
+            0.0
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_load_pictures() {
+        let pictures = load_pictures(Path::new("pictures")).unwrap();
+        let mut names: Vec<&String> = pictures.keys().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "barn.jpg",
+                "eye.jpg",
+                "graves.jpg",
+                "runner.jpg",
+                "tree.jpg"
+            ]
+        );
+
+        let missing = Path::new("does/not/exist");
+        let err = load_pictures(missing).err().unwrap();
+        assert!(
+            err.starts_with("The pictures folder does/not/exist does not exist"),
+            "{}",
+            err
+        );
+        assert!(load_optional_pictures(missing).unwrap().is_empty());
+        assert!(load_pictures(Path::new("Cargo.toml")).is_err());
+        assert!(load_optional_pictures(Path::new("Cargo.toml")).is_err());
+
+        // a folder without images has no pictures
+        let empty_dir = std::env::temp_dir().join(format!(
+            "evolution_test_load_pictures_{}",
+            std::process::id()
+        ));
+        create_dir_all(&empty_dir).unwrap();
+        write(empty_dir.join("notes.txt"), "not a picture").unwrap();
+        assert!(load_pictures(&empty_dir).unwrap().is_empty());
+        std::fs::remove_dir_all(&empty_dir).unwrap();
+    }
+
+    const SMALL_BATCH: BatchRender = BatchRender {
+        w: 32,
+        h: 16,
+        t: 0.0,
+        supersample: 1,
+    };
+
+    #[test]
+    fn test_render_batch_without_pictures() {
+        let out_dir = std::env::temp_dir().join(format!(
+            "evolution_test_render_batch_without_pictures_{}",
+            std::process::id()
+        ));
+        let pictures = Arc::new(HashMap::new());
+        let config = GenerationConfig::new(1, 10);
+        let written = render_batch(4, &out_dir, pictures, &config, 7, SMALL_BATCH).unwrap();
+        assert_eq!(written.len(), 8);
+        for path in written.iter().filter(|p| p.extension().unwrap() == "sexpr") {
+            let sexpr = std::fs::read_to_string(path).unwrap();
+            assert!(!sexpr.contains("PIC"), "{}", sexpr);
+        }
+        std::fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn test_render_batch() {
+        let out_dir = std::env::temp_dir().join(format!(
+            "evolution_test_render_batch_{}",
+            std::process::id()
+        ));
+        let pictures = Arc::new(load_pictures(Path::new("pictures")).unwrap());
+        let config = GenerationConfig::new(1, 10);
+        let written =
+            render_batch(3, &out_dir, pictures.clone(), &config, 42, SMALL_BATCH).unwrap();
+        assert_eq!(written.len(), 6);
+        assert_eq!(read_dir(&out_dir).unwrap().count(), 6);
+        assert!(out_dir.join("42_0002.png").exists());
+        assert!(out_dir.join("42_0002.sexpr").exists());
+
+        // the same seed gives the same pics
+        let sexpr = std::fs::read_to_string(out_dir.join("42_0001.sexpr")).unwrap();
+        render_batch(2, &out_dir, pictures, &config, 42, SMALL_BATCH).unwrap();
+        let again = std::fs::read_to_string(out_dir.join("42_0001.sexpr")).unwrap();
+        assert_eq!(sexpr, again);
+        assert_eq!(read_dir(&out_dir).unwrap().count(), 6);
+        std::fs::remove_dir_all(&out_dir).unwrap();
+    }
+
     #[cfg(feature = "ui")]
     #[test]
     fn test_get_picture_path() {
@@ -114,8 +415,48 @@ mod tests {
             time: 0.0,
             input: None,
             output: None,
+            format: None,
+            check: false,
+            stats: false,
+            quiet: false,
+            verbose: false,
+            invert: false,
+            gamma: DEFAULT_GAMMA,
+            color_space: DEFAULT_COLOR_SPACE,
+            smooth_edges: DEFAULT_SMOOTH_EDGES,
+            background: DEFAULT_BACKGROUND,
+            compose: None,
+            blend: DEFAULT_BLEND_MODE,
             copy_path: None,
             coordinate_system: DEFAULT_COORDINATE_SYSTEM,
+            supersample: 1,
+            downscale_filter: DEFAULT_DOWNSCALE_FILTER,
+            thumb_quality: DEFAULT_THUMB_QUALITY,
+            grid_cols: DEFAULT_GRID_COLS,
+            grid_rows: DEFAULT_GRID_ROWS,
+            thumb_size: DEFAULT_THUMB_SIZE,
+            simd: DEFAULT_SIMD_BACKEND,
+            verify_simd: false,
+            benchmark: false,
+            seed: None,
+            min_complexity: 0,
+            palette: None,
+            bit_depth: 8,
+            jpeg_quality: DEFAULT_JPEG_QUALITY,
+            time_range: DEFAULT_TIME_RANGE,
+            loop_mode: DEFAULT_LOOP_MODE,
+            loop_seamless_time: false,
+            motion_blur: 1,
+            mutation_rate: DEFAULT_MUTATION_RATE,
+            max_render_time: None,
+            symmetry: None,
+            subsample: None,
+            contact_sheet: None,
+            load_population: None,
+            batch: None,
+            extract_metadata: None,
+            out_dir: None,
+            debounce_ms: DEFAULT_DEBOUNCE_MS,
         };
         assert!(get_picture_path(&args)
             .to_string_lossy()
@@ -1,41 +1,54 @@
-// todo
-// - fix up gradient to work properly when parsing
-// - cross breeding of picture expressions
-// - load up thumbnails in a background thread so ui isn't blocked
-
 mod ui;
 extern crate evolution;
 
 extern crate image;
 extern crate minifb;
 
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs::{copy, create_dir_all, File};
 use std::io::prelude::*;
+use std::io::{stdout, Cursor};
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[cfg(feature = "ui")]
-use evolution::ui::{fsm::FSM, state::State};
+use evolution::ui::{fsm::FSM, grid::Grid, state::State};
 use evolution::{
-    filename_to_copy_to, get_picture_path, keep_aspect_ratio, lisp_to_pic, load_pictures,
-    pic_get_rgba8_runtime_select, pic_get_video_runtime_select, pic_simplify_runtime_select,
-    ActualPicture, Args, Pic, DEFAULT_FILE_OUT, DEFAULT_FPS, DEFAULT_VIDEO_DURATION, EXEC_NAME,
+    blend_rgba8, debug, encode_png16_with_metadata, encode_png_with_metadata, filename_to_copy_to,
+    flatten_rgba8_over, get_generation_config, get_picture_path, info, keep_aspect_ratio,
+    lisp_to_pic, load_optional_pictures, pic_get_rgba16_viewport_runtime_select,
+    pic_get_rgba8_runtime_select, pic_get_rgba8_viewport_runtime_select,
+    pic_get_video_runtime_select, pic_simplify_runtime_select, read_png_metadata, render_batch,
+    report, run_benchmark, select_simd_backend, set_verbosity, smooth_edges_rgba8, verify_simd,
+    warn, ActualPicture, Args, BatchRender, BenchmarkRun, Channel, Color, CoordinateSystem,
+    DownscaleFilter, GenerationConfig, LoopMode, Pic, RenderMetadata, SimdBackend, SimdDivergence,
+    Verbosity, BENCHMARK_ROUNDS, BENCHMARK_SIZE, DEFAULT_FILE_OUT, DEFAULT_FPS,
+    DEFAULT_RENDER_OPTIONS, DEFAULT_VIDEO_DURATION, DEFAULT_VIEWPORT, EXEC_NAME,
+    SEAMLESS_TIME_RANGE, SIMD_VERIFY_SIZE,
 };
 #[cfg(feature = "ui")]
 use evolution::{
-    EXEC_UI_THUMB_COLS, EXEC_UI_THUMB_HEIGHT, EXEC_UI_THUMB_ROWS, EXEC_UI_THUMB_WIDTH,
+    lisp_to_population, population_to_lisp, TimeRange, EXEC_UI_GRID_CELLS_MAX,
+    EXEC_UI_HISTORY_SIZE, EXEC_UI_PIN_BORDER, EXEC_UI_PIN_COLOR, EXEC_UI_PLACEHOLDER_GRAY,
+    EXEC_UI_ZOOM_LEVEL_MAX, EXEC_UI_ZOOM_LEVEL_MIN,
 };
 
 use clap::Parser;
 use image::codecs::gif::{GifEncoder, Repeat};
-use image::{save_buffer_with_format, ColorType, Frame, ImageBuffer, ImageFormat};
+use image::codecs::jpeg::JpegEncoder;
+use image::{ColorType, Delay, Frame, ImageBuffer, ImageFormat, Pixel, Rgba};
 use minifb::{Key, Scale, Window, WindowOptions};
 use notify::{
-    event::{AccessKind, AccessMode},
-    Config, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+    event::{AccessKind, AccessMode, ModifyKind, RenameMode},
+    Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
 };
+use png::{BitDepth, ColorType as PngColorType, Encoder as PngEncoder};
+use rand::Rng;
+use webp_animation::{AnimParams, Encoder as WebPEncoder, EncoderOptions as WebPEncoderOptions};
 
 fn main_gui(args: &Args) -> Result<(), String> {
     match rayon::ThreadPoolBuilder::new()
@@ -87,107 +100,887 @@ fn main_gui(args: &Args) -> Result<(), String> {
     Ok(())
 }
 
+fn format_for_extension(ext: &str) -> Option<(ImageFormat, bool)> {
+    match ext.to_lowercase().as_str() {
+        // support these?
+        "tga" => Some((ImageFormat::Tga, false)),
+        "dds" => Some((ImageFormat::Dds, false)),
+        "hdr" => Some((ImageFormat::Hdr, false)),
+        "farb" => Some((ImageFormat::Farbfeld, false)),
+        // these do imply video!
+        "gif" => Some((ImageFormat::Gif, true)),
+        "webp" => Some((ImageFormat::WebP, true)),
+        "apng" => Some((ImageFormat::Png, true)),
+        "avi" => Some((ImageFormat::Avif, false)), // Todo: find out how to create avi writer
+        // commodity
+        "bmp" => Some((ImageFormat::Bmp, false)),
+        "ico" => Some((ImageFormat::Ico, false)),
+        "pnm" => Some((ImageFormat::Pnm, false)),
+        "tif" | "tiff" => Some((ImageFormat::Tiff, false)),
+        "jpg" | "jpeg" => Some((ImageFormat::Jpeg, false)),
+        "png" => Some((ImageFormat::Png, false)),
+        _ => None,
+    }
+}
+
+/// Whether `format` holds the frames of an animation
+fn is_animation_format(format: ImageFormat) -> bool {
+    matches!(format, ImageFormat::Gif | ImageFormat::WebP)
+}
+
 fn select_image_format(out_file: &Path) -> (ImageFormat, bool) {
     match out_file.extension() {
-        Some(ext) => {
-            match ext
-                .to_str()
-                .expect("Invalid file extension")
-                .to_lowercase()
-                .as_str()
-            {
-                // support these?
-                "tga" => (ImageFormat::Tga, false),
-                "dds" => (ImageFormat::Dds, false),
-                "hdr" => (ImageFormat::Hdr, false),
-                "farb" => (ImageFormat::Farbfeld, false),
-                // these do imply video!
-                "gif" => (ImageFormat::Gif, true),
-                "avi" => (ImageFormat::Avif, false), // Todo: find out how to create avi writer
-                // commodity
-                "bmp" => (ImageFormat::Bmp, false),
-                "ico" => (ImageFormat::Ico, false),
-                "webp" => (ImageFormat::WebP, false),
-                "pnm" => (ImageFormat::Pnm, false),
-                "tif" | "tiff" => (ImageFormat::Tiff, false),
-                "jpg" | "jpeg" => (ImageFormat::Jpeg, false),
-                "png" => (ImageFormat::Png, false),
-                _ => (ImageFormat::Png, false),
-            }
-        }
+        Some(ext) => format_for_extension(ext.to_str().expect("Invalid file extension"))
+            .unwrap_or((ImageFormat::Png, false)),
         None => (ImageFormat::Png, false),
     }
 }
 
-fn main_cli(args: &Args) -> Result<(PathBuf, PathBuf), String> {
-    let out_filename = args.output.as_ref().expect("Invalid filename");
-    let input_filename = args.input.as_ref().expect("Invalid filename");
-    let (width, height, t) = (args.width, args.height, args.time);
-    assert!(t >= 0.0);
-    let pic_path = get_picture_path(&args);
-    let pictures = Arc::new(
-        load_pictures(pic_path.as_path())
-            .map_err(|e| format!("Cannot load picture folder. {:?}", e))?,
-    );
+/// `-` as the output writes the image to stdout
+fn is_stdout(out_file: &Path) -> bool {
+    out_file == Path::new("-")
+}
+
+/// Use the `--format` override when it is set, otherwise fall back to the output file's extension
+fn resolve_image_format(
+    out_file: &Path,
+    forced: Option<ImageFormat>,
+) -> Result<(ImageFormat, bool), String> {
+    let forced = match forced {
+        Some(forced) => forced,
+        None if is_stdout(out_file) => {
+            return Err(
+                "Writing the image to stdout needs a --format, e.g. --format png".to_string(),
+            )
+        }
+        None => return Ok(select_image_format(out_file)),
+    };
+    let by_extension = out_file
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(format_for_extension);
+    match by_extension {
+        Some((format, _)) if format != forced => Err(format!(
+            "Cannot write {:?} to {}, its extension asks for {:?}",
+            forced,
+            out_file.display(),
+            format
+        )),
+        // an .apng is a png that animates
+        Some((format, is_video)) => Ok((format, is_video)),
+        None => Ok((forced, is_animation_format(forced))),
+    }
+}
+
+fn write_gif(
+    out_file: &Path,
+    raw_frames: Vec<Vec<u8>>,
+    width: u32,
+    height: u32,
+    fps: u16,
+    loop_mode: LoopMode,
+) -> Result<(), String> {
+    let mut bytes = Vec::new();
+    let mut encoder = GifEncoder::new(&mut bytes);
+    // without a repeat a gif is played once
+    if loop_mode.repeats() {
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(|e| format!("Cannot set the gif repeat. {}", e))?;
+    }
+    let delay = Delay::from_numer_denom_ms(1000, fps as u32);
+    for rgba8 in raw_frames {
+        let gen_buf = ImageBuffer::from_raw(width, height, rgba8)
+            .ok_or("Frame buffer does not match the image dimensions")?;
+        let frame = Frame::from_parts(gen_buf, 0, 0, delay);
+        encoder
+            .encode_frame(frame)
+            .map_err(|e| format!("Cannot encode frame. {}", e))?;
+    }
+    drop(encoder);
+    write_output(out_file, &bytes)
+}
+
+/// Write the frames as an animated webp, every frame is shown 1/`fps` of a second
+fn write_webp(
+    out_file: &Path,
+    raw_frames: Vec<Vec<u8>>,
+    width: u32,
+    height: u32,
+    fps: u16,
+    loop_mode: LoopMode,
+) -> Result<(), String> {
+    let options = WebPEncoderOptions {
+        // 0 repeats the animation forever
+        anim_params: AnimParams {
+            loop_count: if loop_mode.repeats() { 0 } else { 1 },
+        },
+        ..WebPEncoderOptions::default()
+    };
+    let mut encoder = WebPEncoder::new_with_options((width, height), options)
+        .map_err(|e| format!("Cannot create the webp encoder. {:?}", e))?;
+    let timestamp = |frame: usize| (frame as u64 * 1000 / fps as u64) as i32;
+    for (i, rgba8) in raw_frames.iter().enumerate() {
+        encoder
+            .add_frame(rgba8, timestamp(i))
+            .map_err(|e| format!("Cannot encode frame. {:?}", e))?;
+    }
+    let bytes = encoder
+        .finalize(timestamp(raw_frames.len()))
+        .map_err(|e| format!("Cannot finish the webp. {:?}", e))?;
+    write_output(out_file, &bytes)
+}
+
+/// Write the frames as an animated png, lossless and with all the colors, every frame is shown
+/// 1/`fps` of a second
+fn write_apng(
+    out_file: &Path,
+    raw_frames: Vec<Vec<u8>>,
+    width: u32,
+    height: u32,
+    fps: u16,
+    loop_mode: LoopMode,
+) -> Result<(), String> {
+    let mut bytes = Vec::new();
+    let mut encoder = PngEncoder::new(&mut bytes, width, height);
+    encoder.set_color(PngColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+    // 0 plays means the animation repeats forever
+    let plays = if loop_mode.repeats() { 0 } else { 1 };
+    encoder
+        .set_animated(raw_frames.len() as u32, plays)
+        .map_err(|e| format!("Cannot make the png animated. {}", e))?;
+    encoder
+        .set_frame_delay(1, fps)
+        .map_err(|e| format!("Cannot set the frame delay. {}", e))?;
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| format!("Cannot write the png header. {}", e))?;
+    for rgba8 in raw_frames {
+        writer
+            .write_image_data(&rgba8)
+            .map_err(|e| format!("Cannot encode frame. {}", e))?;
+    }
+    writer
+        .finish()
+        .map_err(|e| format!("Cannot finish the png. {}", e))?;
+    write_output(out_file, &bytes)
+}
+
+/// Jpeg has no alpha channel, it is dropped after the image is flattened onto the background
+fn rgba8_to_rgb8(rgba8: &[u8]) -> Vec<u8> {
+    rgba8
+        .chunks_exact(4)
+        .flat_map(|pixel| pixel[0..3].iter().copied())
+        .collect()
+}
+
+/// Negate the colors of RGBA pixels, with `full` as the largest value of a channel,
+/// the alpha stays as it is
+fn invert_rgb<C: Copy + std::ops::Sub<Output = C>>(pixels: &mut [C], full: C) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        for c in &mut pixel[0..3] {
+            *c = full - *c;
+        }
+    }
+}
+
+/// The gamma corrected value of every channel value up to `full`, `full * (v / full)^(1 / gamma)`
+fn gamma_lut<C: TryFrom<usize>>(gamma: f32, full: usize) -> Vec<C> {
+    (0..=full)
+        .map(|v| {
+            let corrected = full as f32 * (v as f32 / full as f32).powf(1.0 / gamma);
+            C::try_from((corrected.round() as usize).min(full))
+                .ok()
+                .expect("a corrected value is at most full")
+        })
+        .collect()
+}
+
+/// Replace the colors of RGBA pixels by their entry in `lut`, the alpha stays as it is
+fn apply_lut<C: Copy + Into<usize>>(pixels: &mut [C], lut: &[C]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        for c in &mut pixel[0..3] {
+            *c = lut[(*c).into()];
+        }
+    }
+}
+
+/// A `width` x `height` render of `render(w, h, samples)` with the --supersample of `args`.
+/// The box filter averages the samples while rendering, the other filters scale a render of all
+/// the samples down.
+fn supersampled<C>(
+    width: u32,
+    height: u32,
+    args: &Args,
+    render: impl FnOnce(u32, u32, u32) -> Vec<C>,
+) -> Vec<C>
+where
+    C: Channel + 'static,
+    Rgba<C>: Pixel<Subpixel = C>,
+{
+    let n = args.supersample;
+    if args.downscale_filter == DownscaleFilter::Box {
+        render(width, height, n)
+    } else {
+        args.downscale_filter.downscale(
+            render(width * n, height * n, 1),
+            width,
+            height,
+            n,
+            args.color_space,
+        )
+    }
+}
+
+/// The passes over the rendered pixels of a w x h image that are asked for on the command line
+fn post_process<C>(pixels: &mut [C], full: C, width: u32, height: u32, args: &Args)
+where
+    C: Channel + Into<usize> + TryFrom<usize> + std::ops::Sub<Output = C>,
+{
+    if args.smooth_edges > 0.0 {
+        smooth_edges_rgba8(pixels, width, height, args.smooth_edges);
+    }
+    if args.gamma != 1.0 {
+        let lut = gamma_lut::<C>(args.gamma, full.into());
+        apply_lut(pixels, &lut);
+    }
+    if args.invert {
+        invert_rgb(pixels, full);
+    }
+}
+
+/// Write an encoded image to `out_file`, or to stdout when it is `-`
+fn write_output(out_file: &Path, bytes: &[u8]) -> Result<(), String> {
+    if is_stdout(out_file) {
+        stdout()
+            .lock()
+            .write_all(bytes)
+            .map_err(|e| format!("Cannot write the image to stdout. {}", e))
+    } else {
+        std::fs::write(out_file, bytes)
+            .map_err(|e| format!("Cannot write {}. {}", out_file.display(), e))
+    }
+}
+
+/// Jpeg has no alpha channel, the transparent pixels are painted over `background`
+fn encode_jpeg(
+    rgba8: &[u8],
+    width: u32,
+    height: u32,
+    quality: u8,
+    background: Color,
+) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let rgb8 = rgba8_to_rgb8(&flatten_rgba8_over(rgba8, background));
+    JpegEncoder::new_with_quality(&mut bytes, quality)
+        .encode(&rgb8[0..], width, height, ColorType::Rgb8)
+        .map_err(|e| format!("Could not save {}", e))?;
+    Ok(bytes)
+}
+
+/// Encode 8 bit pixels as a still image in `format`, jpeg is encoded with the
+/// `jpeg_quality` and `background` of `args`, png holds the `metadata` when it is given
+fn encode_rgba8(
+    rgba8: &[u8],
+    width: u32,
+    height: u32,
+    format: ImageFormat,
+    args: &Args,
+    metadata: Option<&RenderMetadata>,
+) -> Result<Vec<u8>, String> {
+    match (format, metadata) {
+        (ImageFormat::Jpeg, _) => {
+            return encode_jpeg(rgba8, width, height, args.jpeg_quality, args.background)
+        }
+        (ImageFormat::Png, Some(metadata)) => {
+            return encode_png_with_metadata(rgba8, width, height, metadata)
+        }
+        _ => {}
+    }
+    let img = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, rgba8)
+        .ok_or("Pixel buffer does not match the image dimensions")?;
+    let mut bytes = Cursor::new(Vec::new());
+    img.write_to(&mut bytes, format)
+        .map_err(|e| format!("Could not save {}", e))?;
+    Ok(bytes.into_inner())
+}
+
+/// Encode 16 bit pixels as a still image in `format`, only png and tiff keep the 16 bits,
+/// png holds the `metadata` when it is given
+fn encode_rgba16(
+    rgba16: Vec<u16>,
+    width: u32,
+    height: u32,
+    format: ImageFormat,
+    metadata: Option<&RenderMetadata>,
+) -> Result<Vec<u8>, String> {
+    if let (ImageFormat::Png, Some(metadata)) = (format, metadata) {
+        return encode_png16_with_metadata(&rgba16, width, height, metadata);
+    }
+    let img = ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, rgba16)
+        .ok_or("Pixel buffer does not match the image dimensions")?;
+    let mut bytes = Cursor::new(Vec::new());
+    img.write_to(&mut bytes, format)
+        .map_err(|e| format!("Could not save {}", e))?;
+    Ok(bytes.into_inner())
+}
+
+/// Write 8 bit pixels as a still image in `format` to `out_file`, or to stdout when it is `-`
+fn write_rgba8(
+    out_file: &Path,
+    rgba8: &[u8],
+    width: u32,
+    height: u32,
+    format: ImageFormat,
+    args: &Args,
+    metadata: Option<&RenderMetadata>,
+) -> Result<(), String> {
+    let bytes = encode_rgba8(rgba8, width, height, format, args, metadata)?;
+    write_output(out_file, &bytes)
+}
+
+/// Append the line `nr` of `contents`, where a parse error was found, to the error
+fn with_line_snippet(err: String, contents: &str, nr: usize) -> String {
+    match contents.lines().nth(nr) {
+        Some(line) => format!("{}\n    {}", err, line.trim()),
+        None => err,
+    }
+}
+
+fn parse_source(
+    contents: String,
+    source_name: &str,
+    coordinate_system: CoordinateSystem,
+) -> Result<Pic, String> {
+    let snippet_source = if source_name == "-" {
+        Some(contents.clone())
+    } else {
+        None
+    };
+    lisp_to_pic(contents, coordinate_system).map_err(|e| {
+        let name = if source_name == "-" {
+            "stdin"
+        } else {
+            source_name
+        };
+        let message = format!("Cannot parse {}. {}", name, e);
+        match (snippet_source, e.pos()) {
+            (Some(contents), Some(nr)) => with_line_snippet(message, &contents, nr),
+            _ => message,
+        }
+    })
+}
+
+/// Load the pictures of `--pictures-path`, without that folder the Pic- operation is not used
+fn load_args_pictures(args: &Args) -> Result<HashMap<String, ActualPicture>, String> {
+    let pic_path = get_picture_path(args);
+    let pictures = load_optional_pictures(pic_path.as_path())
+        .map_err(|e| format!("Cannot load picture folder. {}", e))?;
+    if pictures.is_empty() {
+        warn(format!(
+            "there are no pictures in {}, the Pic- operation is not used",
+            pic_path.display()
+        ));
+    }
+    Ok(pictures)
+}
+
+/// Point the pic to the loaded pictures, a picture that was renamed is reported
+fn resolve_pictures(
+    pic: &mut Pic,
+    pictures: &HashMap<String, ActualPicture>,
+    source_name: &str,
+) -> Result<(), String> {
+    let replaced = pic
+        .resolve_pictures(pictures)
+        .map_err(|e| format!("Cannot render {}. {}", source_name, e))?;
+    for (name, resolved) in replaced {
+        warn(format!(
+            "the picture {} is missing, using {}",
+            name, resolved
+        ));
+    }
+    Ok(())
+}
+
+/// Read the sexpr from the file or, for "-", from stdin
+fn read_source(input_filename: &str) -> Result<String, String> {
     let mut contents = String::new();
     if input_filename == "-" {
-        let _bytes = std::io::stdin()
+        std::io::stdin()
             .read_to_string(&mut contents)
-            .map_err(|e| format!("Cannot read from stdin. {}", e));
+            .map_err(|e| format!("Cannot read from stdin. {}", e))?;
     } else {
         let mut file =
             File::open(input_filename).map_err(|e| format!("Cannot open input filename. {}", e))?;
         file.read_to_string(&mut contents)
             .map_err(|e| format!("Cannot read input filename. {}", e))?;
     }
-    let mut pic = lisp_to_pic(contents, args.coordinate_system.clone()).unwrap();
-    pic_simplify_runtime_select(&mut pic, pictures.clone(), width, height, t);
+    Ok(contents)
+}
+
+/// Parse the input without loading pictures or rendering, the color mode and the
+/// normalized expression are returned
+fn main_check(args: &Args) -> Result<String, String> {
+    let input_filename = args.input.as_ref().expect("Invalid filename");
+    let contents = read_source(input_filename)?;
+    let pic = parse_source(contents, input_filename, args.coordinate_system.clone())?;
+    if args.stats {
+        return Ok(format!("{}\n{}", pic.stats(), pic.to_lisp()));
+    }
+    Ok(format!(
+        "mode: {}
+{}",
+        pic.mode_name(),
+        pic.to_lisp()
+    ))
+}
+
+/// The metadata that is embedded in a png that was rendered by evolution
+fn main_extract_metadata(png_filename: &str) -> Result<RenderMetadata, String> {
+    let png =
+        std::fs::read(png_filename).map_err(|e| format!("Cannot read {}. {}", png_filename, e))?;
+    read_png_metadata(&png)
+        .map_err(|e| format!("Cannot extract the metadata of {}. {}", png_filename, e))
+}
+
+/// Time the suite with --simd, or with every backend the cpu supports when it is auto
+fn main_benchmark(args: &Args) -> Result<Vec<BenchmarkRun>, String> {
+    let backends = match args.simd {
+        SimdBackend::Auto => SimdBackend::list_supported(),
+        backend => vec![backend],
+    };
+    run_benchmark(&backends, BENCHMARK_SIZE, BENCHMARK_ROUNDS)
+}
+
+fn main_verify_simd(args: &Args) -> Result<SimdDivergence, String> {
+    let input_filename = args.input.as_ref().expect("Invalid filename");
+    let pictures = Arc::new(load_args_pictures(args)?);
+    let contents = read_source(input_filename)?;
+    let mut pic = parse_source(contents, input_filename, args.coordinate_system.clone())?;
+    resolve_pictures(&mut pic, &pictures, input_filename)?;
+    let t = args.time_range.at_ms(args.time, DEFAULT_VIDEO_DURATION);
+    verify_simd(
+        &pic,
+        pictures,
+        args.simd,
+        SIMD_VERIFY_SIZE,
+        SIMD_VERIFY_SIZE,
+        t,
+    )
+}
+
+fn main_cli(args: &Args) -> Result<(PathBuf, PathBuf), String> {
+    let out_filename = args.output.as_ref().expect("Invalid filename");
+    let input_filename = args.input.as_ref().expect("Invalid filename");
+    let (width, height, t) = (args.width, args.height, args.time);
+    assert!(t >= 0.0);
+    // a still shows the moment `t` of an animation that lasts DEFAULT_VIDEO_DURATION
+    let still_t = args.time_range.at_ms(t, DEFAULT_VIDEO_DURATION);
+    let pictures = Arc::new(load_args_pictures(args)?);
+    let contents = read_source(input_filename)?;
+    let mut pic = parse_source(contents, input_filename, args.coordinate_system.clone())?;
+    resolve_pictures(&mut pic, &pictures, input_filename)?;
+    if args.loop_seamless_time {
+        pic.seamless_time();
+    }
+    // before T is folded into a constant
+    let metadata = RenderMetadata::new(&pic, width, height, still_t, args.seed);
+    let started = Instant::now();
+    if args.stats {
+        report(pic.stats());
+    }
     let out_file = Path::new(out_filename);
-    let (format, mut is_video) = select_image_format(out_file);
+    let (format, mut is_video) = resolve_image_format(out_file, args.format)?;
+    let options = DEFAULT_RENDER_OPTIONS
+        .with_symmetry(args.symmetry)
+        .with_subsample(args.subsample.unwrap_or_default())
+        .with_motion_blur(args.motion_blur)
+        .with_color_space(args.color_space)
+        .with_deadline(args.max_render_time.map(|budget| started + budget));
+    if let Some(sheet) = args.contact_sheet {
+        if !pic.can_animate() {
+            warn("the T Operator is needed to make an animation, every cell of the contact sheet is the same");
+        }
+        let (sheet_w, sheet_h, mut rgba8) =
+            sheet.compose(width, height, args.time_range, |cell_w, cell_h, t| {
+                supersampled(cell_w, cell_h, args, |w, h, samples| {
+                    pic_get_rgba8_viewport_runtime_select(
+                        &pic,
+                        true,
+                        pictures.clone(),
+                        w,
+                        h,
+                        t,
+                        samples,
+                        DEFAULT_VIEWPORT,
+                        options,
+                    )
+                })
+            })?;
+        options.check_deadline()?;
+        post_process(&mut rgba8, u8::MAX, sheet_w, sheet_h, args);
+        write_rgba8(out_file, &rgba8[0..], sheet_w, sheet_h, format, args, None)?;
+        return Ok((
+            Path::new(input_filename).to_path_buf(),
+            out_file.to_path_buf(),
+        ));
+    }
     if is_video {
         if !pic.can_animate() {
-            println!("warning: the T Operator is needed to make an animation");
+            warn("the T Operator is needed to make an animation, writing a still image instead");
             is_video = false;
         }
     }
     if is_video {
-        assert_eq!(format, ImageFormat::Gif);
         let duration = if t == 0.0 { DEFAULT_VIDEO_DURATION } else { t };
-        let raw_frames =
-            pic_get_video_runtime_select(&pic, pictures, width, height, DEFAULT_FPS, duration);
+        let mut raw_frames = pic_get_video_runtime_select(
+            &pic,
+            pictures.clone(),
+            width,
+            height,
+            DEFAULT_FPS,
+            duration,
+            args.time_range,
+            args.loop_mode,
+            DEFAULT_VIEWPORT,
+            options,
+            Some(&mut |frame: usize, total: usize| info(format!("frame {}/{}", frame, total))),
+        );
+        options.check_deadline()?;
+        for frame in raw_frames.iter_mut() {
+            post_process(frame, u8::MAX, width, height, args);
+        }
         if raw_frames.len() == 0 {
-            println!("warning: not enough frames to make a usefull gif");
+            warn("not enough frames to make a usefull animation, writing a still image instead");
+            is_video = false;
+        } else if format == ImageFormat::Png {
+            write_apng(
+                out_file,
+                raw_frames,
+                width,
+                height,
+                DEFAULT_FPS,
+                args.loop_mode,
+            )?;
+        } else if format == ImageFormat::WebP {
+            write_webp(
+                out_file,
+                raw_frames,
+                width,
+                height,
+                DEFAULT_FPS,
+                args.loop_mode,
+            )?;
         } else {
-            let file_out = File::create(out_file).unwrap();
-            let mut encoder = GifEncoder::new(&file_out);
-            encoder.set_repeat(Repeat::Infinite).unwrap();
-            for rgba8 in raw_frames {
-                let gen_buf = ImageBuffer::from_raw(width, height, rgba8).unwrap();
-                let rgba_img = gen_buf.into();
-                let frame = Frame::new(rgba_img);
-                encoder.encode_frame(frame).unwrap();
-            }
+            write_gif(
+                out_file,
+                raw_frames,
+                width,
+                height,
+                DEFAULT_FPS,
+                args.loop_mode,
+            )?;
         }
-    } else {
-        let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures, width, height, t);
-        save_buffer_with_format(
+    }
+    if !is_video {
+        // folding T into a constant is only allowed once it is known that no animation is made
+        pic_simplify_runtime_select(&mut pic, pictures.clone(), width, height, still_t);
+    }
+    let mut is_16_bit = args.bit_depth == 16;
+    if is_16_bit && !is_video {
+        if format != ImageFormat::Png && format != ImageFormat::Tiff {
+            warn("16 bits per channel needs a png or tiff file, writing 8 bits instead");
+            is_16_bit = false;
+        }
+    }
+    if !is_video && is_16_bit {
+        let mut rgba16 = supersampled(width, height, args, |w, h, samples| {
+            pic_get_rgba16_viewport_runtime_select(
+                &pic,
+                false,
+                pictures,
+                w,
+                h,
+                still_t,
+                samples,
+                DEFAULT_VIEWPORT,
+                options,
+            )
+        });
+        options.check_deadline()?;
+        post_process(&mut rgba16, u16::MAX, width, height, args);
+        let bytes = encode_rgba16(rgba16, width, height, format, Some(&metadata))?;
+        write_output(out_file, &bytes)?;
+    } else if !is_video {
+        let mut rgba8 = supersampled(width, height, args, |w, h, samples| {
+            pic_get_rgba8_viewport_runtime_select(
+                &pic,
+                false,
+                pictures,
+                w,
+                h,
+                still_t,
+                samples,
+                DEFAULT_VIEWPORT,
+                options,
+            )
+        });
+        options.check_deadline()?;
+        post_process(&mut rgba8, u8::MAX, width, height, args);
+        write_rgba8(
             out_file,
             &rgba8[0..],
             width,
             height,
-            ColorType::Rgba8,
             format,
-        )
-        .map_err(|e| format!("Could not save {}", e))?;
+            args,
+            Some(&metadata),
+        )?;
     }
+    debug(format!(
+        "rendered {} in {} ms",
+        out_file.display(),
+        started.elapsed().as_millis()
+    ));
     Ok((
         Path::new(input_filename).to_path_buf(),
         out_file.to_path_buf(),
     ))
 }
 
+fn main_batch(args: &Args, count: usize) -> Result<(), String> {
+    let out_dir = args.out_dir.as_ref().expect("Invalid directory");
+    let pictures = Arc::new(load_args_pictures(args)?);
+    let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    info(format!("using seed {}", seed));
+    let config = get_generation_config(args)?;
+    let t = args.time_range.at_ms(args.time, DEFAULT_VIDEO_DURATION);
+    let written = render_batch(
+        count,
+        Path::new(out_dir),
+        pictures,
+        &config,
+        seed,
+        BatchRender {
+            w: args.width,
+            h: args.height,
+            t,
+            supersample: args.supersample,
+        },
+    )?;
+    info(format!("wrote {} files to {}", written.len(), out_dir));
+    Ok(())
+}
+
+/// Render the layers at the same size and moment and blend them, the first layer is the bottom
+fn main_compose(args: &Args, layers: &[String]) -> Result<PathBuf, String> {
+    let out_filename = args
+        .output
+        .clone()
+        .unwrap_or_else(|| DEFAULT_FILE_OUT.to_string());
+    let out_file = Path::new(&out_filename);
+    let (format, _) = resolve_image_format(out_file, args.format)?;
+    let (width, height) = (args.width, args.height);
+    let t = args.time_range.at_ms(args.time, DEFAULT_VIDEO_DURATION);
+    let pictures = Arc::new(load_args_pictures(args)?);
+    let mut pics = Vec::with_capacity(layers.len());
+    for layer in layers {
+        let contents = read_source(layer)?;
+        let mut pic = parse_source(contents, layer, args.coordinate_system.clone())?;
+        resolve_pictures(&mut pic, &pictures, layer)?;
+        if args.loop_seamless_time {
+            pic.seamless_time();
+        }
+        pics.push(pic);
+    }
+    if let Some(i) = pics.iter().position(|pic| pic.coord() != pics[0].coord()) {
+        return Err(format!(
+            "Cannot compose {} and {}, the layers are {} and {} and must share a coordinate system",
+            layers[0],
+            layers[i],
+            pics[0].coord(),
+            pics[i].coord()
+        ));
+    }
+    let started = Instant::now();
+    let options = DEFAULT_RENDER_OPTIONS
+        .with_symmetry(args.symmetry)
+        .with_subsample(args.subsample.unwrap_or_default())
+        .with_color_space(args.color_space)
+        .with_deadline(args.max_render_time.map(|budget| started + budget));
+    let mut rgba8: Option<Vec<u8>> = None;
+    for pic in pics.iter_mut() {
+        pic_simplify_runtime_select(pic, pictures.clone(), width, height, t);
+        let layer = supersampled(width, height, args, |w, h, samples| {
+            pic_get_rgba8_viewport_runtime_select(
+                pic,
+                true,
+                pictures.clone(),
+                w,
+                h,
+                t,
+                samples,
+                DEFAULT_VIEWPORT,
+                options,
+            )
+        });
+        rgba8 = Some(match rgba8 {
+            Some(base) => blend_rgba8(&base, &layer, args.blend)?,
+            None => layer,
+        });
+    }
+    options.check_deadline()?;
+    let mut rgba8 = rgba8.expect("at least two layers");
+    post_process(&mut rgba8, u8::MAX, width, height, args);
+    write_rgba8(out_file, &rgba8[0..], width, height, format, args, None)?;
+    Ok(out_file.to_path_buf())
+}
+
+/// The directory of the input file, the current directory for a bare filename
+fn watch_dir_of(input_file: &Path) -> PathBuf {
+    match input_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Does the event leave new contents in the input file: a write that is done, a file that is
+/// created, or a file that is renamed to the input's name.
+fn is_input_change(event: &Event, input_file: &Path) -> bool {
+    let is_input =
+        |path: &PathBuf| path.file_name().is_some() && path.file_name() == input_file.file_name();
+    match event.kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) | EventKind::Create(_) => {
+            event.paths.iter().any(is_input)
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => false,
+        // the paths are the old and the new name
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            event.paths.last().map_or(false, is_input)
+        }
+        EventKind::Modify(ModifyKind::Name(_)) => event.paths.iter().any(is_input),
+        _ => false,
+    }
+}
+
+/// Collect `first` and everything that arrives until `window` passes without a new message,
+/// so a burst of events is handled at once
+fn debounce<T>(rx: &Receiver<T>, first: T, window: Duration) -> Vec<T> {
+    let mut burst = vec![first];
+    loop {
+        match rx.recv_timeout(window) {
+            Ok(message) => burst.push(message),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    burst
+}
+
+/// Render the input again, on success the input and the output are copied to `target_dir`
+fn rerun_and_copy(args: &Args, target_dir: &Path) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    match main_cli(args) {
+        Err(e) => eprintln!("{}", e),
+        Ok((sexpr_filename, img_filename)) => {
+            let dest = filename_to_copy_to(
+                target_dir,
+                now,
+                &sexpr_filename.file_name().unwrap().to_string_lossy(),
+            );
+            copy(&sexpr_filename, dest.as_path()).unwrap();
+
+            let dest = filename_to_copy_to(
+                target_dir,
+                now,
+                &img_filename.file_name().unwrap().to_string_lossy(),
+            );
+            copy(img_filename, dest.as_path()).unwrap();
+            info(format!(
+                ".. ran and copied as {} and {}",
+                sexpr_filename.display(),
+                dest.display()
+            ));
+        }
+    }
+}
+
 pub fn main() {
     let mut args = Args::parse();
+    let verbosity = if args.quiet {
+        Verbosity::Quiet
+    } else if args.verbose {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    };
+    set_verbosity(verbosity, args.output.as_deref() == Some("-"));
+    if args.loop_seamless_time {
+        args.time_range = SEAMLESS_TIME_RANGE;
+    }
+    match select_simd_backend(args.simd) {
+        // --check and --extract-metadata render nothing and their output is read by scripts,
+        // --benchmark names the backends it renders with
+        Ok(_) if args.check || args.extract_metadata.is_some() || args.benchmark => {}
+        Ok(backend) if args.simd == SimdBackend::Auto => info(format!(
+            "rendering with {}, the widest the cpu supports",
+            backend
+        )),
+        Ok(backend) => info(format!("rendering with {}", backend)),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+    if let Some(count) = args.batch {
+        if let Err(e) = main_batch(&args, count) {
+            eprintln!("{}", e);
+            exit(1);
+        }
+        return;
+    }
+    if let Some(png_filename) = &args.extract_metadata {
+        match main_extract_metadata(png_filename) {
+            Ok(metadata) => println!("{}", metadata.expression),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+    if args.benchmark {
+        info(format!(
+            "rendering {} x {} on one thread, the fastest of {}",
+            BENCHMARK_SIZE, BENCHMARK_SIZE, BENCHMARK_ROUNDS
+        ));
+        match main_benchmark(&args) {
+            Ok(runs) => {
+                for run in runs {
+                    println!("{}", run);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(layers) = &args.compose {
+        match main_compose(&args, layers) {
+            Ok(out_file) => info(format!("wrote {}", out_file.display())),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+        return;
+    }
     let run_gui = match &args.input {
         None => true,
         Some(_x) => {
@@ -198,92 +991,117 @@ pub fn main() {
         }
     };
     if run_gui {
-        let min_width = EXEC_UI_THUMB_ROWS as u32 * EXEC_UI_THUMB_WIDTH;
-        let min_height = EXEC_UI_THUMB_COLS as u32 * EXEC_UI_THUMB_HEIGHT;
-        if min_width <= args.width {
-            args.width = min_width;
-        }
-        if min_height <= args.height {
-            args.height = min_height;
+        let (width, height) = Grid::fit_window(
+            args.grid_cols,
+            args.grid_rows,
+            args.thumb_size,
+            (args.width, args.height),
+        );
+        args.width = width;
+        args.height = height;
+        if let Err(e) = main_gui(&args) {
+            eprintln!("{}", e);
+            exit(1);
         }
-        //todo keep also aspect ratio for thumbs and recalculate dimensions
-        // calculate it once and set it to the the state to avoid usage of THUMBS constants
-        main_gui(&args).unwrap();
     } else {
+        if args.check {
+            match main_check(&args) {
+                Ok(report) => println!("{}", report),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1);
+                }
+            }
+            return;
+        }
+        if args.verify_simd {
+            match main_verify_simd(&args) {
+                Ok(divergence) => {
+                    println!("{}", divergence);
+                    if divergence.diverges() {
+                        exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1);
+                }
+            }
+            return;
+        }
         let input_filename = args.input.as_ref().unwrap();
-        let one_shot = input_filename == "-" || args.copy_path.is_none();
+        // an image on stdout cannot be copied
+        let one_shot = input_filename == "-"
+            || args.output.as_deref() == Some("-")
+            || args.copy_path.is_none();
         if one_shot {
-            let (_sexpr_filename, _img_filename) = main_cli(&args).unwrap();
+            if let Err(e) = main_cli(&args) {
+                eprintln!("{}", e);
+                exit(1);
+            }
         } else {
             let copy_path = args.copy_path.as_ref().unwrap();
             let target_dir = Path::new(&copy_path);
             if !target_dir.exists() {
-                println!("Creating {} directory", copy_path);
+                info(format!("Creating {} directory", copy_path));
                 create_dir_all(target_dir).unwrap();
             }
             let input_file = Path::new(input_filename);
-            println!("Watching changes to {}", input_filename);
+            info(format!("Watching changes to {}", input_filename));
             let (tx, rx) = std::sync::mpsc::channel();
             let mut watcher = RecommendedWatcher::new(tx, Config::default()).unwrap();
-            watcher
-                .watch(input_file.as_ref(), RecursiveMode::NonRecursive)
-                .unwrap();
-            for res in rx {
-                match res {
-                    /*
-                    If you came here to find out why this runs only during the first save, welcome!
-                    Your editor is probably swapping files instead of actually writing them.
-                    Try these workarounds:
-                    - for vim users:
-                      set backupcopy=yes
-                      set nobackup
-                      set nowritebackup
-                    - use a real filesystem watcher like [entr](http://eradman.com/entrproject/)
-                    - fix this, preferably by commiting something to [notify](https://crates.io/crates/notify)
-                      watch the directory instead of a file, for every event, if the filename matches, then launch
-                    */
-                    Ok(event) => {
-                        match event.kind {
-                            EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
-                                println!("file {} changed, rerunning", input_filename);
-                                let now = SystemTime::now()
-                                    .duration_since(UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_secs();
-                                // todo better handle errors during run
-                                if let Ok((sexpr_filename, img_filename)) = main_cli(&args) {
-                                    let dest = filename_to_copy_to(
-                                        &target_dir,
-                                        now,
-                                        &sexpr_filename.file_name().unwrap().to_string_lossy(),
-                                    );
-                                    copy(&sexpr_filename, dest.as_path()).unwrap();
-
-                                    let dest = filename_to_copy_to(
-                                        &target_dir,
-                                        now,
-                                        &img_filename.file_name().unwrap().to_string_lossy(),
-                                    );
-                                    copy(img_filename, dest.as_path()).unwrap();
-                                    println!(
-                                        ".. ran and copied as {} and {}",
-                                        sexpr_filename.display(),
-                                        dest.display()
-                                    );
-                                }
-                            }
-                            EventKind::Remove(_) => {
+            /*
+            Editors like vim swap files instead of writing them: the new contents go to another
+            file that is renamed over the input. A watch on the input itself ends with the first
+            save, so the directory is watched and its events are filtered on the input's name.
+            When the directory cannot be watched the file itself is, if this then runs only
+            during the first save, try these workarounds:
+            - for vim users:
+              set backupcopy=yes
+              set nobackup
+              set nowritebackup
+            - use a real filesystem watcher like [entr](http://eradman.com/entrproject/)
+            */
+            let watch_dir = watch_dir_of(input_file);
+            let watching_dir = match watcher.watch(&watch_dir, RecursiveMode::NonRecursive) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn(format!(
+                        "cannot watch {}, watching {} only. {}",
+                        watch_dir.display(),
+                        input_filename,
+                        e
+                    ));
+                    watcher
+                        .watch(input_file.as_ref(), RecursiveMode::NonRecursive)
+                        .unwrap();
+                    false
+                }
+            };
+            let window = Duration::from_millis(args.debounce_ms);
+            while let Ok(res) = rx.recv() {
+                // an editor can emit several events for one save, render only once
+                let mut changed = false;
+                for res in debounce(&rx, res, window) {
+                    match res {
+                        Ok(event) => {
+                            if is_input_change(&event, input_file) {
+                                changed = true;
+                            } else if !watching_dir && matches!(event.kind, EventKind::Remove(_)) {
                                 eprintln!("File was removed {:?}", input_filename);
                                 exit(1);
                             }
-                            _ => {}
                         }
-                    }
-                    Err(e) => {
-                        eprintln!("watch error: {:?}", e);
-                        exit(1);
+                        Err(e) => {
+                            eprintln!("watch error: {:?}", e);
+                            exit(1);
+                        }
                     }
                 }
+                if changed {
+                    info(format!("file {} changed, rerunning", input_filename));
+                    rerun_and_copy(&args, target_dir);
+                }
             }
         }
     }
@@ -292,6 +1110,487 @@ pub fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use evolution::DEFAULT_JPEG_QUALITY;
+
+    #[test]
+    fn test_parse_source_error() {
+        let err = parse_source("RGB (garbage".to_string(), "-", CoordinateSystem::Polar)
+            .err()
+            .unwrap();
+        assert!(err.starts_with("Cannot parse stdin. Expected '(' on line 0, got a 'RGB'"));
+        assert!(err.ends_with("\n    RGB (garbage"));
+
+        let err = parse_source(
+            "RGB (garbage".to_string(),
+            "some.sexpr",
+            CoordinateSystem::Polar,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err,
+            "Cannot parse some.sexpr. Expected '(' on line 0, got a 'RGB'"
+        );
+    }
+
+    #[test]
+    fn test_debounce() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let window = Duration::from_millis(50);
+        let sender = std::thread::spawn(move || {
+            tx.send(2).unwrap();
+            std::thread::sleep(Duration::from_millis(10));
+            tx.send(3).unwrap();
+            std::thread::sleep(Duration::from_millis(300));
+            tx.send(4).unwrap();
+        });
+        assert_eq!(debounce(&rx, 1, window), vec![1, 2, 3]);
+        let next = rx.recv().unwrap();
+        assert_eq!(debounce(&rx, next, window), vec![4]);
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn test_watch_dir_of() {
+        assert_eq!(watch_dir_of(Path::new("in.sexpr")), PathBuf::from("."));
+        assert_eq!(
+            watch_dir_of(Path::new("some/dir/in.sexpr")),
+            PathBuf::from("some/dir")
+        );
+        assert_eq!(
+            watch_dir_of(Path::new("/abs/in.sexpr")),
+            PathBuf::from("/abs")
+        );
+    }
+
+    #[test]
+    fn test_is_input_change() {
+        let input = Path::new("some/dir/in.sexpr");
+        let event = |kind: EventKind, paths: Vec<&str>| {
+            paths.into_iter().fold(Event::new(kind), |event, path| {
+                event.add_path(PathBuf::from(path))
+            })
+        };
+        let close_write = EventKind::Access(AccessKind::Close(AccessMode::Write));
+        assert!(is_input_change(
+            &event(close_write, vec!["/abs/some/dir/in.sexpr"]),
+            input
+        ));
+        assert!(!is_input_change(
+            &event(close_write, vec!["/abs/some/dir/other.sexpr"]),
+            input
+        ));
+        assert!(is_input_change(
+            &event(
+                EventKind::Create(notify::event::CreateKind::File),
+                vec!["some/dir/in.sexpr"]
+            ),
+            input
+        ));
+        // vim writes 4913 and in.sexpr~ before it renames the new file to in.sexpr
+        assert!(!is_input_change(
+            &event(
+                EventKind::Create(notify::event::CreateKind::File),
+                vec!["some/dir/4913"]
+            ),
+            input
+        ));
+        assert!(is_input_change(
+            &event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                vec!["some/dir/.in.sexpr.swp", "some/dir/in.sexpr"]
+            ),
+            input
+        ));
+        assert!(!is_input_change(
+            &event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                vec!["some/dir/in.sexpr", "some/dir/in.sexpr~"]
+            ),
+            input
+        ));
+        assert!(is_input_change(
+            &event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+                vec!["some/dir/in.sexpr"]
+            ),
+            input
+        ));
+        assert!(!is_input_change(
+            &event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+                vec!["some/dir/in.sexpr"]
+            ),
+            input
+        ));
+        assert!(!is_input_change(
+            &event(
+                EventKind::Remove(notify::event::RemoveKind::File),
+                vec!["some/dir/in.sexpr"]
+            ),
+            input
+        ));
+    }
+
+    #[test]
+    fn test_main_check() {
+        let dir = std::env::temp_dir();
+        let valid = dir.join(format!("evolution_test_check_{}.sexpr", std::process::id()));
+        std::fs::write(&valid, "( RGB CARTESIAN (X) ( * Y 0.5 ) (T) )").unwrap();
+        let args =
+            Args::try_parse_from(["evolution", "--check", "-i", &valid.to_string_lossy()]).unwrap();
+        let report = main_check(&args).unwrap();
+        let (mode, sexpr) = report.split_once('\n').unwrap();
+        assert_eq!(mode, "mode: RGB");
+        assert!(sexpr.starts_with("( RGB CARTESIAN\n"));
+        // the normalized expression is the same pic
+        assert_eq!(
+            lisp_to_pic(sexpr.to_string(), CoordinateSystem::Polar).unwrap(),
+            lisp_to_pic(
+                "( RGB CARTESIAN (X) ( * Y 0.5 ) (T) )".to_string(),
+                CoordinateSystem::Polar
+            )
+            .unwrap()
+        );
+
+        let args = Args::try_parse_from([
+            "evolution",
+            "--check",
+            "--stats",
+            "-i",
+            &valid.to_string_lossy(),
+        ])
+        .unwrap();
+        let report = main_check(&args).unwrap();
+        assert!(
+            report.starts_with(
+                "mode: RGB\ndepth: R 1, G 2, B 1\nnodes: 5\noperations:\n  * 1\n( RGB"
+            ),
+            "{}",
+            report
+        );
+
+        std::fs::write(&valid, "RGB CARTESIAN (X) (Y) (T) )").unwrap();
+        let err = main_check(&args).err().unwrap();
+        assert!(err.starts_with("Cannot parse "), "{}", err);
+        assert!(
+            err.ends_with("Expected '(' on line 0, got a 'RGB'"),
+            "{}",
+            err
+        );
+        std::fs::remove_file(&valid).unwrap();
+
+        let args =
+            Args::try_parse_from(["evolution", "--check", "-i", "does/not/exist.sexpr"]).unwrap();
+        assert!(main_check(&args).is_err());
+    }
+
+    #[test]
+    fn test_main_cli_contact_sheet() {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("evolution_test_sheet_{}.sexpr", std::process::id()));
+        let output = dir.join(format!("evolution_test_sheet_{}.png", std::process::id()));
+        std::fs::write(&input, "( GRAYSCALE CARTESIAN ( T ) )").unwrap();
+        let args = Args::try_parse_from([
+            "evolution",
+            "-i",
+            &input.to_string_lossy(),
+            "-o",
+            &output.to_string_lossy(),
+            "--contact-sheet",
+            "3x2",
+            "-w",
+            "61",
+            "--height",
+            "20",
+        ])
+        .unwrap();
+        main_cli(&args).unwrap();
+        let sheet = image::open(&output).unwrap().to_rgba8();
+        // cropped to whole cells of 20x10
+        assert_eq!(sheet.dimensions(), (60, 20));
+        // T differs per cell, so does the gray
+        let grays: Vec<u8> = [(0, 0), (20, 0), (40, 0), (0, 10), (20, 10), (40, 10)]
+            .iter()
+            .map(|&(x, y)| sheet.get_pixel(x, y)[0])
+            .collect();
+        assert!(grays.windows(2).all(|w| w[0] < w[1]), "{:?}", grays);
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn test_main_extract_metadata() {
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("evolution_test_meta_{}.sexpr", std::process::id()));
+        let output = dir.join(format!("evolution_test_meta_{}.png", std::process::id()));
+        std::fs::write(&input, "( RGB CARTESIAN ( X ) ( * Y T ) ( 0.5 ) )").unwrap();
+        let args = Args::try_parse_from([
+            "evolution",
+            "-i",
+            &input.to_string_lossy(),
+            "-o",
+            &output.to_string_lossy(),
+            "-w",
+            "24",
+            "--height",
+            "16",
+            "--seed",
+            "7",
+        ])
+        .unwrap();
+        main_cli(&args).unwrap();
+        let metadata = main_extract_metadata(&output.to_string_lossy()).unwrap();
+        assert_eq!((metadata.width, metadata.height), (24, 16));
+        assert_eq!(metadata.coordinate_system, CoordinateSystem::Cartesian);
+        assert_eq!(metadata.seed, Some(7));
+        // T is still in the expression, not folded into the constant of the still
+        let source = std::fs::read_to_string(&input).unwrap();
+        let expected = parse_source(source, "-", CoordinateSystem::Polar).unwrap();
+        let extracted = parse_source(metadata.expression, "-", CoordinateSystem::Polar).unwrap();
+        assert_eq!(extracted.to_lisp(), expected.to_lisp());
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+
+        assert!(main_extract_metadata("does/not/exist.png").is_err());
+    }
+
+    #[test]
+    fn test_main_compose() {
+        let dir = std::env::temp_dir();
+        let name = |n: &str| {
+            dir.join(format!(
+                "evolution_test_compose_{}_{}",
+                std::process::id(),
+                n
+            ))
+        };
+        let (bottom, top, output) = (name("bottom.sexpr"), name("top.sexpr"), name("out.png"));
+        std::fs::write(&bottom, "( RGB CARTESIAN ( 1 ) ( 1 ) ( -1 ) )").unwrap();
+        std::fs::write(&top, "( RGB CARTESIAN ( 0 ) ( 1 ) ( 1 ) )").unwrap();
+        let args = Args::try_parse_from([
+            "evolution",
+            "--compose",
+            &bottom.to_string_lossy(),
+            &top.to_string_lossy(),
+            "--blend",
+            "multiply",
+            "-o",
+            &output.to_string_lossy(),
+            "-w",
+            "8",
+            "--height",
+            "4",
+        ])
+        .unwrap();
+        let layers = args.compose.clone().unwrap();
+        assert_eq!(main_compose(&args, &layers).unwrap(), output);
+        let composed = image::open(&output).unwrap().to_rgba8();
+        assert_eq!(composed.dimensions(), (8, 4));
+        assert!(composed.pixels().all(|p| p.0 == [128, 255, 0, 255]));
+
+        // the layers are encoded before they are scaled down and blended
+        let linear = Args::try_parse_from([
+            "evolution",
+            "--compose",
+            &bottom.to_string_lossy(),
+            &top.to_string_lossy(),
+            "--blend",
+            "multiply",
+            "-o",
+            &output.to_string_lossy(),
+            "-w",
+            "8",
+            "--height",
+            "4",
+            "--color-space",
+            "linear",
+            "--supersample",
+            "2",
+            "--downscale-filter",
+            "triangle",
+        ])
+        .unwrap();
+        assert_eq!(main_compose(&linear, &layers).unwrap(), output);
+        let composed = image::open(&output).unwrap().to_rgba8();
+        assert!(composed.pixels().all(|p| p.0 == [188, 255, 0, 255]));
+
+        std::fs::write(&top, "( RGB POLAR ( 0 ) ( 1 ) ( 1 ) )").unwrap();
+        let err = main_compose(&args, &layers).err().unwrap();
+        assert!(err.contains("coordinate system"), "{}", err);
+
+        for file in [bottom, top, output] {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_write_webp() {
+        use image::codecs::webp::WebPDecoder;
+        use image::AnimationDecoder;
+
+        let output =
+            std::env::temp_dir().join(format!("evolution_test_webp_{}.webp", std::process::id()));
+        let (width, height) = (4, 2);
+        // the frames differ, the encoder merges the ones that are the same
+        let frames: Vec<Vec<u8>> = (0..5u8)
+            .map(|i| [i * 50, 255 - i * 50, 0, 255].repeat(width * height))
+            .collect();
+        for (loop_mode, frame_count) in [(LoopMode::Forward, 5), (LoopMode::PingPong, 8)] {
+            write_webp(
+                &output,
+                loop_mode.arrange(frames.clone()),
+                width as u32,
+                height as u32,
+                10,
+                loop_mode,
+            )
+            .unwrap();
+            let decoder = WebPDecoder::new(File::open(&output).unwrap()).unwrap();
+            let decoded = decoder.into_frames().collect_frames().unwrap();
+            assert_eq!(decoded.len(), frame_count);
+            for frame in decoded {
+                assert_eq!(frame.buffer().dimensions(), (width as u32, height as u32));
+                assert_eq!(frame.delay().numer_denom_ms(), (100, 1));
+            }
+        }
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn test_write_apng() {
+        let output =
+            std::env::temp_dir().join(format!("evolution_test_apng_{}.apng", std::process::id()));
+        let (width, height) = (4, 2);
+        let frames: Vec<Vec<u8>> = (0..5u8)
+            .map(|i| [i * 50, 255 - i * 50, 0, 255].repeat(width * height))
+            .collect();
+        for (loop_mode, plays) in [(LoopMode::Forward, 0), (LoopMode::Once, 1)] {
+            write_apng(
+                &output,
+                frames.clone(),
+                width as u32,
+                height as u32,
+                15,
+                loop_mode,
+            )
+            .unwrap();
+            let mut reader = png::Decoder::new(File::open(&output).unwrap())
+                .read_info()
+                .unwrap();
+            let animation = reader.info().animation_control.unwrap();
+            assert_eq!((animation.num_frames, animation.num_plays), (5, plays));
+            let mut buffer = vec![0; reader.output_buffer_size()];
+            for expected in &frames {
+                reader.next_frame(&mut buffer).unwrap();
+                let control = reader.info().frame_control.unwrap();
+                assert_eq!((control.delay_num, control.delay_den), (1, 15));
+                assert_eq!(&buffer, expected);
+            }
+        }
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn test_with_line_snippet() {
+        assert_eq!(
+            with_line_snippet("Unexpected ')'".to_string(), "( RGB\n  ) )\n", 1),
+            "Unexpected ')'\n    ) )"
+        );
+        assert_eq!(
+            with_line_snippet("Unexpected ')'".to_string(), "( RGB", 3),
+            "Unexpected ')'"
+        );
+    }
+
+    #[test]
+    fn test_rgba8_to_rgb8() {
+        assert_eq!(
+            rgba8_to_rgb8(&[1, 2, 3, 255, 4, 5, 6, 255]),
+            vec![1, 2, 3, 4, 5, 6]
+        );
+        assert_eq!(rgba8_to_rgb8(&[]), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_invert_rgb() {
+        let rgba8: Vec<u8> = vec![0, 128, 255, 255, 10, 20, 30, 40];
+        let mut inverted = rgba8.clone();
+        invert_rgb(&mut inverted, u8::MAX);
+        assert_eq!(inverted, vec![255, 127, 0, 255, 245, 235, 225, 40]);
+        invert_rgb(&mut inverted, u8::MAX);
+        assert_eq!(inverted, rgba8);
+
+        let rgba16: Vec<u16> = vec![0, 32768, 65535, 1000];
+        let mut inverted = rgba16.clone();
+        invert_rgb(&mut inverted, u16::MAX);
+        assert_eq!(inverted, vec![65535, 32767, 0, 1000]);
+        invert_rgb(&mut inverted, u16::MAX);
+        assert_eq!(inverted, rgba16);
+    }
+
+    #[test]
+    fn test_gamma_lut() {
+        let lut = gamma_lut::<u8>(2.2, 255);
+        assert_eq!(lut.len(), 256);
+        for v in [0u8, 1, 64, 128, 200, 255] {
+            let expected = 255.0 * (v as f32 / 255.0).powf(1.0 / 2.2);
+            assert_eq!(lut[v as usize], expected.round() as u8, "{}", v);
+        }
+        // midtones get brighter, black and white stay
+        assert_eq!((lut[0], lut[128], lut[255]), (0, 186, 255));
+        assert!(gamma_lut::<u8>(1.0, 255)
+            .iter()
+            .enumerate()
+            .all(|(v, &c)| v == c as usize));
+        let lut = gamma_lut::<u16>(0.5, 65535);
+        assert_eq!((lut[0], lut[32768], lut[65535]), (0, 16384, 65535));
+
+        let mut rgba8 = vec![0, 128, 255, 77];
+        apply_lut(&mut rgba8, &gamma_lut::<u8>(2.2, 255));
+        assert_eq!(rgba8, vec![0, 186, 255, 77]);
+    }
+
+    #[test]
+    fn test_encode_jpeg() {
+        let (width, height) = (16, 8);
+        let rgba8: Vec<u8> = (0..width * height)
+            .flat_map(|i| [(i * 2) as u8, 128, 64, 255])
+            .collect();
+        let mut sizes = vec![];
+        for quality in [1, DEFAULT_JPEG_QUALITY, 100] {
+            let bytes = encode_jpeg(&rgba8[0..], width, height, quality, Color::WHITE).unwrap();
+            let img = image::load_from_memory_with_format(&bytes, ImageFormat::Jpeg).unwrap();
+            assert_eq!((img.width(), img.height()), (width, height));
+            assert_eq!(img.color(), ColorType::Rgb8);
+            sizes.push(bytes.len());
+        }
+        assert!(sizes[0] < sizes[2]);
+    }
+
+    #[test]
+    fn test_encode_for_stdout() {
+        let (width, height) = (5, 3);
+        let rgba8: Vec<u8> = (0..width * height)
+            .flat_map(|i| [(i * 16) as u8, 255 - i as u8, 7, 200])
+            .collect();
+        let args =
+            Args::try_parse_from(["evolution", "-i", "in.sexpr", "-o", "-", "--format", "png"])
+                .unwrap();
+        let out_file = Path::new(args.output.as_ref().unwrap());
+        assert!(is_stdout(out_file));
+        let (format, _) = resolve_image_format(out_file, args.format).unwrap();
+        // the bytes that go to stdout decode to the same pixels
+        let bytes = encode_rgba8(&rgba8[0..], width, height, format, &args, None).unwrap();
+        let img = image::load_from_memory(&bytes).unwrap().to_rgba8();
+        assert_eq!(img.dimensions(), (width, height));
+        assert_eq!(img.into_raw(), rgba8);
+
+        let rgba16: Vec<u16> = rgba8.iter().map(|&c| c as u16 * 257 + 1).collect();
+        let bytes = encode_rgba16(rgba16.clone(), width, height, format, None).unwrap();
+        let img = image::load_from_memory(&bytes).unwrap().to_rgba16();
+        assert_eq!(img.into_raw(), rgba16);
+    }
 
     #[test]
     fn test_select_image_format() {
@@ -329,7 +1628,11 @@ mod tests {
         );
         assert_eq!(
             select_image_format(&Path::new("somefile.webp")),
-            (ImageFormat::WebP, false)
+            (ImageFormat::WebP, true)
+        );
+        assert_eq!(
+            select_image_format(&Path::new("somefile.apng")),
+            (ImageFormat::Png, true)
         );
         assert_eq!(
             select_image_format(&Path::new("somefile.pnm")),
@@ -367,5 +1670,44 @@ mod tests {
             select_image_format(&Path::new("./somedir")),
             (ImageFormat::Png, false)
         );
+        assert_eq!(
+            select_image_format(&Path::new("somefile.unknown")),
+            (ImageFormat::Png, false)
+        );
+    }
+
+    #[test]
+    fn test_resolve_image_format() {
+        assert_eq!(
+            resolve_image_format(&Path::new("somefile.jpg"), None),
+            Ok((ImageFormat::Jpeg, false))
+        );
+        assert_eq!(
+            resolve_image_format(&Path::new("/dev/stdout"), Some(ImageFormat::Jpeg)),
+            Ok((ImageFormat::Jpeg, false))
+        );
+        assert_eq!(
+            resolve_image_format(&Path::new("somefile"), Some(ImageFormat::Gif)),
+            Ok((ImageFormat::Gif, true))
+        );
+        assert_eq!(
+            resolve_image_format(&Path::new("somefile.out"), Some(ImageFormat::WebP)),
+            Ok((ImageFormat::WebP, true))
+        );
+        assert_eq!(
+            resolve_image_format(&Path::new("somefile.JPEG"), Some(ImageFormat::Jpeg)),
+            Ok((ImageFormat::Jpeg, false))
+        );
+        assert_eq!(
+            resolve_image_format(&Path::new("somefile.apng"), Some(ImageFormat::Png)),
+            Ok((ImageFormat::Png, true))
+        );
+        assert!(resolve_image_format(&Path::new("somefile.png"), Some(ImageFormat::Jpeg)).is_err());
+        assert!(resolve_image_format(&Path::new("somefile.gif"), Some(ImageFormat::Png)).is_err());
+        assert_eq!(
+            resolve_image_format(&Path::new("-"), Some(ImageFormat::Png)),
+            Ok((ImageFormat::Png, false))
+        );
+        assert!(resolve_image_format(&Path::new("-"), None).is_err());
     }
 }
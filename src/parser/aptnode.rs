@@ -1,24 +1,46 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::mpsc::Receiver;
 use std::sync::Arc;
 
+use crate::constants::{
+    PIC_DERIVATIVE_STEP, PIC_FBM_OCTAVES_DEFAULT, PIC_MUTATE_CONSTANT_DELTA,
+    PIC_MUTATE_SWAP_ATTEMPTS, PIC_MUTATE_TREE_MAX, PIC_RANDOM_TREE_MAX_DEPTH,
+    PIC_RANDOM_TREE_MAX_NODES,
+};
+use crate::parser::lexer::{expect_close_paren, extract_line_number, unexpected_token};
+use crate::parser::parseerror::ParseError;
 use crate::parser::token::Token;
-use crate::pic::actual_picture::ActualPicture;
+use crate::pic::actual_picture::{resolve_picture_name, ActualPicture, Sampling};
 use crate::pic::coordinatesystem::{cartesian_to_polar, CoordinateSystem};
+use crate::pic::generation::OperatorWeights;
+use crate::pic::glsl::{glsl_float, glsl_noise_basis, sampler_name};
+use crate::pic::noisebasis::NoiseBasis;
 use crate::vm::stackmachine::StackMachine;
 
+use rand::distributions::WeightedIndex;
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 use simdeez::Simd;
 use variant_count::VariantCount;
 
-#[derive(VariantCount, Clone, Debug, PartialEq)]
+/// The value of TSIN at time `t`, the same as the StackMachine computes
+pub fn time_sin(t: f32) -> f32 {
+    (std::f32::consts::PI * t.rem_euclid(2.0)).sin()
+}
+
+/// The value of TCOS at time `t`, the same as the StackMachine computes
+pub fn time_cos(t: f32) -> f32 {
+    (std::f32::consts::PI * t.rem_euclid(2.0)).cos()
+}
+
+#[derive(VariantCount, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum APTNode {
     Add(Vec<APTNode>),
     Sub(Vec<APTNode>),
     Mul(Vec<APTNode>),
     Div(Vec<APTNode>),
     Mod(Vec<APTNode>),
-    FBM(Vec<APTNode>),
+    FBM(NoiseBasis, Vec<APTNode>),
     Ridge(Vec<APTNode>),
     Turbulence(Vec<APTNode>),
     Cell1(Vec<APTNode>),
@@ -28,17 +50,29 @@ pub enum APTNode {
     Atan(Vec<APTNode>),
     Atan2(Vec<APTNode>),
     Tan(Vec<APTNode>),
+    Sinh(Vec<APTNode>),
+    Tanh(Vec<APTNode>),
     Log(Vec<APTNode>),
     Abs(Vec<APTNode>),
     Floor(Vec<APTNode>),
     Ceil(Vec<APTNode>),
+    Fract(Vec<APTNode>),
     Clamp(Vec<APTNode>),
     Wrap(Vec<APTNode>),
     Square(Vec<APTNode>),
     Max(Vec<APTNode>),
     Min(Vec<APTNode>),
     Mandelbrot(Vec<APTNode>),
-    Picture(String, Vec<APTNode>),
+    Warp(Vec<APTNode>),
+    Smoothstep(Vec<APTNode>),
+    Mix(Vec<APTNode>),
+    Step(Vec<APTNode>),
+    Voronoi(Vec<APTNode>),
+    RidgedFBM(Vec<APTNode>),
+    // the slope of the child along X or Y
+    DX(Vec<APTNode>),
+    DY(Vec<APTNode>),
+    Picture(String, Sampling, Vec<APTNode>),
     Constant(f32),
     Width,
     Height,
@@ -47,106 +81,103 @@ pub enum APTNode {
     X,
     Y,
     T,
+    // sin(PI * T) and cos(PI * T), they loop over every time range of a length of 2
+    TSin,
+    TCos,
     Empty,
 }
 
 impl APTNode {
     pub fn to_lisp(&self) -> String {
-        match self {
-            APTNode::Add(children) => {
-                format!("( + {} {} )", children[0].to_lisp(), children[1].to_lisp())
-            }
-            APTNode::Sub(children) => {
-                format!("( - {} {} )", children[0].to_lisp(), children[1].to_lisp())
-            }
-            APTNode::Mul(children) => {
-                format!("( * {} {} )", children[0].to_lisp(), children[1].to_lisp())
+        match self.lisp_children() {
+            Some(children) => {
+                let mut s = format!("( {}", self.lisp_name());
+                for child in children {
+                    s += &format!(" {}", child.to_lisp());
+                }
+                s + " )"
             }
-            APTNode::Div(children) => {
-                format!("( / {} {} )", children[0].to_lisp(), children[1].to_lisp())
+            None => self.lisp_name(),
+        }
+    }
+
+    /// Like to_lisp, over several lines: an operation that has operations as children puts every
+    /// child on a line of its own, indented `indent` spaces deeper than the operation at `level`
+    pub fn to_lisp_pretty(&self, indent: usize, level: usize) -> String {
+        match self.lisp_children() {
+            Some(children) if children.iter().any(|child| !child.is_leaf()) => {
+                let pad = " ".repeat(indent * (level + 1));
+                let mut s = format!("( {}", self.lisp_name());
+                for child in children {
+                    s += &format!("\n{}{}", pad, child.to_lisp_pretty(indent, level + 1));
+                }
+                s + &format!("\n{})", " ".repeat(indent * level))
             }
-            APTNode::Mod(children) => {
-                format!("( % {} {} )", children[0].to_lisp(), children[1].to_lisp())
+            _ => self.to_lisp(),
+        }
+    }
+
+    /// The children that to_lisp writes, the last ones are left out where they are the defaults
+    /// of optional_children
+    fn lisp_children(&self) -> Option<&[APTNode]> {
+        self.get_children().map(|children| {
+            let optional = self.optional_children();
+            let required = children.len().saturating_sub(optional.len());
+            let mut len = children.len();
+            while len > required && children[len - 1] == optional[len - 1 - required] {
+                len -= 1;
             }
-            APTNode::FBM(children) => format!(
-                "( FBM {} {} {} {} {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp(),
-                children[2].to_lisp(),
-                children[3].to_lisp(),
-                children[4].to_lisp(),
-                children[5].to_lisp()
-            ),
-            APTNode::Ridge(children) => format!(
-                "( RIDGE {} {} {} {} {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp(),
-                children[2].to_lisp(),
-                children[3].to_lisp(),
-                children[4].to_lisp(),
-                children[5].to_lisp()
-            ),
-            APTNode::Cell1(children) => format!(
-                "( CELL1 {} {} {} {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp(),
-                children[2].to_lisp(),
-                children[3].to_lisp(),
-                children[4].to_lisp()
-            ),
-            APTNode::Cell2(children) => format!(
-                "( CELL2 {} {} {} {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp(),
-                children[2].to_lisp(),
-                children[3].to_lisp(),
-                children[4].to_lisp()
-            ),
-            APTNode::Turbulence(children) => format!(
-                "( TURBULENCE {} {} {} {} {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp(),
-                children[2].to_lisp(),
-                children[3].to_lisp(),
-                children[4].to_lisp(),
-                children[5].to_lisp()
-            ),
-            APTNode::Sqrt(children) => format!("( SQRT {} )", children[0].to_lisp()),
-            APTNode::Sin(children) => format!("( SIN {} )", children[0].to_lisp()),
-            APTNode::Atan(children) => format!("( ATAN {} )", children[0].to_lisp()),
-            APTNode::Atan2(children) => format!(
-                "( ATAN2 {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp()
-            ),
-            APTNode::Tan(children) => format!("( TAN {} )", children[0].to_lisp()),
-            APTNode::Log(children) => format!("( LOG {} )", children[0].to_lisp()),
-            APTNode::Abs(children) => format!("( ABS {} )", children[0].to_lisp()),
-            APTNode::Floor(children) => format!("( FLOOR {} )", children[0].to_lisp()),
-            APTNode::Ceil(children) => format!("( CEIL {} )", children[0].to_lisp()),
-            APTNode::Clamp(children) => format!("( CLAMP {} )", children[0].to_lisp()),
-            APTNode::Wrap(children) => format!("( WRAP {} )", children[0].to_lisp()),
-            APTNode::Square(children) => format!("( SQUARE {} )", children[0].to_lisp()),
-            APTNode::Max(children) => format!(
-                "( MAX {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp()
-            ),
-            APTNode::Min(children) => format!(
-                "( MIN {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp()
-            ),
-            APTNode::Mandelbrot(children) => format!(
-                "( MANDELBROT {} {} )",
-                children[0].to_lisp(),
-                children[1].to_lisp()
-            ),
-            APTNode::Picture(name, children) => format!(
-                "( PIC-{} {} {} )",
-                name,
-                children[0].to_lisp(),
-                children[1].to_lisp()
+            &children[..len]
+        })
+    }
+
+    /// The keyword of the node in lisp, without its children
+    pub fn lisp_name(&self) -> String {
+        match self {
+            APTNode::Add(_) => format!("+"),
+            APTNode::Sub(_) => format!("-"),
+            APTNode::Mul(_) => format!("*"),
+            APTNode::Div(_) => format!("/"),
+            APTNode::Mod(_) => format!("%"),
+            APTNode::FBM(NoiseBasis::Simplex, _) => format!("FBM"),
+            APTNode::FBM(basis, _) => format!("FBM-{}", basis.to_string().to_uppercase()),
+            APTNode::Ridge(_) => format!("RIDGE"),
+            APTNode::Cell1(_) => format!("CELL1"),
+            APTNode::Cell2(_) => format!("CELL2"),
+            APTNode::Turbulence(_) => format!("TURBULENCE"),
+            APTNode::Sqrt(_) => format!("SQRT"),
+            APTNode::Sin(_) => format!("SIN"),
+            APTNode::Atan(_) => format!("ATAN"),
+            APTNode::Atan2(_) => format!("ATAN2"),
+            APTNode::Tan(_) => format!("TAN"),
+            APTNode::Sinh(_) => format!("SINH"),
+            APTNode::Tanh(_) => format!("TANH"),
+            APTNode::Log(_) => format!("LOG"),
+            APTNode::Abs(_) => format!("ABS"),
+            APTNode::Floor(_) => format!("FLOOR"),
+            APTNode::Ceil(_) => format!("CEIL"),
+            APTNode::Fract(_) => format!("FRACT"),
+            APTNode::Clamp(_) => format!("CLAMP"),
+            APTNode::Wrap(_) => format!("WRAP"),
+            APTNode::Square(_) => format!("SQUARE"),
+            APTNode::Max(_) => format!("MAX"),
+            APTNode::Min(_) => format!("MIN"),
+            APTNode::Mandelbrot(_) => format!("MANDELBROT"),
+            APTNode::Warp(_) => format!("WARP"),
+            APTNode::Smoothstep(_) => format!("SMOOTHSTEP"),
+            APTNode::Mix(_) => format!("MIX"),
+            APTNode::Step(_) => format!("STEP"),
+            APTNode::Voronoi(_) => format!("VORONOI"),
+            APTNode::RidgedFBM(_) => format!("RIDGEDFBM"),
+            APTNode::DX(_) => format!("DX"),
+            APTNode::DY(_) => format!("DY"),
+            APTNode::Picture(name, sampling, _) => format!(
+                "{}{}",
+                match sampling {
+                    Sampling::Bilinear => "PIC-",
+                    Sampling::Nearest => "PICN-",
+                },
+                name
             ),
             APTNode::Constant(v) => {
                 if v == &std::f32::consts::PI {
@@ -164,10 +195,158 @@ impl APTNode {
             APTNode::X => format!("X"),
             APTNode::Y => format!("Y"),
             APTNode::T => format!("T"),
+            APTNode::TSin => format!("TSIN"),
+            APTNode::TCos => format!("TCOS"),
             APTNode::Empty => format!("EMPTY"),
         }
     }
 
+    /// A GLSL expression that computes the same as the StackMachine, see pic::glsl for the helpers
+    pub fn to_glsl(&self) -> String {
+        self.to_glsl_at("uv.x", "uv.y")
+    }
+
+    /// Like to_glsl, with `x` and `y` as the expressions for X and Y, Warp shifts them
+    fn to_glsl_at(&self, x: &str, y: &str) -> String {
+        let child = |i: usize| -> String {
+            match self.get_children() {
+                Some(children) => children[i].to_glsl_at(x, y),
+                None => panic!("got a leaf building glsl"),
+            }
+        };
+        match self {
+            APTNode::Add(_) => format!("({} + {})", child(0), child(1)),
+            APTNode::Sub(_) => format!("({} - {})", child(0), child(1)),
+            APTNode::Mul(_) => format!("({} * {})", child(0), child(1)),
+            APTNode::Div(_) => format!("evo_div({}, {})", child(0), child(1)),
+            APTNode::Mod(_) => format!("evo_mod({}, {})", child(1), child(0)),
+            APTNode::FBM(basis, _) => format!(
+                "evo_fbm(vec2({} * {} * 15.0, {} * {} * 15.0), {} * 5.0, {} * 0.5, {}, {})",
+                child(3),
+                child(5),
+                child(4),
+                child(0),
+                child(2),
+                child(1),
+                child(6),
+                glsl_noise_basis(basis)
+            ),
+            APTNode::Ridge(_) | APTNode::Turbulence(_) | APTNode::RidgedFBM(_) => {
+                let function = match self {
+                    APTNode::Ridge(_) => "evo_ridge",
+                    APTNode::RidgedFBM(_) => "evo_ridged_fbm",
+                    _ => "evo_turbulence",
+                };
+                format!(
+                    "{}(vec2({} * {} * 15.0, {} * {} * 15.0), {} * 5.0, {} * 0.5)",
+                    function,
+                    child(3),
+                    child(5),
+                    child(4),
+                    child(0),
+                    child(2),
+                    child(1)
+                )
+            }
+            APTNode::Cell1(_) | APTNode::Cell2(_) => {
+                let component = if matches!(self, APTNode::Cell1(_)) {
+                    "x"
+                } else {
+                    "y"
+                };
+                format!(
+                    "evo_cell(vec2({} * {} * 4.0, {} * {} * 4.0), {} * 0.5).{}",
+                    child(2),
+                    child(4),
+                    child(3),
+                    child(0),
+                    child(1),
+                    component
+                )
+            }
+            APTNode::Sqrt(_) => format!("evo_sqrt({})", child(0)),
+            APTNode::Sin(_) => format!("sin({} * EVO_PI)", child(0)),
+            APTNode::Atan(_) => format!("(atan({} * 4.0) * 0.666666666)", child(0)),
+            APTNode::Atan2(_) => format!("(atan({} * 4.0, {}) / EVO_PI)", child(0), child(1)),
+            APTNode::Tan(_) => format!("clamp(tan({} * EVO_PI / 2.0), -1.0, 1.0)", child(0)),
+            APTNode::Sinh(_) => format!("evo_sinh({})", child(0)),
+            APTNode::Tanh(_) => format!("evo_tanh({})", child(0)),
+            APTNode::Log(_) => format!("evo_log({})", child(0)),
+            APTNode::Abs(_) => format!("abs({})", child(0)),
+            APTNode::Floor(_) => format!("floor({})", child(0)),
+            APTNode::Ceil(_) => format!("ceil({})", child(0)),
+            APTNode::Fract(_) => format!("fract({})", child(0)),
+            APTNode::Clamp(_) => format!("min(max({}, {}), {})", child(0), child(1), child(2)),
+            APTNode::Wrap(_) => format!("evo_wrap({})", child(0)),
+            APTNode::Square(_) => format!("evo_square({})", child(0)),
+            APTNode::Max(_) => format!("max({}, {})", child(0), child(1)),
+            APTNode::Min(_) => format!("min({}, {})", child(0), child(1)),
+            // a No-Op in the StackMachine too, that leaves the second parameter
+            APTNode::Mandelbrot(_) => child(1),
+            APTNode::Warp(children) => {
+                let x = format!("({} + {})", x, child(0));
+                let y = format!("({} + {})", y, child(1));
+                children[2].to_glsl_at(&x, &y)
+            }
+            APTNode::Smoothstep(_) => {
+                format!("evo_smoothstep({}, {}, {})", child(0), child(1), child(2))
+            }
+            APTNode::Mix(_) => format!(
+                "mix({}, {}, clamp({}, 0.0, 1.0))",
+                child(0),
+                child(1),
+                child(2)
+            ),
+            APTNode::Step(_) => format!("(step({}, {}) * 2.0 - 1.0)", child(0), child(1)),
+            APTNode::Voronoi(_) => format!(
+                "evo_voronoi(vec2({} * 4.0, {} * 4.0), {})",
+                child(0),
+                child(1),
+                child(2)
+            ),
+            APTNode::DX(children) | APTNode::DY(children) => {
+                // a central difference, like the StackMachine takes it
+                let step = glsl_float(PIC_DERIVATIVE_STEP);
+                let (before, after) = if matches!(self, APTNode::DX(_)) {
+                    (
+                        children[0].to_glsl_at(&format!("({} - {})", x, step), y),
+                        children[0].to_glsl_at(&format!("({} + {})", x, step), y),
+                    )
+                } else {
+                    (
+                        children[0].to_glsl_at(x, &format!("({} - {})", y, step)),
+                        children[0].to_glsl_at(x, &format!("({} + {})", y, step)),
+                    )
+                };
+                format!("(({} - {}) / (2.0 * {}))", after, before, step)
+            }
+            APTNode::Picture(name, Sampling::Bilinear, _) => format!(
+                "evo_picture({}, {}, {})",
+                sampler_name(name),
+                child(0),
+                child(1)
+            ),
+            APTNode::Picture(name, Sampling::Nearest, _) => format!(
+                "evo_picture_nearest({}, {}_size, {}, {})",
+                sampler_name(name),
+                sampler_name(name),
+                child(0),
+                child(1)
+            ),
+            APTNode::Constant(v) => glsl_float(*v),
+            APTNode::Width => "resolution.x".to_string(),
+            APTNode::Height => "resolution.y".to_string(),
+            APTNode::PI => "EVO_PI".to_string(),
+            APTNode::E => "EVO_E".to_string(),
+            APTNode::X => x.to_string(),
+            APTNode::Y => y.to_string(),
+            APTNode::T => "t".to_string(),
+            APTNode::TSin => "sin(EVO_PI * mod(t, 2.0))".to_string(),
+            APTNode::TCos => "cos(EVO_PI * mod(t, 2.0))".to_string(),
+            APTNode::Empty => panic!("got empty building glsl"),
+        }
+    }
+
     pub fn str_to_node(s: &str) -> Result<APTNode, String> {
         let lower = &s.to_lowercase()[..];
         match lower {
@@ -176,14 +355,18 @@ impl APTNode {
             "*" => Ok(APTNode::Mul(vec![APTNode::Empty, APTNode::Empty])),
             "/" => Ok(APTNode::Div(vec![APTNode::Empty, APTNode::Empty])),
             "%" => Ok(APTNode::Mod(vec![APTNode::Empty, APTNode::Empty])),
-            "fbm" => Ok(APTNode::FBM(vec![
-                APTNode::Empty,
-                APTNode::Empty,
-                APTNode::Empty,
-                APTNode::Empty,
-                APTNode::Empty,
-                APTNode::Empty,
-            ])),
+            "fbm" => Ok(APTNode::FBM(
+                NoiseBasis::Simplex,
+                vec![
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                ],
+            )),
             "ridge" => Ok(APTNode::Ridge(vec![
                 APTNode::Empty,
                 APTNode::Empty,
@@ -219,16 +402,54 @@ impl APTNode {
             "atan" => Ok(APTNode::Atan(vec![APTNode::Empty])),
             "atan2" => Ok(APTNode::Atan2(vec![APTNode::Empty, APTNode::Empty])),
             "tan" => Ok(APTNode::Tan(vec![APTNode::Empty])),
+            "sinh" => Ok(APTNode::Sinh(vec![APTNode::Empty])),
+            "tanh" => Ok(APTNode::Tanh(vec![APTNode::Empty])),
             "log" => Ok(APTNode::Log(vec![APTNode::Empty])),
             "abs" => Ok(APTNode::Abs(vec![APTNode::Empty])),
             "floor" => Ok(APTNode::Floor(vec![APTNode::Empty])),
             "ceil" => Ok(APTNode::Ceil(vec![APTNode::Empty])),
-            "clamp" => Ok(APTNode::Clamp(vec![APTNode::Empty])),
+            "fract" => Ok(APTNode::Fract(vec![APTNode::Empty])),
+            "clamp" => Ok(APTNode::Clamp(vec![
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+            ])),
             "wrap" => Ok(APTNode::Wrap(vec![APTNode::Empty])),
             "square" => Ok(APTNode::Square(vec![APTNode::Empty])),
             "max" => Ok(APTNode::Max(vec![APTNode::Empty, APTNode::Empty])),
             "min" => Ok(APTNode::Min(vec![APTNode::Empty, APTNode::Empty])),
             "mandelbrot" => Ok(APTNode::Mandelbrot(vec![APTNode::Empty, APTNode::Empty])),
+            "warp" => Ok(APTNode::Warp(vec![
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+            ])),
+            "smoothstep" => Ok(APTNode::Smoothstep(vec![
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+            ])),
+            "mix" => Ok(APTNode::Mix(vec![
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+            ])),
+            "step" => Ok(APTNode::Step(vec![APTNode::Empty, APTNode::Empty])),
+            "voronoi" => Ok(APTNode::Voronoi(vec![
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+            ])),
+            "ridgedfbm" => Ok(APTNode::RidgedFBM(vec![
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+            ])),
+            "dx" => Ok(APTNode::DX(vec![APTNode::Empty])),
+            "dy" => Ok(APTNode::DY(vec![APTNode::Empty])),
             "width" => Ok(APTNode::Width),
             "height" => Ok(APTNode::Height),
             "pi" => Ok(APTNode::PI),
@@ -236,45 +457,92 @@ impl APTNode {
             "x" => Ok(APTNode::X),
             "y" => Ok(APTNode::Y),
             "t" => Ok(APTNode::T),
+            "tsin" => Ok(APTNode::TSin),
+            "tcos" => Ok(APTNode::TCos),
             _ => {
-                if lower.starts_with("pic-") {
+                if lower.starts_with("fbm-") {
+                    let basis = lower[4..]
+                        .parse()
+                        .map_err(|_| format!("Unknown operation '{}' ", s.to_string()))?;
+                    Ok(APTNode::FBM(basis, vec![APTNode::Empty; 7]))
+                } else if lower.starts_with("pic-") {
                     let name = lower[4..].to_owned();
-                    Ok(APTNode::Picture(name, vec![APTNode::Empty, APTNode::Empty]))
+                    Ok(APTNode::Picture(
+                        name,
+                        Sampling::Bilinear,
+                        vec![APTNode::Empty, APTNode::Empty],
+                    ))
+                } else if lower.starts_with("picn-") {
+                    let name = lower[5..].to_owned();
+                    Ok(APTNode::Picture(
+                        name,
+                        Sampling::Nearest,
+                        vec![APTNode::Empty, APTNode::Empty],
+                    ))
                 } else {
                     Err(format!("Unknown operation '{}' ", s.to_string()))
                 }
             }
         }
     }
-    pub fn pick_random_coord(rng: &mut StdRng) -> CoordinateSystem {
+    pub fn pick_random_coord(rng: &mut impl Rng) -> CoordinateSystem {
         let r = rng.gen_range(0..CoordinateSystem::VARIANT_COUNT);
 
         match r {
             0 => CoordinateSystem::Polar,
             1 => CoordinateSystem::Cartesian,
+            2 => CoordinateSystem::AspectCorrect,
             _ => panic!("pick_random_coord generated unhandled r:{}", r),
         }
     }
 
-    pub fn pick_random_node(rng: &mut StdRng, pic_names: &Vec<&String>) -> APTNode {
-        let ignore_variant_count = 9;
+    pub fn pick_random_node(rng: &mut impl Rng, pic_names: &[&String]) -> APTNode {
+        let ignore_variant_count = 11;
         let ignore_pictures = if pic_names.len() == 0 { 1 } else { 0 };
         let r = rng.gen_range(0..APTNode::VARIANT_COUNT - ignore_variant_count - ignore_pictures);
+        APTNode::node_for_index(r, rng, pic_names)
+    }
+
+    /// Like pick_random_node, but the chance of every operation follows the weights.
+    /// When all the weights are 0.0 the operation is picked uniformly.
+    pub fn pick_weighted_node(
+        rng: &mut impl Rng,
+        pic_names: &[&String],
+        weights: &OperatorWeights,
+    ) -> APTNode {
+        let mut node_weights = weights.node_weights();
+        if pic_names.len() == 0 {
+            // Pictures are the last one
+            *node_weights.last_mut().unwrap() = 0.0;
+        }
+        match WeightedIndex::new(&node_weights) {
+            Ok(dist) => {
+                let r = dist.sample(rng);
+                APTNode::node_for_index(r, rng, pic_names)
+            }
+            Err(_) => APTNode::pick_random_node(rng, pic_names),
+        }
+    }
 
+    fn node_for_index(r: usize, rng: &mut impl Rng, pic_names: &[&String]) -> APTNode {
         match r {
             0 => APTNode::Add(vec![APTNode::Empty, APTNode::Empty]),
             1 => APTNode::Sub(vec![APTNode::Empty, APTNode::Empty]),
             2 => APTNode::Mul(vec![APTNode::Empty, APTNode::Empty]),
             3 => APTNode::Div(vec![APTNode::Empty, APTNode::Empty]),
             4 => APTNode::Mod(vec![APTNode::Empty, APTNode::Empty]),
-            5 => APTNode::FBM(vec![
-                APTNode::Empty,
-                APTNode::Empty,
-                APTNode::Empty,
-                APTNode::Empty,
-                APTNode::Empty,
-                APTNode::Empty,
-            ]),
+            5 => APTNode::FBM(
+                NoiseBasis::pick_random(rng),
+                vec![
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                ],
+            ),
             6 => APTNode::Ridge(vec![
                 APTNode::Empty,
                 APTNode::Empty,
@@ -310,29 +578,48 @@ impl APTNode {
             12 => APTNode::Atan(vec![APTNode::Empty]),
             13 => APTNode::Atan2(vec![APTNode::Empty, APTNode::Empty]),
             14 => APTNode::Tan(vec![APTNode::Empty]),
-            15 => APTNode::Log(vec![APTNode::Empty]),
-            16 => APTNode::Abs(vec![APTNode::Empty]),
-            17 => APTNode::Floor(vec![APTNode::Empty]),
-            18 => APTNode::Ceil(vec![APTNode::Empty]),
-            19 => APTNode::Clamp(vec![APTNode::Empty]),
-            20 => APTNode::Wrap(vec![APTNode::Empty]),
-            21 => APTNode::Square(vec![APTNode::Empty]),
-            22 => APTNode::Max(vec![APTNode::Empty, APTNode::Empty]),
-            23 => APTNode::Min(vec![APTNode::Empty, APTNode::Empty]),
-            24 => APTNode::Mandelbrot(vec![APTNode::Empty, APTNode::Empty]),
+            15 => APTNode::Sinh(vec![APTNode::Empty]),
+            16 => APTNode::Tanh(vec![APTNode::Empty]),
+            17 => APTNode::Log(vec![APTNode::Empty]),
+            18 => APTNode::Abs(vec![APTNode::Empty]),
+            19 => APTNode::Floor(vec![APTNode::Empty]),
+            20 => APTNode::Ceil(vec![APTNode::Empty]),
+            21 => APTNode::Fract(vec![APTNode::Empty]),
+            22 => APTNode::Clamp(vec![APTNode::Empty, APTNode::Empty, APTNode::Empty]),
+            23 => APTNode::Wrap(vec![APTNode::Empty]),
+            24 => APTNode::Square(vec![APTNode::Empty]),
+            25 => APTNode::Max(vec![APTNode::Empty, APTNode::Empty]),
+            26 => APTNode::Min(vec![APTNode::Empty, APTNode::Empty]),
+            27 => APTNode::Mandelbrot(vec![APTNode::Empty, APTNode::Empty]),
+            28 => APTNode::Warp(vec![APTNode::Empty, APTNode::Empty, APTNode::Empty]),
+            29 => APTNode::Smoothstep(vec![APTNode::Empty, APTNode::Empty, APTNode::Empty]),
+            30 => APTNode::Mix(vec![APTNode::Empty, APTNode::Empty, APTNode::Empty]),
+            31 => APTNode::Step(vec![APTNode::Empty, APTNode::Empty]),
+            32 => APTNode::Voronoi(vec![APTNode::Empty, APTNode::Empty, APTNode::Empty]),
+            33 => APTNode::RidgedFBM(vec![
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+                APTNode::Empty,
+            ]),
+            34 => APTNode::DX(vec![APTNode::Empty]),
+            35 => APTNode::DY(vec![APTNode::Empty]),
             // Pictures should be the last one (see _ignore_pictures variable)
-            25 => {
+            36 => {
                 let r = rng.gen_range(0..pic_names.len()) as usize;
                 APTNode::Picture(
                     pic_names[r].to_string(),
+                    Sampling::Bilinear,
                     vec![APTNode::Empty, APTNode::Empty],
                 )
             }
-            _ => panic!("pick_random_node generated unhandled r:{}", r),
+            _ => panic!("node_for_index got unhandled r:{}", r),
         }
     }
 
-    pub fn pick_random_leaf(rng: &mut StdRng) -> APTNode {
+    pub fn pick_random_leaf(rng: &mut impl Rng) -> APTNode {
         let r = rng.gen_range(0..3);
         match r {
             0 => APTNode::X,
@@ -342,7 +629,7 @@ impl APTNode {
         }
     }
 
-    pub fn pick_random_leaf_video(rng: &mut StdRng) -> APTNode {
+    pub fn pick_random_leaf_video(rng: &mut impl Rng) -> APTNode {
         let r = rng.gen_range(0..4);
         match r {
             0 => APTNode::X,
@@ -353,7 +640,26 @@ impl APTNode {
         }
     }
 
-    pub fn add_random(&mut self, node: APTNode, rng: &mut StdRng) {
+    /// A leaf where the chance of X, Y, Constant and (for videos) T follows the weights.
+    /// When all the weights are 0.0 the leaf is picked uniformly.
+    pub fn pick_weighted_leaf(
+        rng: &mut impl Rng,
+        video: bool,
+        weights: &OperatorWeights,
+    ) -> APTNode {
+        match WeightedIndex::new(&weights.leaf_weights(video)) {
+            Ok(dist) => match dist.sample(rng) {
+                0 => APTNode::X,
+                1 => APTNode::Y,
+                2 => APTNode::Constant(rng.gen_range(-1.0..1.0)),
+                _ => APTNode::T,
+            },
+            Err(_) if video => APTNode::pick_random_leaf_video(rng),
+            Err(_) => APTNode::pick_random_leaf(rng),
+        }
+    }
+
+    pub fn add_random(&mut self, node: APTNode, rng: &mut impl Rng) {
         let children = match self.get_children_mut() {
             Some(children) => children,
             None => panic!("tried to add_random to a leaf"),
@@ -365,6 +671,40 @@ impl APTNode {
         }
     }
 
+    /// Like add_random, but only where the node and its leaves stay within `max_depth` levels,
+    /// counting self as the first level. Returns false when there is no such place.
+    pub fn add_random_within(
+        &mut self,
+        node: APTNode,
+        rng: &mut impl Rng,
+        max_depth: usize,
+    ) -> bool {
+        // the node goes one level below self, its leaves one more
+        if max_depth < 3 {
+            return false;
+        }
+        let children = match self.get_children_mut() {
+            Some(children) => children,
+            None => return false,
+        };
+        let mut order: Vec<usize> = (0..children.len()).collect();
+        order.shuffle(rng);
+        for i in order {
+            match children[i] {
+                APTNode::Empty => {
+                    children[i] = node;
+                    return true;
+                }
+                _ => {
+                    if children[i].add_random_within(node.clone(), rng, max_depth - 1) {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
     pub fn add_leaf(&mut self, leaf: &APTNode) -> bool {
         match self.get_children_mut() {
             None => false,
@@ -387,6 +727,190 @@ impl APTNode {
         }
     }
 
+    pub fn count_nodes(&self) -> usize {
+        match self.get_children() {
+            Some(children) => {
+                1 + children
+                    .iter()
+                    .map(|child| child.count_nodes())
+                    .sum::<usize>()
+            }
+            None => 1,
+        }
+    }
+
+    /// Point the Pic- nodes of the tree to the pictures in `names`, see resolve_picture_name.
+    /// Every name that was replaced is added to `replaced` together with its replacement.
+    pub fn resolve_pictures(
+        &mut self,
+        names: &[&String],
+        replaced: &mut Vec<(String, String)>,
+    ) -> Result<(), String> {
+        if let APTNode::Picture(name, _, _) = self {
+            let resolved = resolve_picture_name(name, names.iter().copied())?;
+            if resolved != *name {
+                replaced.push((name.clone(), resolved.clone()));
+                *name = resolved;
+            }
+        }
+        if let Some(children) = self.get_children_mut() {
+            for child in children {
+                child.resolve_pictures(names, replaced)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add how often every operation of the tree is used to `histogram`, leaves are not counted
+    pub fn count_operations(&self, histogram: &mut BTreeMap<String, usize>) {
+        if let Some(children) = self.get_children() {
+            *histogram.entry(self.lisp_name()).or_insert(0) += 1;
+            for child in children {
+                child.count_operations(histogram);
+            }
+        }
+    }
+
+    /// The number of levels of the tree, a leaf is 1
+    pub fn depth(&self) -> usize {
+        match self.get_children() {
+            Some(children) => {
+                1 + children
+                    .iter()
+                    .map(|child| child.depth())
+                    .max()
+                    .unwrap_or(0)
+            }
+            None => 1,
+        }
+    }
+
+    /// The most values on the stack of a StackMachine while the tree is evaluated, at most: the
+    /// children are evaluated from the last to the first and the results of the ones that are
+    /// done wait on the stack
+    pub fn stack_depth(&self) -> usize {
+        if let APTNode::DX(children) | APTNode::DY(children) = self {
+            // the result before the position waits while the two offsets after it are pushed
+            return 1 + children[0].stack_depth().max(2);
+        }
+        match self.get_children() {
+            Some(children) => children
+                .iter()
+                .rev()
+                .enumerate()
+                .map(|(waiting, child)| waiting + child.stack_depth())
+                .max()
+                .unwrap_or(1),
+            None => 1,
+        }
+    }
+
+    /// The subtree at `index`, counting the nodes in pre-order starting with self as 0
+    pub fn get_subtree(&self, index: usize) -> Option<&APTNode> {
+        if index == 0 {
+            return Some(self);
+        }
+        let mut index = index - 1;
+        if let Some(children) = self.get_children() {
+            for child in children {
+                let count = child.count_nodes();
+                if index < count {
+                    return child.get_subtree(index);
+                }
+                index -= count;
+            }
+        }
+        None
+    }
+
+    pub fn get_subtree_mut(&mut self, index: usize) -> Option<&mut APTNode> {
+        if index == 0 {
+            return Some(self);
+        }
+        let mut index = index - 1;
+        if let Some(children) = self.get_children_mut() {
+            for child in children {
+                let count = child.count_nodes();
+                if index < count {
+                    return child.get_subtree_mut(index);
+                }
+                index -= count;
+            }
+        }
+        None
+    }
+
+    /// Replace a random subtree of self with a copy of a random subtree of the donor
+    pub fn crossover(&mut self, donor: &APTNode, rng: &mut impl Rng) {
+        let target = rng.gen_range(0..self.count_nodes());
+        let source = rng.gen_range(0..donor.count_nodes());
+        if let (Some(slot), Some(graft)) = (self.get_subtree_mut(target), donor.get_subtree(source))
+        {
+            *slot = graft.clone();
+        }
+    }
+
+    /// With a chance of `rate` per node: nudge a constant, swap an operation for another one
+    /// that takes the same number of arguments, or regrow the node as a small random subtree.
+    pub fn mutate(&mut self, rng: &mut impl Rng, rate: f32, video: bool, pic_names: &[&String]) {
+        if *self == APTNode::Empty {
+            return;
+        }
+        if rng.gen_range(0.0..1.0) < rate {
+            match (self.is_leaf(), rng.gen_bool(0.5)) {
+                (true, true) => {
+                    if let APTNode::Constant(v) = self {
+                        let delta =
+                            rng.gen_range(-PIC_MUTATE_CONSTANT_DELTA..PIC_MUTATE_CONSTANT_DELTA);
+                        *v = (*v + delta).max(-1.0).min(1.0);
+                        return;
+                    }
+                }
+                (false, true) => {
+                    if self.swap_operation(rng, pic_names) {
+                        return;
+                    }
+                }
+                _ => {}
+            }
+            let count = rng.gen_range(1..PIC_MUTATE_TREE_MAX);
+            *self = APTNode::create_random_tree(
+                count,
+                video,
+                rng,
+                pic_names,
+                &OperatorWeights::default(),
+                PIC_RANDOM_TREE_MAX_DEPTH,
+                PIC_RANDOM_TREE_MAX_NODES,
+            )
+            .0;
+            return;
+        }
+        if let Some(children) = self.get_children_mut() {
+            for child in children {
+                child.mutate(rng, rate, video, pic_names);
+            }
+        }
+    }
+
+    fn swap_operation(&mut self, rng: &mut impl Rng, pic_names: &[&String]) -> bool {
+        let arity = match self.get_children() {
+            Some(children) => children.len(),
+            None => return false,
+        };
+        // most arities have a few candidates, give up after a couple of misses
+        for _ in 0..PIC_MUTATE_SWAP_ATTEMPTS {
+            let mut node = APTNode::pick_random_node(rng, pic_names);
+            if node.get_children().map(|children| children.len()) == Some(arity) {
+                let children = std::mem::take(self.get_children_mut().unwrap());
+                *node.get_children_mut().unwrap() = children;
+                *self = node;
+                return true;
+            }
+        }
+        false
+    }
+
     fn constant_eval<S: Simd>(
         &self,
         coord: &CoordinateSystem,
@@ -416,6 +940,18 @@ impl APTNode {
                     panic!("invalid node passed to constant_esval")
                 }
             },
+            APTNode::TSin => match t {
+                Some(value) => time_sin(value),
+                None => {
+                    panic!("invalid node passed to constant_esval")
+                }
+            },
+            APTNode::TCos => match t {
+                Some(value) => time_cos(value),
+                None => {
+                    panic!("invalid node passed to constant_esval")
+                }
+            },
             APTNode::X => match x {
                 Some(value) => value as f32,
                 None => {
@@ -436,7 +972,7 @@ impl APTNode {
             | APTNode::Mul(children)
             | APTNode::Div(children)
             | APTNode::Mod(children)
-            | APTNode::FBM(children)
+            | APTNode::FBM(_, children)
             | APTNode::Ridge(children)
             | APTNode::Turbulence(children)
             | APTNode::Cell1(children)
@@ -446,17 +982,28 @@ impl APTNode {
             | APTNode::Atan(children)
             | APTNode::Atan2(children)
             | APTNode::Tan(children)
+            | APTNode::Sinh(children)
+            | APTNode::Tanh(children)
             | APTNode::Log(children)
             | APTNode::Abs(children)
             | APTNode::Floor(children)
             | APTNode::Ceil(children)
+            | APTNode::Fract(children)
             | APTNode::Clamp(children)
             | APTNode::Wrap(children)
             | APTNode::Square(children)
             | APTNode::Max(children)
             | APTNode::Min(children)
             | APTNode::Mandelbrot(children)
-            | APTNode::Picture(_, children) => unsafe {
+            | APTNode::Warp(children)
+            | APTNode::Smoothstep(children)
+            | APTNode::Mix(children)
+            | APTNode::Step(children)
+            | APTNode::Voronoi(children)
+            | APTNode::RidgedFBM(children)
+            | APTNode::DX(children)
+            | APTNode::DY(children)
+            | APTNode::Picture(_, _, children) => unsafe {
                 let mut sx = S::set1_ps(0.0);
                 let mut sy = S::set1_ps(0.0);
                 let mut st = S::set1_ps(0.0);
@@ -471,7 +1018,7 @@ impl APTNode {
                         None => panic!("invalid node passed to constant_esval"),
                         Some(value) => sh = S::set1_ps(value as f32),
                     },
-                    APTNode::T => match t {
+                    APTNode::T | APTNode::TSin | APTNode::TCos => match t {
                         None => panic!("invalid node passed to constant_esval"),
                         Some(value) => st = S::set1_ps(value as f32),
                     },
@@ -486,10 +1033,10 @@ impl APTNode {
                     _ => {}
                 });
                 let sm = StackMachine::<S>::build(self);
-                let mut stack = Vec::with_capacity(sm.instructions.len());
-                stack.set_len(sm.instructions.len());
+                let mut stack = Vec::with_capacity(sm.stack_depth);
+                stack.set_len(sm.stack_depth);
 
-                let v = if coord == &CoordinateSystem::Cartesian {
+                let v = if coord.is_cartesian() {
                     sm.execute(&mut stack, pics, sx, sy, st, sw, sh)
                 } else {
                     let (r, theta) = cartesian_to_polar::<S>(sx, sy);
@@ -508,7 +1055,7 @@ impl APTNode {
             APTNode::Mul(_) => APTNode::Mul(children),
             APTNode::Div(_) => APTNode::Div(children),
             APTNode::Mod(_) => APTNode::Mod(children),
-            APTNode::FBM(_) => APTNode::FBM(children),
+            APTNode::FBM(basis, _) => APTNode::FBM(*basis, children),
             APTNode::Ridge(_) => APTNode::Ridge(children),
             APTNode::Turbulence(_) => APTNode::Turbulence(children),
             APTNode::Cell1(_) => APTNode::Cell1(children),
@@ -516,19 +1063,32 @@ impl APTNode {
             APTNode::Sqrt(_) => APTNode::Sqrt(children),
             APTNode::Sin(_) => APTNode::Sin(children),
             APTNode::Atan(_) => APTNode::Atan(children),
-            APTNode::Atan2(_) => APTNode::Atan(children),
+            APTNode::Atan2(_) => APTNode::Atan2(children),
             APTNode::Tan(_) => APTNode::Tan(children),
+            APTNode::Sinh(_) => APTNode::Sinh(children),
+            APTNode::Tanh(_) => APTNode::Tanh(children),
             APTNode::Log(_) => APTNode::Log(children),
             APTNode::Abs(_) => APTNode::Abs(children),
             APTNode::Floor(_) => APTNode::Floor(children),
             APTNode::Ceil(_) => APTNode::Ceil(children),
+            APTNode::Fract(_) => APTNode::Fract(children),
             APTNode::Clamp(_) => APTNode::Clamp(children),
             APTNode::Wrap(_) => APTNode::Wrap(children),
             APTNode::Square(_) => APTNode::Square(children),
             APTNode::Max(_) => APTNode::Max(children),
             APTNode::Min(_) => APTNode::Min(children),
             APTNode::Mandelbrot(_) => APTNode::Mandelbrot(children),
-            APTNode::Picture(name, _) => APTNode::Picture(name.to_string(), children[1..].to_vec()),
+            APTNode::Warp(_) => APTNode::Warp(children),
+            APTNode::Smoothstep(_) => APTNode::Smoothstep(children),
+            APTNode::Mix(_) => APTNode::Mix(children),
+            APTNode::Step(_) => APTNode::Step(children),
+            APTNode::Voronoi(_) => APTNode::Voronoi(children),
+            APTNode::RidgedFBM(_) => APTNode::RidgedFBM(children),
+            APTNode::DX(_) => APTNode::DX(children),
+            APTNode::DY(_) => APTNode::DY(children),
+            APTNode::Picture(name, sampling, _) => {
+                APTNode::Picture(name.to_string(), *sampling, children[1..].to_vec())
+            }
             APTNode::Constant(v) => APTNode::Constant(*v),
             APTNode::Width => APTNode::Width,
             APTNode::Height => APTNode::Height,
@@ -537,6 +1097,8 @@ impl APTNode {
             APTNode::X => APTNode::X,
             APTNode::Y => APTNode::Y,
             APTNode::T => APTNode::T,
+            APTNode::TSin => APTNode::TSin,
+            APTNode::TCos => APTNode::TCos,
             APTNode::Empty => panic!("tried to eval an empty node"),
         }
     }
@@ -560,13 +1122,42 @@ impl APTNode {
             (APTNode::Width, _, _, None, _, _) => APTNode::Width,
             (APTNode::Height, _, _, _, None, _) => APTNode::Height,
             (APTNode::T, _, _, _, _, None) => APTNode::T,
+            (APTNode::TSin, _, _, _, _, None) => APTNode::TSin,
+            (APTNode::TCos, _, _, _, _, None) => APTNode::TCos,
             (APTNode::X, Some(v), _, _, _, _) => APTNode::Constant(v as f32),
             (APTNode::Y, _, Some(v), _, _, _) => APTNode::Constant(v as f32),
             (APTNode::Width, _, _, Some(v), _, _) => APTNode::Constant(v as f32),
             (APTNode::Height, _, _, _, Some(v), _) => APTNode::Constant(v as f32),
             (APTNode::T, _, _, _, _, Some(v)) => APTNode::Constant(v),
-            (APTNode::Picture(name, children), _, _, _, _, _) => {
-                APTNode::Picture(name.to_string(), children.clone())
+            (APTNode::TSin, _, _, _, _, Some(v)) => APTNode::Constant(time_sin(v)),
+            (APTNode::TCos, _, _, _, _, Some(v)) => APTNode::Constant(time_cos(v)),
+            (APTNode::Picture(name, sampling, children), _, _, _, _, _) => {
+                APTNode::Picture(name.to_string(), *sampling, children.clone())
+            }
+            (APTNode::DX(children), _, _, _, _, _) | (APTNode::DY(children), _, _, _, _, _) => {
+                // the child is sampled around the position, so X and Y can't be replaced there
+                let folded =
+                    children[0].constant_fold::<S>(coord, pics.clone(), None, None, w, h, t);
+                let clone = self.set_children(vec![folded]);
+                if clone.is_constant() {
+                    APTNode::Constant(clone.constant_eval::<S>(coord, pics.clone(), x, y, w, h, t))
+                } else {
+                    clone
+                }
+            }
+            (APTNode::Warp(children), _, _, _, _, _) => {
+                // the input is sampled at the warped position, so X and Y can't be replaced there
+                let folded_children = vec![
+                    children[0].constant_fold::<S>(coord, pics.clone(), x, y, w, h, t),
+                    children[1].constant_fold::<S>(coord, pics.clone(), x, y, w, h, t),
+                    children[2].constant_fold::<S>(coord, pics.clone(), None, None, w, h, t),
+                ];
+                let clone = self.set_children(folded_children);
+                if clone.is_constant() {
+                    APTNode::Constant(clone.constant_eval::<S>(coord, pics.clone(), x, y, w, h, t))
+                } else {
+                    clone
+                }
             }
             _ => {
                 let children = self.get_children().unwrap();
@@ -589,23 +1180,31 @@ impl APTNode {
         }
     }
 
+    /// A tree with up to `count` operations, fewer when the tree would get deeper than
+    /// `max_depth` levels or larger than `max_nodes` nodes (leaves included).
+    /// The first operation is always there, so the budgets should leave room for it.
     pub fn create_random_tree(
         count: usize,
         video: bool,
-        rng: &mut StdRng,
-        pic_names: &Vec<&String>,
+        rng: &mut impl Rng,
+        pic_names: &[&String],
+        weights: &OperatorWeights,
+        max_depth: usize,
+        max_nodes: usize,
     ) -> (APTNode, CoordinateSystem) {
         let coord = APTNode::pick_random_coord(rng);
-        let leaf_func = if video {
-            APTNode::pick_random_leaf_video
-        } else {
-            APTNode::pick_random_leaf
-        };
-        let mut first = APTNode::pick_random_node(rng, pic_names);
+        let mut first = APTNode::pick_weighted_node(rng, pic_names, weights);
+        let mut nodes = first.count_nodes();
         for _ in 1..count {
-            first.add_random(APTNode::pick_random_node(rng, pic_names), rng);
+            let node = APTNode::pick_weighted_node(rng, pic_names, weights);
+            // the node takes the place of a leaf, its children become new leaves
+            let added = node.get_children().map_or(0, |children| children.len());
+            if nodes + added > max_nodes || !first.add_random_within(node, rng, max_depth) {
+                break;
+            }
+            nodes += added;
         }
-        while first.add_leaf(&leaf_func(rng)) {}
+        while first.add_leaf(&APTNode::pick_weighted_leaf(rng, video, weights)) {}
         (first, coord)
     }
 
@@ -616,7 +1215,7 @@ impl APTNode {
             | APTNode::Mul(children)
             | APTNode::Div(children)
             | APTNode::Mod(children)
-            | APTNode::FBM(children)
+            | APTNode::FBM(_, children)
             | APTNode::Ridge(children)
             | APTNode::Turbulence(children)
             | APTNode::Cell1(children)
@@ -626,17 +1225,28 @@ impl APTNode {
             | APTNode::Atan(children)
             | APTNode::Atan2(children)
             | APTNode::Tan(children)
+            | APTNode::Sinh(children)
+            | APTNode::Tanh(children)
             | APTNode::Log(children)
             | APTNode::Abs(children)
             | APTNode::Floor(children)
             | APTNode::Ceil(children)
+            | APTNode::Fract(children)
             | APTNode::Clamp(children)
             | APTNode::Wrap(children)
             | APTNode::Square(children)
             | APTNode::Max(children)
             | APTNode::Min(children)
-            | APTNode::Mandelbrot(children) => Some(children),
-            APTNode::Picture(_, children) => Some(children),
+            | APTNode::Mandelbrot(children)
+            | APTNode::Warp(children)
+            | APTNode::Smoothstep(children)
+            | APTNode::Mix(children)
+            | APTNode::Step(children)
+            | APTNode::Voronoi(children)
+            | APTNode::RidgedFBM(children)
+            | APTNode::DX(children)
+            | APTNode::DY(children) => Some(children),
+            APTNode::Picture(_, _, children) => Some(children),
             _ => None,
         }
     }
@@ -648,7 +1258,7 @@ impl APTNode {
             | APTNode::Mul(children)
             | APTNode::Div(children)
             | APTNode::Mod(children)
-            | APTNode::FBM(children)
+            | APTNode::FBM(_, children)
             | APTNode::Ridge(children)
             | APTNode::Turbulence(children)
             | APTNode::Cell1(children)
@@ -658,17 +1268,28 @@ impl APTNode {
             | APTNode::Atan(children)
             | APTNode::Atan2(children)
             | APTNode::Tan(children)
+            | APTNode::Sinh(children)
+            | APTNode::Tanh(children)
             | APTNode::Log(children)
             | APTNode::Abs(children)
             | APTNode::Floor(children)
             | APTNode::Ceil(children)
+            | APTNode::Fract(children)
             | APTNode::Clamp(children)
             | APTNode::Wrap(children)
             | APTNode::Square(children)
             | APTNode::Max(children)
             | APTNode::Min(children)
-            | APTNode::Mandelbrot(children) => Some(children),
-            APTNode::Picture(_, children) => Some(children),
+            | APTNode::Mandelbrot(children)
+            | APTNode::Warp(children)
+            | APTNode::Smoothstep(children)
+            | APTNode::Mix(children)
+            | APTNode::Step(children)
+            | APTNode::Voronoi(children)
+            | APTNode::RidgedFBM(children)
+            | APTNode::DX(children)
+            | APTNode::DY(children) => Some(children),
+            APTNode::Picture(_, _, children) => Some(children),
             _ => None,
         }
     }
@@ -682,44 +1303,251 @@ impl APTNode {
             | APTNode::X
             | APTNode::Y
             | APTNode::T
+            | APTNode::TSin
+            | APTNode::TCos
             | APTNode::Constant(_)
             | APTNode::Empty => true,
             _ => false,
         }
     }
 
-    pub fn parse_apt_node(receiver: &Receiver<Token>) -> Result<APTNode, String> {
+    /// The number of children an operation takes, 0 for the leaves
+    pub fn arity(&self) -> usize {
+        match self {
+            APTNode::FBM(..) => 7,
+            APTNode::Ridge(_) | APTNode::Turbulence(_) | APTNode::RidgedFBM(_) => 6,
+            APTNode::Cell1(_) | APTNode::Cell2(_) => 5,
+            APTNode::Clamp(_)
+            | APTNode::Warp(_)
+            | APTNode::Smoothstep(_)
+            | APTNode::Mix(_)
+            | APTNode::Voronoi(_) => 3,
+            APTNode::Add(_)
+            | APTNode::Sub(_)
+            | APTNode::Mul(_)
+            | APTNode::Div(_)
+            | APTNode::Mod(_)
+            | APTNode::Atan2(_)
+            | APTNode::Max(_)
+            | APTNode::Min(_)
+            | APTNode::Step(_)
+            | APTNode::Mandelbrot(_)
+            | APTNode::Picture(_, _, _) => 2,
+            _ if self.is_leaf() => 0,
+            _ => 1,
+        }
+    }
+
+    /// True when every operation has as many children as it takes and there are no Empty nodes,
+    /// like the trees that the parser makes
+    pub fn is_complete(&self) -> bool {
+        match self {
+            APTNode::Empty => false,
+            _ => match self.get_children() {
+                Some(children) => {
+                    children.len() == self.arity() && children.iter().all(|c| c.is_complete())
+                }
+                None => true,
+            },
+        }
+    }
+
+    /// True when the tree results in the same value for every pixel, at any time,
+    /// in every image size and without looking at pictures
+    pub fn is_constant(&self) -> bool {
+        match self {
+            APTNode::Constant(_) | APTNode::PI | APTNode::E => true,
+            APTNode::Picture(_, _, _) => false,
+            _ => match self.get_children() {
+                Some(children) => children.iter().all(|child| child.is_constant()),
+                None => false,
+            },
+        }
+    }
+
+    /// Replace every T by TSIN, so the tree loops when T sweeps through a length of 2
+    pub fn seamless_time(&mut self) {
+        match self {
+            APTNode::T => *self = APTNode::TSin,
+            _ => {
+                if let Some(children) = self.get_children_mut() {
+                    children.iter_mut().for_each(|child| child.seamless_time());
+                }
+            }
+        }
+    }
+
+    /// True when the tree looks at the time anywhere, only then does it change between frames
+    pub fn uses_t(&self) -> bool {
+        match self {
+            APTNode::T | APTNode::TSin | APTNode::TCos => true,
+            _ => match self.get_children() {
+                Some(children) => children.iter().any(|child| child.uses_t()),
+                None => false,
+            },
+        }
+    }
+
+    /// The defaults of the last children of an operation, these children can be left out in the
+    /// source of a pic
+    pub fn optional_children(&self) -> Vec<APTNode> {
+        match self {
+            APTNode::FBM(..) => vec![APTNode::Constant(PIC_FBM_OCTAVES_DEFAULT)],
+            // CLAMP took a single child before it had bounds
+            APTNode::Clamp(_) => vec![APTNode::Constant(-1.0), APTNode::Constant(1.0)],
+            _ => vec![],
+        }
+    }
+
+    pub fn parse_apt_node(receiver: &Receiver<Token>) -> Result<APTNode, ParseError> {
+        APTNode::parse_node(false, receiver)
+    }
+
+    /// The next node, `opened` when its '(' was already read. A node in parens takes its ')'
+    /// too. An argument, which is opened, also takes the ')' of the parens that only wrap it,
+    /// like the outer ones of `( + ( ( SIN X ) ) Y )`. For a channel the other parens don't
+    /// matter.
+    fn parse_node(mut opened: bool, receiver: &Receiver<Token>) -> Result<APTNode, ParseError> {
+        let argument = opened;
+        let mut wrapping = 0;
         loop {
             match receiver.recv() {
+                Ok(Token::OpenParen(_)) if argument => wrapping += 1,
+                Ok(Token::OpenParen(_)) => opened = true,
+                Ok(Token::CloseParen(_)) if !opened => (),
                 Ok(token) => {
-                    match token {
-                        Token::Operation(s, line_num) => {
-                            let mut node = APTNode::str_to_node(s)
-                                .map_err(|msg| msg + &format!(" on line {}", line_num))?;
-                            match node.get_children_mut() {
-                                Some(children) => {
-                                    for child in children {
-                                        *child = APTNode::parse_apt_node(receiver)?;
-                                    }
-                                    return Ok(node);
-                                }
-                                None => return Ok(node),
-                            }
-                        }
-                        Token::Constant(vstr, line_num) => {
-                            let v = vstr.parse::<f32>().map_err(|_| {
-                                format!("Unable to parse number {} on line {}", vstr, line_num)
-                            })?;
-                            return Ok(APTNode::Constant(v));
-                        }
-                        _ => (), //parens don't matter
+                    let node = APTNode::parse_token(token, opened, receiver)?;
+                    for _ in 0..wrapping {
+                        expect_close_paren(receiver)?;
                     }
+                    return Ok(node);
                 }
-                Err(_) => {
-                    return Err("Unexpected end of file".to_string());
+                Err(_) => return Err(ParseError::UnterminatedList),
+            }
+        }
+    }
+
+    /// The next argument of an operation, None when the ')' of the list comes first
+    fn parse_argument(receiver: &Receiver<Token>) -> Result<Option<APTNode>, ParseError> {
+        match receiver.recv() {
+            Ok(Token::CloseParen(_)) => Ok(None),
+            Ok(Token::OpenParen(_)) => APTNode::parse_node(true, receiver).map(Some),
+            Ok(token) => APTNode::parse_token(token, false, receiver).map(Some),
+            Err(_) => Err(ParseError::UnterminatedList),
+        }
+    }
+
+    /// The number of arguments from `token` up to the ')' of the list, they are parsed to
+    /// count them
+    fn count_arguments(token: Token, receiver: &Receiver<Token>) -> Result<usize, ParseError> {
+        let mut count = 0;
+        let mut token = token;
+        loop {
+            match token {
+                Token::CloseParen(_) => return Ok(count),
+                Token::OpenParen(_) => APTNode::parse_node(true, receiver)?,
+                _ => APTNode::parse_token(token, false, receiver)?,
+            };
+            count += 1;
+            token = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
+        }
+    }
+
+    /// The node that starts with `token`, up to and including the ')' when it is `opened`
+    fn parse_token(
+        token: Token,
+        opened: bool,
+        receiver: &Receiver<Token>,
+    ) -> Result<APTNode, ParseError> {
+        let line_num = extract_line_number(&token);
+        let op = match token {
+            Token::Operation(s, _) => Some(s),
+            _ => None,
+        };
+        let mut closed = false;
+        let node = match token {
+            Token::Operation(s, _) => {
+                let mut node =
+                    APTNode::str_to_node(s).map_err(|_| ParseError::UnknownOperator {
+                        name: s.to_string(),
+                        pos: line_num,
+                    })?;
+                // without its own parens an operation can't tell where it ends, it takes all
+                let optional = if opened {
+                    node.optional_children()
+                } else {
+                    vec![]
+                };
+                if let Some(children) = node.get_children_mut() {
+                    let required = children.len() - optional.len();
+                    for (i, child) in children[..required].iter_mut().enumerate() {
+                        *child = APTNode::parse_argument(receiver)?.ok_or_else(|| {
+                            ParseError::ArityMismatch {
+                                op: s.to_string(),
+                                expected: required,
+                                got: i,
+                                pos: line_num,
+                            }
+                        })?;
+                    }
+                    closed = APTNode::parse_optional_children(
+                        &mut children[required..],
+                        optional,
+                        receiver,
+                    )?;
                 }
+                node
+            }
+            Token::Constant(vstr, _) => {
+                let v = vstr
+                    .parse::<f32>()
+                    .map_err(|_| unexpected_token("a number", &token))?;
+                APTNode::Constant(v)
+            }
+            _ => return Err(unexpected_token("an operation or a number", &token)),
+        };
+        if opened && !closed {
+            let token = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
+            if !matches!(token, Token::CloseParen(_)) {
+                return Err(match op {
+                    Some(op) => {
+                        let expected = node.get_children().map_or(0, |children| children.len());
+                        ParseError::ArityMismatch {
+                            op: op.to_string(),
+                            expected,
+                            got: expected + APTNode::count_arguments(token, receiver)?,
+                            pos: line_num,
+                        }
+                    }
+                    None => unexpected_token("')'", &token),
+                });
+            }
+        }
+        Ok(node)
+    }
+
+    /// Parse the children that can be left out, the ')' of the operation ends it and the
+    /// remaining children get their defaults. True when that ')' was read.
+    fn parse_optional_children(
+        children: &mut [APTNode],
+        defaults: Vec<APTNode>,
+        receiver: &Receiver<Token>,
+    ) -> Result<bool, ParseError> {
+        let mut closed = false;
+        for (child, default) in children.iter_mut().zip(defaults) {
+            if closed {
+                *child = default;
+                continue;
             }
+            *child = match APTNode::parse_argument(receiver)? {
+                Some(node) => node,
+                None => {
+                    closed = true;
+                    default
+                }
+            };
         }
+        Ok(closed)
     }
 }
 
@@ -760,7 +1588,7 @@ pub mod mock {
         mock_params(2, filled)
     }
     pub fn mock_params_fbm(filled: bool) -> Vec<APTNode> {
-        mock_params(6, filled)
+        mock_params(7, filled)
     }
     pub fn mock_params_ridge(filled: bool) -> Vec<APTNode> {
         mock_params(6, filled)
@@ -789,6 +1617,12 @@ pub mod mock {
     pub fn mock_params_tan(filled: bool) -> Vec<APTNode> {
         mock_params(1, filled)
     }
+    pub fn mock_params_sinh(filled: bool) -> Vec<APTNode> {
+        mock_params(1, filled)
+    }
+    pub fn mock_params_tanh(filled: bool) -> Vec<APTNode> {
+        mock_params(1, filled)
+    }
     pub fn mock_params_log(filled: bool) -> Vec<APTNode> {
         mock_params(1, filled)
     }
@@ -801,9 +1635,21 @@ pub mod mock {
     pub fn mock_params_ceil(filled: bool) -> Vec<APTNode> {
         mock_params(1, filled)
     }
-    pub fn mock_params_clamp(filled: bool) -> Vec<APTNode> {
+    pub fn mock_params_fract(filled: bool) -> Vec<APTNode> {
         mock_params(1, filled)
     }
+    pub fn mock_params_clamp(filled: bool) -> Vec<APTNode> {
+        if filled {
+            // the default bounds, like the CLAMP from before it had them
+            vec![
+                APTNode::Constant(1.0),
+                APTNode::Constant(-1.0),
+                APTNode::Constant(1.0),
+            ]
+        } else {
+            mock_params(3, filled)
+        }
+    }
     pub fn mock_params_wrap(filled: bool) -> Vec<APTNode> {
         mock_params(1, filled)
     }
@@ -822,6 +1668,30 @@ pub mod mock {
     pub fn mock_params_mandelbrot(filled: bool) -> Vec<APTNode> {
         mock_params(2, filled)
     }
+    pub fn mock_params_warp(filled: bool) -> Vec<APTNode> {
+        mock_params(3, filled)
+    }
+    pub fn mock_params_smoothstep(filled: bool) -> Vec<APTNode> {
+        mock_params(3, filled)
+    }
+    pub fn mock_params_mix(filled: bool) -> Vec<APTNode> {
+        mock_params(3, filled)
+    }
+    pub fn mock_params_step(filled: bool) -> Vec<APTNode> {
+        mock_params(2, filled)
+    }
+    pub fn mock_params_voronoi(filled: bool) -> Vec<APTNode> {
+        mock_params(3, filled)
+    }
+    pub fn mock_params_ridgedfbm(filled: bool) -> Vec<APTNode> {
+        mock_params(6, filled)
+    }
+    pub fn mock_params_dx(filled: bool) -> Vec<APTNode> {
+        mock_params(1, filled)
+    }
+    pub fn mock_params_dy(filled: bool) -> Vec<APTNode> {
+        mock_params(1, filled)
+    }
     pub fn mock_params_picture(filled: bool) -> Vec<APTNode> {
         mock_params(2, filled)
     }
@@ -830,6 +1700,8 @@ pub mod mock {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser::lexer::lisp_to_pic;
+    use rand::rngs::mock::StepRng;
     use rand::rngs::StdRng;
     use simdeez::avx2::Avx2;
 
@@ -856,8 +1728,8 @@ mod tests {
             "( % 1 2.1 )"
         );
         assert_eq!(
-            APTNode::FBM(mock::mock_params_fbm(true)).to_lisp(),
-            "( FBM 1 2.1 3.1999998 4.2999997 5.3999996 6.4999995 )"
+            APTNode::FBM(NoiseBasis::Simplex, mock::mock_params_fbm(true)).to_lisp(),
+            "( FBM 1 2.1 3.1999998 4.2999997 5.3999996 6.4999995 7.5999994 )"
         );
         assert_eq!(
             APTNode::Ridge(mock::mock_params_ridge(true)).to_lisp(),
@@ -895,6 +1767,14 @@ mod tests {
             APTNode::Tan(mock::mock_params_tan(true)).to_lisp(),
             "( TAN 1 )"
         );
+        assert_eq!(
+            APTNode::Sinh(mock::mock_params_sinh(true)).to_lisp(),
+            "( SINH 1 )"
+        );
+        assert_eq!(
+            APTNode::Tanh(mock::mock_params_tanh(true)).to_lisp(),
+            "( TANH 1 )"
+        );
         assert_eq!(
             APTNode::Log(mock::mock_params_log(true)).to_lisp(),
             "( LOG 1 )"
@@ -923,6 +1803,10 @@ mod tests {
             APTNode::Ceil(vec![APTNode::Constant(10000.5)]).to_lisp(),
             "( CEIL 10000.5 )"
         );
+        assert_eq!(
+            APTNode::Fract(mock::mock_params_fract(true)).to_lisp(),
+            "( FRACT 1 )"
+        );
         assert_eq!(
             APTNode::Clamp(mock::mock_params_clamp(true)).to_lisp(),
             "( CLAMP 1 )"
@@ -947,18 +1831,84 @@ mod tests {
             APTNode::Mandelbrot(mock::mock_params_mandelbrot(true)).to_lisp(),
             "( MANDELBROT 1 2.1 )"
         );
+        assert_eq!(
+            APTNode::Warp(mock::mock_params_warp(true)).to_lisp(),
+            "( WARP 1 2.1 3.1999998 )"
+        );
+        assert_eq!(
+            APTNode::Smoothstep(mock::mock_params_smoothstep(true)).to_lisp(),
+            "( SMOOTHSTEP 1 2.1 3.1999998 )"
+        );
+        // only the bounds at the end that are the defaults are left out
+        assert_eq!(
+            APTNode::Clamp(vec![
+                APTNode::X,
+                APTNode::Constant(0.5),
+                APTNode::Constant(1.0)
+            ])
+            .to_lisp(),
+            "( CLAMP X 0.5 )"
+        );
+        assert_eq!(
+            APTNode::Clamp(vec![
+                APTNode::X,
+                APTNode::Constant(-1.0),
+                APTNode::Constant(0.5)
+            ])
+            .to_lisp(),
+            "( CLAMP X -1 0.5 )"
+        );
+        assert_eq!(
+            APTNode::Mix(mock::mock_params_mix(true)).to_lisp(),
+            "( MIX 1 2.1 3.1999998 )"
+        );
+        assert_eq!(
+            APTNode::Step(mock::mock_params_step(true)).to_lisp(),
+            "( STEP 1 2.1 )"
+        );
+        assert_eq!(
+            APTNode::Voronoi(mock::mock_params_voronoi(true)).to_lisp(),
+            "( VORONOI 1 2.1 3.1999998 )"
+        );
+        assert_eq!(
+            APTNode::RidgedFBM(mock::mock_params_ridgedfbm(true)).to_lisp(),
+            "( RIDGEDFBM 1 2.1 3.1999998 4.2999997 5.3999996 6.4999995 )"
+        );
+        assert_eq!(
+            APTNode::DX(vec![APTNode::Mul(vec![APTNode::X, APTNode::Y])]).to_lisp(),
+            "( DX ( * X Y ) )"
+        );
+        assert_eq!(
+            APTNode::DY(mock::mock_params_dy(true)).to_lisp(),
+            "( DY 1 )"
+        );
         assert_eq!(
             APTNode::Picture(
                 "eye.jpg".to_string(),
+                Sampling::Bilinear,
                 vec![APTNode::Constant(800.0), APTNode::Constant(600.0)]
             )
             .to_lisp(),
             "( PIC-eye.jpg 800 600 )".to_string()
         );
         assert_eq!(
-            APTNode::Picture("eye.jpg".to_string(), mock::mock_params_picture(true)).to_lisp(),
+            APTNode::Picture(
+                "eye.jpg".to_string(),
+                Sampling::Bilinear,
+                mock::mock_params_picture(true)
+            )
+            .to_lisp(),
             "( PIC-eye.jpg 1 2.1 )".to_string()
         );
+        assert_eq!(
+            APTNode::Picture(
+                "eye.jpg".to_string(),
+                Sampling::Nearest,
+                mock::mock_params_picture(true)
+            )
+            .to_lisp(),
+            "( PICN-eye.jpg 1 2.1 )".to_string()
+        );
         assert_eq!(APTNode::Constant(123.456).to_lisp(), "123.456");
         assert_eq!(APTNode::Constant(0.0).to_lisp(), "0");
         assert_eq!(APTNode::Constant(1.0).to_lisp(), "1");
@@ -971,9 +1921,163 @@ mod tests {
         assert_eq!(APTNode::X.to_lisp(), "X");
         assert_eq!(APTNode::Y.to_lisp(), "Y");
         assert_eq!(APTNode::T.to_lisp(), "T");
+        assert_eq!(APTNode::TSin.to_lisp(), "TSIN");
+        assert_eq!(APTNode::TCos.to_lisp(), "TCOS");
         assert_eq!(APTNode::Empty.to_lisp(), "EMPTY");
     }
 
+    #[test]
+    fn test_aptnode_to_lisp_pretty() {
+        let sin = APTNode::Sin(vec![APTNode::Y]);
+        let tree = APTNode::Add(vec![
+            APTNode::X,
+            APTNode::Mul(vec![sin, APTNode::Constant(0.5)]),
+        ]);
+        assert_eq!(
+            tree.to_lisp_pretty(4, 0),
+            "( +\n    X\n    ( *\n        ( SIN Y )\n        0.5\n    )\n)"
+        );
+        assert_eq!(
+            tree.to_lisp_pretty(0, 0),
+            "( +\nX\n( *\n( SIN Y )\n0.5\n)\n)"
+        );
+        // nothing to nest, stays on one line
+        assert_eq!(
+            APTNode::Sin(vec![APTNode::Y]).to_lisp_pretty(4, 0),
+            "( SIN Y )"
+        );
+        assert_eq!(APTNode::X.to_lisp_pretty(4, 2), "X");
+    }
+
+    #[test]
+    fn test_aptnode_arity() {
+        for name in &[
+            "+",
+            "%",
+            "fbm",
+            "cell2",
+            "sqrt",
+            "atan2",
+            "mandelbrot",
+            "clamp",
+            "warp",
+            "smoothstep",
+            "mix",
+            "step",
+            "voronoi",
+            "ridgedfbm",
+            "dx",
+            "dy",
+            "pic-eye.jpg",
+        ] {
+            let node = APTNode::str_to_node(name).unwrap();
+            assert_eq!(node.arity(), node.get_children().unwrap().len(), "{}", name);
+        }
+        assert_eq!(APTNode::X.arity(), 0);
+        assert_eq!(APTNode::Constant(0.5).arity(), 0);
+    }
+
+    #[test]
+    fn test_aptnode_is_complete() {
+        assert!(APTNode::X.is_complete());
+        assert!(APTNode::Add(mock::mock_params_add(true)).is_complete());
+        assert!(!APTNode::Add(mock::mock_params_add(false)).is_complete());
+        assert!(!APTNode::Add(vec![APTNode::X]).is_complete());
+        assert!(!APTNode::Sin(vec![APTNode::Abs(vec![])]).is_complete());
+        assert!(!APTNode::Empty.is_complete());
+    }
+
+    #[test]
+    fn test_aptnode_to_glsl() {
+        assert_eq!(APTNode::X.to_glsl(), "uv.x");
+        assert_eq!(APTNode::Constant(-0.5).to_glsl(), "(-0.5)");
+        assert_eq!(
+            APTNode::Sub(vec![APTNode::X, APTNode::T]).to_glsl(),
+            "(uv.x - t)"
+        );
+        assert_eq!(
+            APTNode::Mod(vec![APTNode::X, APTNode::Y]).to_glsl(),
+            "evo_mod(uv.y, uv.x)"
+        );
+        assert_eq!(
+            APTNode::Warp(vec![
+                APTNode::Constant(0.5),
+                APTNode::Y,
+                APTNode::Add(vec![APTNode::X, APTNode::Y])
+            ])
+            .to_glsl(),
+            "((uv.x + 0.5) + (uv.y + uv.y))"
+        );
+        assert_eq!(
+            APTNode::Clamp(vec![APTNode::X, APTNode::Constant(-1.0), APTNode::Y]).to_glsl(),
+            "min(max(uv.x, (-1.0)), uv.y)"
+        );
+        assert_eq!(
+            APTNode::Smoothstep(vec![APTNode::Constant(0.25), APTNode::Y, APTNode::X]).to_glsl(),
+            "evo_smoothstep(0.25, uv.y, uv.x)"
+        );
+        assert_eq!(
+            APTNode::Mix(vec![APTNode::X, APTNode::Y, APTNode::T]).to_glsl(),
+            "mix(uv.x, uv.y, clamp(t, 0.0, 1.0))"
+        );
+        assert_eq!(
+            APTNode::Step(vec![APTNode::Constant(0.5), APTNode::X]).to_glsl(),
+            "(step(0.5, uv.x) * 2.0 - 1.0)"
+        );
+        assert_eq!(
+            APTNode::Picture(
+                "eye.jpg".to_string(),
+                Sampling::Nearest,
+                vec![APTNode::X, APTNode::Y]
+            )
+            .to_glsl(),
+            "evo_picture_nearest(pic_eye_jpg, pic_eye_jpg_size, uv.x, uv.y)"
+        );
+        assert_eq!(
+            APTNode::DX(vec![APTNode::X]).to_glsl(),
+            "(((uv.x + 0.00390625) - (uv.x - 0.00390625)) / (2.0 * 0.00390625))"
+        );
+        assert_eq!(
+            APTNode::DY(vec![APTNode::Add(vec![APTNode::X, APTNode::Y])]).to_glsl(),
+            "(((uv.x + (uv.y + 0.00390625)) - (uv.x + (uv.y - 0.00390625))) / (2.0 * 0.00390625))"
+        );
+    }
+
+    #[test]
+    fn test_aptnode_fbm_basis() {
+        assert_eq!(
+            APTNode::str_to_node("FBM-Perlin"),
+            Ok(APTNode::FBM(NoiseBasis::Perlin, vec![APTNode::Empty; 7]))
+        );
+        assert_eq!(
+            APTNode::str_to_node("fbm-simplex"),
+            APTNode::str_to_node("fbm")
+        );
+        assert!(APTNode::str_to_node("fbm-worley").is_err());
+        assert_eq!(
+            APTNode::FBM(NoiseBasis::Value, mock::mock_params_fbm(true)).to_lisp(),
+            "( FBM-VALUE 1 2.1 3.1999998 4.2999997 5.3999996 6.4999995 7.5999994 )"
+        );
+        assert!(
+            APTNode::FBM(NoiseBasis::Perlin, mock::mock_params_fbm(true))
+                .to_glsl()
+                .ends_with(", EVO_PERLIN)")
+        );
+
+        // the basis survives a round trip, simplex is left out like before
+        for (source, basis) in [
+            ("( FBM X Y 0.5 0.5 0.5 0.5 )", NoiseBasis::Simplex),
+            ("( FBM-PERLIN X Y 0.5 0.5 0.5 0.5 )", NoiseBasis::Perlin),
+            ("( FBM-VALUE X Y 0.5 0.5 0.5 0.5 0.25 )", NoiseBasis::Value),
+        ] {
+            let sexpr = format!("( MONO CARTESIAN ( {} ) )", source);
+            let pic = lisp_to_pic(sexpr, CoordinateSystem::Polar).unwrap();
+            let tree = pic.to_tree()[0];
+            assert!(matches!(tree, APTNode::FBM(b, _) if *b == basis));
+            assert_eq!(tree.to_lisp(), source);
+        }
+    }
+
     #[test]
     fn test_aptnode_str_to_node() {
         assert_eq!(
@@ -998,14 +2102,18 @@ mod tests {
         );
         assert_eq!(
             APTNode::str_to_node("fbm"),
-            Ok(APTNode::FBM(vec![
-                APTNode::Empty,
-                APTNode::Empty,
-                APTNode::Empty,
-                APTNode::Empty,
-                APTNode::Empty,
-                APTNode::Empty
-            ]))
+            Ok(APTNode::FBM(
+                NoiseBasis::Simplex,
+                vec![
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty,
+                    APTNode::Empty
+                ]
+            ))
         );
         assert_eq!(
             APTNode::str_to_node("ridge"),
@@ -1050,6 +2158,14 @@ mod tests {
             APTNode::str_to_node("tan"),
             Ok(APTNode::Tan(mock::mock_params_tan(false)))
         );
+        assert_eq!(
+            APTNode::str_to_node("sinh"),
+            Ok(APTNode::Sinh(mock::mock_params_sinh(false)))
+        );
+        assert_eq!(
+            APTNode::str_to_node("tanh"),
+            Ok(APTNode::Tanh(mock::mock_params_tanh(false)))
+        );
         assert_eq!(
             APTNode::str_to_node("log"),
             Ok(APTNode::Log(mock::mock_params_log(false)))
@@ -1066,6 +2182,10 @@ mod tests {
             APTNode::str_to_node("ceil"),
             Ok(APTNode::Ceil(mock::mock_params_ceil(false)))
         );
+        assert_eq!(
+            APTNode::str_to_node("fract"),
+            Ok(APTNode::Fract(mock::mock_params_fract(false)))
+        );
         assert_eq!(
             APTNode::str_to_node("clamp"),
             Ok(APTNode::Clamp(mock::mock_params_clamp(false)))
@@ -1090,42 +2210,295 @@ mod tests {
             APTNode::str_to_node("mandelbrot"),
             Ok(APTNode::Mandelbrot(mock::mock_params_mandelbrot(false)))
         );
+        assert_eq!(
+            APTNode::str_to_node("warp"),
+            Ok(APTNode::Warp(mock::mock_params_warp(false)))
+        );
+        assert_eq!(
+            APTNode::str_to_node("smoothstep"),
+            Ok(APTNode::Smoothstep(mock::mock_params_smoothstep(false)))
+        );
+        assert_eq!(
+            APTNode::str_to_node("mix"),
+            Ok(APTNode::Mix(mock::mock_params_mix(false)))
+        );
+        assert_eq!(
+            APTNode::str_to_node("step"),
+            Ok(APTNode::Step(mock::mock_params_step(false)))
+        );
+        assert_eq!(
+            APTNode::str_to_node("voronoi"),
+            Ok(APTNode::Voronoi(mock::mock_params_voronoi(false)))
+        );
+        assert_eq!(
+            APTNode::str_to_node("ridgedfbm"),
+            Ok(APTNode::RidgedFBM(mock::mock_params_ridgedfbm(false)))
+        );
+        assert_eq!(
+            APTNode::str_to_node("dx"),
+            Ok(APTNode::DX(mock::mock_params_dx(false)))
+        );
+        assert_eq!(
+            APTNode::str_to_node("DY"),
+            Ok(APTNode::DY(mock::mock_params_dy(false)))
+        );
         assert_eq!(
             APTNode::str_to_node("PIC-eye.jpg"),
             Ok(APTNode::Picture(
                 "eye.jpg".to_string(),
+                Sampling::Bilinear,
                 mock::mock_params_picture(false)
             ))
         );
-        assert_eq!(APTNode::str_to_node("Width"), Ok(APTNode::Width));
-        assert_eq!(APTNode::str_to_node("Height"), Ok(APTNode::Height));
-        assert_eq!(APTNode::str_to_node("Pi"), Ok(APTNode::PI));
-        assert_eq!(APTNode::str_to_node("e"), Ok(APTNode::E));
-        assert_eq!(APTNode::str_to_node("x"), Ok(APTNode::X));
-        assert_eq!(APTNode::str_to_node("y"), Ok(APTNode::Y));
-        assert_eq!(APTNode::str_to_node("t"), Ok(APTNode::T));
         assert_eq!(
-            APTNode::str_to_node("pizza 60.0 \""),
-            Err("Unknown operation 'pizza 60.0 \"' ".to_string())
+            APTNode::str_to_node("PICN-eye.jpg"),
+            Ok(APTNode::Picture(
+                "eye.jpg".to_string(),
+                Sampling::Nearest,
+                mock::mock_params_picture(false)
+            ))
+        );
+        assert_eq!(APTNode::str_to_node("Width"), Ok(APTNode::Width));
+        assert_eq!(APTNode::str_to_node("Height"), Ok(APTNode::Height));
+        assert_eq!(APTNode::str_to_node("Pi"), Ok(APTNode::PI));
+        assert_eq!(APTNode::str_to_node("e"), Ok(APTNode::E));
+        assert_eq!(APTNode::str_to_node("x"), Ok(APTNode::X));
+        assert_eq!(APTNode::str_to_node("y"), Ok(APTNode::Y));
+        assert_eq!(APTNode::str_to_node("t"), Ok(APTNode::T));
+        assert_eq!(APTNode::str_to_node("TSin"), Ok(APTNode::TSin));
+        assert_eq!(APTNode::str_to_node("tcos"), Ok(APTNode::TCos));
+        assert_eq!(
+            APTNode::str_to_node("pizza 60.0 \""),
+            Err("Unknown operation 'pizza 60.0 \"' ".to_string())
+        );
+    }
+
+    #[test]
+    fn test_aptnode_add_leaf() {
+        let mut root = APTNode::Add(vec![APTNode::Empty, APTNode::Empty]);
+        assert_eq!(root.add_leaf(&APTNode::Constant(1.0)), true);
+        assert_eq!(root.add_leaf(&APTNode::Constant(2.1)), true);
+        assert_eq!(
+            root,
+            APTNode::Add(vec![APTNode::Constant(1.0), APTNode::Constant(2.1)])
+        );
+        assert_eq!(root.add_leaf(&APTNode::Constant(3.1)), false);
+        assert_eq!(
+            root,
+            APTNode::Add(vec![APTNode::Constant(1.0), APTNode::Constant(2.1)])
+        );
+    }
+
+    #[test]
+    fn test_aptnode_count_nodes() {
+        assert_eq!(APTNode::X.count_nodes(), 1);
+        assert_eq!(APTNode::Add(mock::mock_params_add(true)).count_nodes(), 3);
+        assert_eq!(
+            APTNode::Sin(vec![APTNode::FBM(
+                NoiseBasis::Simplex,
+                mock::mock_params_fbm(true)
+            )])
+            .count_nodes(),
+            8
+        );
+    }
+
+    #[test]
+    fn test_aptnode_depth() {
+        assert_eq!(APTNode::X.depth(), 1);
+        assert_eq!(APTNode::Add(mock::mock_params_add(true)).depth(), 2);
+        assert_eq!(
+            APTNode::Add(vec![
+                APTNode::X,
+                APTNode::Sin(vec![APTNode::FBM(
+                    NoiseBasis::Simplex,
+                    mock::mock_params_fbm(true)
+                )])
+            ])
+            .depth(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_aptnode_stack_depth() {
+        assert_eq!(APTNode::X.stack_depth(), 1);
+        assert_eq!(APTNode::Add(mock::mock_params_add(true)).stack_depth(), 2);
+        // the last child is evaluated first, its result waits for the others
+        let right = APTNode::Add(vec![
+            APTNode::X,
+            APTNode::Add(vec![APTNode::X, APTNode::Add(vec![APTNode::X, APTNode::Y])]),
+        ]);
+        assert_eq!(right.stack_depth(), 2);
+        let left = APTNode::Add(vec![
+            APTNode::Add(vec![APTNode::Add(vec![APTNode::X, APTNode::Y]), APTNode::X]),
+            APTNode::X,
+        ]);
+        assert_eq!(left.stack_depth(), 4);
+        assert_eq!(left.depth(), right.depth());
+        // the first result waits while the offsets of the second are pushed
+        assert_eq!(APTNode::DX(vec![APTNode::X]).stack_depth(), 3);
+        assert_eq!(APTNode::DY(vec![left.clone()]).stack_depth(), 5);
+        // the children of FBM all wait, none of them is deep
+        assert_eq!(
+            APTNode::FBM(NoiseBasis::Simplex, mock::mock_params_fbm(true)).stack_depth(),
+            7
         );
     }
 
     #[test]
-    fn test_aptnode_add_leaf() {
-        let mut root = APTNode::Add(vec![APTNode::Empty, APTNode::Empty]);
-        assert_eq!(root.add_leaf(&APTNode::Constant(1.0)), true);
-        assert_eq!(root.add_leaf(&APTNode::Constant(2.1)), true);
+    fn test_aptnode_count_operations() {
+        let mut histogram = BTreeMap::new();
+        APTNode::X.count_operations(&mut histogram);
+        assert!(histogram.is_empty());
+        APTNode::Add(vec![
+            APTNode::Sin(vec![APTNode::X]),
+            APTNode::Add(vec![APTNode::Sin(vec![APTNode::Y]), APTNode::T]),
+        ])
+        .count_operations(&mut histogram);
+        APTNode::Sin(vec![APTNode::Constant(0.5)]).count_operations(&mut histogram);
+        assert_eq!(
+            histogram,
+            BTreeMap::from([("+".to_string(), 2), ("SIN".to_string(), 3)])
+        );
+    }
+
+    #[test]
+    fn test_aptnode_get_subtree() {
+        let mut root = APTNode::Add(vec![
+            APTNode::Sin(vec![APTNode::X]),
+            APTNode::Mul(vec![APTNode::Y, APTNode::T]),
+        ]);
+        assert_eq!(root.get_subtree(0), Some(&root.clone()));
+        assert_eq!(root.get_subtree(1), Some(&APTNode::Sin(vec![APTNode::X])));
+        assert_eq!(root.get_subtree(2), Some(&APTNode::X));
         assert_eq!(
-            root,
-            APTNode::Add(vec![APTNode::Constant(1.0), APTNode::Constant(2.1)])
+            root.get_subtree(3),
+            Some(&APTNode::Mul(vec![APTNode::Y, APTNode::T]))
         );
-        assert_eq!(root.add_leaf(&APTNode::Constant(3.1)), false);
+        assert_eq!(root.get_subtree(4), Some(&APTNode::Y));
+        assert_eq!(root.get_subtree(5), Some(&APTNode::T));
+        assert_eq!(root.get_subtree(6), None);
+
+        *root.get_subtree_mut(4).unwrap() = APTNode::Constant(0.5);
         assert_eq!(
             root,
-            APTNode::Add(vec![APTNode::Constant(1.0), APTNode::Constant(2.1)])
+            APTNode::Add(vec![
+                APTNode::Sin(vec![APTNode::X]),
+                APTNode::Mul(vec![APTNode::Constant(0.5), APTNode::T]),
+            ])
+        );
+        assert_eq!(root.get_subtree_mut(6), None);
+    }
+
+    #[test]
+    fn test_aptnode_crossover() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let donor = APTNode::Mul(vec![APTNode::Y, APTNode::T]);
+        for _i in 0..100 {
+            let mut this_node = APTNode::Add(vec![APTNode::X, APTNode::Constant(0.5)]);
+            this_node.crossover(&donor, &mut rng);
+            let grafted = (0..this_node.count_nodes())
+                .map(|i| this_node.get_subtree(i).unwrap())
+                .any(|node| node == &APTNode::Y || node == &APTNode::T);
+            assert!(grafted);
+        }
+    }
+
+    #[test]
+    fn test_aptnode_mutate() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let original = APTNode::Add(vec![
+            APTNode::Sin(vec![APTNode::X]),
+            APTNode::Mul(vec![APTNode::Constant(0.5), APTNode::T]),
+        ]);
+        let mut this_node = original.clone();
+        this_node.mutate(&mut rng, 0.0, true, &vec![]);
+        assert_eq!(this_node, original);
+        for _i in 0..100 {
+            let mut this_node = original.clone();
+            this_node.mutate(&mut rng, 1.0, true, &vec![]);
+            assert_ne!(this_node, APTNode::Empty);
+            let nodes = (0..this_node.count_nodes()).map(|i| this_node.get_subtree(i).unwrap());
+            for node in nodes {
+                assert_ne!(node, &APTNode::Empty);
+                if let APTNode::Constant(v) = node {
+                    assert!(*v >= -1.0 && *v <= 1.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_aptnode_swap_operation() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        for _i in 0..100 {
+            let mut this_node = APTNode::Sin(vec![APTNode::X]);
+            if this_node.swap_operation(&mut rng, &vec![]) {
+                assert_eq!(this_node.get_children(), Some(&vec![APTNode::X]));
+            }
+            assert!(!APTNode::X.swap_operation(&mut rng, &vec![]));
+        }
+    }
+
+    #[test]
+    fn test_aptnode_is_constant() {
+        assert!(APTNode::Constant(0.5).is_constant());
+        assert!(APTNode::PI.is_constant());
+        assert!(APTNode::E.is_constant());
+        assert!(!APTNode::X.is_constant());
+        assert!(!APTNode::Width.is_constant());
+        assert!(!APTNode::Empty.is_constant());
+        assert!(APTNode::Add(vec![APTNode::PI, APTNode::Constant(0.5)]).is_constant());
+        assert!(!APTNode::Add(vec![APTNode::T, APTNode::Constant(0.5)]).is_constant());
+        assert!(!APTNode::Picture(
+            "eye.jpg".to_string(),
+            Sampling::Bilinear,
+            mock::mock_params_picture(true)
+        )
+        .is_constant());
+    }
+
+    #[test]
+    fn test_aptnode_seamless_time() {
+        let mut tree = APTNode::Sin(vec![APTNode::Add(vec![
+            APTNode::X,
+            APTNode::Mul(vec![APTNode::TCos, APTNode::T]),
+        ])]);
+        tree.seamless_time();
+        assert_eq!(tree.to_lisp(), "( SIN ( + X ( * TCOS TSIN ) ) )");
+        assert_eq!(time_sin(0.5), 1.0);
+        assert_eq!(time_sin(0.0), time_sin(2.0));
+        assert_eq!(time_cos(-1.0), time_cos(1.0));
+        assert_eq!(
+            APTNode::TCos.constant_fold::<Avx2>(
+                &CoordinateSystem::Polar,
+                mock::mock_pics(),
+                None,
+                None,
+                None,
+                None,
+                Some(1.0)
+            ),
+            APTNode::Constant(-1.0)
         );
     }
 
+    #[test]
+    fn test_aptnode_uses_t() {
+        assert!(APTNode::T.uses_t());
+        assert!(APTNode::TSin.uses_t());
+        assert!(APTNode::TCos.uses_t());
+        assert!(!APTNode::X.uses_t());
+        assert!(!APTNode::Constant(0.5).uses_t());
+        assert!(!APTNode::Add(vec![APTNode::X, APTNode::Y]).uses_t());
+        assert!(APTNode::Sin(vec![APTNode::Add(vec![
+            APTNode::X,
+            APTNode::Mul(vec![APTNode::Y, APTNode::T])
+        ])])
+        .uses_t());
+    }
+
     #[test]
     fn test_aptnode_constant_eval() {
         let pics = mock::mock_pics();
@@ -1182,14 +2555,18 @@ mod tests {
             9.803922
         );
         assert_eq!(
-            APTNode::FBM(vec![
-                APTNode::Constant(0.0),
-                APTNode::Constant(1.1),
-                APTNode::Constant(2.2),
-                APTNode::Constant(3.3),
-                APTNode::Constant(4.4),
-                APTNode::Constant(5.5)
-            ])
+            APTNode::FBM(
+                NoiseBasis::Simplex,
+                vec![
+                    APTNode::Constant(0.0),
+                    APTNode::Constant(1.1),
+                    APTNode::Constant(2.2),
+                    APTNode::Constant(3.3),
+                    APTNode::Constant(4.4),
+                    APTNode::Constant(5.5),
+                    APTNode::Constant(0.375)
+                ]
+            )
             .constant_eval::<Avx2>(
                 &CoordinateSystem::Polar,
                 pics.clone(),
@@ -1307,7 +2684,31 @@ mod tests {
                 None,
                 None
             ),
-            -22877334.0
+            -1.0
+        );
+        assert_eq!(
+            APTNode::Sinh(mock::mock_params_sinh(true)).constant_eval::<Avx2>(
+                &CoordinateSystem::Polar,
+                pics.clone(),
+                None,
+                None,
+                None,
+                None,
+                None
+            ),
+            1.1752012
+        );
+        assert_eq!(
+            APTNode::Tanh(mock::mock_params_tanh(true)).constant_eval::<Avx2>(
+                &CoordinateSystem::Polar,
+                pics.clone(),
+                None,
+                None,
+                None,
+                None,
+                None
+            ),
+            0.7615942
         );
         assert_eq!(
             APTNode::Log(mock::mock_params_log(true)).constant_eval::<Avx2>(
@@ -1429,6 +2830,30 @@ mod tests {
             ),
             -10000.0
         );
+        assert_eq!(
+            APTNode::Fract(vec![APTNode::Constant(10000.25)]).constant_eval::<Avx2>(
+                &CoordinateSystem::Polar,
+                pics.clone(),
+                None,
+                None,
+                None,
+                None,
+                None
+            ),
+            0.25
+        );
+        assert_eq!(
+            APTNode::Fract(vec![APTNode::Constant(-0.25)]).constant_eval::<Avx2>(
+                &CoordinateSystem::Polar,
+                pics.clone(),
+                None,
+                None,
+                None,
+                None,
+                None
+            ),
+            0.75
+        );
         assert_eq!(
             APTNode::Clamp(mock::mock_params_clamp(true)).constant_eval::<Avx2>(
                 &CoordinateSystem::Polar,
@@ -1442,7 +2867,12 @@ mod tests {
             1.0
         );
         assert_eq!(
-            APTNode::Clamp(vec![APTNode::Constant(10000.5)]).constant_eval::<Avx2>(
+            APTNode::Clamp(vec![
+                APTNode::Constant(10000.5),
+                APTNode::Constant(-1.0),
+                APTNode::Constant(1.0),
+            ])
+            .constant_eval::<Avx2>(
                 &CoordinateSystem::Polar,
                 pics.clone(),
                 None,
@@ -1454,7 +2884,12 @@ mod tests {
             1.0
         );
         assert_eq!(
-            APTNode::Clamp(vec![APTNode::Constant(1.0)]).constant_eval::<Avx2>(
+            APTNode::Clamp(vec![
+                APTNode::Constant(1.0),
+                APTNode::Constant(-1.0),
+                APTNode::Constant(1.0),
+            ])
+            .constant_eval::<Avx2>(
                 &CoordinateSystem::Polar,
                 pics.clone(),
                 None,
@@ -1466,7 +2901,12 @@ mod tests {
             1.0
         );
         assert_eq!(
-            APTNode::Clamp(vec![APTNode::Constant(0.8)]).constant_eval::<Avx2>(
+            APTNode::Clamp(vec![
+                APTNode::Constant(0.8),
+                APTNode::Constant(-1.0),
+                APTNode::Constant(1.0),
+            ])
+            .constant_eval::<Avx2>(
                 &CoordinateSystem::Polar,
                 pics.clone(),
                 None,
@@ -1478,7 +2918,12 @@ mod tests {
             0.8
         );
         assert_eq!(
-            APTNode::Clamp(vec![APTNode::Constant(-0.8)]).constant_eval::<Avx2>(
+            APTNode::Clamp(vec![
+                APTNode::Constant(-0.8),
+                APTNode::Constant(-1.0),
+                APTNode::Constant(1.0),
+            ])
+            .constant_eval::<Avx2>(
                 &CoordinateSystem::Polar,
                 pics.clone(),
                 None,
@@ -1490,7 +2935,12 @@ mod tests {
             -0.8
         );
         assert_eq!(
-            APTNode::Clamp(vec![APTNode::Constant(-1.0)]).constant_eval::<Avx2>(
+            APTNode::Clamp(vec![
+                APTNode::Constant(-1.0),
+                APTNode::Constant(-1.0),
+                APTNode::Constant(1.0),
+            ])
+            .constant_eval::<Avx2>(
                 &CoordinateSystem::Polar,
                 pics.clone(),
                 None,
@@ -1502,7 +2952,12 @@ mod tests {
             -1.0
         );
         assert_eq!(
-            APTNode::Clamp(vec![APTNode::Constant(-10000.5)]).constant_eval::<Avx2>(
+            APTNode::Clamp(vec![
+                APTNode::Constant(-10000.5),
+                APTNode::Constant(-1.0),
+                APTNode::Constant(1.0),
+            ])
+            .constant_eval::<Avx2>(
                 &CoordinateSystem::Polar,
                 pics.clone(),
                 None,
@@ -1696,6 +3151,20 @@ mod tests {
                 ),
             1.0
         );
+        // a zero divisor keeps the dividend
+        assert_eq!(
+            APTNode::Mod(vec![APTNode::Constant(0.0), APTNode::Constant(0.75)])
+                .constant_eval::<Avx2>(
+                    &CoordinateSystem::Polar,
+                    pics.clone(),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None
+                ),
+            0.75
+        );
         assert_eq!(
             APTNode::Mandelbrot(mock::mock_params_mandelbrot(true)).constant_eval::<Avx2>(
                 &CoordinateSystem::Polar,
@@ -1778,6 +3247,69 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_aptnode_constant_eval_mix() {
+        let pics = mock::mock_pics();
+        // a, b, t and the result, t is clamped to 0.0 .. 1.0
+        for (a, b, t, expected) in [
+            (-1.0, 1.0, 0.25, -0.5),
+            (0.2, 0.8, 0.5, 0.5),
+            (-1.0, 1.0, 0.0, -1.0),
+            (-1.0, 1.0, 1.0, 1.0),
+            (-1.0, 1.0, -3.0, -1.0),
+            (-1.0, 1.0, 3.0, 1.0),
+        ] {
+            let node = APTNode::Mix(vec![
+                APTNode::Constant(a),
+                APTNode::Constant(b),
+                APTNode::Constant(t),
+            ]);
+            assert_eq!(
+                node.constant_eval::<Avx2>(
+                    &CoordinateSystem::Polar,
+                    pics.clone(),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None
+                ),
+                expected,
+                "{}",
+                node.to_lisp()
+            );
+        }
+    }
+
+    #[test]
+    fn test_aptnode_constant_eval_step() {
+        let pics = mock::mock_pics();
+        // edge, x and the result
+        for (edge, x, expected) in [
+            (0.0, -0.5, -1.0),
+            (0.0, 0.5, 1.0),
+            (0.25, 0.25, 1.0),
+            (0.25, 0.2499, -1.0),
+            (-2.0, -1.0, 1.0),
+        ] {
+            let node = APTNode::Step(vec![APTNode::Constant(edge), APTNode::Constant(x)]);
+            assert_eq!(
+                node.constant_eval::<Avx2>(
+                    &CoordinateSystem::Polar,
+                    pics.clone(),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None
+                ),
+                expected,
+                "{}",
+                node.to_lisp()
+            );
+        }
+    }
+
     #[should_panic(expected = "invalid node passed to constant_esval")]
     #[test]
     fn test_aptnode_constant_eval_width() {
@@ -1868,6 +3400,75 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_aptnode_constant_eval_clamp() {
+        let pics = mock::mock_pics();
+        // value, lo, hi and the result, with lo above hi the result is hi
+        for (v, lo, hi, expected) in [
+            (0.1, 0.0, 0.25, 0.1),
+            (0.5, 0.0, 0.25, 0.25),
+            (-0.5, 0.0, 0.25, 0.0),
+            (3.0, -2.0, 2.0, 2.0),
+            (0.5, 0.75, 0.25, 0.25),
+        ] {
+            let node = APTNode::Clamp(vec![
+                APTNode::Constant(v),
+                APTNode::Constant(lo),
+                APTNode::Constant(hi),
+            ]);
+            assert_eq!(
+                node.constant_eval::<Avx2>(
+                    &CoordinateSystem::Polar,
+                    pics.clone(),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None
+                ),
+                expected,
+                "{}",
+                node.to_lisp()
+            );
+        }
+    }
+
+    #[test]
+    fn test_aptnode_constant_eval_smoothstep() {
+        let pics = mock::mock_pics();
+        // edge0, edge1, x and the result, 0.0 .. 1.0 of the curve is scaled to -1.0 .. 1.0
+        for (edge0, edge1, x, expected) in [
+            (0.0, 1.0, 0.5, 0.0),
+            (0.0, 1.0, 0.25, -0.6875),
+            (0.0, 1.0, -1.0, -1.0),
+            (0.0, 1.0, 2.0, 1.0),
+            // equal edges are a step at the edge
+            (0.5, 0.5, 0.7, 1.0),
+            (0.5, 0.5, 0.2, -1.0),
+            (0.5, 0.5, 0.5, -1.0),
+            // reversed edges ramp down
+            (1.0, 0.0, 0.25, 0.6875),
+            (1.0, 0.0, 2.0, -1.0),
+        ] {
+            let node = APTNode::Smoothstep(vec![
+                APTNode::Constant(edge0),
+                APTNode::Constant(edge1),
+                APTNode::Constant(x),
+            ]);
+            let v = node.constant_eval::<Avx2>(
+                &CoordinateSystem::Polar,
+                pics.clone(),
+                None,
+                None,
+                None,
+                None,
+                None,
+            );
+            assert!(!v.is_nan(), "{}", node.to_lisp());
+            assert_eq!(v, expected, "{}", node.to_lisp());
+        }
+    }
+
     #[test]
     fn test_aptnode_constant_set_children() {
         //@todo: check that the vector lengths are correct for each of the enums
@@ -1892,14 +3493,12 @@ mod tests {
             APTNode::Atan(mock::mock_params_atan(true))
         );
 
-        if false {
-            assert_eq!(
-                APTNode::str_to_node("atan2")
-                    .unwrap()
-                    .set_children(mock::mock_params_atan2(true)),
-                APTNode::Atan2(mock::mock_params_atan2(true))
-            );
-        }
+        assert_eq!(
+            APTNode::str_to_node("atan2")
+                .unwrap()
+                .set_children(mock::mock_params_atan2(true)),
+            APTNode::Atan2(mock::mock_params_atan2(true))
+        );
 
         assert_eq!(
             APTNode::str_to_node("ceil")
@@ -1908,6 +3507,13 @@ mod tests {
             APTNode::Ceil(mock::mock_params_ceil(true))
         );
 
+        assert_eq!(
+            APTNode::str_to_node("fract")
+                .unwrap()
+                .set_children(mock::mock_params_fract(true)),
+            APTNode::Fract(mock::mock_params_fract(true))
+        );
+
         assert_eq!(
             APTNode::str_to_node("cell1")
                 .unwrap()
@@ -1950,16 +3556,21 @@ mod tests {
                 APTNode::Constant(2.2),
                 APTNode::Constant(3.3),
                 APTNode::Constant(4.4),
-                APTNode::Constant(5.5)
+                APTNode::Constant(5.5),
+                APTNode::Constant(0.375)
             ]),
-            APTNode::FBM(vec![
-                APTNode::Constant(0.0),
-                APTNode::Constant(1.1),
-                APTNode::Constant(2.2),
-                APTNode::Constant(3.3),
-                APTNode::Constant(4.4),
-                APTNode::Constant(5.5)
-            ])
+            APTNode::FBM(
+                NoiseBasis::Simplex,
+                vec![
+                    APTNode::Constant(0.0),
+                    APTNode::Constant(1.1),
+                    APTNode::Constant(2.2),
+                    APTNode::Constant(3.3),
+                    APTNode::Constant(4.4),
+                    APTNode::Constant(5.5),
+                    APTNode::Constant(0.375)
+                ]
+            )
         );
 
         assert_eq!(
@@ -1977,9 +3588,51 @@ mod tests {
         );
 
         assert_eq!(
-            APTNode::Mandelbrot(vec![APTNode::Constant(1.0), APTNode::Constant(2.1)])
-                .set_children(mock::mock_params_mandelbrot(true)),
-            APTNode::Mandelbrot(mock::mock_params_mandelbrot(true))
+            APTNode::Mandelbrot(vec![APTNode::Constant(1.0), APTNode::Constant(2.1)])
+                .set_children(mock::mock_params_mandelbrot(true)),
+            APTNode::Mandelbrot(mock::mock_params_mandelbrot(true))
+        );
+
+        assert_eq!(
+            APTNode::str_to_node("warp")
+                .unwrap()
+                .set_children(mock::mock_params_warp(true)),
+            APTNode::Warp(mock::mock_params_warp(true))
+        );
+
+        assert_eq!(
+            APTNode::str_to_node("smoothstep")
+                .unwrap()
+                .set_children(mock::mock_params_smoothstep(true)),
+            APTNode::Smoothstep(mock::mock_params_smoothstep(true))
+        );
+
+        assert_eq!(
+            APTNode::str_to_node("mix")
+                .unwrap()
+                .set_children(mock::mock_params_mix(true)),
+            APTNode::Mix(mock::mock_params_mix(true))
+        );
+
+        assert_eq!(
+            APTNode::str_to_node("step")
+                .unwrap()
+                .set_children(mock::mock_params_step(true)),
+            APTNode::Step(mock::mock_params_step(true))
+        );
+
+        assert_eq!(
+            APTNode::str_to_node("voronoi")
+                .unwrap()
+                .set_children(mock::mock_params_voronoi(true)),
+            APTNode::Voronoi(mock::mock_params_voronoi(true))
+        );
+
+        assert_eq!(
+            APTNode::str_to_node("ridgedfbm")
+                .unwrap()
+                .set_children(mock::mock_params_ridgedfbm(true)),
+            APTNode::RidgedFBM(mock::mock_params_ridgedfbm(true))
         );
 
         assert_eq!(
@@ -2014,12 +3667,17 @@ mod tests {
             APTNode::str_to_node("pic-eye.jpg")
                 .unwrap()
                 .set_children(vec![
-                    APTNode::Picture("cow.jpg".to_string(), mock::mock_params_picture(true)),
+                    APTNode::Picture(
+                        "cow.jpg".to_string(),
+                        Sampling::Bilinear,
+                        mock::mock_params_picture(true)
+                    ),
                     APTNode::Constant(333.0),
                     APTNode::Constant(444.0)
                 ]),
             APTNode::Picture(
                 "eye.jpg".to_string(),
+                Sampling::Bilinear,
                 vec![APTNode::Constant(333.0), APTNode::Constant(444.0)]
             )
         );
@@ -2066,6 +3724,20 @@ mod tests {
             APTNode::Tan(mock::mock_params_tan(true))
         );
 
+        assert_eq!(
+            APTNode::str_to_node("sinh")
+                .unwrap()
+                .set_children(mock::mock_params_sinh(true)),
+            APTNode::Sinh(mock::mock_params_sinh(true))
+        );
+
+        assert_eq!(
+            APTNode::str_to_node("tanh")
+                .unwrap()
+                .set_children(mock::mock_params_tanh(true)),
+            APTNode::Tanh(mock::mock_params_tanh(true))
+        );
+
         assert_eq!(
             APTNode::str_to_node("turbulence")
                 .unwrap()
@@ -2250,6 +3922,84 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_aptnode_constant_fold_warp() {
+        let pics = mock::mock_pics();
+        // the offsets are folded, the input keeps X and Y as it is sampled at the warped position
+        assert_eq!(
+            APTNode::Warp(vec![
+                APTNode::Mul(vec![APTNode::X, APTNode::Constant(2.0)]),
+                APTNode::Constant(0.5),
+                APTNode::Add(vec![APTNode::X, APTNode::Y]),
+            ])
+            .constant_fold::<Avx2>(
+                &CoordinateSystem::Cartesian,
+                pics.clone(),
+                Some(12),
+                Some(20),
+                None,
+                None,
+                None,
+            ),
+            APTNode::Warp(vec![
+                APTNode::Constant(24.0),
+                APTNode::Constant(0.5),
+                APTNode::Add(vec![APTNode::X, APTNode::Y]),
+            ])
+        );
+        assert_eq!(
+            APTNode::Warp(vec![
+                APTNode::X,
+                APTNode::Y,
+                APTNode::Add(vec![APTNode::PI, APTNode::Constant(1.0)]),
+            ])
+            .constant_fold::<Avx2>(
+                &CoordinateSystem::Cartesian,
+                pics.clone(),
+                Some(12),
+                Some(20),
+                None,
+                None,
+                None,
+            ),
+            APTNode::Constant(std::f32::consts::PI + 1.0)
+        );
+    }
+
+    #[test]
+    fn test_aptnode_constant_fold_derivative() {
+        let pics = mock::mock_pics();
+        // X changes around the position even when the position is known
+        assert_eq!(
+            APTNode::DX(vec![APTNode::X]).constant_fold::<Avx2>(
+                &CoordinateSystem::Cartesian,
+                pics.clone(),
+                Some(12),
+                Some(20),
+                None,
+                None,
+                None,
+            ),
+            APTNode::DX(vec![APTNode::X])
+        );
+        assert_eq!(
+            APTNode::DY(vec![APTNode::Add(vec![
+                APTNode::Constant(0.5),
+                APTNode::Constant(0.25)
+            ])])
+            .constant_fold::<Avx2>(
+                &CoordinateSystem::Cartesian,
+                pics.clone(),
+                None,
+                None,
+                None,
+                None,
+                None,
+            ),
+            APTNode::Constant(0.0)
+        );
+    }
+
     #[test]
     fn test_aptnode_get_children_mut() {
         assert_eq!(
@@ -2281,11 +4031,14 @@ mod tests {
             2
         );
         assert_eq!(
-            APTNode::FBM(vec![
-                APTNode::Constant(0.0),
-                APTNode::Constant(1.1),
-                APTNode::Constant(2.2)
-            ])
+            APTNode::FBM(
+                NoiseBasis::Simplex,
+                vec![
+                    APTNode::Constant(0.0),
+                    APTNode::Constant(1.1),
+                    APTNode::Constant(2.2)
+                ]
+            )
             .get_children_mut()
             .unwrap()
             .len(),
@@ -2354,6 +4107,20 @@ mod tests {
                 .len(),
             1
         );
+        assert_eq!(
+            APTNode::Sinh(mock::mock_params_sinh(true))
+                .get_children_mut()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            APTNode::Tanh(mock::mock_params_tanh(true))
+                .get_children_mut()
+                .unwrap()
+                .len(),
+            1
+        );
         assert_eq!(
             APTNode::Log(mock::mock_params_log(true))
                 .get_children_mut()
@@ -2383,12 +4150,19 @@ mod tests {
             1
         );
         assert_eq!(
-            APTNode::Clamp(mock::mock_params_clamp(true))
+            APTNode::Fract(mock::mock_params_fract(true))
                 .get_children_mut()
                 .unwrap()
                 .len(),
             1
         );
+        assert_eq!(
+            APTNode::Clamp(mock::mock_params_clamp(true))
+                .get_children_mut()
+                .unwrap()
+                .len(),
+            3
+        );
         assert_eq!(
             APTNode::Wrap(mock::mock_params_wrap(true))
                 .get_children_mut()
@@ -2432,10 +4206,56 @@ mod tests {
             2
         );
         assert_eq!(
-            APTNode::Picture("eye.jpg".to_string(), mock::mock_params_picture(true))
+            APTNode::Warp(mock::mock_params_warp(true))
+                .get_children_mut()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            APTNode::Smoothstep(mock::mock_params_smoothstep(true))
+                .get_children_mut()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            APTNode::Mix(mock::mock_params_mix(true))
+                .get_children_mut()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            APTNode::Step(mock::mock_params_step(true))
+                .get_children_mut()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            APTNode::Voronoi(mock::mock_params_voronoi(true))
+                .get_children_mut()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            APTNode::RidgedFBM(mock::mock_params_ridgedfbm(true))
                 .get_children_mut()
                 .unwrap()
                 .len(),
+            6
+        );
+        assert_eq!(
+            APTNode::Picture(
+                "eye.jpg".to_string(),
+                Sampling::Bilinear,
+                mock::mock_params_picture(true)
+            )
+            .get_children_mut()
+            .unwrap()
+            .len(),
             2
         );
         assert_eq!(APTNode::Constant(1.2).get_children_mut(), None);
@@ -2480,11 +4300,14 @@ mod tests {
             2
         );
         assert_eq!(
-            APTNode::FBM(vec![
-                APTNode::Constant(0.0),
-                APTNode::Constant(1.1),
-                APTNode::Constant(2.2)
-            ])
+            APTNode::FBM(
+                NoiseBasis::Simplex,
+                vec![
+                    APTNode::Constant(0.0),
+                    APTNode::Constant(1.1),
+                    APTNode::Constant(2.2)
+                ]
+            )
             .get_children()
             .unwrap()
             .len(),
@@ -2553,6 +4376,20 @@ mod tests {
                 .len(),
             1
         );
+        assert_eq!(
+            APTNode::Sinh(mock::mock_params_sinh(true))
+                .get_children()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            APTNode::Tanh(mock::mock_params_tanh(true))
+                .get_children()
+                .unwrap()
+                .len(),
+            1
+        );
         assert_eq!(
             APTNode::Log(mock::mock_params_log(true))
                 .get_children()
@@ -2582,12 +4419,19 @@ mod tests {
             1
         );
         assert_eq!(
-            APTNode::Clamp(mock::mock_params_clamp(true))
+            APTNode::Fract(mock::mock_params_fract(true))
                 .get_children()
                 .unwrap()
                 .len(),
             1
         );
+        assert_eq!(
+            APTNode::Clamp(mock::mock_params_clamp(true))
+                .get_children()
+                .unwrap()
+                .len(),
+            3
+        );
         assert_eq!(
             APTNode::Wrap(mock::mock_params_wrap(true))
                 .get_children()
@@ -2631,10 +4475,56 @@ mod tests {
             2
         );
         assert_eq!(
-            APTNode::Picture("eye.jpg".to_string(), mock::mock_params_picture(true))
+            APTNode::Warp(mock::mock_params_warp(true))
+                .get_children()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            APTNode::Smoothstep(mock::mock_params_smoothstep(true))
+                .get_children()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            APTNode::Mix(mock::mock_params_mix(true))
+                .get_children()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            APTNode::Step(mock::mock_params_step(true))
+                .get_children()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            APTNode::Voronoi(mock::mock_params_voronoi(true))
+                .get_children()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            APTNode::RidgedFBM(mock::mock_params_ridgedfbm(true))
                 .get_children()
                 .unwrap()
                 .len(),
+            6
+        );
+        assert_eq!(
+            APTNode::Picture(
+                "eye.jpg".to_string(),
+                Sampling::Bilinear,
+                mock::mock_params_picture(true)
+            )
+            .get_children()
+            .unwrap()
+            .len(),
             2
         );
         assert_eq!(APTNode::Constant(1.2).get_children(), None);
@@ -2667,11 +4557,14 @@ mod tests {
             false
         );
         assert_eq!(
-            APTNode::FBM(vec![
-                APTNode::Constant(0.0),
-                APTNode::Constant(1.1),
-                APTNode::Constant(2.2)
-            ])
+            APTNode::FBM(
+                NoiseBasis::Simplex,
+                vec![
+                    APTNode::Constant(0.0),
+                    APTNode::Constant(1.1),
+                    APTNode::Constant(2.2)
+                ]
+            )
             .is_leaf(),
             false
         );
@@ -2699,6 +4592,8 @@ mod tests {
             false
         );
         assert_eq!(APTNode::Tan(mock::mock_params_tan(true)).is_leaf(), false);
+        assert_eq!(APTNode::Sinh(mock::mock_params_sinh(true)).is_leaf(), false);
+        assert_eq!(APTNode::Tanh(mock::mock_params_tanh(true)).is_leaf(), false);
         assert_eq!(APTNode::Log(mock::mock_params_log(true)).is_leaf(), false);
         assert_eq!(APTNode::Abs(mock::mock_params_abs(true)).is_leaf(), false);
         assert_eq!(
@@ -2706,6 +4601,10 @@ mod tests {
             false
         );
         assert_eq!(APTNode::Ceil(mock::mock_params_ceil(true)).is_leaf(), false);
+        assert_eq!(
+            APTNode::Fract(mock::mock_params_fract(true)).is_leaf(),
+            false
+        );
         assert_eq!(
             APTNode::Clamp(mock::mock_params_clamp(true)).is_leaf(),
             false
@@ -2722,8 +4621,28 @@ mod tests {
             APTNode::Mandelbrot(mock::mock_params_mandelbrot(true)).is_leaf(),
             false
         );
+        assert_eq!(APTNode::Warp(mock::mock_params_warp(true)).is_leaf(), false);
+        assert_eq!(APTNode::Mix(mock::mock_params_mix(true)).is_leaf(), false);
+        assert_eq!(APTNode::Step(mock::mock_params_step(true)).is_leaf(), false);
+        assert_eq!(
+            APTNode::Smoothstep(mock::mock_params_smoothstep(true)).is_leaf(),
+            false
+        );
+        assert_eq!(
+            APTNode::Voronoi(mock::mock_params_voronoi(true)).is_leaf(),
+            false
+        );
+        assert_eq!(
+            APTNode::RidgedFBM(mock::mock_params_ridgedfbm(true)).is_leaf(),
+            false
+        );
         assert_eq!(
-            APTNode::Picture("eye.jpg".to_string(), mock::mock_params_picture(true)).is_leaf(),
+            APTNode::Picture(
+                "eye.jpg".to_string(),
+                Sampling::Bilinear,
+                mock::mock_params_picture(true)
+            )
+            .is_leaf(),
             false
         );
         assert_eq!(APTNode::Constant(1.2).is_leaf(), true);
@@ -2753,6 +4672,101 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_aptnode_pick_weighted_node() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let name = "eye.jpg".to_string();
+        let pic_names = vec![&name];
+        let none = OperatorWeights {
+            add: 0.0,
+            sub: 0.0,
+            mul: 0.0,
+            div: 0.0,
+            modulo: 0.0,
+            fbm: 0.0,
+            ridge: 0.0,
+            turbulence: 0.0,
+            cell1: 0.0,
+            cell2: 0.0,
+            sqrt: 0.0,
+            sin: 0.0,
+            atan: 0.0,
+            atan2: 0.0,
+            tan: 0.0,
+            sinh: 0.0,
+            tanh: 0.0,
+            log: 0.0,
+            abs: 0.0,
+            floor: 0.0,
+            ceil: 0.0,
+            fract: 0.0,
+            clamp: 0.0,
+            wrap: 0.0,
+            square: 0.0,
+            max: 0.0,
+            min: 0.0,
+            mandelbrot: 0.0,
+            warp: 0.0,
+            smoothstep: 0.0,
+            mix: 0.0,
+            step: 0.0,
+            voronoi: 0.0,
+            ridged_fbm: 0.0,
+            dx: 0.0,
+            dy: 0.0,
+            picture: 0.0,
+            x: 0.0,
+            y: 0.0,
+            t: 0.0,
+            constant: 0.0,
+        };
+        let only_fbm = OperatorWeights {
+            fbm: 1.0,
+            ..none.clone()
+        };
+        let only_pictures = OperatorWeights {
+            picture: 1.0,
+            sin: 1.0,
+            ..none.clone()
+        };
+        for _i in 0..100 {
+            // the basis of the noise is random
+            match APTNode::pick_weighted_node(&mut rng, &pic_names, &only_fbm) {
+                APTNode::FBM(_, children) => assert_eq!(children, mock::mock_params_fbm(false)),
+                node => panic!("not an FBM: {:?}", node),
+            }
+            // without pictures, the weight of the picture is ignored
+            assert_eq!(
+                APTNode::pick_weighted_node(&mut rng, &vec![], &only_pictures),
+                APTNode::Sin(mock::mock_params_sin(false))
+            );
+            // all weights 0.0 falls back to a uniform pick
+            assert!(!APTNode::pick_weighted_node(&mut rng, &pic_names, &none).is_leaf());
+        }
+    }
+
+    #[test]
+    fn test_aptnode_pick_weighted_leaf() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let only_t = OperatorWeights {
+            x: 0.0,
+            y: 0.0,
+            constant: 0.0,
+            ..OperatorWeights::uniform()
+        };
+        for _i in 0..100 {
+            assert_eq!(
+                APTNode::pick_weighted_leaf(&mut rng, true, &only_t),
+                APTNode::T
+            );
+            // T is only for videos, so this falls back to a uniform pick
+            assert_ne!(
+                APTNode::pick_weighted_leaf(&mut rng, false, &only_t),
+                APTNode::T
+            );
+        }
+    }
+
     #[test]
     fn test_aptnode_pick_random_leaf() {
         let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
@@ -2796,6 +4810,47 @@ mod tests {
         this_node.add_random(that_node, &mut rng);
     }
 
+    #[test]
+    fn test_aptnode_add_random_within() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let mut this_node = APTNode::Sin(vec![APTNode::Empty]);
+        // Sin, Sqrt and the leaf of Sqrt make 3 levels
+        assert!(!this_node.add_random_within(APTNode::Sqrt(vec![APTNode::Empty]), &mut rng, 2));
+        assert!(this_node.add_random_within(APTNode::Sqrt(vec![APTNode::Empty]), &mut rng, 3));
+        assert_eq!(
+            this_node,
+            APTNode::Sin(vec![APTNode::Sqrt(vec![APTNode::Empty])])
+        );
+        assert!(!this_node.add_random_within(APTNode::Abs(vec![APTNode::Empty]), &mut rng, 3));
+        assert!(!APTNode::X.add_random_within(APTNode::Abs(vec![APTNode::Empty]), &mut rng, 9));
+    }
+
+    #[test]
+    fn test_aptnode_create_random_tree_budget() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let name = "eye.jpg".to_string();
+        let pic_names = vec![&name];
+        let weights = OperatorWeights::uniform();
+        for _i in 0..1000 {
+            let (tree, _coord) =
+                APTNode::create_random_tree(40, true, &mut rng, &pic_names, &weights, 5, 30);
+            assert!(tree.depth() <= 5, "too deep: {}", tree.to_lisp());
+            assert!(tree.count_nodes() <= 30, "too large: {}", tree.to_lisp());
+            assert!(!tree.to_lisp().contains("EMPTY"));
+        }
+    }
+
+    #[test]
+    fn test_aptnode_create_random_tree_scripted() {
+        // always 0, so every choice is the first one: ADD for the operations, X for the leaves
+        // and a shuffle that tries the last child first
+        let mut rng = StepRng::new(0, 0);
+        let weights = OperatorWeights::uniform();
+        let (tree, coord) = APTNode::create_random_tree(3, false, &mut rng, &[], &weights, 5, 30);
+        assert_eq!(coord, CoordinateSystem::Polar);
+        assert_eq!(tree.to_lisp(), "( + X ( + X ( + X X ) ) )");
+    }
+
     #[test]
     #[ignore] // findout what is wrong here
     fn test_aptnode_add_random() {
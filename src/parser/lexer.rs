@@ -1,13 +1,20 @@
 use std::sync::mpsc::{channel, Receiver, Sender};
 
+use crate::constants::PIC_MAX_STACK_DEPTH;
 use crate::parser::aptnode::APTNode;
+use crate::parser::parseerror::ParseError;
 use crate::parser::token::Token;
 use crate::pic::coordinatesystem::CoordinateSystem;
+use crate::pic::data::cmyk::CMYKData;
 use crate::pic::data::gradient::GradientData;
 use crate::pic::data::grayscale::GrayscaleData;
 use crate::pic::data::hsv::HSVData;
+use crate::pic::data::lab::LABData;
 use crate::pic::data::mono::MonoData;
 use crate::pic::data::rgb::RGBData;
+use crate::pic::data::rgba::RGBAData;
+use crate::pic::gradientwrap::GradientWrap;
+use crate::pic::normalization::Normalization;
 use crate::pic::color::Color;
 use crate::pic::pic::Pic;
 
@@ -48,11 +55,11 @@ impl<'a> Lexer<'a> {
             self.width = 0;
             None
         } else {
-            self.width = 1; // Assuming one always for now
             let c = self.input[self.pos..]
                 .chars()
                 .next()
                 .expect("unexpected end of input");
+            self.width = c.len_utf8();
             if Lexer::is_linebreak(c) {
                 self.current_line += 1;
             }
@@ -62,7 +69,8 @@ impl<'a> Lexer<'a> {
     }
 
     fn backup(&mut self) {
-        self.pos -= 1;
+        // at the end of the input next did not move, width is 0 then
+        self.pos -= self.width;
     }
 
     fn ignore(&mut self) {
@@ -71,7 +79,8 @@ impl<'a> Lexer<'a> {
 
     fn emit(&mut self, token: Token<'a>) {
         // println!("token:{:?}", token);
-        self.token_sender.send(token).expect("token send failure");
+        // the parser hangs up on the first error, the remaining tokens are of no use then
+        let _ = self.token_sender.send(token);
         self.start = self.pos;
     }
 
@@ -147,7 +156,7 @@ impl<'a> Lexer<'a> {
     }
 
     fn is_white_space(c: char) -> bool {
-        c == ' ' || c == '\n' || c == '\t' || c == '\r'
+        c.is_whitespace()
     }
 
     fn is_linebreak(c: char) -> bool {
@@ -155,7 +164,7 @@ impl<'a> Lexer<'a> {
     }
 }
 
-pub fn lisp_to_pic(code: String, coord: CoordinateSystem) -> Result<Pic, String> {
+pub fn lisp_to_pic(code: String, coord: CoordinateSystem) -> Result<Pic, ParseError> {
     let mut pic_opt = None;
     rayon::scope(|s| {
         let (sender, receiver) = channel();
@@ -168,71 +177,56 @@ pub fn lisp_to_pic(code: String, coord: CoordinateSystem) -> Result<Pic, String>
 
         pic_opt = Some(parse_pic(&receiver, coord))
     });
-    pic_opt.unwrap()
+    let pic = pic_opt.unwrap()?;
+    pic.check_stack_depth(PIC_MAX_STACK_DEPTH)?;
+    Ok(pic)
+}
+
+/// The error for `token`, found where `expected` should have been
+pub fn unexpected_token(expected: &str, token: &Token) -> ParseError {
+    let found = match token {
+        Token::OpenParen(_) => "(",
+        Token::CloseParen(_) => ")",
+        Token::Operation(s, _) | Token::Constant(s, _) => *s,
+    };
+    ParseError::UnexpectedToken {
+        expected: expected.to_string(),
+        found: found.to_string(),
+        pos: extract_line_number(token),
+    }
 }
 
 #[must_use]
-pub fn expect_open_paren(receiver: &Receiver<Token>) -> Result<(), String> {
-    let open_paren = receiver.recv().map_err(|_| "Unexpected end of file")?;
+pub fn expect_open_paren(receiver: &Receiver<Token>) -> Result<(), ParseError> {
+    let open_paren = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
     match open_paren {
         Token::OpenParen(_) => Ok(()),
-        Token::Operation(v, line) | Token::Constant(v, line) => {
-            return Err(format!("Expected '(' on line {}, got a '{}'", line, v))
-        }
-        _ => {
-            return Err(format!(
-                "Expected '(' on line {}",
-                extract_line_number(&open_paren)
-            ))
-        }
+        _ => Err(unexpected_token("'('", &open_paren)),
     }
 }
 
 #[must_use]
-pub fn expect_close_paren(receiver: &Receiver<Token>) -> Result<(), String> {
-    let close_paren = receiver.recv().map_err(|_| "Unexpected end of file")?;
+pub fn expect_close_paren(receiver: &Receiver<Token>) -> Result<(), ParseError> {
+    let close_paren = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
     match close_paren {
         Token::CloseParen(_) => Ok(()),
-        _ => {
-            return Err(format!(
-                "Expected '(' on line {}",
-                extract_line_number(&close_paren)
-            ))
-        }
+        _ => Err(unexpected_token("')'", &close_paren)),
     }
 }
 
 #[must_use]
-pub fn expect_operation(s: &str, receiver: &Receiver<Token>) -> Result<(), String> {
-    let op = receiver.recv().map_err(|_| "Unexpected end of file")?;
+pub fn expect_operation(s: &str, receiver: &Receiver<Token>) -> Result<(), ParseError> {
+    let op = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
     match op {
-        Token::Operation(op_str, _) => {
-            if op_str.to_lowercase() == s {
-                Ok(())
-            } else {
-                Err(format!(
-                    "Expected '{}' on line {}, found {}",
-                    s,
-                    extract_line_number(&op),
-                    op_str
-                ))
-            }
-        }
-        _ => {
-            return Err(format!(
-                "Expected '{}' on line {}, found {:?}",
-                s,
-                extract_line_number(&op),
-                op
-            ))
-        }
+        Token::Operation(op_str, _) if op_str.to_lowercase() == s => Ok(()),
+        _ => Err(unexpected_token(&format!("'{}'", s), &op)),
     }
 }
 
 #[must_use]
-pub fn expect_operations(ops: Vec<&str>, receiver: &Receiver<Token>) -> Result<String, String> {
-    let op = receiver.recv().map_err(|_| "Unexpected end of file")?;
-    for s in ops {
+pub fn expect_operations(ops: Vec<&str>, receiver: &Receiver<Token>) -> Result<String, ParseError> {
+    let op = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
+    for s in &ops {
         match op {
             Token::Operation(op_str, _) => {
                 if op_str.to_lowercase() == s.to_lowercase() {
@@ -242,39 +236,27 @@ pub fn expect_operations(ops: Vec<&str>, receiver: &Receiver<Token>) -> Result<S
             _ => (),
         }
     }
-    return Err(format!(
-        "Unexpected token on line {}",
-        extract_line_number(&op),
-    ));
+    return Err(unexpected_token(&ops.join(" or "), &op));
 }
 
 #[must_use]
-pub fn expect_constant(receiver: &Receiver<Token>) -> Result<f32, String> {
-    let op = receiver.recv().map_err(|_| "Unexpected end of file")?;
+pub fn expect_constant(receiver: &Receiver<Token>) -> Result<f32, ParseError> {
+    let op = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
     match op {
-        Token::Constant(vstr, line_number) => {
-            let v = vstr
-                .parse::<f32>()
-                .map_err(|_| format!("Unable to parse number {} on line {}", vstr, line_number))?;
-            Ok(v)
-        }
-        _ => {
-            return Err(format!(
-                "Expected constant on line {}, found {:?}",
-                extract_line_number(&op),
-                op
-            ))
-        }
+        Token::Constant(vstr, _) => vstr
+            .parse::<f32>()
+            .map_err(|_| unexpected_token("a number", &op)),
+        _ => Err(unexpected_token("a number", &op)),
     }
 }
 
 pub fn parse_pic(
     receiver: &Receiver<Token>,
     coord_default: CoordinateSystem,
-) -> Result<Pic, String> {
+) -> Result<Pic, ParseError> {
     let mut coord = coord_default;
     expect_open_paren(receiver)?;
-    let pic_type = receiver.recv().map_err(|_| "Unexpected end of file")?;
+    let pic_type = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
     match pic_type {
         Token::Operation(s, line_number) => match &s.to_lowercase()[..] {
             "mono" => {
@@ -293,6 +275,35 @@ pub fn parse_pic(
                 }))
             }
             "grayscale" => {
+                let mut normalization = Normalization::Wrap;
+                let mut options = CoordinateSystem::list_all();
+                options.extend(Normalization::list_all());
+                if let Ok(option) =
+                    expect_operations(options.iter().map(|x| x.as_str()).collect(), receiver)
+                {
+                    match option.parse() {
+                        Ok(coord_system) => {
+                            coord = coord_system;
+                            if let Ok(n) = expect_operations(
+                                Normalization::list_all()
+                                    .iter()
+                                    .map(|x| x.as_str())
+                                    .collect(),
+                                receiver,
+                            ) {
+                                normalization = n.parse().unwrap();
+                            }
+                        }
+                        Err(_) => normalization = option.parse().unwrap(),
+                    }
+                };
+                Ok(Pic::Grayscale(GrayscaleData {
+                    c: APTNode::parse_apt_node(receiver)?,
+                    coord,
+                    normalization,
+                }))
+            }
+            "rgb" => {
                 if let Ok(coord_system) = expect_operations(
                     CoordinateSystem::list_all()
                         .iter()
@@ -302,12 +313,14 @@ pub fn parse_pic(
                 ) {
                     coord = coord_system.parse().unwrap();
                 };
-                Ok(Pic::Grayscale(GrayscaleData {
-                    c: APTNode::parse_apt_node(receiver)?,
+                Ok(Pic::RGB(RGBData {
+                    r: APTNode::parse_apt_node(receiver)?,
+                    g: APTNode::parse_apt_node(receiver)?,
+                    b: APTNode::parse_apt_node(receiver)?,
                     coord,
                 }))
             }
-            "rgb" => {
+            "rgba" => {
                 if let Ok(coord_system) = expect_operations(
                     CoordinateSystem::list_all()
                         .iter()
@@ -317,10 +330,11 @@ pub fn parse_pic(
                 ) {
                     coord = coord_system.parse().unwrap();
                 };
-                Ok(Pic::RGB(RGBData {
+                Ok(Pic::RGBA(RGBAData {
                     r: APTNode::parse_apt_node(receiver)?,
                     g: APTNode::parse_apt_node(receiver)?,
                     b: APTNode::parse_apt_node(receiver)?,
+                    a: APTNode::parse_apt_node(receiver)?,
                     coord,
                 }))
             }
@@ -341,7 +355,7 @@ pub fn parse_pic(
                     coord,
                 }))
             }
-            "gradient" => {
+            "cmyk" => {
                 if let Ok(coord_system) = expect_operations(
                     CoordinateSystem::list_all()
                         .iter()
@@ -351,20 +365,65 @@ pub fn parse_pic(
                 ) {
                     coord = coord_system.parse().unwrap();
                 };
+                Ok(Pic::CMYK(CMYKData {
+                    c: APTNode::parse_apt_node(receiver)?,
+                    m: APTNode::parse_apt_node(receiver)?,
+                    y: APTNode::parse_apt_node(receiver)?,
+                    k: APTNode::parse_apt_node(receiver)?,
+                    coord,
+                }))
+            }
+            "lab" => {
+                if let Ok(coord_system) = expect_operations(
+                    CoordinateSystem::list_all()
+                        .iter()
+                        .map(|x| x.as_str())
+                        .collect(),
+                    receiver,
+                ) {
+                    coord = coord_system.parse().unwrap();
+                };
+                Ok(Pic::LAB(LABData {
+                    l: APTNode::parse_apt_node(receiver)?,
+                    a: APTNode::parse_apt_node(receiver)?,
+                    b: APTNode::parse_apt_node(receiver)?,
+                    coord,
+                }))
+            }
+            "gradient" => {
+                // the colors follow the options, so the '(' after them can't be skipped
+                let mut wrap = GradientWrap::Wrap;
+                let mut token = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
+                if let Token::Operation(option, _) = token {
+                    if let Ok(coord_system) = option.parse() {
+                        coord = coord_system;
+                        token = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
+                    }
+                }
+                if let Token::Operation(option, _) = token {
+                    if let Ok(w) = option.parse() {
+                        wrap = w;
+                        token = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
+                    }
+                }
+                let mut cycle = false;
+                if let Token::Operation(option, _) = token {
+                    if option.to_lowercase() == "cycle" {
+                        cycle = true;
+                        token = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
+                    }
+                }
+                if !matches!(token, Token::OpenParen(_)) {
+                    return Err(unexpected_token("'('", &token));
+                }
                 let mut colors = Vec::new();
-                expect_open_paren(receiver)?;
                 expect_operation("colors", receiver)?;
                 loop {
-                    let _token = receiver.recv().map_err(|_| "Unexpected end of file")?;
-                    match expect_operations(vec!["color", "stopcolor"], receiver) {
-                        Err(e) => {
-                            if e.starts_with("Unexpected token on line ") {
-                                break;
-                            } else {
-                                panic!("{:?}", e);
-                            }
-                        }
-                        Ok(color_type) => {
+                    let token = receiver.recv().map_err(|_| ParseError::UnterminatedList)?;
+                    match token {
+                        Token::OpenParen(_) => {
+                            let color_type =
+                                expect_operations(vec!["color", "stopcolor"], receiver)?;
                             let r = expect_constant(receiver)?;
                             let g = expect_constant(receiver)?;
                             let b = expect_constant(receiver)?;
@@ -375,17 +434,32 @@ pub fn parse_pic(
                             }
                             expect_close_paren(receiver)?;
                         }
+                        Token::CloseParen(_) => break,
+                        _ => return Err(unexpected_token("a color or ')'", &token)),
                     }
                 }
+                if colors.len() < 2 {
+                    return Err(ParseError::ArityMismatch {
+                        op: "COLORS".to_string(),
+                        expected: 2,
+                        got: colors.len(),
+                        pos: line_number,
+                    });
+                }
                 Ok(Pic::Gradient(GradientData {
                     colors: colors,
                     index: APTNode::parse_apt_node(receiver)?,
                     coord,
+                    wrap,
+                    cycle,
                 }))
             }
-            _ => Err(format!("Unknown pic type {} at line {}", s, line_number)),
+            _ => Err(ParseError::UnknownOperator {
+                name: s.to_string(),
+                pos: line_number,
+            }),
         },
-        _ => Err(format!("Invalid picture type")), //todo line number etc
+        _ => Err(unexpected_token("a pic type", &pic_type)),
     }
 }
 
@@ -415,6 +489,7 @@ pub mod mock {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants::PIC_FBM_OCTAVES_DEFAULT;
     use std::sync::mpsc::channel;
 
     const CODE: &'static str = r#"( RGB
@@ -543,6 +618,8 @@ mod tests {
         assert_eq!(Lexer::is_white_space('\t'), true);
         assert_eq!(Lexer::is_white_space('\n'), true);
         assert_eq!(Lexer::is_white_space('\r'), true);
+        assert_eq!(Lexer::is_white_space('\u{0C}'), true);
+        assert_eq!(Lexer::is_white_space('\u{A0}'), true);
     }
 
     #[test]
@@ -562,4 +639,359 @@ mod tests {
         assert_eq!(extract_line_number(&Token::Operation("blablabla", 6)), 6);
         assert_eq!(extract_line_number(&Token::Constant("blablabla", 6)), 6);
     }
+
+    #[test]
+    fn test_parse_gradient_colors() {
+        let source = "( GRADIENT CARTESIAN ( COLORS ( COLOR 0.1 0.2 0.3 ) ( STOPCOLOR 0.4 0.5 0.6 ) ) ( X ) )";
+        match lisp_to_pic(source.to_string(), CoordinateSystem::Polar).unwrap() {
+            Pic::Gradient(data) => {
+                assert_eq!(
+                    data.colors,
+                    vec![
+                        (Color::new(0.1, 0.2, 0.3, 1.0), false),
+                        (Color::new(0.4, 0.5, 0.6, 1.0), true)
+                    ]
+                );
+                assert_eq!(data.index, APTNode::X);
+                assert_eq!(data.coord, CoordinateSystem::Cartesian);
+                assert_eq!(data.wrap, GradientWrap::Wrap);
+            }
+            _ => panic!("wrong type"),
+        }
+        let source = "( GRADIENT CARTESIAN ( COLORS ( COLOR 0.1 0.2 0.3 ) X ) ( X ) )";
+        assert!(lisp_to_pic(source.to_string(), CoordinateSystem::Polar).is_err());
+        let source = "( GRADIENT CARTESIAN ( COLORS ( COLOR 0.1 0.2 0.3 ) ) ( X ) )";
+        assert!(lisp_to_pic(source.to_string(), CoordinateSystem::Polar).is_err());
+    }
+
+    #[test]
+    fn test_parse_gradient_wrap() {
+        let colors = "( COLORS ( COLOR 0.1 0.2 0.3 ) ( COLOR 0.4 0.5 0.6 ) )";
+        for (options, coord, wrap) in [
+            ("", CoordinateSystem::Polar, GradientWrap::Wrap),
+            ("CARTESIAN", CoordinateSystem::Cartesian, GradientWrap::Wrap),
+            ("CLAMP", CoordinateSystem::Polar, GradientWrap::Clamp),
+            (
+                "CARTESIAN MIRROR",
+                CoordinateSystem::Cartesian,
+                GradientWrap::Mirror,
+            ),
+        ] {
+            let source = format!("( GRADIENT {} {} ( X ) )", options, colors);
+            match lisp_to_pic(source, CoordinateSystem::Polar).unwrap() {
+                Pic::Gradient(data) => {
+                    assert_eq!(data.coord, coord);
+                    assert_eq!(data.wrap, wrap);
+                    assert_eq!(data.index, APTNode::X);
+                    assert!(!data.cycle);
+                }
+                _ => panic!("wrong type"),
+            }
+        }
+        let source = format!("( GRADIENT CARTESIAN MIRROR CYCLE {} ( X ) )", colors);
+        match lisp_to_pic(source, CoordinateSystem::Polar).unwrap() {
+            Pic::Gradient(data) => {
+                assert_eq!(data.wrap, GradientWrap::Mirror);
+                assert!(data.cycle);
+            }
+            _ => panic!("wrong type"),
+        }
+        let source = format!("( GRADIENT CYCLE {} ( X ) )", colors);
+        match lisp_to_pic(source, CoordinateSystem::Polar).unwrap() {
+            Pic::Gradient(data) => assert!(data.cycle),
+            _ => panic!("wrong type"),
+        }
+        let source = format!("( GRADIENT MIRROR CARTESIAN {} ( X ) )", colors);
+        assert!(lisp_to_pic(source, CoordinateSystem::Polar).is_err());
+    }
+
+    #[test]
+    fn test_parse_warp() {
+        let source = "( MONO CARTESIAN\n\t( ( WARP ( SIN X ) 0.5 ( + X Y ) ) )\n)";
+        let pic = lisp_to_pic(source.to_string(), CoordinateSystem::Polar).unwrap();
+        match &pic {
+            Pic::Mono(data) => assert_eq!(
+                data.c,
+                APTNode::Warp(vec![
+                    APTNode::Sin(vec![APTNode::X]),
+                    APTNode::Constant(0.5),
+                    APTNode::Add(vec![APTNode::X, APTNode::Y]),
+                ])
+            ),
+            _ => panic!("wrong type"),
+        }
+        assert_eq!(pic.to_lisp(), source);
+    }
+
+    #[test]
+    fn test_parse_clamp_smoothstep() {
+        let source =
+            "( MONO CARTESIAN\n\t( ( SMOOTHSTEP -0.5 ( CLAMP Y -0.25 0.75 ) ( SIN X ) ) )\n)";
+        let pic = lisp_to_pic(source.to_string(), CoordinateSystem::Polar).unwrap();
+        match &pic {
+            Pic::Mono(data) => assert_eq!(
+                data.c,
+                APTNode::Smoothstep(vec![
+                    APTNode::Constant(-0.5),
+                    APTNode::Clamp(vec![
+                        APTNode::Y,
+                        APTNode::Constant(-0.25),
+                        APTNode::Constant(0.75),
+                    ]),
+                    APTNode::Sin(vec![APTNode::X]),
+                ])
+            ),
+            _ => panic!("wrong type"),
+        }
+        assert_eq!(pic.to_lisp(), source);
+        // without bounds CLAMP keeps to -1.0 .. 1.0, like before it had them
+        let source = "( MONO CARTESIAN\n\t( ( + ( CLAMP X ) Y ) )\n)";
+        let pic = lisp_to_pic(source.to_string(), CoordinateSystem::Polar).unwrap();
+        match &pic {
+            Pic::Mono(data) => assert_eq!(
+                data.c,
+                APTNode::Add(vec![
+                    APTNode::Clamp(vec![
+                        APTNode::X,
+                        APTNode::Constant(-1.0),
+                        APTNode::Constant(1.0),
+                    ]),
+                    APTNode::Y,
+                ])
+            ),
+            _ => panic!("wrong type"),
+        }
+        assert_eq!(pic.to_lisp(), source);
+        // the list ends after the bounds
+        let source = "( MONO CARTESIAN ( CLAMP X -0.5 0.5 Y ) )";
+        assert!(lisp_to_pic(source.to_string(), CoordinateSystem::Polar).is_err());
+    }
+
+    #[test]
+    fn test_parse_mix() {
+        let source = "( MONO CARTESIAN\n\t( ( MIX ( SIN X ) Y ( ABS X ) ) )\n)";
+        let pic = lisp_to_pic(source.to_string(), CoordinateSystem::Polar).unwrap();
+        match &pic {
+            Pic::Mono(data) => assert_eq!(
+                data.c,
+                APTNode::Mix(vec![
+                    APTNode::Sin(vec![APTNode::X]),
+                    APTNode::Y,
+                    APTNode::Abs(vec![APTNode::X]),
+                ])
+            ),
+            _ => panic!("wrong type"),
+        }
+        assert_eq!(pic.to_lisp(), source);
+    }
+
+    #[test]
+    fn test_parse_fbm_octaves() {
+        let source = "( MONO CARTESIAN\n\t( ( FBM X Y 0.5 0.5 0.5 0.5 ) )\n)";
+        let pic = lisp_to_pic(source.to_string(), CoordinateSystem::Polar).unwrap();
+        match &pic {
+            Pic::Mono(data) => assert_eq!(
+                data.c.get_children().unwrap()[6],
+                APTNode::Constant(PIC_FBM_OCTAVES_DEFAULT)
+            ),
+            _ => panic!("wrong type"),
+        }
+        let source = "( MONO CARTESIAN\n\t( ( FBM X Y 0.5 0.5 0.5 0.5 ( SIN T ) ) )\n)";
+        let pic = lisp_to_pic(source.to_string(), CoordinateSystem::Polar).unwrap();
+        assert_eq!(pic.to_lisp(), source);
+        let source = "( MONO CARTESIAN\n\t( ( + ( FBM X Y 0.5 0.5 0.5 0.5 ) T ) )\n)";
+        let pic = lisp_to_pic(source.to_string(), CoordinateSystem::Polar).unwrap();
+        match &pic {
+            Pic::Mono(data) => assert_eq!(data.c.get_children().unwrap()[1], APTNode::T),
+            _ => panic!("wrong type"),
+        }
+    }
+
+    #[test]
+    fn test_parse_grayscale_normalization() {
+        let source = "( GRAYSCALE CARTESIAN CLAMP\n\t( X )\n)";
+        let pic = lisp_to_pic(source.to_string(), CoordinateSystem::Polar).unwrap();
+        match &pic {
+            Pic::Grayscale(data) => {
+                assert_eq!(data.coord, CoordinateSystem::Cartesian);
+                assert_eq!(data.normalization, Normalization::Clamp);
+            }
+            _ => panic!("wrong type"),
+        }
+        assert_eq!(pic.to_lisp(), source);
+
+        let source = "( GRAYSCALE AUTO ( X ) )";
+        match lisp_to_pic(source.to_string(), CoordinateSystem::Polar).unwrap() {
+            Pic::Grayscale(data) => {
+                assert_eq!(data.coord, CoordinateSystem::Polar);
+                assert_eq!(data.normalization, Normalization::Auto);
+            }
+            _ => panic!("wrong type"),
+        }
+
+        let source = "( GRAYSCALE POLAR ( X ) )";
+        match lisp_to_pic(source.to_string(), CoordinateSystem::Cartesian).unwrap() {
+            Pic::Grayscale(data) => assert_eq!(data.normalization, Normalization::Wrap),
+            _ => panic!("wrong type"),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let parse = |source: &str| lisp_to_pic(source.to_string(), CoordinateSystem::Polar);
+        assert_eq!(
+            parse("RGB ( X ) ( Y ) ( T )"),
+            Err(ParseError::UnexpectedToken {
+                expected: "'('".to_string(),
+                found: "RGB".to_string(),
+                pos: 0,
+            })
+        );
+        assert_eq!(
+            parse("( MONO CARTESIAN\n\t( SIN . ) )"),
+            Err(ParseError::UnexpectedToken {
+                expected: "a number".to_string(),
+                found: ".".to_string(),
+                pos: 1,
+            })
+        );
+        assert_eq!(
+            parse("( MONO CARTESIAN\n\t( + X ( PIZZA Y ) ) )"),
+            Err(ParseError::UnknownOperator {
+                name: "PIZZA".to_string(),
+                pos: 1,
+            })
+        );
+        assert_eq!(
+            parse("( SEPIA ( X ) )"),
+            Err(ParseError::UnknownOperator {
+                name: "SEPIA".to_string(),
+                pos: 0,
+            })
+        );
+        assert_eq!(
+            parse("( GRADIENT ( COLORS ( COLOR 0.1 0.2 0.3 ) ) ( X ) )"),
+            Err(ParseError::ArityMismatch {
+                op: "COLORS".to_string(),
+                expected: 2,
+                got: 1,
+                pos: 0,
+            })
+        );
+        assert_eq!(
+            parse("( RGB CARTESIAN ( X ) ( + Y"),
+            Err(ParseError::UnterminatedList)
+        );
+        assert_eq!(parse(""), Err(ParseError::UnterminatedList));
+    }
+
+    #[test]
+    fn test_parse_arity() {
+        let arity = |source: &str| match lisp_to_pic(source.to_string(), CoordinateSystem::Polar) {
+            Err(ParseError::ArityMismatch {
+                op,
+                expected,
+                got,
+                pos,
+            }) => (op, expected, got, pos),
+            other => panic!("{:?} for {}", other, source),
+        };
+        let mismatch = |op: &str, expected: usize, got: usize, pos: usize| {
+            (op.to_string(), expected, got, pos)
+        };
+        // too few
+        assert_eq!(arity("( MONO CARTESIAN ( + X ) )"), mismatch("+", 2, 1, 0));
+        assert_eq!(
+            arity("( RGB CARTESIAN ( X )\n\t( ATAN2 ( * X Y ) )\n\t( Y ) )"),
+            mismatch("ATAN2", 2, 1, 1)
+        );
+        assert_eq!(
+            arity("( MONO CARTESIAN ( FBM X Y 0.5 ) )"),
+            mismatch("FBM", 6, 3, 0)
+        );
+        assert_eq!(
+            arity("( MONO CARTESIAN ( PIC-EYE X ) )"),
+            mismatch("PIC-EYE", 2, 1, 0)
+        );
+        // an operation without parens runs into the ')' of its parent
+        assert_eq!(
+            arity("( MONO CARTESIAN ( + X SIN ) )"),
+            mismatch("SIN", 1, 0, 0)
+        );
+        // too many
+        assert_eq!(
+            arity("( MONO CARTESIAN ( SIN X Y ) )"),
+            mismatch("SIN", 1, 2, 0)
+        );
+        assert_eq!(
+            arity("( HSV CARTESIAN ( X ) ( Y )\n\t( + ( X Y ) T ) )"),
+            mismatch("X", 0, 1, 1)
+        );
+        assert_eq!(
+            arity("( MONO CARTESIAN ( CLAMP X -0.5 0.5 ( SIN Y ) T ) )"),
+            mismatch("CLAMP", 3, 5, 0)
+        );
+        assert_eq!(
+            arity("( MONO CARTESIAN ( FBM X Y 0.5 0.5 0.5 0.5 4 0.1 ) )"),
+            mismatch("FBM", 7, 8, 0)
+        );
+        // parens that only wrap an argument are not an argument
+        let pic = lisp_to_pic(
+            "( MONO CARTESIAN ( + ( ( SIN X ) ) Y ) )".to_string(),
+            CoordinateSystem::Polar,
+        )
+        .unwrap();
+        match &pic {
+            Pic::Mono(data) => assert_eq!(
+                data.c,
+                APTNode::Add(vec![APTNode::Sin(vec![APTNode::X]), APTNode::Y])
+            ),
+            _ => panic!("wrong type"),
+        }
+    }
+
+    #[test]
+    fn test_parse_stack_depth() {
+        let nested = |levels: usize, left: bool| {
+            let channel = (0..levels).fold("X".to_string(), |inner, _| {
+                if left {
+                    format!("( + {} X )", inner)
+                } else {
+                    format!("( + X {} )", inner)
+                }
+            });
+            lisp_to_pic(
+                format!("( MONO CARTESIAN {} )", channel),
+                CoordinateSystem::Polar,
+            )
+        };
+        // every level leaves a value on the stack while the one below it is evaluated
+        assert_eq!(
+            nested(PIC_MAX_STACK_DEPTH + 44, true),
+            Err(ParseError::TooDeep {
+                depth: PIC_MAX_STACK_DEPTH + 45,
+                limit: PIC_MAX_STACK_DEPTH,
+            })
+        );
+        assert!(nested(PIC_MAX_STACK_DEPTH - 1, true).is_ok());
+        // a tree as deep that is evaluated from the bottom up needs a stack of 2
+        assert!(nested(PIC_MAX_STACK_DEPTH + 44, false).is_ok());
+    }
+
+    #[test]
+    fn test_parse_any_white_space() {
+        let expected = lisp_to_pic(
+            "( MONO CARTESIAN ( + X Y ) )".to_string(),
+            CoordinateSystem::Polar,
+        )
+        .unwrap();
+        for source in [
+            "(MONO CARTESIAN(+ X Y))",
+            "( MONO\r\n\tCARTESIAN\n\n  ( +\n    X\n    Y\n  )\n)\n",
+            "(\u{A0}MONO\u{0C}CARTESIAN\u{2003}( + X\u{3000}Y ) )",
+        ] {
+            let pic = lisp_to_pic(source.to_string(), CoordinateSystem::Polar).unwrap();
+            assert_eq!(pic, expected, "{:?}", source);
+        }
+    }
 }
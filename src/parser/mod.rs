@@ -1,3 +1,4 @@
 pub mod aptnode;
 pub mod lexer;
+pub mod parseerror;
 pub mod token;
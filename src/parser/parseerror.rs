@@ -0,0 +1,130 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FResult};
+
+/// Why a sexpr is not a pic. The `pos` of a variant is the line, counted from 0, where the
+/// parser found the problem.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    /// Another token than `expected` was `found`
+    UnexpectedToken {
+        expected: String,
+        found: String,
+        pos: usize,
+    },
+    /// An operation or a pic type that does not exist
+    UnknownOperator { name: String, pos: usize },
+    /// An operation with more or fewer arguments than it takes
+    ArityMismatch {
+        op: String,
+        expected: usize,
+        got: usize,
+        pos: usize,
+    },
+    /// The sexpr ended before every '(' was closed
+    UnterminatedList,
+    /// A channel needs a stack of `depth` values, more than the `limit`
+    TooDeep { depth: usize, limit: usize },
+}
+
+impl ParseError {
+    /// The line where the parser found the problem, None when it is not on one line
+    pub fn pos(&self) -> Option<usize> {
+        match self {
+            ParseError::UnexpectedToken { pos, .. }
+            | ParseError::UnknownOperator { pos, .. }
+            | ParseError::ArityMismatch { pos, .. } => Some(*pos),
+            ParseError::UnterminatedList | ParseError::TooDeep { .. } => None,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        match self {
+            ParseError::UnexpectedToken {
+                expected,
+                found,
+                pos,
+            } => write!(
+                f,
+                "Expected {} on line {}, got a '{}'",
+                expected, pos, found
+            ),
+            ParseError::UnknownOperator { name, pos } => {
+                write!(f, "Unknown operation '{}' on line {}", name, pos)
+            }
+            ParseError::ArityMismatch {
+                op,
+                expected,
+                got,
+                pos,
+            } => write!(
+                f,
+                "'{}' on line {} takes {} argument{}, got {}",
+                op,
+                pos,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                got
+            ),
+            ParseError::TooDeep { depth, limit } => write!(
+                f,
+                "The expression is too deep, it needs a stack of {} values and {} are allowed",
+                depth, limit
+            ),
+            ParseError::UnterminatedList => {
+                write!(f, "Unexpected end of file, a '(' is not closed")
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+// the callers that report errors as a String can use ? on a parse
+impl From<ParseError> for String {
+    fn from(e: ParseError) -> String {
+        e.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_error_display() {
+        let e = ParseError::UnexpectedToken {
+            expected: "'('".to_string(),
+            found: "RGB".to_string(),
+            pos: 0,
+        };
+        assert_eq!(e.to_string(), "Expected '(' on line 0, got a 'RGB'");
+        assert_eq!(e.pos(), Some(0));
+        let e = ParseError::UnknownOperator {
+            name: "PIZZA".to_string(),
+            pos: 2,
+        };
+        assert_eq!(String::from(e), "Unknown operation 'PIZZA' on line 2");
+        let e = ParseError::ArityMismatch {
+            op: "SIN".to_string(),
+            expected: 1,
+            got: 2,
+            pos: 1,
+        };
+        assert_eq!(e.to_string(), "'SIN' on line 1 takes 1 argument, got 2");
+        assert_eq!(
+            ParseError::TooDeep {
+                depth: 300,
+                limit: 256
+            }
+            .to_string(),
+            "The expression is too deep, it needs a stack of 300 values and 256 are allowed"
+        );
+        assert_eq!(ParseError::UnterminatedList.pos(), None);
+        assert_eq!(
+            ParseError::UnterminatedList.to_string(),
+            "Unexpected end of file, a '(' is not closed"
+        );
+    }
+}
@@ -1,5 +1,17 @@
+use std::path::Path;
+
 use image::io::Reader as ImageReader;
 use image::GenericImageView;
+use serde::{Deserialize, Serialize};
+
+/// How a picture is read at coordinates that fall between its pixels
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Sampling {
+    /// Blend the four surrounding pixels
+    Bilinear,
+    /// Take the closest pixel, faster but blocky when the picture is upscaled
+    Nearest,
+}
 
 #[readonly::make]
 pub struct ActualPicture {
@@ -12,9 +24,9 @@ pub struct ActualPicture {
 impl ActualPicture {
     pub fn new_via_file(file_name: &str) -> Result<Self, String> {
         let img = ImageReader::open(file_name)
-            .expect("Could not open file")
+            .map_err(|e| format!("Could not open {}. {}", file_name, e))?
             .decode()
-            .expect("Could not decode file");
+            .map_err(|e| format!("Could not decode {}. {}", file_name, e))?;
 
         let (width, height) = img.dimensions();
         let raw_bytes = img.into_bytes();
@@ -38,6 +50,70 @@ impl ActualPicture {
     }
 }
 
+fn file_name_key(name: &str) -> Option<String> {
+    Path::new(name)
+        .file_name()
+        .map(|f| f.to_string_lossy().to_lowercase())
+}
+
+fn file_stem_key(name: &str) -> Option<String> {
+    Path::new(name)
+        .file_stem()
+        .map(|f| f.to_string_lossy().to_lowercase())
+}
+
+/// The name of the loaded picture that a Pic- node that refers to `name` uses. That is `name`
+/// itself, or else the only picture with the same file name, ignoring its directory and case,
+/// or else the only picture with the same name without extension, so a sexpr keeps working
+/// when its picture is moved or converted.
+pub fn resolve_picture_name<'a>(
+    name: &str,
+    names: impl IntoIterator<Item = &'a String>,
+) -> Result<String, String> {
+    let mut names: Vec<&String> = names.into_iter().collect();
+    names.sort();
+    if names.iter().any(|n| n.as_str() == name) {
+        return Ok(name.to_string());
+    }
+    for key in [file_name_key, file_stem_key] {
+        let wanted = key(name);
+        if wanted.is_none() {
+            continue;
+        }
+        let candidates: Vec<&String> = names.iter().copied().filter(|n| key(n) == wanted).collect();
+        match candidates.len() {
+            0 => {}
+            1 => return Ok(candidates[0].clone()),
+            _ => {
+                return Err(format!(
+                    "The picture {} is missing and could be any of {}",
+                    name,
+                    candidates
+                        .iter()
+                        .map(|n| n.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        }
+    }
+    if names.is_empty() {
+        return Err(format!(
+            "The picture {} is missing, no pictures are loaded",
+            name
+        ));
+    }
+    Err(format!(
+        "The picture {} is missing, the pictures are {}",
+        name,
+        names
+            .iter()
+            .map(|n| n.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    ))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -55,4 +131,65 @@ mod test {
         assert_eq!(ap.w, 800);
         assert_eq!(ap.h, 600);
     }
+
+    #[test]
+    fn test_actualpicture_new_via_file_error() {
+        let err = ActualPicture::new_via_file("does/not/exist.jpg")
+            .err()
+            .unwrap();
+        assert!(
+            err.starts_with("Could not open does/not/exist.jpg."),
+            "{}",
+            err
+        );
+        let err = ActualPicture::new_via_file("Cargo.toml").err().unwrap();
+        assert!(err.starts_with("Could not decode Cargo.toml."), "{}", err);
+    }
+
+    #[test]
+    fn test_resolve_picture_name() {
+        let names: Vec<String> = [
+            "eye.jpg",
+            "tree.jpg",
+            "Barn.PNG",
+            "graves.jpg",
+            "graves.png",
+        ]
+        .iter()
+        .map(|n| n.to_string())
+        .collect();
+        assert_eq!(
+            resolve_picture_name("eye.jpg", &names),
+            Ok("eye.jpg".to_string())
+        );
+        // another directory or case
+        assert_eq!(
+            resolve_picture_name("old/pictures/EYE.jpg", &names),
+            Ok("eye.jpg".to_string())
+        );
+        // converted to another format
+        assert_eq!(
+            resolve_picture_name("tree.webp", &names),
+            Ok("tree.jpg".to_string())
+        );
+        assert_eq!(
+            resolve_picture_name("barn.jpg", &names),
+            Ok("Barn.PNG".to_string())
+        );
+        assert_eq!(
+            resolve_picture_name("graves.gif", &names),
+            Err(
+                "The picture graves.gif is missing and could be any of graves.jpg, graves.png"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            resolve_picture_name("runner.jpg", &names),
+            Err("The picture runner.jpg is missing, the pictures are Barn.PNG, eye.jpg, graves.jpg, graves.png, tree.jpg".to_string())
+        );
+        assert_eq!(
+            resolve_picture_name("runner.jpg", &Vec::new()),
+            Err("The picture runner.jpg is missing, no pictures are loaded".to_string())
+        );
+    }
 }
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::constants::DEFAULT_COORDINATE_SYSTEM;
+use crate::parser::lexer::lisp_to_pic;
+use crate::pic::pic::pic_get_rgba8_with_backend;
+use crate::pic::simdbackend::SimdBackend;
+
+/// The expressions that are timed, one of every kind of work a render does
+const BENCHMARK_SUITE: [(&str, &str); 4] = [
+    ("mono", "( MONO CARTESIAN ( SIN ( * 8 ( + X Y ) ) ) )"),
+    (
+        "rgb",
+        "( RGB CARTESIAN ( SIN ( * 4 X ) ) ( ATAN ( * 4 Y ) ) ( ATAN2 X Y ) )",
+    ),
+    (
+        "hsv",
+        "( HSV POLAR ( X ) ( SQRT ( ABS Y ) ) ( CELL1 X Y 0.5 0.5 0.5 ) )",
+    ),
+    (
+        "fbm",
+        "( RGB CARTESIAN ( FBM X Y 0.5 0.5 0.5 0.9 ) ( RIDGEDFBM 0.3 1.0 0.4 X Y 0.3 ) ( FBM-PERLIN X Y 0.5 0.5 0.5 0.9 ) )",
+    ),
+];
+
+/// How fast an expression of the suite renders with a backend
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchmarkRun {
+    pub backend: SimdBackend,
+    /// The name of the expression in the suite
+    pub name: String,
+    pub megapixels_per_second: f64,
+}
+
+impl Display for BenchmarkRun {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        write!(
+            f,
+            "{:<8}{:<6}{:>10.2} megapixels/s",
+            self.backend, self.name, self.megapixels_per_second
+        )
+    }
+}
+
+/// Render every expression of the suite at `size` x `size` with every backend, on one thread.
+/// Every render is done `rounds` times and the fastest one counts, so a hiccup of the machine
+/// does not.
+pub fn run_benchmark(
+    backends: &[SimdBackend],
+    size: u32,
+    rounds: u32,
+) -> Result<Vec<BenchmarkRun>, String> {
+    let pictures = Arc::new(HashMap::new());
+    let megapixels = (size * size) as f64 / 1_000_000.0;
+    let mut runs = Vec::new();
+    for backend in backends {
+        let backend = backend.resolve()?;
+        for (name, sexpr) in BENCHMARK_SUITE {
+            let pic = lisp_to_pic(sexpr.to_string(), DEFAULT_COORDINATE_SYSTEM)?;
+            let mut fastest = Duration::MAX;
+            for _ in 0..rounds.max(1) {
+                let start = Instant::now();
+                pic_get_rgba8_with_backend(backend, &pic, pictures.clone(), size, size, 0.0)?;
+                fastest = fastest.min(start.elapsed());
+            }
+            runs.push(BenchmarkRun {
+                backend,
+                name: name.to_string(),
+                megapixels_per_second: megapixels / fastest.as_secs_f64().max(f64::EPSILON),
+            });
+        }
+    }
+    Ok(runs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_benchmark_run_display() {
+        let run = BenchmarkRun {
+            backend: SimdBackend::Sse41,
+            name: "fbm".to_string(),
+            megapixels_per_second: 12.345,
+        };
+        assert_eq!(run.to_string(), "sse41   fbm        12.35 megapixels/s");
+    }
+
+    #[test]
+    fn test_run_benchmark() {
+        let runs = run_benchmark(&[SimdBackend::Scalar, SimdBackend::Auto], 8, 1).unwrap();
+        assert_eq!(runs.len(), 2 * BENCHMARK_SUITE.len());
+        let names: Vec<&str> = runs.iter().map(|run| run.name.as_str()).collect();
+        assert_eq!(&names[..4], ["mono", "rgb", "hsv", "fbm"]);
+        assert_eq!(runs[0].backend, SimdBackend::Scalar);
+        assert_eq!(runs[4].backend, SimdBackend::Auto.resolve().unwrap());
+        assert!(runs.iter().all(|run| run.megapixels_per_second > 0.0));
+    }
+}
@@ -0,0 +1,180 @@
+use std::fmt::{Display, Formatter, Result as FResult};
+
+use clap::ValueEnum;
+
+use crate::pic::color::Color;
+
+/// How the pixels of a layer are combined with the pixels below it
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum BlendMode {
+    /// The colors are added, bright layers burn out to white
+    Add,
+    /// The colors are multiplied, every layer makes the image darker
+    Multiply,
+    /// The inverted colors are multiplied, every layer makes the image lighter
+    Screen,
+    /// The layer is painted over the image, its alpha decides how much shines through
+    Over,
+}
+
+impl BlendMode {
+    /// The color of `top` on `base`, both 0.0..=1.0
+    fn mix(&self, base: f32, top: f32) -> f32 {
+        match self {
+            BlendMode::Add => (base + top).min(1.0),
+            BlendMode::Multiply => base * top,
+            BlendMode::Screen => base + top - base * top,
+            BlendMode::Over => top,
+        }
+    }
+}
+
+impl Display for BlendMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        let x = match self {
+            BlendMode::Add => "add",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Screen => "screen",
+            BlendMode::Over => "over",
+        };
+        write!(f, "{}", x)
+    }
+}
+
+/// Blend the RGBA8 pixels of `top` onto those of `base`. The colors are mixed where both are
+/// opaque, and composited with the alpha of the layers where they are not.
+pub fn blend_rgba8(base: &[u8], top: &[u8], mode: BlendMode) -> Result<Vec<u8>, String> {
+    if base.len() != top.len() || base.len() % 4 != 0 {
+        return Err(format!(
+            "Cannot blend {} bytes of pixels with {} bytes, the images must have the same dimensions",
+            top.len(),
+            base.len()
+        ));
+    }
+    let to_u8 = |v: f32| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+    let mut blended = Vec::with_capacity(base.len());
+    for (b, t) in base.chunks_exact(4).zip(top.chunks_exact(4)) {
+        let (base_alpha, top_alpha) = (b[3] as f32 / 255.0, t[3] as f32 / 255.0);
+        let alpha = top_alpha + base_alpha * (1.0 - top_alpha);
+        for i in 0..3 {
+            let (bc, tc) = (b[i] as f32 / 255.0, t[i] as f32 / 255.0);
+            // where the base is transparent the layer keeps its own color
+            let tc = (1.0 - base_alpha) * tc + base_alpha * mode.mix(bc, tc);
+            let c = if alpha > 0.0 {
+                (tc * top_alpha + bc * base_alpha * (1.0 - top_alpha)) / alpha
+            } else {
+                0.0
+            };
+            blended.push(to_u8(c));
+        }
+        blended.push(to_u8(alpha));
+    }
+    Ok(blended)
+}
+
+/// Paint the RGBA8 pixels over an opaque `background`, for the image formats without alpha.
+/// The result is opaque, the colors don't change where the pixels already were.
+pub fn flatten_rgba8_over(rgba8: &[u8], background: Color) -> Vec<u8> {
+    let to_u8 = |v: f32| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+    let background = [background.r, background.g, background.b];
+    let mut flattened = Vec::with_capacity(rgba8.len());
+    for pixel in rgba8.chunks_exact(4) {
+        let alpha = pixel[3] as f32 / 255.0;
+        for i in 0..3 {
+            let c = pixel[i] as f32 / 255.0;
+            flattened.push(to_u8(c * alpha + background[i] * (1.0 - alpha)));
+        }
+        flattened.push(u8::MAX);
+    }
+    flattened
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blend_rgba8_multiply() {
+        let base = [255, 128, 0, 255, 10, 20, 30, 255];
+        let top = [128, 128, 255, 255, 255, 255, 255, 255];
+        assert_eq!(
+            blend_rgba8(&base, &top, BlendMode::Multiply).unwrap(),
+            vec![128, 64, 0, 255, 10, 20, 30, 255]
+        );
+        // black stays black, multiplying with white changes nothing
+        assert_eq!(
+            blend_rgba8(&[0, 0, 0, 255], &[200, 100, 50, 255], BlendMode::Multiply).unwrap(),
+            vec![0, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn test_blend_rgba8_over() {
+        let base = [0, 0, 255, 255, 0, 0, 255, 255, 0, 0, 255, 255];
+        let top = [255, 0, 0, 255, 255, 0, 0, 128, 255, 0, 0, 0];
+        assert_eq!(
+            blend_rgba8(&base, &top, BlendMode::Over).unwrap(),
+            vec![255, 0, 0, 255, 128, 0, 127, 255, 0, 0, 255, 255]
+        );
+        // half over nothing stays half transparent, with its own color
+        assert_eq!(
+            blend_rgba8(&[0, 0, 0, 0], &[255, 0, 0, 128], BlendMode::Over).unwrap(),
+            vec![255, 0, 0, 128]
+        );
+        assert_eq!(
+            blend_rgba8(&[0, 0, 0, 0], &[0, 0, 0, 0], BlendMode::Over).unwrap(),
+            vec![0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_flatten_rgba8_over() {
+        let rgba8 = [
+            10, 20, 30, 255, // opaque stays as it is
+            200, 100, 0, 0, // transparent becomes the background
+            255, 0, 0, 128, // half red over white
+            0, 0, 255, 51, // a fifth blue over white
+        ];
+        assert_eq!(
+            flatten_rgba8_over(&rgba8, Color::WHITE),
+            vec![10, 20, 30, 255, 255, 255, 255, 255, 255, 127, 127, 255, 204, 204, 255, 255]
+        );
+        // half red over cyan: 255 * 128 / 255 = 128, 255 * 127 / 255 = 127
+        assert_eq!(
+            flatten_rgba8_over(&[255, 0, 0, 128], Color::CYAN),
+            vec![128, 127, 127, 255]
+        );
+        assert_eq!(flatten_rgba8_over(&[], Color::WHITE), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_blend_rgba8_add_screen() {
+        let base = [200, 100, 0, 255];
+        let top = [100, 100, 0, 255];
+        assert_eq!(
+            blend_rgba8(&base, &top, BlendMode::Add).unwrap(),
+            vec![255, 200, 0, 255]
+        );
+        assert_eq!(
+            blend_rgba8(&base, &top, BlendMode::Screen).unwrap(),
+            vec![222, 161, 0, 255]
+        );
+    }
+
+    #[test]
+    fn test_blend_rgba8_dimensions() {
+        assert!(blend_rgba8(&[0; 8], &[0; 4], BlendMode::Over).is_err());
+        assert!(blend_rgba8(&[0; 3], &[0; 3], BlendMode::Over).is_err());
+        assert_eq!(
+            blend_rgba8(&[], &[], BlendMode::Add).unwrap(),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[test]
+    fn test_blend_mode_parse() {
+        assert_eq!(BlendMode::from_str("screen", true), Ok(BlendMode::Screen));
+        assert!(BlendMode::from_str("overlay", true).is_err());
+        assert_eq!(BlendMode::Multiply.to_string(), "multiply");
+    }
+}
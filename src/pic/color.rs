@@ -1,8 +1,11 @@
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::str::FromStr;
+
 use rand::prelude::*;
-use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
 /// Taken from https://docs.rs/ggez/0.8.1/src/ggez/graphics/types.rs.html#335-340
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Color {
     pub r: f32,
     pub g: f32,
@@ -47,6 +50,33 @@ impl Color {
     }
 }
 
+/// `#rrggbb`, the alpha is left out
+impl Display for Color {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        let channel = |c: f32| (c * 255.0).round().clamp(0.0, 255.0) as u8;
+        write!(
+            f,
+            "#{:02x}{:02x}{:02x}",
+            channel(self.r),
+            channel(self.g),
+            channel(self.b)
+        )
+    }
+}
+
+/// `#rrggbb` or `rrggbb`, an opaque color
+impl FromStr for Color {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Expected a color like #ff8000, not '{}'", s));
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap() as f32 / 255.0;
+        Ok(Color::new(channel(0), channel(2), channel(4), 1.0))
+    }
+}
+
 pub fn lerp_color(a: Color, b: Color, pct: f32) -> Color {
     let red = a.r * (1.0 - pct) + b.r * pct;
     let green = a.g * (1.0 - pct) + b.g * pct;
@@ -55,7 +85,7 @@ pub fn lerp_color(a: Color, b: Color, pct: f32) -> Color {
     Color::new(red, green, blue, alpha)
 }
 
-pub fn get_random_color(rng: &mut StdRng) -> Color {
+pub fn get_random_color(rng: &mut impl Rng) -> Color {
     let r = rng.gen_range(0.0..1.0);
     let g = rng.gen_range(0.0..1.0);
     let b = rng.gen_range(0.0..1.0);
@@ -86,6 +116,20 @@ mod tests {
         assert_eq!(lerp_color(magenta, cyan, 0.85555), expected_4);
     }
 
+    #[test]
+    fn test_color_parse() {
+        assert_eq!("#ff0000".parse(), Ok(Color::RED));
+        assert_eq!("00FFff".parse(), Ok(Color::CYAN));
+        assert_eq!(
+            "#ff8000".parse::<Color>().unwrap().to_string(),
+            "#ff8000".to_string()
+        );
+        assert_eq!(Color::WHITE.to_string(), "#ffffff".to_string());
+        assert!("#ff00".parse::<Color>().is_err());
+        assert!("red".parse::<Color>().is_err());
+        assert!("#gg0000".parse::<Color>().is_err());
+    }
+
     #[test]
     fn test_get_random_color() {
         let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
@@ -0,0 +1,122 @@
+use std::fmt::{Display, Formatter, Result as FResult};
+
+use clap::ValueEnum;
+
+use crate::pic::data::Channel;
+
+/// What the values of the channels of a rendered pic stand for
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ColorSpace {
+    /// The values are the sRGB colors of the image, this is how pics were always rendered
+    Srgb,
+    /// The values are linear light, they are sRGB encoded before they are quantized and
+    /// --supersample and --motion-blur average them in linear light
+    Linear,
+}
+
+impl ColorSpace {
+    /// The channel of `c`, a value in 0.0..=C::FULL of this color space. Linear values are
+    /// sRGB encoded before they are quantized, so the shadows keep their precision.
+    #[inline(always)]
+    pub fn encode<C: Channel>(&self, c: f32) -> C {
+        match self {
+            ColorSpace::Srgb => C::from_f32(c),
+            ColorSpace::Linear => C::from_f32((C::FULL * linear_to_srgb(c / C::FULL)).round()),
+        }
+    }
+
+    /// The value in 0.0..=C::FULL of this color space of a channel that was made by encode
+    #[inline(always)]
+    pub fn decode<C: Channel>(&self, c: C) -> f32 {
+        let v: u32 = c.into();
+        match self {
+            ColorSpace::Srgb => v as f32,
+            ColorSpace::Linear => C::FULL * srgb_to_linear(v as f32 / C::FULL),
+        }
+    }
+
+    /// The color space of the channel at `index` of an rgba buffer, the alpha is never encoded
+    #[inline(always)]
+    pub fn rgba_channel(&self, index: usize) -> ColorSpace {
+        if index % 4 == 3 {
+            ColorSpace::Srgb
+        } else {
+            *self
+        }
+    }
+
+    /// The channel of the average of `n` values that were summed after decode, rounded once
+    #[inline(always)]
+    pub fn encode_mean<C: Channel>(&self, sum: f32, n: u32) -> C {
+        match self {
+            ColorSpace::Srgb => C::from_f32((sum / n as f32).round()),
+            ColorSpace::Linear => self.encode(sum / n as f32),
+        }
+    }
+}
+
+impl Display for ColorSpace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        let x = match self {
+            ColorSpace::Srgb => "srgb",
+            ColorSpace::Linear => "linear",
+        };
+        write!(f, "{}", x)
+    }
+}
+
+/// The sRGB transfer function of a linear channel that is clamped to 0.0..=1.0
+pub fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.max(0.0).min(1.0);
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The linear light of an sRGB channel that is clamped to 0.0..=1.0, the inverse of linear_to_srgb
+pub fn srgb_to_linear(c: f32) -> f32 {
+    let c = c.max(0.0).min(1.0);
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_space_encode() {
+        assert_eq!(ColorSpace::Srgb.encode::<u8>(128.9), 128);
+        // half of the light is a lot brighter than half of the sRGB range
+        assert_eq!(ColorSpace::Linear.encode::<u8>(128.0), 188);
+        assert_eq!(ColorSpace::Linear.encode::<u16>(32768.0), 48192);
+        // the shadows are encoded before they are quantized, they do not band
+        assert_eq!(ColorSpace::Linear.encode::<u8>(0.5), 6);
+        assert_eq!(ColorSpace::Linear.encode::<u8>(1.0), 13);
+        assert!((1..=u8::MAX).all(|v| {
+            ColorSpace::Linear.encode::<u8>(v as f32)
+                >= ColorSpace::Linear.encode::<u8>(v as f32 - 1.0)
+        }));
+        for v in [0u8, 1, 6, 13, 128, 188, 255] {
+            let linear = ColorSpace::Linear.decode(v);
+            assert_eq!(ColorSpace::Linear.encode::<u8>(linear), v);
+            assert_eq!(ColorSpace::Srgb.decode(v), v as f32);
+        }
+        assert_eq!(ColorSpace::Linear.to_string(), "linear");
+    }
+
+    #[test]
+    fn test_color_space_encode_mean() {
+        // black and white average to half of the light, not to half of the sRGB range
+        let sum = ColorSpace::Linear.decode(0u8) + ColorSpace::Linear.decode(255u8);
+        assert_eq!(ColorSpace::Linear.encode_mean::<u8>(sum, 2), 188);
+        let sum = ColorSpace::Srgb.decode(0u8) + ColorSpace::Srgb.decode(255u8);
+        assert_eq!(ColorSpace::Srgb.encode_mean::<u8>(sum, 2), 128);
+        assert_eq!(ColorSpace::Linear.encode_mean::<u8>(0.0, 4), 0);
+    }
+}
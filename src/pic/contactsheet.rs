@@ -0,0 +1,172 @@
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::str::FromStr;
+
+use crate::constants::MAX_CONTACT_SHEET_CELLS;
+use crate::pic::timerange::TimeRange;
+
+/// A grid of evenly spaced moments of an animation in one image, the first moment is top left
+/// and the time runs from left to right, then from top to bottom
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContactSheet {
+    pub cols: u32,
+    pub rows: u32,
+}
+
+impl ContactSheet {
+    pub fn new(cols: u32, rows: u32) -> Result<Self, String> {
+        if cols == 0 || rows == 0 {
+            return Err(format!(
+                "A contact sheet of {}x{} has no cells, it needs at least 1 column and 1 row",
+                cols, rows
+            ));
+        }
+        match cols.checked_mul(rows) {
+            Some(1) => Err(
+                "A contact sheet of 1 cell shows a single moment, use --time instead".to_string(),
+            ),
+            Some(cells) if cells <= MAX_CONTACT_SHEET_CELLS => Ok(ContactSheet { cols, rows }),
+            _ => Err(format!(
+                "A contact sheet of {}x{} has too many cells, the maximum is {}",
+                cols, rows, MAX_CONTACT_SHEET_CELLS
+            )),
+        }
+    }
+
+    pub fn cells(&self) -> u32 {
+        self.cols * self.rows
+    }
+
+    /// The `T` value of every cell. The animation loops, so the end of the range is left out as
+    /// it looks the same as the start.
+    pub fn cell_times(&self, time_range: TimeRange) -> Vec<f32> {
+        let cells = self.cells();
+        (0..cells)
+            .map(|i| time_range.at(i as f32 / cells as f32))
+            .collect()
+    }
+
+    /// The width and height of a cell when the sheet is at most `w` x `h` pixels
+    pub fn cell_size(&self, w: u32, h: u32) -> Result<(u32, u32), String> {
+        let (cell_w, cell_h) = (w / self.cols, h / self.rows);
+        if cell_w < 2 || cell_h < 1 {
+            return Err(format!(
+                "A {}x{} image is too small for a contact sheet of {}, every cell needs at least 2x1 pixels",
+                w, h, self
+            ));
+        }
+        Ok((cell_w, cell_h))
+    }
+
+    /// Render every cell with `render(cell_w, cell_h, t)` and tile the RGBA8 pixels into one
+    /// image of at most `w` x `h` pixels, its width, height and pixels are returned. The sheet
+    /// is cropped to a whole number of cells.
+    pub fn compose(
+        &self,
+        w: u32,
+        h: u32,
+        time_range: TimeRange,
+        mut render: impl FnMut(u32, u32, f32) -> Vec<u8>,
+    ) -> Result<(u32, u32, Vec<u8>), String> {
+        let (cell_w, cell_h) = self.cell_size(w, h)?;
+        let (sheet_w, sheet_h) = (cell_w * self.cols, cell_h * self.rows);
+        let mut sheet = vec![0u8; (sheet_w * sheet_h * 4) as usize];
+        let row_len = (cell_w * 4) as usize;
+        for (i, t) in self.cell_times(time_range).into_iter().enumerate() {
+            let cell = render(cell_w, cell_h, t);
+            let (col, row) = (i as u32 % self.cols, i as u32 / self.cols);
+            for (y, line) in cell.chunks_exact(row_len).enumerate() {
+                let start = (((row * cell_h + y as u32) * sheet_w + col * cell_w) * 4) as usize;
+                sheet[start..start + row_len].copy_from_slice(line);
+            }
+        }
+        Ok((sheet_w, sheet_h, sheet))
+    }
+}
+
+impl Display for ContactSheet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        write!(f, "{}x{}", self.cols, self.rows)
+    }
+}
+
+impl FromStr for ContactSheet {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (cols, rows) = s.split_once(|c| c == 'x' || c == 'X').ok_or_else(|| {
+            format!(
+                "Cannot parse {}. A contact sheet is written as COLSxROWS",
+                s
+            )
+        })?;
+        let cols: u32 = cols
+            .trim()
+            .parse()
+            .map_err(|e| format!("Cannot parse {}. {}", s, e))?;
+        let rows: u32 = rows
+            .trim()
+            .parse()
+            .map_err(|e| format!("Cannot parse {}. {}", s, e))?;
+        ContactSheet::new(cols, rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contact_sheet_parse() {
+        assert_eq!("4x3".parse(), Ok(ContactSheet { cols: 4, rows: 3 }));
+        assert_eq!(" 2 X 1 ".parse(), Ok(ContactSheet { cols: 2, rows: 1 }));
+        assert!("4".parse::<ContactSheet>().is_err());
+        assert!("4x".parse::<ContactSheet>().is_err());
+        assert!("0x3".parse::<ContactSheet>().is_err());
+        assert!("1x1".parse::<ContactSheet>().is_err());
+        assert!("-2x3".parse::<ContactSheet>().is_err());
+        assert!("100x100".parse::<ContactSheet>().is_err());
+        assert!("65536x65536".parse::<ContactSheet>().is_err());
+        assert_eq!(ContactSheet::new(4, 3).unwrap().to_string(), "4x3");
+    }
+
+    #[test]
+    fn test_contact_sheet_cell_times() {
+        let sheet = ContactSheet::new(2, 2).unwrap();
+        assert_eq!(
+            sheet.cell_times(TimeRange::new(-1.0, 1.0)),
+            vec![-1.0, -0.5, 0.0, 0.5]
+        );
+        assert_eq!(
+            sheet.cell_times(TimeRange::new(0.0, 8.0)),
+            vec![0.0, 2.0, 4.0, 6.0]
+        );
+    }
+
+    #[test]
+    fn test_contact_sheet_compose() {
+        let sheet = ContactSheet::new(3, 2).unwrap();
+        assert_eq!(sheet.cell_size(10, 5), Ok((3, 2)));
+        assert!(sheet.cell_size(5, 5).is_err());
+        assert!(sheet.cell_size(6, 1).is_err());
+
+        let mut calls = vec![];
+        let (w, h, rgba8) = sheet
+            .compose(10, 5, TimeRange::new(0.0, 6.0), |cell_w, cell_h, t| {
+                calls.push((cell_w, cell_h, t));
+                vec![t as u8; (cell_w * cell_h * 4) as usize]
+            })
+            .unwrap();
+        assert_eq!((w, h), (9, 4));
+        assert_eq!(calls.len(), 6);
+        assert!(calls
+            .iter()
+            .all(|&(cell_w, cell_h, _)| (cell_w, cell_h) == (3, 2)));
+        // every pixel has the value of the cell it is in
+        for y in 0..h {
+            for x in 0..w {
+                let cell = (y / 2) * 3 + x / 3;
+                let i = ((y * w + x) * 4) as usize;
+                assert_eq!(rgba8[i..i + 4], [cell as u8; 4], "{} {}", x, y);
+            }
+        }
+    }
+}
@@ -3,21 +3,46 @@ use std::ops::Not;
 use std::str::FromStr;
 
 use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use simdeez::Simd;
 use variant_count::VariantCount;
 
-#[derive(Clone, Debug, PartialEq, ValueEnum, VariantCount)]
+use crate::pic::viewport::Viewport;
+
+#[derive(Clone, Debug, PartialEq, ValueEnum, VariantCount, Serialize, Deserialize)]
 pub enum CoordinateSystem {
     Polar,
     Cartesian,
+    /// Cartesian with the origin in the center, the shorter axis runs from -1 to 1 and the
+    /// longer one further so circles stay round on images that are not square
+    #[value(name = "aspect")]
+    AspectCorrect,
 }
 impl CoordinateSystem {
     pub fn list_all<'a>() -> Vec<String> {
         vec![
             CoordinateSystem::Polar.to_string(),
             CoordinateSystem::Cartesian.to_string(),
+            CoordinateSystem::AspectCorrect.to_string(),
         ]
     }
+
+    /// Whether X and Y are passed to the trees as they are, otherwise they are converted to
+    /// polar coordinates first
+    pub fn is_cartesian(&self) -> bool {
+        match self {
+            CoordinateSystem::Polar => false,
+            CoordinateSystem::Cartesian | CoordinateSystem::AspectCorrect => true,
+        }
+    }
+
+    /// The part of the plane that is rendered for `viewport` on an image of `w` x `h` pixels
+    pub fn viewport(&self, viewport: Viewport, w: u32, h: u32) -> Viewport {
+        match self {
+            CoordinateSystem::AspectCorrect => viewport.keep_aspect_ratio(w, h),
+            CoordinateSystem::Polar | CoordinateSystem::Cartesian => viewport,
+        }
+    }
 }
 
 impl Display for CoordinateSystem {
@@ -25,6 +50,7 @@ impl Display for CoordinateSystem {
         let x = match self {
             CoordinateSystem::Polar => "polar",
             CoordinateSystem::Cartesian => "cartesian",
+            CoordinateSystem::AspectCorrect => "aspect",
         };
         write!(f, "{}", x)
     }
@@ -36,6 +62,7 @@ impl FromStr for CoordinateSystem {
         match s.to_lowercase().as_ref() {
             "polar" => Ok(CoordinateSystem::Polar),
             "cartesian" => Ok(CoordinateSystem::Cartesian),
+            "aspect" => Ok(CoordinateSystem::AspectCorrect),
             _ => Err(format!("Cannot parse {}. Not a known coordinate system", s)),
         }
     }
@@ -46,7 +73,9 @@ impl Not for CoordinateSystem {
     fn not(self) -> Self::Output {
         match self {
             CoordinateSystem::Polar => CoordinateSystem::Cartesian,
-            CoordinateSystem::Cartesian => CoordinateSystem::Polar,
+            CoordinateSystem::Cartesian | CoordinateSystem::AspectCorrect => {
+                CoordinateSystem::Polar
+            }
         }
     }
 }
@@ -72,6 +101,7 @@ pub fn cartesian_to_polar<S: Simd>(x: S::Vf32, y: S::Vf32) -> (S::Vf32, S::Vf32)
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants::DEFAULT_VIEWPORT;
 
     #[test]
     fn test_coordsystem_parse() {
@@ -81,6 +111,7 @@ mod tests {
         assert_eq!("cartesian".parse(), Ok(CoordinateSystem::Cartesian));
         assert_eq!("Cartesian".parse(), Ok(CoordinateSystem::Cartesian));
         assert_eq!("CARTESIAN".parse(), Ok(CoordinateSystem::Cartesian));
+        assert_eq!("Aspect".parse(), Ok(CoordinateSystem::AspectCorrect));
         assert_eq!(
             "mercator".parse::<CoordinateSystem>(),
             Err("Cannot parse mercator. Not a known coordinate system".to_string())
@@ -91,11 +122,40 @@ mod tests {
     fn test_coordsystem_not() {
         assert_eq!(!CoordinateSystem::Polar, CoordinateSystem::Cartesian);
         assert_eq!(!CoordinateSystem::Cartesian, CoordinateSystem::Polar);
+        assert_eq!(!CoordinateSystem::AspectCorrect, CoordinateSystem::Polar);
     }
 
     #[test]
     fn test_coordsystem_display() {
         assert_eq!(&CoordinateSystem::Polar.to_string(), "polar");
         assert_eq!(&CoordinateSystem::Cartesian.to_string(), "cartesian");
+        assert_eq!(&CoordinateSystem::AspectCorrect.to_string(), "aspect");
+    }
+
+    #[test]
+    fn test_coordsystem_aspect() {
+        let extent = |coord: &CoordinateSystem, w, h| {
+            let viewport = coord.viewport(DEFAULT_VIEWPORT, w, h);
+            (
+                viewport.x_max - viewport.x_min,
+                viewport.y_max - viewport.y_min,
+            )
+        };
+        let aspect = CoordinateSystem::AspectCorrect;
+        // square images are the same as cartesian
+        assert_eq!(extent(&aspect, 512, 512), (2.0, 2.0));
+        // the longer axis grows with the aspect ratio, the shorter one stays at 2
+        assert_eq!(extent(&aspect, 1920, 1080), (2.0 * 1920.0 / 1080.0, 2.0));
+        assert_eq!(extent(&aspect, 800, 1600), (2.0, 4.0));
+        let (x_wide, _) = extent(&aspect, 1600, 900);
+        let (x_square, _) = extent(&aspect, 900, 900);
+        assert!(x_wide > x_square);
+        // the origin stays in the center
+        let viewport = aspect.viewport(DEFAULT_VIEWPORT, 1600, 900);
+        assert_eq!(viewport.x_min, -viewport.x_max);
+        assert_eq!(viewport.y_min, -viewport.y_max);
+        for coord in [CoordinateSystem::Polar, CoordinateSystem::Cartesian] {
+            assert_eq!(extent(&coord, 1920, 1080), (2.0, 2.0));
+        }
     }
 }
@@ -0,0 +1,444 @@
+use rand::prelude::*;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::parser::aptnode::APTNode;
+use crate::pic::actual_picture::ActualPicture;
+use crate::pic::coordinatesystem::{cartesian_to_polar, CoordinateSystem};
+use crate::pic::data::{for_each_row, lanes_in_row, render_frames, render_tiles, Channel, PicData};
+use crate::pic::generation::GenerationConfig;
+use crate::pic::pic::Pic;
+use crate::pic::renderoptions::RenderOptions;
+use crate::pic::timerange::TimeRange;
+use crate::pic::viewport::Viewport;
+use crate::vm::cache::StackMachineCache;
+use crate::vm::stackmachine::StackMachine;
+
+use serde::{Deserialize, Serialize};
+use simdeez::Simd;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CMYKData {
+    pub c: APTNode,
+    pub m: APTNode,
+    pub y: APTNode,
+    pub k: APTNode,
+    pub coord: CoordinateSystem,
+}
+
+impl CMYKData {
+    fn render<S: Simd, C: Channel>(
+        &self,
+        c_sm: &StackMachine<S>,
+        m_sm: &StackMachine<S>,
+        y_sm: &StackMachine<S>,
+        k_sm: &StackMachine<S>,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        rows: Range<u32>,
+    ) -> Vec<C> {
+        let viewport = self.coord.viewport(viewport, w, h);
+        unsafe {
+            let ts = S::set1_ps(t);
+            let wf = S::set1_ps(w as f32);
+            let hf = S::set1_ps(h as f32);
+
+            let vec_len = (w * (rows.end - rows.start) * 4) as usize;
+            let mut result = vec![C::from_u32(0); vec_len];
+
+            let max_len = *[
+                c_sm.stack_depth,
+                m_sm.stack_depth,
+                y_sm.stack_depth,
+                k_sm.stack_depth,
+            ]
+            .iter()
+            .max()
+            .unwrap();
+
+            let process = |stack: &mut Vec<S::Vf32>, (y_pixel, chunk): (usize, &mut [C])| {
+                let mut written = 0;
+                let y_pixel = rows.start as usize + y_pixel;
+                let y = S::set1_ps(viewport.y_at(y_pixel as u32, h));
+                let x_step = viewport.x_step(w);
+                let mut x = S::setzero_ps();
+                for i in (0..S::VF32_WIDTH).rev() {
+                    x[i] = viewport.x_min + (x_step * i as f32);
+                }
+                let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
+                for i in (0..w * 4).step_by(S::VF32_WIDTH * 4) {
+                    let (fx, fy) = options.fold::<S>(x, y);
+                    let (cs, ms, ys, ks) = if self.coord.is_cartesian() {
+                        let cs = (c_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps(0.5);
+                        let ms = (m_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps(0.5);
+                        let ys = (y_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps(0.5);
+                        let ks = (k_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps(0.5);
+                        (cs, ms, ys, ks)
+                    } else {
+                        let (fx, fy) = cartesian_to_polar::<S>(fx, fy);
+                        let cs = (c_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps(0.5);
+                        let ms = (m_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps(0.5);
+                        let ys = (y_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps(0.5);
+                        let ks = (k_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps(0.5);
+                        (cs, ms, ys, ks)
+                    };
+
+                    let (mut rs, mut gs, mut bs) = cmyk_to_rgb::<S>(cs, ms, ys, ks);
+                    rs = rs * S::set1_ps(C::FULL);
+                    gs = gs * S::set1_ps(C::FULL);
+                    bs = bs * S::set1_ps(C::FULL);
+                    for j in 0..lanes_in_row::<S>(i as usize / 4, w) {
+                        let ij4 = i as usize + j * 4;
+                        let r = options.color_space.encode::<C>(rs[j]);
+                        let g = options.color_space.encode::<C>(gs[j]);
+                        let b = options.color_space.encode::<C>(bs[j]);
+                        chunk[ij4] = r;
+                        chunk[ij4 + 1] = g;
+                        chunk[ij4 + 2] = b;
+                        chunk[ij4 + 3] = C::from_f32(C::FULL);
+                        written += 4;
+                    }
+                    x = x + x_step;
+                }
+                written
+            };
+            for_each_row::<S, C, _>(
+                &mut result,
+                4 * w as usize,
+                threaded,
+                max_len,
+                options.deadline,
+                process,
+            );
+
+            result
+        }
+    }
+}
+
+impl PicData for CMYKData {
+    fn new(
+        config: &GenerationConfig,
+        video: bool,
+        rng: &mut impl Rng,
+        pic_names: &Vec<&String>,
+    ) -> Pic {
+        let (c, coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        let (m, _coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        let (y, _coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        let (k, _coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        Pic::CMYK(CMYKData { c, m, y, k, coord })
+    }
+    fn get_rgba8<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let c_sm = StackMachine::<S>::build(&self.c);
+        let m_sm = StackMachine::<S>::build(&self.m);
+        let y_sm = StackMachine::<S>::build(&self.y);
+        let k_sm = StackMachine::<S>::build(&self.k);
+        self.render::<S, u8>(
+            &c_sm,
+            &m_sm,
+            &y_sm,
+            &k_sm,
+            threaded,
+            pics,
+            w,
+            h,
+            t,
+            viewport,
+            options,
+            0..h,
+        )
+    }
+    fn get_rgba8_tiled<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        tile_h: u32,
+        sink: &mut dyn FnMut(u32, &[u8]),
+    ) {
+        let c_sm = StackMachine::<S>::build(&self.c);
+        let m_sm = StackMachine::<S>::build(&self.m);
+        let y_sm = StackMachine::<S>::build(&self.y);
+        let k_sm = StackMachine::<S>::build(&self.k);
+        render_tiles(h, tile_h, sink, |rows| {
+            self.render::<S, u8>(
+                &c_sm,
+                &m_sm,
+                &y_sm,
+                &k_sm,
+                threaded,
+                pics.clone(),
+                w,
+                h,
+                t,
+                viewport,
+                options,
+                rows,
+            )
+        });
+    }
+    fn get_rgba16<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u16> {
+        let c_sm = StackMachine::<S>::build(&self.c);
+        let m_sm = StackMachine::<S>::build(&self.m);
+        let y_sm = StackMachine::<S>::build(&self.y);
+        let k_sm = StackMachine::<S>::build(&self.k);
+        self.render::<S, u16>(
+            &c_sm,
+            &m_sm,
+            &y_sm,
+            &k_sm,
+            threaded,
+            pics,
+            w,
+            h,
+            t,
+            viewport,
+            options,
+            0..h,
+        )
+    }
+    fn get_rgba8_cached<S: Simd>(
+        &self,
+        cache: &mut StackMachineCache<S>,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let c_sm = cache.get(&self.c);
+        let m_sm = cache.get(&self.m);
+        let y_sm = cache.get(&self.y);
+        let k_sm = cache.get(&self.k);
+        self.render::<S, u8>(
+            &c_sm,
+            &m_sm,
+            &y_sm,
+            &k_sm,
+            threaded,
+            pics,
+            w,
+            h,
+            t,
+            viewport,
+            options,
+            0..h,
+        )
+    }
+    fn get_video<S: Simd>(
+        &self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        fps: u16,
+        d_ms: f32,
+        time_range: TimeRange,
+        viewport: Viewport,
+        options: RenderOptions,
+        progress: Option<&mut dyn FnMut(usize, usize)>,
+    ) -> Vec<Vec<u8>> {
+        let c_sm = StackMachine::<S>::build(&self.c);
+        let m_sm = StackMachine::<S>::build(&self.m);
+        let y_sm = StackMachine::<S>::build(&self.y);
+        let k_sm = StackMachine::<S>::build(&self.k);
+        render_frames(fps, d_ms, time_range, options, progress, |t| {
+            self.render::<S, u8>(
+                &c_sm,
+                &m_sm,
+                &y_sm,
+                &k_sm,
+                true,
+                pics.clone(),
+                w,
+                h,
+                t,
+                viewport,
+                options,
+                0..h,
+            )
+        })
+    }
+    fn simplify<S: Simd>(
+        &mut self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+    ) {
+        self.c = self.c.constant_fold::<S>(
+            &self.coord,
+            pics.clone(),
+            None,
+            None,
+            Some(w),
+            Some(h),
+            Some(t),
+        );
+        self.m = self.m.constant_fold::<S>(
+            &self.coord,
+            pics.clone(),
+            None,
+            None,
+            Some(w),
+            Some(h),
+            Some(t),
+        );
+        self.y = self.y.constant_fold::<S>(
+            &self.coord,
+            pics.clone(),
+            None,
+            None,
+            Some(w),
+            Some(h),
+            Some(t),
+        );
+        self.k = self.k.constant_fold::<S>(
+            &self.coord,
+            pics.clone(),
+            None,
+            None,
+            Some(w),
+            Some(h),
+            Some(t),
+        );
+    }
+}
+
+/// The ink coverages, clamped to 0.0..=1.0, to red, green and blue
+fn cmyk_to_rgb<S: Simd>(
+    c: S::Vf32,
+    m: S::Vf32,
+    y: S::Vf32,
+    k: S::Vf32,
+) -> (S::Vf32, S::Vf32, S::Vf32) {
+    unsafe {
+        let zero = S::setzero_ps();
+        let one = S::set1_ps(1.0);
+        let clamp = |v: S::Vf32| S::min_ps(S::max_ps(v, zero), one);
+        let white = one - clamp(k);
+        (
+            (one - clamp(c)) * white,
+            (one - clamp(m)) * white,
+            (one - clamp(y)) * white,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pic_new_cmyk() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let pic = CMYKData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
+        match &pic {
+            Pic::CMYK(CMYKData {
+                c,
+                m,
+                y,
+                k,
+                coord: _,
+            }) => {
+                let len = c.get_children().unwrap().len();
+                assert!(len > 0 && len < 60);
+
+                let len = m.get_children().unwrap().len();
+                assert!(len > 0 && len < 60);
+
+                let len = y.get_children().unwrap().len();
+                assert!(len > 0 && len < 60);
+
+                let len = k.get_children().unwrap().len();
+                assert!(len > 0 && len < 60);
+            }
+            _ => {
+                panic!("wrong type");
+            }
+        };
+    }
+}
@@ -1,6 +1,6 @@
 use rand::prelude::*;
-use rand::rngs::StdRng;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::Arc;
 
 use crate::constants::{
@@ -10,152 +10,309 @@ use crate::parser::aptnode::APTNode;
 use crate::pic::actual_picture::ActualPicture;
 use crate::pic::color::{get_random_color, lerp_color, Color};
 use crate::pic::coordinatesystem::{cartesian_to_polar, CoordinateSystem};
-use crate::pic::data::PicData;
+use crate::pic::data::{for_each_row, lanes_in_row, render_frames, render_tiles, Channel, PicData};
+use crate::pic::generation::GenerationConfig;
+use crate::pic::gradientwrap::GradientWrap;
 use crate::pic::pic::Pic;
+use crate::pic::renderoptions::RenderOptions;
+use crate::pic::timerange::TimeRange;
+use crate::pic::viewport::Viewport;
+use crate::vm::cache::StackMachineCache;
 use crate::vm::stackmachine::StackMachine;
 
-use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use simdeez::Simd;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct GradientData {
     pub colors: Vec<(Color, bool)>,
     pub index: APTNode,
     pub coord: CoordinateSystem,
+    pub wrap: GradientWrap,
+    /// The colors move through the gradient with T, a sweep of T from -1.0 to 1.0 moves them
+    /// the whole gradient, even when the index does not use T
+    pub cycle: bool,
 }
 
-impl PicData for GradientData {
-    fn new(min: usize, max: usize, video: bool, rng: &mut StdRng, pic_names: &Vec<&String>) -> Pic {
-        //todo cleanup
-        //color theory?
-        let num_colors = rng.gen_range(PIC_GRADIENT_COUNT_MIN..PIC_GRADIENT_COUNT_MAX);
-        let mut colors = Vec::with_capacity(num_colors);
-
-        for _ in 0..num_colors {
-            let stop = rng.gen_range(0..PIC_GRADIENT_STOP_CHANCE);
-            if stop == 0 {
-                colors.push((get_random_color(rng), true));
-            } else {
-                colors.push((get_random_color(rng), false));
-            }
-        }
+impl GradientData {
+    /// A gradient pic with the colors of `palette` and a random index tree, every color has
+    /// the same chance to be a stop as in a random gradient
+    pub fn from_palette(
+        palette: &[Color],
+        config: &GenerationConfig,
+        video: bool,
+        rng: &mut impl Rng,
+        pic_names: &Vec<&String>,
+    ) -> Pic {
+        let colors = palette
+            .iter()
+            .map(|color| (*color, rng.gen_range(0..PIC_GRADIENT_STOP_CHANCE) == 0))
+            .collect();
+        GradientData::from_colors(colors, config, video, rng, pic_names)
+    }
 
-        let (tree, coord) =
-            APTNode::create_random_tree(rng.gen_range(min..max), video, rng, pic_names);
+    fn from_colors(
+        colors: Vec<(Color, bool)>,
+        config: &GenerationConfig,
+        video: bool,
+        rng: &mut impl Rng,
+        pic_names: &Vec<&String>,
+    ) -> Pic {
+        let (tree, coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
         Pic::Gradient(GradientData {
             colors: colors,
             index: tree,
             coord,
+            wrap: GradientWrap::Wrap,
+            cycle: false,
         })
     }
-    fn get_rgba8<S: Simd>(
+
+    /// Where each of the colors sits in the gradient (0.0..=1.0), the first color at 0.0 and the
+    /// last one at 1.0. The other colors are spread evenly, but a stop color sits just after the
+    /// color before it, so the gradient changes abruptly there. The positions are strictly
+    /// increasing, no part of the gradient has a width of 0.
+    pub fn positions(&self) -> Vec<f32> {
+        let last = self.colors.len() - 1;
+        let segments = 1 + self.colors[1..last]
+            .iter()
+            .filter(|(_, stop)| !stop)
+            .count();
+        let step = 1.0 / segments as f32;
+        // even when all the colors after a color are stops, they stay before the next color
+        let spacing = step / self.colors.len() as f32;
+        let mut positions = Vec::<f32>::with_capacity(self.colors.len());
+        positions.push(0.0);
+        let mut pos = 0.0;
+        for (_, stop) in self.colors[1..last].iter() {
+            if *stop {
+                positions.push(*positions.last().unwrap() + spacing);
+            } else {
+                pos += step;
+                positions.push(pos);
+            }
+        }
+        positions.push(1.0);
+        positions
+    }
+
+    /// The colors of the gradient for the indices 0..PIC_GRADIENT_SIZE
+    pub fn lookup_table(&self) -> Vec<Color> {
+        let positions = self.positions();
+        (0..PIC_GRADIENT_SIZE)
+            .map(|i| {
+                let pct = i as f32 / PIC_GRADIENT_SIZE as f32;
+                // pct stays below 1.0, the position of the last color
+                let color2pos = positions.iter().position(|n| *n >= pct).unwrap();
+                if color2pos == 0 {
+                    self.colors[0].0
+                } else {
+                    let pct1 = positions[color2pos - 1];
+                    let pct2 = positions[color2pos];
+                    lerp_color(
+                        self.colors[color2pos - 1].0,
+                        self.colors[color2pos].0,
+                        (pct - pct1) / (pct2 - pct1),
+                    )
+                }
+            })
+            .collect()
+    }
+
+    fn render<S: Simd, C: Channel>(
         &self,
+        sm: &StackMachine<S>,
         threaded: bool,
         pics: Arc<HashMap<String, ActualPicture>>,
         w: u32,
         h: u32,
         t: f32,
-    ) -> Vec<u8> {
+        viewport: Viewport,
+        options: RenderOptions,
+        rows: Range<u32>,
+    ) -> Vec<C> {
+        let viewport = self.coord.viewport(viewport, w, h);
         unsafe {
             let ts = S::set1_ps(t);
+            let shift = S::set1_ps(if self.cycle { t + 1.0 } else { 1.0 });
             let wf = S::set1_ps(w as f32);
             let hf = S::set1_ps(h as f32);
-            let vec_len = (w * h * 4) as usize;
-            let mut result = Vec::<u8>::with_capacity(vec_len);
-            result.set_len(vec_len);
-            let sm = StackMachine::<S>::build(&self.index);
+            let vec_len = (w * (rows.end - rows.start) * 4) as usize;
+            let mut result = vec![C::from_u32(0); vec_len];
             /*
             let mut min = 999999.0;
             let mut max = -99999.0;
             */
 
-            let color_count = self.colors.iter().filter(|(_, stop)| !stop).count();
-            let mut gradient = Vec::<Color>::new(); //todo actually compute this
-            let step = (PIC_GRADIENT_SIZE as f32 / color_count as f32) / PIC_GRADIENT_SIZE as f32;
-            let mut positions = Vec::<f32>::new();
-            positions.push(0.0);
-            let mut pos = step;
-            for i in 1..self.colors.len() - 1 {
-                let (_, stop) = self.colors[i];
-                if stop {
-                    positions.push(*positions.last().unwrap());
-                } else {
-                    positions.push(pos);
-                    pos += step;
-                }
-            }
-            positions.push(1.0);
+            let gradient = self.lookup_table();
 
-            for i in 0..PIC_GRADIENT_SIZE {
-                let pct = i as f32 / PIC_GRADIENT_SIZE as f32;
-                let color2pos = positions.iter().position(|n| *n >= pct).unwrap();
-                if color2pos == 0 {
-                    gradient.push(self.colors[0].0);
-                } else {
-                    let color1 = self.colors[color2pos - 1].0;
-                    let color2 = self.colors[color2pos].0;
-                    let pct2 = positions[color2pos];
-                    let pct1 = positions[color2pos - 1];
-                    let range = pct2 - pct1;
-                    let pct = (pct - pct1) / range;
-                    gradient.push(lerp_color(color1, color2, pct));
-                }
-            }
-
-            let process = |(y_pixel, chunk): (usize, &mut [u8])| {
-                let mut stack = Vec::with_capacity(sm.instructions.len());
-                stack.set_len(sm.instructions.len());
-
-                let y = S::set1_ps((y_pixel as f32 / h as f32) * 2.0 - 1.0);
-                let x_step = 2.0 / (w - 1) as f32;
+            let process = |stack: &mut Vec<S::Vf32>, (y_pixel, chunk): (usize, &mut [C])| {
+                let mut written = 0;
+                let y_pixel = rows.start as usize + y_pixel;
+                let y = S::set1_ps(viewport.y_at(y_pixel as u32, h));
+                let x_step = viewport.x_step(w);
                 let mut x = S::setzero_ps();
                 for i in (0..S::VF32_WIDTH).rev() {
-                    x[i] = -1.0 + (x_step * i as f32);
+                    x[i] = viewport.x_min + (x_step * i as f32);
                 }
                 let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
-                let chunk_len = chunk.len();
                 for i in (0..w * 4).step_by(S::VF32_WIDTH * 4) {
-                    let v = if self.coord == CoordinateSystem::Cartesian {
-                        sm.execute(&mut stack, pics.clone(), x, y, ts, wf, hf)
+                    let (fx, fy) = options.fold::<S>(x, y);
+                    let v = if self.coord.is_cartesian() {
+                        sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
                     } else {
-                        let (r, theta) = cartesian_to_polar::<S>(x, y);
-                        sm.execute(&mut stack, pics.clone(), r, theta, ts, wf, hf)
+                        let (r, theta) = cartesian_to_polar::<S>(fx, fy);
+                        sm.execute(stack, pics.clone(), r, theta, ts, wf, hf)
                     };
-                    let scaled_v = (v + S::set1_ps(1.0)) * S::set1_ps(0.5);
+                    let scaled_v = (v + shift) * S::set1_ps(0.5);
                     let index = S::cvtps_epi32(scaled_v * S::set1_ps(PIC_GRADIENT_SIZE as f32));
 
-                    for j in 0..S::VF32_WIDTH {
-                        let j4: usize = j * 4;
-                        let ij4 = i as usize + j4;
-                        if ij4 >= chunk_len {
-                            break;
-                        }
-                        let c = gradient[index[j] as usize % PIC_GRADIENT_SIZE];
-                        chunk[ij4] = (c.r * 255.0) as u8;
-                        chunk[ij4 + 1] = (c.g * 255.0) as u8;
-                        chunk[ij4 + 2] = (c.b * 255.0) as u8;
-                        chunk[ij4 + 3] = 255 as u8;
+                    for j in 0..lanes_in_row::<S>(i as usize / 4, w) {
+                        let ij4 = i as usize + j * 4;
+                        let c = gradient[self.wrap.index(index[j], PIC_GRADIENT_SIZE)];
+                        chunk[ij4] = options.color_space.encode::<C>(c.r * C::FULL);
+                        chunk[ij4 + 1] = options.color_space.encode::<C>(c.g * C::FULL);
+                        chunk[ij4 + 2] = options.color_space.encode::<C>(c.b * C::FULL);
+                        chunk[ij4 + 3] = C::from_f32(C::FULL);
+                        written += 4;
                     }
                     x = x + x_step;
                 }
+                written
             };
 
-            if threaded {
-                result
-                    .par_chunks_mut(4 * w as usize)
-                    .enumerate()
-                    .for_each(process);
-            } else {
-                result
-                    .chunks_exact_mut(4 * w as usize)
-                    .enumerate()
-                    .for_each(process);
-            }
+            for_each_row::<S, C, _>(
+                &mut result,
+                4 * w as usize,
+                threaded,
+                sm.stack_depth,
+                options.deadline,
+                process,
+            );
 
             // println!("min:{} max:{} range:{}",min,max,max-min);
             result
         }
     }
+}
+
+impl PicData for GradientData {
+    fn new(
+        config: &GenerationConfig,
+        video: bool,
+        rng: &mut impl Rng,
+        pic_names: &Vec<&String>,
+    ) -> Pic {
+        if let Some(palette) = &config.palette {
+            return GradientData::from_palette(palette, config, video, rng, pic_names);
+        }
+        //todo cleanup
+        //color theory?
+        let num_colors = rng.gen_range(PIC_GRADIENT_COUNT_MIN..PIC_GRADIENT_COUNT_MAX);
+        let mut colors = Vec::with_capacity(num_colors);
+
+        for _ in 0..num_colors {
+            let stop = rng.gen_range(0..PIC_GRADIENT_STOP_CHANCE);
+            if stop == 0 {
+                colors.push((get_random_color(rng), true));
+            } else {
+                colors.push((get_random_color(rng), false));
+            }
+        }
+        GradientData::from_colors(colors, config, video, rng, pic_names)
+    }
+    fn get_rgba8<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let sm = StackMachine::<S>::build(&self.index);
+        self.render::<S, u8>(&sm, threaded, pics, w, h, t, viewport, options, 0..h)
+    }
+    fn get_rgba8_tiled<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        tile_h: u32,
+        sink: &mut dyn FnMut(u32, &[u8]),
+    ) {
+        let sm = StackMachine::<S>::build(&self.index);
+        render_tiles(h, tile_h, sink, |rows| {
+            self.render::<S, u8>(
+                &sm,
+                threaded,
+                pics.clone(),
+                w,
+                h,
+                t,
+                viewport,
+                options,
+                rows,
+            )
+        });
+    }
+    fn get_rgba16<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u16> {
+        let sm = StackMachine::<S>::build(&self.index);
+        self.render::<S, u16>(&sm, threaded, pics, w, h, t, viewport, options, 0..h)
+    }
+    fn get_rgba8_cached<S: Simd>(
+        &self,
+        cache: &mut StackMachineCache<S>,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let sm = cache.get(&self.index);
+        self.render::<S, u8>(&sm, threaded, pics, w, h, t, viewport, options, 0..h)
+    }
+    fn get_video<S: Simd>(
+        &self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        fps: u16,
+        d_ms: f32,
+        time_range: TimeRange,
+        viewport: Viewport,
+        options: RenderOptions,
+        progress: Option<&mut dyn FnMut(usize, usize)>,
+    ) -> Vec<Vec<u8>> {
+        let sm = StackMachine::<S>::build(&self.index);
+        render_frames(fps, d_ms, time_range, options, progress, |t| {
+            self.render::<S, u8>(&sm, true, pics.clone(), w, h, t, viewport, options, 0..h)
+        })
+    }
     fn simplify<S: Simd>(
         &mut self,
         pics: Arc<HashMap<String, ActualPicture>>,
@@ -172,17 +329,29 @@ impl PicData for GradientData {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::pic::palette::parse_palette;
+    use crate::pic::pic::pic_get_rgba8_runtime_select;
+    use rand::rngs::mock::StepRng;
 
     #[test]
     fn test_pic_new_gradient() {
         let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
-        let pic = GradientData::new(0, 60, false, &mut rng, &vec![&"eye.jpg".to_string()]);
+        let pic = GradientData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
         match &pic {
             Pic::Gradient(GradientData {
                 colors,
                 index,
                 coord: _coord,
+                wrap,
+                cycle,
             }) => {
+                assert_eq!(wrap, &GradientWrap::Wrap);
+                assert!(!cycle);
                 let len = colors.len();
                 assert!(len > 1 && len < 10);
                 let len = index.get_children().unwrap().len();
@@ -193,4 +362,176 @@ mod tests {
             }
         };
     }
+
+    #[test]
+    fn test_pic_gradient_from_palette() {
+        let palette = parse_palette("#ff0000\n#00ff00\n#0000ff\n").unwrap();
+        // halfway every range, so none of the colors is a stop
+        let mut rng = StepRng::new(u64::MAX / 2, 0);
+        let pic = GradientData::from_palette(
+            &palette,
+            &GenerationConfig::new(1, 2),
+            false,
+            &mut rng,
+            &vec![],
+        );
+        let pic = match pic {
+            Pic::Gradient(data) => {
+                assert_eq!(
+                    data.colors,
+                    palette
+                        .iter()
+                        .map(|color| (*color, false))
+                        .collect::<Vec<_>>()
+                );
+                Pic::Gradient(GradientData {
+                    index: APTNode::X,
+                    coord: CoordinateSystem::Cartesian,
+                    ..data
+                })
+            }
+            _ => panic!("wrong type"),
+        };
+        let rgba8 =
+            pic_get_rgba8_runtime_select(&pic, false, Arc::new(HashMap::new()), 64, 1, 0.0, 1);
+        assert_eq!(&rgba8[0..4], &[255, 0, 0, 255]);
+        assert!(rgba8[63 * 4 + 2] > 240);
+        // from red to green and then from green to blue
+        for pixel in rgba8.chunks(4) {
+            assert!(pixel[0] == 0 || pixel[2] == 0, "{:?}", pixel);
+        }
+        assert!(rgba8.chunks(4).any(|pixel| pixel[0] > 0 && pixel[1] > 0));
+        assert!(rgba8.chunks(4).any(|pixel| pixel[1] > 0 && pixel[2] > 0));
+    }
+
+    /// A gradient from black to white, every color a bit lighter than the one before it
+    fn gray_ramp(stops: &[bool]) -> GradientData {
+        let last = (stops.len() - 1) as f32;
+        GradientData {
+            colors: stops
+                .iter()
+                .enumerate()
+                .map(|(i, stop)| {
+                    let v = i as f32 / last;
+                    (Color::new(v, v, v, 1.0), *stop)
+                })
+                .collect(),
+            index: APTNode::X,
+            coord: CoordinateSystem::Cartesian,
+            wrap: GradientWrap::Wrap,
+            cycle: false,
+        }
+    }
+
+    fn assert_smooth(data: &GradientData) {
+        let positions = data.positions();
+        assert_eq!(positions.len(), data.colors.len());
+        assert_eq!(positions[0], 0.0);
+        assert_eq!(*positions.last().unwrap(), 1.0);
+        for pair in positions.windows(2) {
+            assert!(pair[0] < pair[1], "{:?}", positions);
+        }
+        let table = data.lookup_table();
+        assert_eq!(table.len(), PIC_GRADIENT_SIZE);
+        assert_eq!(table[0], data.colors[0].0);
+        for pair in table.windows(2) {
+            assert!(
+                pair[1].r.is_finite() && pair[0].r <= pair[1].r,
+                "{:?}",
+                pair
+            );
+        }
+    }
+
+    #[test]
+    fn test_gradient_positions_two_colors() {
+        assert_eq!(gray_ramp(&[false, false]).positions(), vec![0.0, 1.0]);
+        assert_eq!(gray_ramp(&[true, true]).positions(), vec![0.0, 1.0]);
+        assert_smooth(&gray_ramp(&[false, false]));
+        assert_smooth(&gray_ramp(&[false, true]));
+    }
+
+    #[test]
+    fn test_gradient_positions_ten_colors() {
+        let data = gray_ramp(&[false; 10]);
+        let positions = data.positions();
+        for (i, position) in positions.iter().enumerate() {
+            assert!((position - i as f32 / 9.0).abs() < 1e-6, "{:?}", positions);
+        }
+        assert_smooth(&data);
+        // a stop color sits just after the color before it
+        let mut stops = [false; 10];
+        stops[3] = true;
+        stops[4] = true;
+        stops[9] = true;
+        let data = gray_ramp(&stops);
+        let positions = data.positions();
+        assert!(positions[3] - positions[2] < 0.02);
+        assert!(positions[4] - positions[3] < 0.02);
+        assert_smooth(&data);
+        // every color but the first and the last is a stop
+        let mut stops = [true; 10];
+        stops[0] = false;
+        assert_smooth(&gray_ramp(&stops));
+    }
+
+    #[test]
+    fn test_gradient_wrap_out_of_range() {
+        let pictures = Arc::new(HashMap::new());
+        // 1.5 is index 640 and -1.5 is index -128 of the 512 colors
+        for (v, wrap, expected) in [
+            (1.5, GradientWrap::Wrap, 128),
+            (1.5, GradientWrap::Clamp, 511),
+            (1.5, GradientWrap::Mirror, 383),
+            (-1.5, GradientWrap::Wrap, 384),
+            (-1.5, GradientWrap::Clamp, 0),
+            (-1.5, GradientWrap::Mirror, 127),
+        ] {
+            let data = GradientData {
+                index: APTNode::Constant(v),
+                wrap,
+                ..gray_ramp(&[false, false])
+            };
+            let color = data.lookup_table()[expected];
+            let rgba8 = pic_get_rgba8_runtime_select(
+                &Pic::Gradient(data),
+                false,
+                pictures.clone(),
+                8,
+                1,
+                0.0,
+                1,
+            );
+            let expected = u8::from_f32(color.r * u8::FULL);
+            assert!(
+                rgba8.chunks(4).all(|pixel| pixel[0] == expected),
+                "{:?}",
+                rgba8
+            );
+        }
+    }
+
+    #[test]
+    fn test_gradient_cycle() {
+        let pictures = Arc::new(HashMap::new());
+        let render = |cycle: bool, t: f32| {
+            let pic = Pic::Gradient(GradientData {
+                cycle,
+                ..gray_ramp(&[false, false, false])
+            });
+            pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 16, 4, t, 1)
+        };
+        assert_eq!(render(false, 0.0), render(false, 0.5));
+        assert_eq!(render(true, 0.0), render(false, 0.0));
+        assert_ne!(render(true, 0.0), render(true, 0.5));
+        // a sweep of T moves the colors through the whole gradient
+        assert_eq!(render(true, -1.0), render(true, 1.0));
+        let data = gray_ramp(&[false, false]);
+        assert!(!Pic::Gradient(data.clone()).can_animate());
+        assert!(Pic::Gradient(GradientData {
+            cycle: true,
+            ..data
+        })
+        .can_animate());
+    }
 }
@@ -1,107 +1,259 @@
 use rand::prelude::*;
-use rand::rngs::StdRng;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::Arc;
 
 use crate::parser::aptnode::APTNode;
 use crate::pic::actual_picture::ActualPicture;
 use crate::pic::coordinatesystem::{cartesian_to_polar, CoordinateSystem};
-use crate::pic::data::PicData;
+use crate::pic::data::{
+    for_each_row, lanes_in_row, new_stack, render_frames, render_tiles, Channel, PicData,
+};
+use crate::pic::generation::GenerationConfig;
+use crate::pic::normalization::Normalization;
 use crate::pic::pic::Pic;
+use crate::pic::renderoptions::RenderOptions;
+use crate::pic::timerange::TimeRange;
+use crate::pic::viewport::Viewport;
+use crate::vm::cache::StackMachineCache;
 use crate::vm::stackmachine::StackMachine;
 
-use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use simdeez::Simd;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct GrayscaleData {
     pub c: APTNode,
     pub coord: CoordinateSystem,
+    pub normalization: Normalization,
 }
 
-impl PicData for GrayscaleData {
-    fn new(min: usize, max: usize, video: bool, rng: &mut StdRng, pic_names: &Vec<&String>) -> Pic {
-        let (tree, coord) =
-            APTNode::create_random_tree(rng.gen_range(min..max), video, rng, pic_names);
-        Pic::Grayscale(GrayscaleData { c: tree, coord })
+impl GrayscaleData {
+    /// The lowest and highest value of the first row, scaled to 0.0..C::FULL
+    fn first_row_range<S: Simd, C: Channel>(
+        &self,
+        sm: &StackMachine<S>,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> (f32, f32) {
+        unsafe {
+            let ts = S::set1_ps(t);
+            let wf = S::set1_ps(w as f32);
+            let hf = S::set1_ps(h as f32);
+            let mut stack = new_stack::<S>(sm.stack_depth);
+
+            let mut min = std::f32::MAX;
+            let mut max = std::f32::MIN;
+            let y = S::set1_ps(viewport.y_min);
+            let x_step = viewport.x_step(w);
+            let mut x = S::setzero_ps();
+            for i in (0..S::VF32_WIDTH).rev() {
+                x[i] = viewport.x_min + (x_step * i as f32);
+            }
+            let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
+            for i in (0..w as usize).step_by(S::VF32_WIDTH) {
+                let (fx, fy) = options.fold::<S>(x, y);
+                let v = if self.coord.is_cartesian() {
+                    sm.execute(&mut stack, pics.clone(), fx, fy, ts, wf, hf)
+                } else {
+                    let (r, theta) = cartesian_to_polar::<S>(fx, fy);
+                    sm.execute(&mut stack, pics.clone(), r, theta, ts, wf, hf)
+                };
+                let cs = (v + S::set1_ps(1.0)) * S::set1_ps(C::FULL / 2.0);
+                for j in 0..lanes_in_row::<S>(i, w) {
+                    min = min.min(cs[j]);
+                    max = max.max(cs[j]);
+                }
+                x = x + x_step;
+            }
+            (min, max)
+        }
     }
-    fn get_rgba8<S: Simd>(
+
+    fn render<S: Simd, C: Channel>(
         &self,
+        sm: &StackMachine<S>,
         threaded: bool,
         pics: Arc<HashMap<String, ActualPicture>>,
         w: u32,
         h: u32,
         t: f32,
-    ) -> Vec<u8> {
+        viewport: Viewport,
+        options: RenderOptions,
+        rows: Range<u32>,
+    ) -> Vec<C> {
+        let viewport = self.coord.viewport(viewport, w, h);
         unsafe {
             let ts = S::set1_ps(t);
             let wf = S::set1_ps(w as f32);
             let hf = S::set1_ps(h as f32);
-            let vec_len = (w * h * 4) as usize;
-            let mut result = Vec::<u8>::with_capacity(vec_len);
-            result.set_len(vec_len);
-            let sm = StackMachine::<S>::build(&self.c);
-            /*
-            let mut min = 999999.0;
-            let mut max = -99999.0;
-            */
-
-            let process = |(y_pixel, chunk): (usize, &mut [u8])| {
-                let mut stack = Vec::with_capacity(sm.instructions.len());
-                stack.set_len(sm.instructions.len());
+            let vec_len = (w * (rows.end - rows.start) * 4) as usize;
+            let mut result = vec![C::from_u32(0); vec_len];
+            let range = if self.normalization == Normalization::Auto {
+                self.first_row_range::<S, C>(sm, pics.clone(), w, h, t, viewport, options)
+            } else {
+                (0.0, C::FULL)
+            };
 
-                let y = S::set1_ps((y_pixel as f32 / h as f32) * 2.0 - 1.0);
-                let x_step = 2.0 / (w - 1) as f32;
+            let process = |stack: &mut Vec<S::Vf32>, (y_pixel, chunk): (usize, &mut [C])| {
+                let mut written = 0;
+                let y_pixel = rows.start as usize + y_pixel;
+                let y = S::set1_ps(viewport.y_at(y_pixel as u32, h));
+                let x_step = viewport.x_step(w);
                 let mut x = S::setzero_ps();
                 for i in (0..S::VF32_WIDTH).rev() {
-                    x[i] = -1.0 + (x_step * i as f32);
+                    x[i] = viewport.x_min + (x_step * i as f32);
                 }
                 let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
-                let chunk_len = chunk.len();
                 for i in (0..w * 4).step_by(S::VF32_WIDTH * 4) {
-                    let v = if self.coord == CoordinateSystem::Cartesian {
-                        sm.execute(&mut stack, pics.clone(), x, y, ts, wf, hf)
+                    let (fx, fy) = options.fold::<S>(x, y);
+                    let v = if self.coord.is_cartesian() {
+                        sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
                     } else {
-                        let (r, theta) = cartesian_to_polar::<S>(x, y);
-                        sm.execute(&mut stack, pics.clone(), r, theta, ts, wf, hf)
+                        let (r, theta) = cartesian_to_polar::<S>(fx, fy);
+                        sm.execute(stack, pics.clone(), r, theta, ts, wf, hf)
                     };
 
-                    // if v[0] > max { max = v[0]; }
-                    // if v[0] < min { min = v[0]; }
+                    let cs = (v + S::set1_ps(1.0)) * S::set1_ps(C::FULL / 2.0);
 
-                    let cs = (v + S::set1_ps(1.0)) * S::set1_ps(127.5);
-
-                    for j in 0..S::VF32_WIDTH {
-                        let j4: usize = j * 4;
-                        let ij4 = i as usize + j4;
-                        if ij4 >= chunk_len {
-                            break;
-                        }
-                        let c = (cs[j] as i32 % 256) as u8;
+                    for j in 0..lanes_in_row::<S>(i as usize / 4, w) {
+                        let ij4 = i as usize + j * 4;
+                        let c = self.normalization.normalize::<C>(cs[j], range);
+                        let c = options.color_space.encode::<C>(c);
                         chunk[ij4] = c;
                         chunk[ij4 + 1] = c;
                         chunk[ij4 + 2] = c;
-                        chunk[ij4 + 3] = 255 as u8;
+                        chunk[ij4 + 3] = C::from_f32(C::FULL);
+                        written += 4;
                     }
                     x = x + x_step;
                 }
+                written
             };
 
-            if threaded {
-                result
-                    .par_chunks_mut(4 * w as usize)
-                    .enumerate()
-                    .for_each(process);
-            } else {
-                result
-                    .chunks_exact_mut(4 * w as usize)
-                    .enumerate()
-                    .for_each(process);
-            }
-            // println!("min:{} max:{} range:{}",min, max, max-min);
+            for_each_row::<S, C, _>(
+                &mut result,
+                4 * w as usize,
+                threaded,
+                sm.stack_depth,
+                options.deadline,
+                process,
+            );
             result
         }
     }
+}
+
+impl PicData for GrayscaleData {
+    fn new(
+        config: &GenerationConfig,
+        video: bool,
+        rng: &mut impl Rng,
+        pic_names: &Vec<&String>,
+    ) -> Pic {
+        let (tree, coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        Pic::Grayscale(GrayscaleData {
+            c: tree,
+            coord,
+            normalization: Normalization::Wrap,
+        })
+    }
+    fn get_rgba8<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let sm = StackMachine::<S>::build(&self.c);
+        self.render::<S, u8>(&sm, threaded, pics, w, h, t, viewport, options, 0..h)
+    }
+    fn get_rgba8_tiled<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        tile_h: u32,
+        sink: &mut dyn FnMut(u32, &[u8]),
+    ) {
+        let sm = StackMachine::<S>::build(&self.c);
+        render_tiles(h, tile_h, sink, |rows| {
+            self.render::<S, u8>(
+                &sm,
+                threaded,
+                pics.clone(),
+                w,
+                h,
+                t,
+                viewport,
+                options,
+                rows,
+            )
+        });
+    }
+    fn get_rgba16<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u16> {
+        let sm = StackMachine::<S>::build(&self.c);
+        self.render::<S, u16>(&sm, threaded, pics, w, h, t, viewport, options, 0..h)
+    }
+    fn get_rgba8_cached<S: Simd>(
+        &self,
+        cache: &mut StackMachineCache<S>,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let sm = cache.get(&self.c);
+        self.render::<S, u8>(&sm, threaded, pics, w, h, t, viewport, options, 0..h)
+    }
+    fn get_video<S: Simd>(
+        &self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        fps: u16,
+        d_ms: f32,
+        time_range: TimeRange,
+        viewport: Viewport,
+        options: RenderOptions,
+        progress: Option<&mut dyn FnMut(usize, usize)>,
+    ) -> Vec<Vec<u8>> {
+        let sm = StackMachine::<S>::build(&self.c);
+        render_frames(fps, d_ms, time_range, options, progress, |t| {
+            self.render::<S, u8>(&sm, true, pics.clone(), w, h, t, viewport, options, 0..h)
+        })
+    }
     fn simplify<S: Simd>(
         &mut self,
         pics: Arc<HashMap<String, ActualPicture>>,
@@ -118,13 +270,28 @@ impl PicData for GrayscaleData {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants::{DEFAULT_RENDER_OPTIONS, DEFAULT_VIEWPORT};
+    use simdeez::avx2::*;
+    use simdeez::scalar::*;
+    use simdeez::sse2::*;
+    use simdeez::sse41::*;
 
     #[test]
     fn test_pic_new_grayscale() {
         let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
-        let pic = GrayscaleData::new(0, 60, false, &mut rng, &vec![&"eye.jpg".to_string()]);
+        let pic = GrayscaleData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
         match &pic {
-            Pic::Grayscale(GrayscaleData { c, coord: _coord }) => {
+            Pic::Grayscale(GrayscaleData {
+                c,
+                coord: _coord,
+                normalization,
+            }) => {
+                assert_eq!(normalization, &Normalization::Wrap);
                 let len = c.get_children().unwrap().len();
                 assert!(len > 0 && len < 60);
             }
@@ -133,4 +300,48 @@ mod tests {
             }
         };
     }
+
+    simd_runtime_generate!(
+        fn impl_grayscale_normalization() {
+            let mut data = GrayscaleData {
+                c: APTNode::Mul(vec![APTNode::X, APTNode::Constant(2.0)]),
+                coord: CoordinateSystem::Cartesian,
+                normalization: Normalization::Wrap,
+            };
+            let w = 9;
+            let first_and_last = |data: &GrayscaleData| {
+                let rgba8 = data.get_rgba8::<S>(
+                    false,
+                    Arc::new(HashMap::new()),
+                    w,
+                    2,
+                    0.0,
+                    DEFAULT_VIEWPORT,
+                    DEFAULT_RENDER_OPTIONS,
+                );
+                (rgba8[0], rgba8[(w as usize - 1) * 4])
+            };
+            // -2.0 and 2.0 end up outside of the 0..255 range
+            assert_eq!(first_and_last(&data), (129, 126));
+            data.normalization = Normalization::Clamp;
+            assert_eq!(first_and_last(&data), (0, 255));
+            data.normalization = Normalization::Auto;
+            assert_eq!(first_and_last(&data), (0, 255));
+            let rgba8 = data.get_rgba8::<S>(
+                false,
+                Arc::new(HashMap::new()),
+                w,
+                2,
+                0.0,
+                DEFAULT_VIEWPORT,
+                DEFAULT_RENDER_OPTIONS,
+            );
+            assert_eq!(rgba8[4 * 4], 127);
+        }
+    );
+
+    #[test]
+    fn test_grayscale_normalization() {
+        impl_grayscale_normalization_runtime_select();
+    }
 }
@@ -1,19 +1,27 @@
 use rand::prelude::*;
-use rand::rngs::StdRng;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::Arc;
 
 use crate::parser::aptnode::APTNode;
 use crate::pic::actual_picture::ActualPicture;
 use crate::pic::coordinatesystem::{cartesian_to_polar, CoordinateSystem};
-use crate::pic::data::PicData;
+use crate::pic::data::{
+    for_each_row, lanes_in_row, plane_vector, render_frames, render_planes, render_tiles,
+    split_channels, vectors_per_row, Channel, HalfPlane, PicData,
+};
+use crate::pic::generation::GenerationConfig;
 use crate::pic::pic::Pic;
+use crate::pic::renderoptions::RenderOptions;
+use crate::pic::timerange::TimeRange;
+use crate::pic::viewport::Viewport;
+use crate::vm::cache::StackMachineCache;
 use crate::vm::stackmachine::StackMachine;
 
-use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use simdeez::Simd;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct HSVData {
     pub h: APTNode,
     pub s: APTNode,
@@ -21,119 +29,331 @@ pub struct HSVData {
     pub coord: CoordinateSystem,
 }
 
-impl PicData for HSVData {
-    fn new(min: usize, max: usize, video: bool, rng: &mut StdRng, pic_names: &Vec<&String>) -> Pic {
-        let (h, coord) =
-            APTNode::create_random_tree(rng.gen_range(min..max), video, rng, pic_names);
-        let (s, _coord) =
-            APTNode::create_random_tree(rng.gen_range(min..max), video, rng, pic_names);
-        let (v, _coord) =
-            APTNode::create_random_tree(rng.gen_range(min..max), video, rng, pic_names);
-        Pic::HSV(HSVData { h, s, v, coord })
-    }
-    fn get_rgba8<S: Simd>(
+impl HSVData {
+    fn render<S: Simd, C: Channel>(
         &self,
+        h_sm: &StackMachine<S>,
+        s_sm: &StackMachine<S>,
+        v_sm: &StackMachine<S>,
         threaded: bool,
         pics: Arc<HashMap<String, ActualPicture>>,
         w: u32,
         h: u32,
         t: f32,
-    ) -> Vec<u8> {
+        viewport: Viewport,
+        options: RenderOptions,
+        rows: Range<u32>,
+    ) -> Vec<C> {
+        let viewport = self.coord.viewport(viewport, w, h);
         unsafe {
             let ts = S::set1_ps(t);
             let wf = S::set1_ps(w as f32);
             let hf = S::set1_ps(h as f32);
-            let vec_len = (w * h * 4) as usize;
-            let mut result = Vec::<u8>::with_capacity(vec_len);
-            result.set_len(vec_len);
+            let vec_len = (w * (rows.end - rows.start) * 4) as usize;
+            let mut result = vec![C::from_u32(0); vec_len];
+
+            let max_len = *[h_sm.stack_depth, s_sm.stack_depth, v_sm.stack_depth]
+                .iter()
+                .max()
+                .unwrap();
 
-            let h_sm = StackMachine::<S>::build(&self.h);
-            let s_sm = StackMachine::<S>::build(&self.s);
-            let v_sm = StackMachine::<S>::build(&self.v);
-            let max_len = *[
-                h_sm.instructions.len(),
-                s_sm.instructions.len(),
-                v_sm.instructions.len(),
-            ]
-            .iter()
-            .max()
-            .unwrap();
+            // the channels that are subsampled are evaluated at half the resolution up front
+            let halves: Vec<Option<HalfPlane>> = [("H", h_sm), ("S", s_sm), ("V", v_sm)]
+                .iter()
+                .map(|&(name, sm)| {
+                    options.subsample.contains(name).then(|| {
+                        HalfPlane::render(
+                            sm,
+                            &self.coord,
+                            pics.clone(),
+                            w,
+                            h,
+                            t,
+                            viewport,
+                            options,
+                            rows.clone(),
+                            threaded,
+                        )
+                    })
+                })
+                .collect();
+            let subsampled = halves.iter().any(Option::is_some);
 
-            let process = |(y_pixel, chunk): (usize, &mut [u8])| {
-                let mut stack = Vec::with_capacity(max_len);
-                stack.set_len(max_len);
-                let y = S::set1_ps((y_pixel as f32 / h as f32) * 2.0 - 1.0);
-                let x_step = 2.0 / (w - 1) as f32;
+            // for few rows of large trees every channel gets tasks of its own
+            let machines = [h_sm, s_sm, v_sm];
+            let planes = if !subsampled && split_channels(threaded, &rows, &machines) {
+                Some(render_planes(
+                    &machines,
+                    &self.coord,
+                    pics.clone(),
+                    w,
+                    h,
+                    t,
+                    viewport,
+                    options,
+                    rows.clone(),
+                ))
+            } else {
+                None
+            };
+            let vectors = vectors_per_row::<S>(w);
+
+            let process = |stack: &mut Vec<S::Vf32>, (y_pixel, chunk): (usize, &mut [C])| {
+                let mut written = 0;
+                let row = y_pixel;
+                let y_pixel = rows.start as usize + y_pixel;
+                let y = S::set1_ps(viewport.y_at(y_pixel as u32, h));
+                let x_step = viewport.x_step(w);
                 let mut x = S::setzero_ps();
                 for i in (0..S::VF32_WIDTH).rev() {
-                    x[i] = -1.0 + (x_step * i as f32);
+                    x[i] = viewport.x_min + (x_step * i as f32);
                 }
                 let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
-                let chunk_len = chunk.len();
-                for i in (0..w * 4).step_by(S::VF32_WIDTH * 4) {
-                    let (hs, ss, vs) = if self.coord == CoordinateSystem::Cartesian {
-                        let hs = (h_sm.execute(&mut stack, pics.clone(), x, y, ts, wf, hf)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(0.5);
-                        let ss = (s_sm.execute(&mut stack, pics.clone(), x, y, ts, wf, hf)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(0.5);
-                        let vs = (v_sm.execute(&mut stack, pics.clone(), x, y, ts, wf, hf)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(0.5);
-                        (hs, ss, vs)
+                for (vector, i) in (0..w * 4).step_by(S::VF32_WIDTH * 4).enumerate() {
+                    let (fx, fy) = options.fold::<S>(x, y);
+                    let (hs, ss, vs) = if let Some(planes) = &planes {
+                        let at = (row * vectors + vector) * S::VF32_WIDTH;
+                        (
+                            plane_vector::<S>(&planes[0], at),
+                            plane_vector::<S>(&planes[1], at),
+                            plane_vector::<S>(&planes[2], at),
+                        )
                     } else {
-                        let (x, y) = cartesian_to_polar::<S>(x, y);
-                        let hs = (h_sm.execute(&mut stack, pics.clone(), x, y, ts, wf, hf)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(0.5);
-                        let ss = (s_sm.execute(&mut stack, pics.clone(), x, y, ts, wf, hf)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(0.5);
-                        let vs = (v_sm.execute(&mut stack, pics.clone(), x, y, ts, wf, hf)
-                            + S::set1_ps(1.0))
-                            * S::set1_ps(0.5);
-                        (hs, ss, vs)
+                        let (fx, fy) = if self.coord.is_cartesian() {
+                            (fx, fy)
+                        } else {
+                            cartesian_to_polar::<S>(fx, fy)
+                        };
+                        let mut channel =
+                            |half: &Option<HalfPlane>, sm: &StackMachine<S>| match half {
+                                Some(half) => half.vector::<S>(i as usize / 4, y_pixel),
+                                None => sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf),
+                            };
+                        (
+                            channel(&halves[0], h_sm),
+                            channel(&halves[1], s_sm),
+                            channel(&halves[2], v_sm),
+                        )
                     };
+                    let hs = (hs + S::set1_ps(1.0)) * S::set1_ps(0.5);
+                    let ss = (ss + S::set1_ps(1.0)) * S::set1_ps(0.5);
+                    let vs = (vs + S::set1_ps(1.0)) * S::set1_ps(0.5);
 
                     let (mut rs, mut gs, mut bs) =
                         hsv_to_rgb::<S>(wrap_0_1::<S>(hs), wrap_0_1::<S>(ss), wrap_0_1::<S>(vs));
-                    rs = rs * S::set1_ps(255.0);
-                    gs = gs * S::set1_ps(255.0);
-                    bs = bs * S::set1_ps(255.0);
-                    for j in 0..S::VF32_WIDTH {
-                        let j4: usize = j * 4;
-                        let ij4 = i as usize + j4;
-                        if ij4 >= chunk_len {
-                            break;
-                        }
-                        let r = (rs[j] as i32 % 255) as u8;
-                        let g = (gs[j] as i32 % 255) as u8;
-                        let b = (bs[j] as i32 % 255) as u8;
+                    rs = rs * S::set1_ps(C::FULL);
+                    gs = gs * S::set1_ps(C::FULL);
+                    bs = bs * S::set1_ps(C::FULL);
+                    for j in 0..lanes_in_row::<S>(i as usize / 4, w) {
+                        let ij4 = i as usize + j * 4;
+                        let r = options.color_space.encode::<C>(rs[j]);
+                        let g = options.color_space.encode::<C>(gs[j]);
+                        let b = options.color_space.encode::<C>(bs[j]);
                         chunk[ij4] = r;
                         chunk[ij4 + 1] = g;
                         chunk[ij4 + 2] = b;
-                        chunk[ij4 + 3] = 255 as u8;
+                        chunk[ij4 + 3] = C::from_f32(C::FULL);
+                        written += 4;
                     }
                     x = x + x_step;
                 }
+                written
             };
-            if threaded {
-                result
-                    .par_chunks_mut(4 * w as usize)
-                    .enumerate()
-                    .for_each(process);
-            } else {
-                result
-                    .chunks_exact_mut(4 * w as usize)
-                    .enumerate()
-                    .for_each(process);
-            }
+            for_each_row::<S, C, _>(
+                &mut result,
+                4 * w as usize,
+                threaded,
+                max_len,
+                options.deadline,
+                process,
+            );
 
             //   println!("img elapsed:{}", now.elapsed().as_millis());
             result
         }
     }
+}
+
+impl PicData for HSVData {
+    fn new(
+        config: &GenerationConfig,
+        video: bool,
+        rng: &mut impl Rng,
+        pic_names: &Vec<&String>,
+    ) -> Pic {
+        let (h, coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        let (s, _coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        let (v, _coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        Pic::HSV(HSVData { h, s, v, coord })
+    }
+    fn get_rgba8<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let h_sm = StackMachine::<S>::build(&self.h);
+        let s_sm = StackMachine::<S>::build(&self.s);
+        let v_sm = StackMachine::<S>::build(&self.v);
+        self.render::<S, u8>(
+            &h_sm,
+            &s_sm,
+            &v_sm,
+            threaded,
+            pics,
+            w,
+            h,
+            t,
+            viewport,
+            options,
+            0..h,
+        )
+    }
+    fn get_rgba8_tiled<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        tile_h: u32,
+        sink: &mut dyn FnMut(u32, &[u8]),
+    ) {
+        let h_sm = StackMachine::<S>::build(&self.h);
+        let s_sm = StackMachine::<S>::build(&self.s);
+        let v_sm = StackMachine::<S>::build(&self.v);
+        render_tiles(h, tile_h, sink, |rows| {
+            self.render::<S, u8>(
+                &h_sm,
+                &s_sm,
+                &v_sm,
+                threaded,
+                pics.clone(),
+                w,
+                h,
+                t,
+                viewport,
+                options,
+                rows,
+            )
+        });
+    }
+    fn get_rgba16<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u16> {
+        let h_sm = StackMachine::<S>::build(&self.h);
+        let s_sm = StackMachine::<S>::build(&self.s);
+        let v_sm = StackMachine::<S>::build(&self.v);
+        self.render::<S, u16>(
+            &h_sm,
+            &s_sm,
+            &v_sm,
+            threaded,
+            pics,
+            w,
+            h,
+            t,
+            viewport,
+            options,
+            0..h,
+        )
+    }
+    fn get_rgba8_cached<S: Simd>(
+        &self,
+        cache: &mut StackMachineCache<S>,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let h_sm = cache.get(&self.h);
+        let s_sm = cache.get(&self.s);
+        let v_sm = cache.get(&self.v);
+        self.render::<S, u8>(
+            &h_sm,
+            &s_sm,
+            &v_sm,
+            threaded,
+            pics,
+            w,
+            h,
+            t,
+            viewport,
+            options,
+            0..h,
+        )
+    }
+    fn get_video<S: Simd>(
+        &self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        fps: u16,
+        d_ms: f32,
+        time_range: TimeRange,
+        viewport: Viewport,
+        options: RenderOptions,
+        progress: Option<&mut dyn FnMut(usize, usize)>,
+    ) -> Vec<Vec<u8>> {
+        let h_sm = StackMachine::<S>::build(&self.h);
+        let s_sm = StackMachine::<S>::build(&self.s);
+        let v_sm = StackMachine::<S>::build(&self.v);
+        render_frames(fps, d_ms, time_range, options, progress, |t| {
+            self.render::<S, u8>(
+                &h_sm,
+                &s_sm,
+                &v_sm,
+                true,
+                pics.clone(),
+                w,
+                h,
+                t,
+                viewport,
+                options,
+                0..h,
+            )
+        })
+    }
     fn simplify<S: Simd>(
         &mut self,
         pics: Arc<HashMap<String, ActualPicture>>,
@@ -242,7 +462,12 @@ mod tests {
     #[test]
     fn test_pic_new_hsv() {
         let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
-        let pic = HSVData::new(0, 60, false, &mut rng, &vec![&"eye.jpg".to_string()]);
+        let pic = HSVData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
         match &pic {
             Pic::HSV(HSVData { h, s, v, coord: _ }) => {
                 let len = h.get_children().unwrap().len();
@@ -0,0 +1,413 @@
+use rand::prelude::*;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::parser::aptnode::APTNode;
+use crate::pic::actual_picture::ActualPicture;
+use crate::pic::coordinatesystem::{cartesian_to_polar, CoordinateSystem};
+use crate::pic::data::{for_each_row, lanes_in_row, render_frames, render_tiles, Channel, PicData};
+use crate::pic::generation::GenerationConfig;
+use crate::pic::pic::Pic;
+use crate::pic::renderoptions::RenderOptions;
+use crate::pic::timerange::TimeRange;
+use crate::pic::viewport::Viewport;
+use crate::vm::cache::StackMachineCache;
+use crate::vm::stackmachine::StackMachine;
+
+use serde::{Deserialize, Serialize};
+use simdeez::Simd;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LABData {
+    pub l: APTNode,
+    pub a: APTNode,
+    pub b: APTNode,
+    pub coord: CoordinateSystem,
+}
+
+impl LABData {
+    fn render<S: Simd, C: Channel>(
+        &self,
+        l_sm: &StackMachine<S>,
+        a_sm: &StackMachine<S>,
+        b_sm: &StackMachine<S>,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        rows: Range<u32>,
+    ) -> Vec<C> {
+        let viewport = self.coord.viewport(viewport, w, h);
+        unsafe {
+            let ts = S::set1_ps(t);
+            let wf = S::set1_ps(w as f32);
+            let hf = S::set1_ps(h as f32);
+            let vec_len = (w * (rows.end - rows.start) * 4) as usize;
+            let mut result = vec![C::from_u32(0); vec_len];
+
+            let max_len = *[l_sm.stack_depth, a_sm.stack_depth, b_sm.stack_depth]
+                .iter()
+                .max()
+                .unwrap();
+
+            let process = |stack: &mut Vec<S::Vf32>, (y_pixel, chunk): (usize, &mut [C])| {
+                let mut written = 0;
+                let y_pixel = rows.start as usize + y_pixel;
+                let y = S::set1_ps(viewport.y_at(y_pixel as u32, h));
+                let x_step = viewport.x_step(w);
+                let mut x = S::setzero_ps();
+                for i in (0..S::VF32_WIDTH).rev() {
+                    x[i] = viewport.x_min + (x_step * i as f32);
+                }
+                let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
+                for i in (0..w * 4).step_by(S::VF32_WIDTH * 4) {
+                    let (fx, fy) = options.fold::<S>(x, y);
+                    let (ls, a_s, bs) = if self.coord.is_cartesian() {
+                        let ls = (l_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps(50.0);
+                        let a_s = a_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            * S::set1_ps(128.0);
+                        let bs = b_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            * S::set1_ps(128.0);
+                        (ls, a_s, bs)
+                    } else {
+                        let (fx, fy) = cartesian_to_polar::<S>(fx, fy);
+                        let ls = (l_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps(50.0);
+                        let a_s = a_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            * S::set1_ps(128.0);
+                        let bs = b_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            * S::set1_ps(128.0);
+                        (ls, a_s, bs)
+                    };
+
+                    let (mut rs, mut gs, mut bs) = lab_to_rgb::<S>(ls, a_s, bs);
+                    rs = rs * S::set1_ps(C::FULL);
+                    gs = gs * S::set1_ps(C::FULL);
+                    bs = bs * S::set1_ps(C::FULL);
+                    for j in 0..lanes_in_row::<S>(i as usize / 4, w) {
+                        let ij4 = i as usize + j * 4;
+                        let r = options.color_space.encode::<C>(rs[j]);
+                        let g = options.color_space.encode::<C>(gs[j]);
+                        let b = options.color_space.encode::<C>(bs[j]);
+                        chunk[ij4] = r;
+                        chunk[ij4 + 1] = g;
+                        chunk[ij4 + 2] = b;
+                        chunk[ij4 + 3] = C::from_f32(C::FULL);
+                        written += 4;
+                    }
+                    x = x + x_step;
+                }
+                written
+            };
+            for_each_row::<S, C, _>(
+                &mut result,
+                4 * w as usize,
+                threaded,
+                max_len,
+                options.deadline,
+                process,
+            );
+
+            result
+        }
+    }
+}
+
+impl PicData for LABData {
+    fn new(
+        config: &GenerationConfig,
+        video: bool,
+        rng: &mut impl Rng,
+        pic_names: &Vec<&String>,
+    ) -> Pic {
+        let (l, coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        let (a, _coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        let (b, _coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        Pic::LAB(LABData { l, a, b, coord })
+    }
+    fn get_rgba8<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let l_sm = StackMachine::<S>::build(&self.l);
+        let a_sm = StackMachine::<S>::build(&self.a);
+        let b_sm = StackMachine::<S>::build(&self.b);
+        self.render::<S, u8>(
+            &l_sm,
+            &a_sm,
+            &b_sm,
+            threaded,
+            pics,
+            w,
+            h,
+            t,
+            viewport,
+            options,
+            0..h,
+        )
+    }
+    fn get_rgba8_tiled<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        tile_h: u32,
+        sink: &mut dyn FnMut(u32, &[u8]),
+    ) {
+        let l_sm = StackMachine::<S>::build(&self.l);
+        let a_sm = StackMachine::<S>::build(&self.a);
+        let b_sm = StackMachine::<S>::build(&self.b);
+        render_tiles(h, tile_h, sink, |rows| {
+            self.render::<S, u8>(
+                &l_sm,
+                &a_sm,
+                &b_sm,
+                threaded,
+                pics.clone(),
+                w,
+                h,
+                t,
+                viewport,
+                options,
+                rows,
+            )
+        });
+    }
+    fn get_rgba16<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u16> {
+        let l_sm = StackMachine::<S>::build(&self.l);
+        let a_sm = StackMachine::<S>::build(&self.a);
+        let b_sm = StackMachine::<S>::build(&self.b);
+        self.render::<S, u16>(
+            &l_sm,
+            &a_sm,
+            &b_sm,
+            threaded,
+            pics,
+            w,
+            h,
+            t,
+            viewport,
+            options,
+            0..h,
+        )
+    }
+    fn get_rgba8_cached<S: Simd>(
+        &self,
+        cache: &mut StackMachineCache<S>,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let l_sm = cache.get(&self.l);
+        let a_sm = cache.get(&self.a);
+        let b_sm = cache.get(&self.b);
+        self.render::<S, u8>(
+            &l_sm,
+            &a_sm,
+            &b_sm,
+            threaded,
+            pics,
+            w,
+            h,
+            t,
+            viewport,
+            options,
+            0..h,
+        )
+    }
+    fn get_video<S: Simd>(
+        &self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        fps: u16,
+        d_ms: f32,
+        time_range: TimeRange,
+        viewport: Viewport,
+        options: RenderOptions,
+        progress: Option<&mut dyn FnMut(usize, usize)>,
+    ) -> Vec<Vec<u8>> {
+        let l_sm = StackMachine::<S>::build(&self.l);
+        let a_sm = StackMachine::<S>::build(&self.a);
+        let b_sm = StackMachine::<S>::build(&self.b);
+        render_frames(fps, d_ms, time_range, options, progress, |t| {
+            self.render::<S, u8>(
+                &l_sm,
+                &a_sm,
+                &b_sm,
+                true,
+                pics.clone(),
+                w,
+                h,
+                t,
+                viewport,
+                options,
+                0..h,
+            )
+        })
+    }
+    fn simplify<S: Simd>(
+        &mut self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+    ) {
+        self.l = self.l.constant_fold::<S>(
+            &self.coord,
+            pics.clone(),
+            None,
+            None,
+            Some(w),
+            Some(h),
+            Some(t),
+        );
+        self.a = self.a.constant_fold::<S>(
+            &self.coord,
+            pics.clone(),
+            None,
+            None,
+            Some(w),
+            Some(h),
+            Some(t),
+        );
+        self.b = self.b.constant_fold::<S>(
+            &self.coord,
+            pics.clone(),
+            None,
+            None,
+            Some(w),
+            Some(h),
+            Some(t),
+        );
+    }
+}
+
+/// The inverse of the cube root that Lab uses, with the linear part near black
+#[inline(always)]
+fn lab_f_inv<S: Simd>(t: S::Vf32) -> S::Vf32 {
+    unsafe {
+        let delta = 6.0 / 29.0;
+        let cube = t * t * t;
+        let linear = S::set1_ps(3.0 * delta * delta) * (t - S::set1_ps(4.0 / 29.0));
+        let mask = S::cmpgt_ps(t, S::set1_ps(delta));
+        S::blendv_ps(linear, cube, mask)
+    }
+}
+
+/// The sRGB gamma of a linear channel that is clamped to 0.0..=1.0
+#[inline(always)]
+fn srgb_gamma<S: Simd>(c: S::Vf32) -> S::Vf32 {
+    unsafe {
+        let c = S::min_ps(S::max_ps(c, S::setzero_ps()), S::set1_ps(1.0));
+        let linear = c * S::set1_ps(12.92);
+        let mut curve = S::setzero_ps();
+        for i in 0..S::VF32_WIDTH {
+            curve[i] = 1.055 * c[i].powf(1.0 / 2.4) - 0.055;
+        }
+        let mask = S::cmpgt_ps(c, S::set1_ps(0.0031308));
+        S::blendv_ps(linear, curve, mask)
+    }
+}
+
+/// CIE L*a*b*, with the D65 white point, to sRGB in 0.0..=1.0, out of gamut colors are clamped
+fn lab_to_rgb<S: Simd>(l: S::Vf32, a: S::Vf32, b: S::Vf32) -> (S::Vf32, S::Vf32, S::Vf32) {
+    unsafe {
+        let fy = (l + S::set1_ps(16.0)) / S::set1_ps(116.0);
+        let fx = fy + a / S::set1_ps(500.0);
+        let fz = fy - b / S::set1_ps(200.0);
+        let x = lab_f_inv::<S>(fx) * S::set1_ps(0.95047);
+        let y = lab_f_inv::<S>(fy);
+        let z = lab_f_inv::<S>(fz) * S::set1_ps(1.08883);
+
+        let r = S::set1_ps(3.2406) * x - S::set1_ps(1.5372) * y - S::set1_ps(0.4986) * z;
+        let g = S::set1_ps(-0.9689) * x + S::set1_ps(1.8758) * y + S::set1_ps(0.0415) * z;
+        let b = S::set1_ps(0.0557) * x - S::set1_ps(0.2040) * y + S::set1_ps(1.0570) * z;
+        (srgb_gamma::<S>(r), srgb_gamma::<S>(g), srgb_gamma::<S>(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pic_new_lab() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let pic = LABData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
+        match &pic {
+            Pic::LAB(LABData { l, a, b, coord: _ }) => {
+                let len = l.get_children().unwrap().len();
+                assert!(len > 0 && len < 60);
+
+                let len = a.get_children().unwrap().len();
+                assert!(len > 0 && len < 60);
+
+                let len = b.get_children().unwrap().len();
+                assert!(len > 0 && len < 60);
+            }
+            _ => {
+                panic!("wrong type");
+            }
+        };
+    }
+}
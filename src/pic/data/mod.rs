@@ -1,25 +1,48 @@
+pub mod cmyk;
 pub mod gradient;
 pub mod grayscale;
 pub mod hsv;
+pub mod lab;
 pub mod mono;
 pub mod rgb;
+pub mod rgba;
 
-use rand::rngs::StdRng;
+use rand::Rng;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::Arc;
+use std::time::Instant;
 
+use crate::constants::{PIC_CHANNEL_SPLIT_MAX_ROWS, PIC_CHANNEL_SPLIT_MIN_INSTRUCTIONS};
 use crate::pic::actual_picture::ActualPicture;
+use crate::pic::colorspace::ColorSpace;
+use crate::pic::coordinatesystem::{cartesian_to_polar, CoordinateSystem};
+use crate::pic::generation::GenerationConfig;
 pub use crate::pic::pic::Pic;
+use crate::pic::renderoptions::RenderOptions;
+use crate::pic::timerange::TimeRange;
+use crate::pic::viewport::Viewport;
+use crate::vm::cache::StackMachineCache;
+use crate::vm::stackmachine::StackMachine;
+pub use cmyk::CMYKData;
 pub use gradient::GradientData;
 pub use grayscale::GrayscaleData;
 pub use hsv::HSVData;
+pub use lab::LABData;
 pub use mono::MonoData;
 pub use rgb::RGBData;
+pub use rgba::RGBAData;
 
+use rayon::prelude::*;
 use simdeez::Simd;
 
 pub trait PicData {
-    fn new(min: usize, max: usize, video: bool, rng: &mut StdRng, pic_names: &Vec<&String>) -> Pic;
+    fn new(
+        config: &GenerationConfig,
+        video: bool,
+        rng: &mut impl Rng,
+        pic_names: &Vec<&String>,
+    ) -> Pic;
     fn get_rgba8<S: Simd>(
         &self,
         threaded: bool,
@@ -27,7 +50,54 @@ pub trait PicData {
         w: u32,
         h: u32,
         t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
     ) -> Vec<u8>;
+    fn get_rgba8_tiled<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        tile_h: u32,
+        sink: &mut dyn FnMut(u32, &[u8]),
+    );
+    fn get_rgba16<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u16>;
+    fn get_rgba8_cached<S: Simd>(
+        &self,
+        cache: &mut StackMachineCache<S>,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8>;
+    fn get_video<S: Simd>(
+        &self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        fps: u16,
+        d_ms: f32,
+        time_range: TimeRange,
+        viewport: Viewport,
+        options: RenderOptions,
+        progress: Option<&mut dyn FnMut(usize, usize)>,
+    ) -> Vec<Vec<u8>>;
     fn simplify<S: Simd>(
         &mut self,
         pics: Arc<HashMap<String, ActualPicture>>,
@@ -36,3 +106,802 @@ pub trait PicData {
         t: f32,
     );
 }
+
+/// A channel of a rendered pixel, u8 for rgba8 and u16 for rgba16 images
+pub trait Channel: Copy + Send + Sync + Into<u32> {
+    /// The value of a channel at full brightness
+    const FULL: f32;
+    /// Values outside of 0.0..=FULL are clamped
+    fn from_f32(c: f32) -> Self;
+    /// Values outside of 0.0..=FULL wrap around
+    fn wrap(c: f32) -> Self;
+    fn from_u32(v: u32) -> Self;
+}
+
+impl Channel for u8 {
+    const FULL: f32 = 255.0;
+    #[inline(always)]
+    fn from_f32(c: f32) -> Self {
+        c.max(0.0).min(255.0) as u8
+    }
+    #[inline(always)]
+    fn wrap(c: f32) -> Self {
+        (c as i32 % 256) as u8
+    }
+    fn from_u32(v: u32) -> Self {
+        v as u8
+    }
+}
+
+impl Channel for u16 {
+    const FULL: f32 = 65535.0;
+    #[inline(always)]
+    fn from_f32(c: f32) -> Self {
+        c.max(0.0).min(65535.0) as u16
+    }
+    #[inline(always)]
+    fn wrap(c: f32) -> Self {
+        (c as i32 % 65536) as u16
+    }
+    fn from_u32(v: u32) -> Self {
+        v as u16
+    }
+}
+
+/// The T values for each frame of a video of `d_ms` milliseconds, sweeping from the min of
+/// `time_range` towards its max
+pub fn frame_times(fps: u16, d_ms: f32, time_range: TimeRange) -> Vec<f32> {
+    sub_frame_times(fps, d_ms, time_range, 1)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// The T values of the `motion_blur` sub-frames of each frame of frame_times, spread evenly
+/// over the time until the next frame. The first sub-frame is at the T of the frame.
+pub fn sub_frame_times(
+    fps: u16,
+    d_ms: f32,
+    time_range: TimeRange,
+    motion_blur: u32,
+) -> Vec<Vec<f32>> {
+    let frames = (fps as f32 * (d_ms / 1000.0)) as i32;
+    let n = motion_blur.max(1);
+    (0..frames)
+        .map(|i| {
+            (0..n)
+                .map(|j| time_range.at((i as f32 + j as f32 / n as f32) / frames as f32))
+                .collect()
+        })
+        .collect()
+}
+
+/// The average of the sub-frames of a frame. The channels are summed in f32 and rounded once,
+/// so a single sub-frame comes out as it went in. The sub-frames of the `color_space` are
+/// averaged in linear light.
+pub fn average_sub_frames<I>(sub_frames: I, color_space: ColorSpace) -> Vec<u8>
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    let mut sub_frames = sub_frames.into_iter();
+    let first = match sub_frames.next() {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+    let mut sum: Option<Vec<f32>> = None;
+    let mut n = 1;
+    for frame in sub_frames {
+        let sum = sum.get_or_insert_with(|| {
+            first
+                .iter()
+                .enumerate()
+                .map(|(i, c)| color_space.rgba_channel(i).decode(*c))
+                .collect()
+        });
+        for (i, (s, c)) in sum.iter_mut().zip(frame).enumerate() {
+            *s += color_space.rgba_channel(i).decode(c);
+        }
+        n += 1;
+    }
+    match sum {
+        Some(sum) => sum
+            .iter()
+            .enumerate()
+            .map(|(i, s)| color_space.rgba_channel(i).encode_mean(*s, n))
+            .collect(),
+        None => first,
+    }
+}
+
+/// A stack of `len` vectors for `StackMachine::execute`, it is made once per rayon task so
+/// zeroing it costs next to nothing
+pub(crate) fn new_stack<S: Simd>(len: usize) -> Vec<S::Vf32> {
+    vec![unsafe { S::setzero_ps() }; len]
+}
+
+/// Call `process` with every row of `row_len` channels of `result` and its index, on rayon
+/// tasks when `threaded`. A stack of `stack_len` vectors is made once per task, or once in
+/// all when not threaded, and reused for all the rows of that task. `process` returns the
+/// number of channels it wrote, debug builds check that every row is written completely.
+/// The rows that did not start by `deadline` are skipped and stay as they are.
+pub(crate) fn for_each_row<S, C, F>(
+    result: &mut [C],
+    row_len: usize,
+    threaded: bool,
+    stack_len: usize,
+    deadline: Option<Instant>,
+    process: F,
+) where
+    S: Simd,
+    C: Send,
+    F: Fn(&mut Vec<S::Vf32>, (usize, &mut [C])) -> usize + Send + Sync,
+{
+    debug_assert_eq!(result.len() % row_len, 0, "the rows don't cover the buffer");
+    let process_row = |stack: &mut Vec<S::Vf32>, row: (usize, &mut [C])| {
+        if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            return;
+        }
+        let y = row.0;
+        let written = process(stack, row);
+        debug_assert_eq!(written, row_len, "row {} is not written completely", y);
+    };
+    if threaded {
+        result
+            .par_chunks_mut(row_len)
+            .enumerate()
+            .for_each_init(|| new_stack::<S>(stack_len), process_row);
+    } else {
+        let mut stack = new_stack::<S>(stack_len);
+        result
+            .chunks_exact_mut(row_len)
+            .enumerate()
+            .for_each(|row| process_row(&mut stack, row));
+    }
+}
+
+/// How many lanes of the vector that starts at pixel `x_pixel` are inside a row of `w`
+/// pixels. Only the last vector of a row that is no multiple of the lanes wide sticks out,
+/// the lanes past the row are not written or they would end up in the next row.
+pub(crate) fn lanes_in_row<S: Simd>(x_pixel: usize, w: u32) -> usize {
+    (w as usize).saturating_sub(x_pixel).min(S::VF32_WIDTH)
+}
+
+/// Whether the channels of a render of `rows` are better evaluated by `render_planes` than
+/// together per row: a threaded render of few rows, so there are few tasks to spread over the
+/// cores, of trees that take long enough per pixel to make up for the planes
+pub(crate) fn split_channels<S: Simd>(
+    threaded: bool,
+    rows: &Range<u32>,
+    machines: &[&StackMachine<S>],
+) -> bool {
+    let instructions = machines
+        .iter()
+        .map(|sm| sm.instructions.len())
+        .max()
+        .unwrap_or(0);
+    threaded
+        && rows.end - rows.start <= PIC_CHANNEL_SPLIT_MAX_ROWS
+        && instructions >= PIC_CHANNEL_SPLIT_MIN_INSTRUCTIONS
+}
+
+/// The number of vectors a row of `w` pixels is evaluated in
+pub(crate) fn vectors_per_row<S: Simd>(w: u32) -> usize {
+    (w as usize + S::VF32_WIDTH - 1) / S::VF32_WIDTH
+}
+
+/// The values of every stack machine for the pixels of `rows`, one plane per machine with the
+/// rows padded to whole vectors. Every row of every machine is a task of its own, they are the
+/// values `execute` returns in the row by row render. The rows that did not start by the
+/// deadline of the render options are zeros.
+pub(crate) fn render_planes<S: Simd>(
+    machines: &[&StackMachine<S>],
+    coord: &CoordinateSystem,
+    pics: Arc<HashMap<String, ActualPicture>>,
+    w: u32,
+    h: u32,
+    t: f32,
+    viewport: Viewport,
+    options: RenderOptions,
+    rows: Range<u32>,
+) -> Vec<Vec<f32>> {
+    let vectors = vectors_per_row::<S>(w);
+    machines
+        .par_iter()
+        .map(|sm| {
+            let rows: Vec<Vec<f32>> = rows
+                .clone()
+                .into_par_iter()
+                .map_init(
+                    || new_stack::<S>(sm.stack_depth),
+                    |stack, y_pixel| unsafe {
+                        if options.expired() {
+                            return vec![0.0; vectors * S::VF32_WIDTH];
+                        }
+                        let ts = S::set1_ps(t);
+                        let wf = S::set1_ps(w as f32);
+                        let hf = S::set1_ps(h as f32);
+                        let y = S::set1_ps(viewport.y_at(y_pixel, h));
+                        let x_step = viewport.x_step(w);
+                        let mut x = S::setzero_ps();
+                        for i in (0..S::VF32_WIDTH).rev() {
+                            x[i] = viewport.x_min + (x_step * i as f32);
+                        }
+                        let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
+                        let mut row = Vec::with_capacity(vectors * S::VF32_WIDTH);
+                        for _ in 0..vectors {
+                            let (fx, fy) = options.fold::<S>(x, y);
+                            let v = if coord.is_cartesian() {
+                                sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            } else {
+                                let (fx, fy) = cartesian_to_polar::<S>(fx, fy);
+                                sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            };
+                            for j in 0..S::VF32_WIDTH {
+                                row.push(v[j]);
+                            }
+                            x = x + x_step;
+                        }
+                        row
+                    },
+                )
+                .collect();
+            rows.concat()
+        })
+        .collect()
+}
+
+/// The lanes of a plane of `render_planes` from `at` on as a vector
+#[inline(always)]
+pub(crate) fn plane_vector<S: Simd>(plane: &[f32], at: usize) -> S::Vf32 {
+    unsafe {
+        let mut v = S::setzero_ps();
+        for j in 0..S::VF32_WIDTH {
+            v[j] = plane[at + j];
+        }
+        v
+    }
+}
+
+/// The values of a stack machine at the even pixels of the even rows, the pixels in between
+/// are interpolated. The samples are one column and one row past the pixels, so every pixel
+/// has samples on both sides.
+pub(crate) struct HalfPlane {
+    values: Vec<f32>,
+    cols: usize,
+    rows: usize,
+    // the first row of samples is at row 2 * first_row of the image
+    first_row: usize,
+}
+
+impl HalfPlane {
+    /// Evaluate `sm` at the even pixels of the even rows around `rows`, on rayon tasks when
+    /// `threaded`. The rows of samples that did not start by the deadline of the render options are
+    /// zeros.
+    pub(crate) fn render<S: Simd>(
+        sm: &StackMachine<S>,
+        coord: &CoordinateSystem,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        rows: Range<u32>,
+        threaded: bool,
+    ) -> Self {
+        let cols = w as usize / 2 + 1;
+        let first_row = rows.start as usize / 2;
+        let sample_rows = (rows.end.max(1) as usize - 1) / 2 + 2 - first_row;
+        let vectors = (cols + S::VF32_WIDTH - 1) / S::VF32_WIDTH;
+        let sample_row = |stack: &mut Vec<S::Vf32>, row: usize| unsafe {
+            if options.expired() {
+                return vec![0.0; cols];
+            }
+            let ts = S::set1_ps(t);
+            let wf = S::set1_ps(w as f32);
+            let hf = S::set1_ps(h as f32);
+            let y = S::set1_ps(viewport.y_at(2 * (first_row + row) as u32, h));
+            let x_step = viewport.x_step(w) * 2.0;
+            let mut x = S::setzero_ps();
+            for i in (0..S::VF32_WIDTH).rev() {
+                x[i] = viewport.x_min + (x_step * i as f32);
+            }
+            let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
+            let mut samples = Vec::with_capacity(vectors * S::VF32_WIDTH);
+            for _ in 0..vectors {
+                let (fx, fy) = options.fold::<S>(x, y);
+                let v = if coord.is_cartesian() {
+                    sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                } else {
+                    let (fx, fy) = cartesian_to_polar::<S>(fx, fy);
+                    sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                };
+                for j in 0..S::VF32_WIDTH {
+                    samples.push(v[j]);
+                }
+                x = x + x_step;
+            }
+            samples.truncate(cols);
+            samples
+        };
+        let values: Vec<Vec<f32>> = if threaded {
+            (0..sample_rows)
+                .into_par_iter()
+                .map_init(|| new_stack::<S>(sm.stack_depth), sample_row)
+                .collect()
+        } else {
+            let mut stack = new_stack::<S>(sm.stack_depth);
+            (0..sample_rows)
+                .map(|row| sample_row(&mut stack, row))
+                .collect()
+        };
+        HalfPlane {
+            values: values.concat(),
+            cols,
+            rows: sample_rows,
+            first_row,
+        }
+    }
+
+    /// The value at pixel `x` of row `y` of the image, between the four samples around it
+    fn at(&self, x: usize, y: usize) -> f32 {
+        let sample = |col: usize, row: usize| {
+            self.values[row.min(self.rows - 1) * self.cols + col.min(self.cols - 1)]
+        };
+        let (col, row) = (x / 2, y / 2 - self.first_row);
+        let (dx, dy) = ((x % 2) as f32 * 0.5, (y % 2) as f32 * 0.5);
+        let top = sample(col, row) * (1.0 - dx) + sample(col + 1, row) * dx;
+        let bottom = sample(col, row + 1) * (1.0 - dx) + sample(col + 1, row + 1) * dx;
+        top * (1.0 - dy) + bottom * dy
+    }
+
+    /// The values of the lanes from pixel `x_pixel` of row `y_pixel` of the image on
+    #[inline(always)]
+    pub(crate) fn vector<S: Simd>(&self, x_pixel: usize, y_pixel: usize) -> S::Vf32 {
+        unsafe {
+            let mut v = S::setzero_ps();
+            for j in 0..S::VF32_WIDTH {
+                v[j] = self.at(x_pixel + j, y_pixel);
+            }
+            v
+        }
+    }
+}
+
+/// Render the `h` rows of an image in strips of `tile_h` rows, every strip is
+/// passed to `sink` together with the index of its first row
+pub fn render_tiles<F>(h: u32, tile_h: u32, sink: &mut dyn FnMut(u32, &[u8]), mut render: F)
+where
+    F: FnMut(Range<u32>) -> Vec<u8>,
+{
+    let tile_h = tile_h.max(1);
+    let mut y = 0;
+    while y < h {
+        let end = (y + tile_h).min(h);
+        let strip = render(y..end);
+        sink(y, &strip);
+        y = end;
+    }
+}
+
+/// Render a frame for each of the `frame_times`, `progress` is called with
+/// (frames done, total frames) after each frame is rendered. With a motion_blur of more
+/// than 1 in the `options` every frame is the average of that many sub-frames, see
+/// sub_frame_times.
+pub fn render_frames<F>(
+    fps: u16,
+    d_ms: f32,
+    time_range: TimeRange,
+    options: RenderOptions,
+    progress: Option<&mut dyn FnMut(usize, usize)>,
+    mut render: F,
+) -> Vec<Vec<u8>>
+where
+    F: FnMut(f32) -> Vec<u8>,
+{
+    let times = sub_frame_times(fps, d_ms, time_range, options.motion_blur);
+    let mut render_frame = |times: Vec<f32>| {
+        average_sub_frames(times.into_iter().map(&mut render), options.color_space)
+    };
+    match progress {
+        None => times.into_iter().map(render_frame).collect(),
+        Some(progress) => {
+            let total = times.len();
+            times
+                .into_iter()
+                .enumerate()
+                .map(|(i, t)| {
+                    let frame = render_frame(t);
+                    progress(i + 1, total);
+                    frame
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{DEFAULT_RENDER_OPTIONS, DEFAULT_TIME_RANGE, DEFAULT_VIEWPORT};
+    use crate::parser::lexer::lisp_to_pic;
+    use simdeez::avx2::Avx2;
+    use simdeez::sse2::Sse2;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    /// A channel of `depth` nested operations, large enough for split_channels
+    fn deep_channel(depth: usize) -> String {
+        (0..depth).fold("X".to_string(), |inner, i| {
+            if i % 2 == 0 {
+                format!("( SIN ( + {} Y ) )", inner)
+            } else {
+                format!("( * {} ( COS T ) )", inner)
+            }
+        })
+    }
+
+    #[test]
+    fn test_frame_times() {
+        assert_eq!(
+            frame_times(4, 1000.0, DEFAULT_TIME_RANGE),
+            vec![-1.0, -0.5, 0.0, 0.5]
+        );
+        assert_eq!(
+            frame_times(4, 1000.0, TimeRange::new(0.0, 8.0)),
+            vec![0.0, 2.0, 4.0, 6.0]
+        );
+        assert_eq!(frame_times(15, 0.0, DEFAULT_TIME_RANGE), Vec::<f32>::new());
+        assert_eq!(frame_times(15, 5000.0, DEFAULT_TIME_RANGE).len(), 75);
+    }
+
+    #[test]
+    fn test_channel() {
+        assert_eq!(u8::from_f32(255.0), 255);
+        assert_eq!(u8::from_f32(300.0), 255);
+        assert_eq!(u8::from_f32(-3.0), 0);
+        assert_eq!(u8::wrap(300.0), 44);
+        assert_eq!(u16::from_f32(u16::FULL), 65535);
+        assert_eq!(u16::from_f32(70000.0), 65535);
+        assert_eq!(u16::from_f32(-3.0), 0);
+        assert_eq!(u16::wrap(65536.0 + 44.0), 44);
+    }
+
+    #[test]
+    fn test_for_each_row() {
+        let stacks = Mutex::new(HashSet::new());
+        let mut result = vec![0u8; 12 * 50];
+        for_each_row::<Sse2, u8, _>(&mut result, 12, false, 8, None, |stack, (y, row)| {
+            assert_eq!(stack.len(), 8);
+            stacks.lock().unwrap().insert(stack.as_ptr() as usize);
+            row.iter_mut().for_each(|c| *c = y as u8);
+            row.len()
+        });
+        // one stack for all the rows
+        assert_eq!(stacks.lock().unwrap().len(), 1);
+        let expected: Vec<u8> = (0..50).flat_map(|y| [y as u8; 12]).collect();
+        assert_eq!(result, expected);
+
+        stacks.lock().unwrap().clear();
+        let mut result = vec![0u8; 12 * 50];
+        for_each_row::<Sse2, u8, _>(&mut result, 12, true, 8, None, |stack, (y, row)| {
+            assert_eq!(stack.len(), 8);
+            stacks.lock().unwrap().insert(stack.as_ptr() as usize);
+            row.iter_mut().for_each(|c| *c = y as u8);
+            row.len()
+        });
+        assert!(stacks.lock().unwrap().len() <= 50);
+        assert_eq!(result, expected);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "row 3 is not written completely")]
+    fn test_for_each_row_incomplete() {
+        let mut result = vec![0u8; 12 * 5];
+        for_each_row::<Sse2, u8, _>(&mut result, 12, false, 8, None, |_, (y, row)| {
+            if y == 3 {
+                row.len() - 4
+            } else {
+                row.len()
+            }
+        });
+    }
+
+    #[test]
+    fn test_threaded_render() {
+        let c = "( SIN ( + X ( * Y T ) ) )";
+        for sexpr in [
+            format!("( MONO POLAR {} )", c),
+            format!("( GRAYSCALE CARTESIAN {} )", c),
+            format!("( GRAYSCALE AUTO {} )", c),
+            format!(
+                "( GRADIENT POLAR ( COLORS ( COLOR 0.1 0.2 0.3 ) ( STOPCOLOR 0.4 0.5 0.6 ) ) {} )",
+                c
+            ),
+            format!("( RGB CARTESIAN {} ( Y ) {} )", c, c),
+            format!("( RGBA POLAR {} ( Y ) {} ( X ) )", c, c),
+            format!("( HSV CARTESIAN {} ( Y ) {} )", c, c),
+            format!("( LAB POLAR {} ( Y ) {} )", c, c),
+            format!("( CMYK CARTESIAN {} ( Y ) {} ( X ) )", c, c),
+        ] {
+            let pic = lisp_to_pic(sexpr.clone(), CoordinateSystem::Polar).unwrap();
+            let pics = Arc::new(HashMap::new());
+            let threaded = pic.get_rgba8::<Sse2>(
+                true,
+                pics.clone(),
+                13,
+                40,
+                0.5,
+                DEFAULT_VIEWPORT,
+                DEFAULT_RENDER_OPTIONS,
+            );
+            let per_row = pic.get_rgba8::<Sse2>(
+                false,
+                pics,
+                13,
+                40,
+                0.5,
+                DEFAULT_VIEWPORT,
+                DEFAULT_RENDER_OPTIONS,
+            );
+            assert_eq!(threaded.len(), 13 * 40 * 4);
+            assert_eq!(threaded, per_row, "{}", sexpr);
+        }
+    }
+
+    #[test]
+    fn test_lanes_in_row() {
+        assert_eq!(Sse2::VF32_WIDTH, 4);
+        assert_eq!(lanes_in_row::<Sse2>(0, 101), 4);
+        assert_eq!(lanes_in_row::<Sse2>(96, 101), 4);
+        assert_eq!(lanes_in_row::<Sse2>(100, 101), 1);
+        assert_eq!(lanes_in_row::<Sse2>(0, 3), 3);
+        assert_eq!(lanes_in_row::<Sse2>(104, 101), 0);
+    }
+
+    #[test]
+    fn test_odd_widths() {
+        // a horizontal ramp, so every row is the same and a lane past the end of a row would
+        // show up at the start of the next one
+        let (w, h) = (101, 97);
+        for sexpr in [
+            "( RGB CARTESIAN ( X ) ( X ) ( X ) )",
+            "( GRAYSCALE CARTESIAN ( X ) )",
+            "( RGBA CARTESIAN ( X ) ( X ) ( X ) ( X ) )",
+        ] {
+            let pic = lisp_to_pic(sexpr.to_string(), CoordinateSystem::Polar).unwrap();
+            let pics = Arc::new(HashMap::new());
+            let mut renders = vec![
+                pic.get_rgba8::<Sse2>(
+                    true,
+                    pics.clone(),
+                    w,
+                    h,
+                    0.0,
+                    DEFAULT_VIEWPORT,
+                    DEFAULT_RENDER_OPTIONS,
+                ),
+                pic.get_rgba8::<Sse2>(
+                    false,
+                    pics.clone(),
+                    w,
+                    h,
+                    0.0,
+                    DEFAULT_VIEWPORT,
+                    DEFAULT_RENDER_OPTIONS,
+                ),
+            ];
+            if is_x86_feature_detected!("avx2") {
+                renders.push(pic.get_rgba8::<Avx2>(
+                    true,
+                    pics.clone(),
+                    w,
+                    h,
+                    0.0,
+                    DEFAULT_VIEWPORT,
+                    DEFAULT_RENDER_OPTIONS,
+                ));
+            }
+            for rgba8 in renders {
+                assert_eq!(rgba8.len(), (w * h * 4) as usize);
+                let rows: Vec<&[u8]> = rgba8.chunks_exact(4 * w as usize).collect();
+                for (y, row) in rows.iter().enumerate() {
+                    assert_eq!(row, &rows[0], "{} row {}", sexpr, y);
+                }
+                let first = rows[0][0];
+                let last = rows[0][4 * (w as usize - 1)];
+                assert!(first < 10 && last > 245, "{} {} {}", sexpr, first, last);
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_channels() {
+        for mode in ["RGB", "HSV"] {
+            for coord in ["POLAR", "CARTESIAN"] {
+                let sexpr = format!(
+                    "( {} {} {} {} {} )",
+                    mode,
+                    coord,
+                    deep_channel(12),
+                    deep_channel(9),
+                    deep_channel(14)
+                );
+                let pic = lisp_to_pic(sexpr, CoordinateSystem::Polar).unwrap();
+                let trees = pic.to_tree();
+                let machines: Vec<StackMachine<Sse2>> =
+                    trees.iter().map(|tree| StackMachine::build(tree)).collect();
+                let machines: Vec<&StackMachine<Sse2>> = machines.iter().collect();
+                // a width that is no multiple of the lanes, so the planes are padded
+                let (w, h) = (10, 6);
+                assert!(split_channels(true, &(0..h), &machines));
+                assert!(!split_channels(false, &(0..h), &machines));
+                let pics = Arc::new(HashMap::new());
+                let split = pic.get_rgba8::<Sse2>(
+                    true,
+                    pics.clone(),
+                    w,
+                    h,
+                    0.3,
+                    DEFAULT_VIEWPORT,
+                    DEFAULT_RENDER_OPTIONS,
+                );
+                let per_row = pic.get_rgba8::<Sse2>(
+                    false,
+                    pics,
+                    w,
+                    h,
+                    0.3,
+                    DEFAULT_VIEWPORT,
+                    DEFAULT_RENDER_OPTIONS,
+                );
+                assert_eq!(split, per_row, "{} {}", mode, coord);
+            }
+        }
+        // small trees are quick enough per row
+        let mono =
+            lisp_to_pic("( MONO POLAR ( X ) )".to_string(), CoordinateSystem::Polar).unwrap();
+        let small = StackMachine::<Sse2>::build(mono.to_tree()[0]);
+        assert!(!split_channels(true, &(0..6), &[&small]));
+    }
+
+    #[test]
+    fn test_half_plane() {
+        let pic = lisp_to_pic(
+            "( MONO CARTESIAN ( + X ( * 0.5 Y ) ) )".to_string(),
+            CoordinateSystem::Cartesian,
+        )
+        .unwrap();
+        let sm = StackMachine::<Sse2>::build(pic.to_tree()[0]);
+        let (w, h) = (7, 9);
+        // a strip that starts at an odd row, like a tile
+        for threaded in [false, true] {
+            let plane = HalfPlane::render(
+                &sm,
+                pic.coord(),
+                Arc::new(HashMap::new()),
+                w,
+                h,
+                0.0,
+                DEFAULT_VIEWPORT,
+                DEFAULT_RENDER_OPTIONS,
+                3..8,
+                threaded,
+            );
+            // a plane is interpolated without a difference
+            for y in 3..8 {
+                for x in 0..w as usize {
+                    let expected = DEFAULT_VIEWPORT.x_min
+                        + DEFAULT_VIEWPORT.x_step(w) * x as f32
+                        + 0.5 * DEFAULT_VIEWPORT.y_at(y as u32, h);
+                    assert!((plane.at(x, y) - expected).abs() < 1e-5, "{} {}", x, y);
+                }
+            }
+            let v = plane.vector::<Sse2>(4, 5);
+            assert_eq!(v[0], plane.at(4, 5));
+            assert_eq!(v[2], plane.at(6, 5));
+        }
+    }
+
+    #[test]
+    fn test_render_tiles() {
+        let mut strips = Vec::new();
+        let mut sink = |y: u32, strip: &[u8]| strips.push((y, strip.to_vec()));
+        render_tiles(5, 2, &mut sink, |rows| rows.map(|y| y as u8).collect());
+        assert_eq!(strips, vec![(0, vec![0, 1]), (2, vec![2, 3]), (4, vec![4])]);
+    }
+
+    #[test]
+    fn test_render_frames() {
+        let mut calls = Vec::new();
+        let mut progress = |frame: usize, total: usize| calls.push((frame, total));
+        let frames = render_frames(
+            4,
+            1000.0,
+            DEFAULT_TIME_RANGE,
+            DEFAULT_RENDER_OPTIONS,
+            Some(&mut progress),
+            |t| vec![((t + 1.0) * 10.0) as u8],
+        );
+        assert_eq!(frames, vec![vec![0], vec![5], vec![10], vec![15]]);
+        assert_eq!(calls, vec![(1, 4), (2, 4), (3, 4), (4, 4)]);
+
+        let frames = render_frames(
+            4,
+            1000.0,
+            DEFAULT_TIME_RANGE,
+            DEFAULT_RENDER_OPTIONS,
+            None,
+            |_| vec![1],
+        );
+        assert_eq!(frames.len(), 4);
+
+        // the sub-frames at -1.0 and -0.75 average to the first frame
+        let mut calls = Vec::new();
+        let mut progress = |frame: usize, total: usize| calls.push((frame, total));
+        let blurred = DEFAULT_RENDER_OPTIONS.with_motion_blur(2);
+        let frames = render_frames(
+            4,
+            1000.0,
+            DEFAULT_TIME_RANGE,
+            blurred,
+            Some(&mut progress),
+            |t| vec![((t + 1.0) * 20.0) as u8],
+        );
+        assert_eq!(frames, vec![vec![3], vec![13], vec![23], vec![33]]);
+        assert_eq!(calls, vec![(1, 4), (2, 4), (3, 4), (4, 4)]);
+    }
+
+    #[test]
+    fn test_sub_frame_times() {
+        assert_eq!(
+            sub_frame_times(2, 1000.0, DEFAULT_TIME_RANGE, 4),
+            vec![vec![-1.0, -0.75, -0.5, -0.25], vec![0.0, 0.25, 0.5, 0.75]]
+        );
+        // a single sub-frame is the frame itself
+        assert_eq!(
+            sub_frame_times(15, 5000.0, DEFAULT_TIME_RANGE, 1)
+                .into_iter()
+                .flatten()
+                .collect::<Vec<f32>>(),
+            frame_times(15, 5000.0, DEFAULT_TIME_RANGE)
+        );
+        assert_eq!(sub_frame_times(4, 1000.0, DEFAULT_TIME_RANGE, 0).len(), 4);
+    }
+
+    #[test]
+    fn test_average_sub_frames() {
+        assert_eq!(
+            average_sub_frames(vec![vec![1, 2, 255]], ColorSpace::Srgb),
+            vec![1, 2, 255]
+        );
+        assert_eq!(
+            average_sub_frames(
+                vec![vec![0, 10, 255, 255], vec![1, 20, 255, 0]],
+                ColorSpace::Srgb
+            ),
+            vec![1, 15, 255, 128]
+        );
+        // 3 of 5 sub-frames are lit
+        assert_eq!(
+            average_sub_frames(
+                vec![vec![0], vec![1], vec![1], vec![0], vec![1]],
+                ColorSpace::Srgb
+            ),
+            vec![1]
+        );
+        assert_eq!(
+            average_sub_frames(Vec::<Vec<u8>>::new(), ColorSpace::Srgb),
+            Vec::<u8>::new()
+        );
+        // black and white average to half of the light, the alpha is averaged as it is
+        assert_eq!(
+            average_sub_frames(
+                vec![vec![0, 0, 255, 255], vec![255, 0, 255, 0]],
+                ColorSpace::Linear
+            ),
+            vec![188, 0, 255, 128]
+        );
+    }
+}
@@ -1,102 +1,207 @@
 use rand::prelude::*;
-use rand::rngs::StdRng;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::Arc;
 
 use crate::parser::aptnode::APTNode;
 use crate::pic::actual_picture::ActualPicture;
 use crate::pic::coordinatesystem::{cartesian_to_polar, CoordinateSystem};
-use crate::pic::data::PicData;
+use crate::pic::data::{for_each_row, lanes_in_row, render_frames, render_tiles, Channel, PicData};
+use crate::pic::generation::GenerationConfig;
 use crate::pic::pic::Pic;
+use crate::pic::renderoptions::RenderOptions;
+use crate::pic::timerange::TimeRange;
+use crate::pic::viewport::Viewport;
+use crate::vm::cache::StackMachineCache;
 use crate::vm::stackmachine::StackMachine;
 
-use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use simdeez::Simd;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MonoData {
     pub c: APTNode,
     pub coord: CoordinateSystem,
 }
 
-impl PicData for MonoData {
-    fn new(min: usize, max: usize, video: bool, rng: &mut StdRng, pic_names: &Vec<&String>) -> Pic {
-        let (tree, coord) =
-            APTNode::create_random_tree(rng.gen_range(min..max), video, rng, pic_names);
-        Pic::Mono(MonoData { c: tree, coord })
-    }
-    fn get_rgba8<S: Simd>(
+impl MonoData {
+    fn render<S: Simd, C: Channel>(
         &self,
+        sm: &StackMachine<S>,
         threaded: bool,
         pics: Arc<HashMap<String, ActualPicture>>,
         w: u32,
         h: u32,
         t: f32,
-    ) -> Vec<u8> {
+        viewport: Viewport,
+        options: RenderOptions,
+        rows: Range<u32>,
+    ) -> Vec<C> {
+        let viewport = self.coord.viewport(viewport, w, h);
         unsafe {
             let ts = S::set1_ps(t);
             let wf = S::set1_ps(w as f32);
             let hf = S::set1_ps(h as f32);
-            let vec_len = (w * h * 4) as usize;
-            let mut result = Vec::<u8>::with_capacity(vec_len);
-            result.set_len(vec_len);
-            let sm = StackMachine::<S>::build(&self.c);
+            let vec_len = (w * (rows.end - rows.start) * 4) as usize;
+            let mut result = vec![C::from_u32(0); vec_len];
             /*
             let mut min = 999999.0;
             let mut max = -99999.0;
             */
 
-            let process = |(y_pixel, chunk): (usize, &mut [u8])| {
-                let mut stack = Vec::with_capacity(sm.instructions.len());
-                stack.set_len(sm.instructions.len());
-
-                let y = S::set1_ps((y_pixel as f32 / h as f32) * 2.0 - 1.0);
-                let x_step = 2.0 / (w - 1) as f32;
+            let process = |stack: &mut Vec<S::Vf32>, (y_pixel, chunk): (usize, &mut [C])| {
+                let mut written = 0;
+                let y_pixel = rows.start as usize + y_pixel;
+                let y = S::set1_ps(viewport.y_at(y_pixel as u32, h));
+                let x_step = viewport.x_step(w);
                 let mut x = S::setzero_ps();
                 for i in (0..S::VF32_WIDTH).rev() {
-                    x[i] = -1.0 + (x_step * i as f32);
+                    x[i] = viewport.x_min + (x_step * i as f32);
                 }
                 let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
-                let chunk_len = chunk.len();
                 for i in (0..w * 4).step_by(S::VF32_WIDTH * 4) {
-                    let v = if self.coord == CoordinateSystem::Cartesian {
-                        sm.execute(&mut stack, pics.clone(), x, y, ts, wf, hf)
+                    let (fx, fy) = options.fold::<S>(x, y);
+                    let v = if self.coord.is_cartesian() {
+                        sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
                     } else {
-                        let (r, theta) = cartesian_to_polar::<S>(x, y);
-                        sm.execute(&mut stack, pics.clone(), r, theta, ts, wf, hf)
+                        let (r, theta) = cartesian_to_polar::<S>(fx, fy);
+                        sm.execute(stack, pics.clone(), r, theta, ts, wf, hf)
                     };
 
-                    for j in 0..S::VF32_WIDTH {
-                        let j4: usize = j * 4;
-                        let ij4 = i as usize + j4;
-                        if ij4 >= chunk_len {
-                            break;
-                        }
-                        let c = if v[j] >= 0.0 { 255 } else { 0 };
+                    for j in 0..lanes_in_row::<S>(i as usize / 4, w) {
+                        let ij4 = i as usize + j * 4;
+                        let c = if v[j] >= 0.0 {
+                            C::from_f32(C::FULL)
+                        } else {
+                            C::from_f32(0.0)
+                        };
                         chunk[ij4] = c;
                         chunk[ij4 + 1] = c;
                         chunk[ij4 + 2] = c;
-                        chunk[ij4 + 3] = 255 as u8;
+                        chunk[ij4 + 3] = C::from_f32(C::FULL);
+                        written += 4;
                     }
                     x = x + x_step;
                 }
+                written
             };
 
-            if threaded {
-                result
-                    .par_chunks_mut(4 * w as usize)
-                    .enumerate()
-                    .for_each(process);
-            } else {
-                result
-                    .chunks_exact_mut(4 * w as usize)
-                    .enumerate()
-                    .for_each(process);
-            }
+            for_each_row::<S, C, _>(
+                &mut result,
+                4 * w as usize,
+                threaded,
+                sm.stack_depth,
+                options.deadline,
+                process,
+            );
             // println!("min:{} max:{} range:{}",min,max,max-min);
             result
         }
     }
+}
+
+impl PicData for MonoData {
+    fn new(
+        config: &GenerationConfig,
+        video: bool,
+        rng: &mut impl Rng,
+        pic_names: &Vec<&String>,
+    ) -> Pic {
+        let (tree, coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        Pic::Mono(MonoData { c: tree, coord })
+    }
+    fn get_rgba8<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let sm = StackMachine::<S>::build(&self.c);
+        self.render::<S, u8>(&sm, threaded, pics, w, h, t, viewport, options, 0..h)
+    }
+    fn get_rgba8_tiled<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        tile_h: u32,
+        sink: &mut dyn FnMut(u32, &[u8]),
+    ) {
+        let sm = StackMachine::<S>::build(&self.c);
+        render_tiles(h, tile_h, sink, |rows| {
+            self.render::<S, u8>(
+                &sm,
+                threaded,
+                pics.clone(),
+                w,
+                h,
+                t,
+                viewport,
+                options,
+                rows,
+            )
+        });
+    }
+    fn get_rgba16<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u16> {
+        let sm = StackMachine::<S>::build(&self.c);
+        self.render::<S, u16>(&sm, threaded, pics, w, h, t, viewport, options, 0..h)
+    }
+    fn get_rgba8_cached<S: Simd>(
+        &self,
+        cache: &mut StackMachineCache<S>,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let sm = cache.get(&self.c);
+        self.render::<S, u8>(&sm, threaded, pics, w, h, t, viewport, options, 0..h)
+    }
+    fn get_video<S: Simd>(
+        &self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        fps: u16,
+        d_ms: f32,
+        time_range: TimeRange,
+        viewport: Viewport,
+        options: RenderOptions,
+        progress: Option<&mut dyn FnMut(usize, usize)>,
+    ) -> Vec<Vec<u8>> {
+        let sm = StackMachine::<S>::build(&self.c);
+        render_frames(fps, d_ms, time_range, options, progress, |t| {
+            self.render::<S, u8>(&sm, true, pics.clone(), w, h, t, viewport, options, 0..h)
+        })
+    }
     fn simplify<S: Simd>(
         &mut self,
         pics: Arc<HashMap<String, ActualPicture>>,
@@ -117,7 +222,12 @@ mod tests {
     #[test]
     fn test_pic_new_mono() {
         let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
-        let pic = MonoData::new(0, 60, false, &mut rng, &vec![&"eye.jpg".to_string()]);
+        let pic = MonoData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
         match &pic {
             Pic::Mono(MonoData { c, coord: _coord }) => {
                 let len = c.get_children().unwrap().len();
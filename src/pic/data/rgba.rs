@@ -0,0 +1,421 @@
+use rand::prelude::*;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::parser::aptnode::APTNode;
+use crate::pic::actual_picture::ActualPicture;
+use crate::pic::coordinatesystem::{cartesian_to_polar, CoordinateSystem};
+use crate::pic::data::{for_each_row, lanes_in_row, render_frames, render_tiles, Channel, PicData};
+use crate::pic::generation::GenerationConfig;
+use crate::pic::pic::Pic;
+use crate::pic::renderoptions::RenderOptions;
+use crate::pic::timerange::TimeRange;
+use crate::pic::viewport::Viewport;
+use crate::vm::cache::StackMachineCache;
+use crate::vm::stackmachine::StackMachine;
+
+use serde::{Deserialize, Serialize};
+use simdeez::Simd;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RGBAData {
+    pub r: APTNode,
+    pub g: APTNode,
+    pub b: APTNode,
+    pub a: APTNode,
+    pub coord: CoordinateSystem,
+}
+
+impl RGBAData {
+    fn render<S: Simd, C: Channel>(
+        &self,
+        r_sm: &StackMachine<S>,
+        g_sm: &StackMachine<S>,
+        b_sm: &StackMachine<S>,
+        a_sm: &StackMachine<S>,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        rows: Range<u32>,
+    ) -> Vec<C> {
+        let viewport = self.coord.viewport(viewport, w, h);
+        unsafe {
+            let ts = S::set1_ps(t);
+            let wf = S::set1_ps(w as f32);
+            let hf = S::set1_ps(h as f32);
+
+            let vec_len = (w * (rows.end - rows.start) * 4) as usize;
+            let mut result = vec![C::from_u32(0); vec_len];
+
+            let max_len = *[
+                r_sm.stack_depth,
+                g_sm.stack_depth,
+                b_sm.stack_depth,
+                a_sm.stack_depth,
+            ]
+            .iter()
+            .max()
+            .unwrap();
+
+            let process = |stack: &mut Vec<S::Vf32>, (y_pixel, chunk): (usize, &mut [C])| {
+                let mut written = 0;
+                let y_pixel = rows.start as usize + y_pixel;
+                let y = S::set1_ps(viewport.y_at(y_pixel as u32, h));
+                let x_step = viewport.x_step(w);
+                let mut x = S::setzero_ps();
+                for i in (0..S::VF32_WIDTH).rev() {
+                    x[i] = viewport.x_min + (x_step * i as f32);
+                }
+                let x_step = S::set1_ps(x_step * S::VF32_WIDTH as f32);
+                for i in (0..w * 4).step_by(S::VF32_WIDTH * 4) {
+                    let (fx, fy) = options.fold::<S>(x, y);
+                    let (rs, gs, bs, a_s) = if self.coord.is_cartesian() {
+                        let rs = (r_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps((C::FULL + 1.0) / 2.0);
+                        let gs = (g_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps((C::FULL + 1.0) / 2.0);
+                        let bs = (b_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps((C::FULL + 1.0) / 2.0);
+                        let a_s = (a_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps((C::FULL + 1.0) / 2.0);
+                        (rs, gs, bs, a_s)
+                    } else {
+                        let (fx, fy) = cartesian_to_polar::<S>(fx, fy);
+                        let rs = (r_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps((C::FULL + 1.0) / 2.0);
+                        let gs = (g_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps((C::FULL + 1.0) / 2.0);
+                        let bs = (b_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps((C::FULL + 1.0) / 2.0);
+                        let a_s = (a_sm.execute(stack, pics.clone(), fx, fy, ts, wf, hf)
+                            + S::set1_ps(1.0))
+                            * S::set1_ps((C::FULL + 1.0) / 2.0);
+                        (rs, gs, bs, a_s)
+                    };
+
+                    for j in 0..lanes_in_row::<S>(i as usize / 4, w) {
+                        let ij4 = i as usize + j * 4;
+                        let r = options.color_space.encode::<C>(rs[j]);
+                        let g = options.color_space.encode::<C>(gs[j]);
+                        let b = options.color_space.encode::<C>(bs[j]);
+                        let a = C::from_f32(a_s[j]);
+                        chunk[ij4] = r;
+                        chunk[ij4 + 1] = g;
+                        chunk[ij4 + 2] = b;
+                        chunk[ij4 + 3] = a;
+                        written += 4;
+                    }
+                    x = x + x_step;
+                }
+                written
+            };
+            for_each_row::<S, C, _>(
+                &mut result,
+                4 * w as usize,
+                threaded,
+                max_len,
+                options.deadline,
+                process,
+            );
+
+            result
+        }
+    }
+}
+
+impl PicData for RGBAData {
+    fn new(
+        config: &GenerationConfig,
+        video: bool,
+        rng: &mut impl Rng,
+        pic_names: &Vec<&String>,
+    ) -> Pic {
+        let (r, coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        let (g, _coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        let (b, _coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        let (a, _coord) = APTNode::create_random_tree(
+            rng.gen_range(config.tree_min..config.tree_max),
+            video,
+            rng,
+            pic_names,
+            &config.weights,
+            config.max_depth,
+            config.max_nodes,
+        );
+        Pic::RGBA(RGBAData { r, g, b, a, coord })
+    }
+    fn get_rgba8<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let r_sm = StackMachine::<S>::build(&self.r);
+        let g_sm = StackMachine::<S>::build(&self.g);
+        let b_sm = StackMachine::<S>::build(&self.b);
+        let a_sm = StackMachine::<S>::build(&self.a);
+        self.render::<S, u8>(
+            &r_sm,
+            &g_sm,
+            &b_sm,
+            &a_sm,
+            threaded,
+            pics,
+            w,
+            h,
+            t,
+            viewport,
+            options,
+            0..h,
+        )
+    }
+    fn get_rgba8_tiled<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        tile_h: u32,
+        sink: &mut dyn FnMut(u32, &[u8]),
+    ) {
+        let r_sm = StackMachine::<S>::build(&self.r);
+        let g_sm = StackMachine::<S>::build(&self.g);
+        let b_sm = StackMachine::<S>::build(&self.b);
+        let a_sm = StackMachine::<S>::build(&self.a);
+        render_tiles(h, tile_h, sink, |rows| {
+            self.render::<S, u8>(
+                &r_sm,
+                &g_sm,
+                &b_sm,
+                &a_sm,
+                threaded,
+                pics.clone(),
+                w,
+                h,
+                t,
+                viewport,
+                options,
+                rows,
+            )
+        });
+    }
+    fn get_rgba16<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u16> {
+        let r_sm = StackMachine::<S>::build(&self.r);
+        let g_sm = StackMachine::<S>::build(&self.g);
+        let b_sm = StackMachine::<S>::build(&self.b);
+        let a_sm = StackMachine::<S>::build(&self.a);
+        self.render::<S, u16>(
+            &r_sm,
+            &g_sm,
+            &b_sm,
+            &a_sm,
+            threaded,
+            pics,
+            w,
+            h,
+            t,
+            viewport,
+            options,
+            0..h,
+        )
+    }
+    fn get_rgba8_cached<S: Simd>(
+        &self,
+        cache: &mut StackMachineCache<S>,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        let r_sm = cache.get(&self.r);
+        let g_sm = cache.get(&self.g);
+        let b_sm = cache.get(&self.b);
+        let a_sm = cache.get(&self.a);
+        self.render::<S, u8>(
+            &r_sm,
+            &g_sm,
+            &b_sm,
+            &a_sm,
+            threaded,
+            pics,
+            w,
+            h,
+            t,
+            viewport,
+            options,
+            0..h,
+        )
+    }
+    fn get_video<S: Simd>(
+        &self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        fps: u16,
+        d_ms: f32,
+        time_range: TimeRange,
+        viewport: Viewport,
+        options: RenderOptions,
+        progress: Option<&mut dyn FnMut(usize, usize)>,
+    ) -> Vec<Vec<u8>> {
+        let r_sm = StackMachine::<S>::build(&self.r);
+        let g_sm = StackMachine::<S>::build(&self.g);
+        let b_sm = StackMachine::<S>::build(&self.b);
+        let a_sm = StackMachine::<S>::build(&self.a);
+        render_frames(fps, d_ms, time_range, options, progress, |t| {
+            self.render::<S, u8>(
+                &r_sm,
+                &g_sm,
+                &b_sm,
+                &a_sm,
+                true,
+                pics.clone(),
+                w,
+                h,
+                t,
+                viewport,
+                options,
+                0..h,
+            )
+        })
+    }
+    fn simplify<S: Simd>(
+        &mut self,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+    ) {
+        self.r = self.r.constant_fold::<S>(
+            &self.coord,
+            pics.clone(),
+            None,
+            None,
+            Some(w),
+            Some(h),
+            Some(t),
+        );
+        self.g = self.g.constant_fold::<S>(
+            &self.coord,
+            pics.clone(),
+            None,
+            None,
+            Some(w),
+            Some(h),
+            Some(t),
+        );
+        self.b = self.b.constant_fold::<S>(
+            &self.coord,
+            pics.clone(),
+            None,
+            None,
+            Some(w),
+            Some(h),
+            Some(t),
+        );
+        self.a = self.a.constant_fold::<S>(
+            &self.coord,
+            pics.clone(),
+            None,
+            None,
+            Some(w),
+            Some(h),
+            Some(t),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pic_new_rgba() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let pic = RGBAData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
+        match &pic {
+            Pic::RGBA(RGBAData {
+                r,
+                g,
+                b,
+                a,
+                coord: _,
+            }) => {
+                let len = r.get_children().unwrap().len();
+                assert!(len > 0 && len < 60);
+
+                let len = g.get_children().unwrap().len();
+                assert!(len > 0 && len < 60);
+
+                let len = b.get_children().unwrap().len();
+                assert!(len > 0 && len < 60);
+
+                let len = a.get_children().unwrap().len();
+                assert!(len > 0 && len < 60);
+            }
+            _ => {
+                panic!("wrong type");
+            }
+        };
+    }
+}
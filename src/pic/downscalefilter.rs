@@ -0,0 +1,178 @@
+use std::fmt::{Display, Formatter, Result as FResult};
+
+use clap::ValueEnum;
+use image::imageops::{resize, FilterType};
+use image::{ImageBuffer, Pixel, Rgba};
+
+use crate::pic::colorspace::ColorSpace;
+use crate::pic::data::Channel;
+use crate::pic::pic::box_downsample;
+
+/// How a supersampled render is scaled down to the size of the image
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum DownscaleFilter {
+    /// Every pixel is the average of its samples, this is how renders were always scaled down
+    Box,
+    /// Every pixel is one of its samples, crisp like pixel art
+    Nearest,
+    /// Linear interpolation between the samples
+    Triangle,
+    /// Cubic interpolation, sharper than triangle
+    #[value(name = "catmull")]
+    CatmullRom,
+    /// Lanczos with a window of 3, the sharpest and the slowest
+    Lanczos3,
+}
+
+impl DownscaleFilter {
+    fn filter_type(&self) -> Option<FilterType> {
+        match self {
+            DownscaleFilter::Box => None,
+            DownscaleFilter::Nearest => Some(FilterType::Nearest),
+            DownscaleFilter::Triangle => Some(FilterType::Triangle),
+            DownscaleFilter::CatmullRom => Some(FilterType::CatmullRom),
+            DownscaleFilter::Lanczos3 => Some(FilterType::Lanczos3),
+        }
+    }
+
+    /// Scale an rgba buffer of (n * w) x (n * h) pixels down to w x h pixels, the samples of
+    /// the `color_space` Linear are weighed in linear light
+    pub fn downscale<C>(
+        &self,
+        rgba: Vec<C>,
+        w: u32,
+        h: u32,
+        n: u32,
+        color_space: ColorSpace,
+    ) -> Vec<C>
+    where
+        C: Channel + 'static,
+        Rgba<C>: Pixel<Subpixel = C>,
+    {
+        if n <= 1 {
+            return rgba;
+        }
+        match self.filter_type() {
+            None => box_downsample(&rgba, w, h, n, color_space),
+            Some(filter) if color_space == ColorSpace::Linear => {
+                // image clamps f32 channels to 0.0..=1.0
+                let linear: Vec<f32> = rgba
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| color_space.rgba_channel(i).decode(*c) / C::FULL)
+                    .collect();
+                let source = ImageBuffer::<Rgba<f32>, _>::from_raw(w * n, h * n, linear)
+                    .expect("the buffer holds n x n samples per pixel");
+                resize(&source, w, h, filter)
+                    .into_raw()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| color_space.rgba_channel(i).encode_mean(c * C::FULL, 1))
+                    .collect()
+            }
+            Some(filter) => {
+                let source = ImageBuffer::<Rgba<C>, _>::from_raw(w * n, h * n, rgba)
+                    .expect("the buffer holds n x n samples per pixel");
+                resize(&source, w, h, filter).into_raw()
+            }
+        }
+    }
+}
+
+impl Display for DownscaleFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        let x = match self {
+            DownscaleFilter::Box => "box",
+            DownscaleFilter::Nearest => "nearest",
+            DownscaleFilter::Triangle => "triangle",
+            DownscaleFilter::CatmullRom => "catmull",
+            DownscaleFilter::Lanczos3 => "lanczos3",
+        };
+        write!(f, "{}", x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A horizontal ramp of w x h pixels, the alpha is opaque
+    fn ramp(w: u32, h: u32) -> Vec<u8> {
+        (0..h)
+            .flat_map(|_| (0..w).flat_map(move |x| [(x * 255 / (w - 1)) as u8, 0, 0, 255]))
+            .collect()
+    }
+
+    #[test]
+    fn test_downscale_filter_parse() {
+        assert_eq!(
+            DownscaleFilter::from_str("catmull", true),
+            Ok(DownscaleFilter::CatmullRom)
+        );
+        assert_eq!(
+            DownscaleFilter::from_str("Lanczos3", true),
+            Ok(DownscaleFilter::Lanczos3)
+        );
+        assert!(DownscaleFilter::from_str("bicubic", true).is_err());
+        assert_eq!(DownscaleFilter::CatmullRom.to_string(), "catmull");
+    }
+
+    #[test]
+    fn test_downscale_box() {
+        let rgba8 = ramp(32, 8);
+        assert_eq!(
+            DownscaleFilter::Box.downscale(rgba8.clone(), 8, 2, 4, ColorSpace::Srgb),
+            box_downsample(&rgba8, 8, 2, 4, ColorSpace::Srgb)
+        );
+        // without supersampling nothing is scaled
+        assert_eq!(
+            DownscaleFilter::Lanczos3.downscale(rgba8.clone(), 32, 8, 1, ColorSpace::Linear),
+            rgba8
+        );
+    }
+
+    #[test]
+    fn test_downscale_nearest_lanczos() {
+        let (w, h, n) = (8, 2, 4);
+        let nearest =
+            DownscaleFilter::Nearest.downscale(ramp(w * n, h * n), w, h, n, ColorSpace::Srgb);
+        let lanczos =
+            DownscaleFilter::Lanczos3.downscale(ramp(w * n, h * n), w, h, n, ColorSpace::Srgb);
+        assert_eq!(nearest.len(), (w * h * 4) as usize);
+        assert_eq!(lanczos.len(), (w * h * 4) as usize);
+        // nearest picks one sample, lanczos weighs the samples around it
+        let differences = nearest
+            .chunks_exact(4)
+            .zip(lanczos.chunks_exact(4))
+            .filter(|(a, b)| (a[0] as i32 - b[0] as i32).abs() > 2)
+            .count();
+        assert!(differences >= w as usize, "{:?} {:?}", nearest, lanczos);
+
+        let rgba16: Vec<u16> = ramp(w * n, h * n).iter().map(|&c| c as u16 * 257).collect();
+        let scaled = DownscaleFilter::Triangle.downscale(rgba16, w, h, n, ColorSpace::Srgb);
+        assert_eq!(scaled.len(), (w * h * 4) as usize);
+    }
+
+    #[test]
+    fn test_downscale_linear() {
+        // a flat color stays as it is, in linear light as well
+        let flat: Vec<u8> = [200, 60, 13, 255].repeat(16 * 16);
+        let scaled = DownscaleFilter::Triangle.downscale(flat, 4, 4, 4, ColorSpace::Linear);
+        assert_eq!(scaled, [200, 60, 13, 255].repeat(4 * 4));
+        // the average of black and white stripes is half of the light
+        let stripes: Vec<u8> = (0..8 * 8)
+            .flat_map(|i| {
+                if i % 2 == 0 {
+                    [0, 0, 0, 255]
+                } else {
+                    [255, 255, 255, 255]
+                }
+            })
+            .collect();
+        let srgb = DownscaleFilter::Triangle.downscale(stripes.clone(), 1, 1, 8, ColorSpace::Srgb);
+        let linear = DownscaleFilter::Triangle.downscale(stripes, 1, 1, 8, ColorSpace::Linear);
+        assert!((srgb[0] as i32 - 128).abs() <= 2, "{:?}", srgb);
+        assert!((linear[0] as i32 - 188).abs() <= 2, "{:?}", linear);
+        assert_eq!(linear[3], 255);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::pic::data::Channel;
+
+/// Blur the edges of an rgba buffer of w x h pixels, a cheap way to smooth the hard edges
+/// of e.g. STEP without supersampling the whole image. The edges are found with a Sobel
+/// operator on the brightness, `strength` (0.0..=1.0) is how much of the blurred color replaces
+/// the color on the sharpest edges. Flat areas and the alpha stay as they are.
+pub fn smooth_edges_rgba8<C: Channel>(rgba8: &mut [C], w: u32, h: u32, strength: f32) {
+    if strength <= 0.0 {
+        return;
+    }
+    let (w, h) = (w as usize, h as usize);
+    let source = rgba8.to_vec();
+    let brightness: Vec<f32> = source
+        .chunks_exact(4)
+        .map(|pixel| {
+            let (r, g, b): (u32, u32, u32) = (pixel[0].into(), pixel[1].into(), pixel[2].into());
+            (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) / C::FULL
+        })
+        .collect();
+    // the pixels outside of the image repeat the border
+    let neighbour = |x: usize, y: usize, dx: isize, dy: isize| -> usize {
+        let nx = (x as isize + dx).clamp(0, w as isize - 1) as usize;
+        let ny = (y as isize + dy).clamp(0, h as isize - 1) as usize;
+        ny * w + nx
+    };
+    for y in 0..h {
+        for x in 0..w {
+            let b = |dx: isize, dy: isize| brightness[neighbour(x, y, dx, dy)];
+            let gx = b(1, -1) + 2.0 * b(1, 0) + b(1, 1) - b(-1, -1) - 2.0 * b(-1, 0) - b(-1, 1);
+            let gy = b(-1, 1) + 2.0 * b(0, 1) + b(1, 1) - b(-1, -1) - 2.0 * b(0, -1) - b(1, -1);
+            // a step from black to white is 4.0
+            let edge = ((gx * gx + gy * gy).sqrt() / 4.0).min(1.0);
+            let amount = edge * strength.min(1.0);
+            if amount == 0.0 {
+                continue;
+            }
+            let offset = (y * w + x) * 4;
+            for c in 0..3 {
+                // a 3x3 tent blur, the weights 1 2 1, 2 4 2, 1 2 1 add up to 16
+                let mut sum = 0.0;
+                for dy in -1..=1isize {
+                    for dx in -1..=1isize {
+                        let weight = ((2 - dx.abs()) * (2 - dy.abs())) as f32;
+                        let v: u32 = source[neighbour(x, y, dx, dy) * 4 + c].into();
+                        sum += weight * v as f32;
+                    }
+                }
+                let original: u32 = source[offset + c].into();
+                let original = original as f32;
+                let smoothed = original + (sum / 16.0 - original) * amount;
+                rgba8[offset + c] = C::from_f32(smoothed.round());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// w x h pixels, black on the left half and white on the right half
+    fn half_white(w: u32, h: u32) -> Vec<u8> {
+        (0..h)
+            .flat_map(|_| {
+                (0..w).flat_map(move |x| {
+                    if x < w / 2 {
+                        [0, 0, 0, 200]
+                    } else {
+                        [255, 255, 255, 200]
+                    }
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_smooth_edges_flat_image() {
+        let flat: Vec<u8> = [90, 160, 30, 255].repeat(8 * 6);
+        let mut smoothed = flat.clone();
+        smooth_edges_rgba8(&mut smoothed, 8, 6, 1.0);
+        assert_eq!(smoothed, flat);
+
+        let flat: Vec<u16> = [9000, 60000, 300, 65535].repeat(5 * 5);
+        let mut smoothed = flat.clone();
+        smooth_edges_rgba8(&mut smoothed, 5, 5, 1.0);
+        assert_eq!(smoothed, flat);
+    }
+
+    #[test]
+    fn test_smooth_edges_step() {
+        let step = half_white(8, 4);
+        let mut smoothed = step.clone();
+        smooth_edges_rgba8(&mut smoothed, 8, 4, 0.0);
+        assert_eq!(smoothed, step);
+
+        smooth_edges_rgba8(&mut smoothed, 8, 4, 1.0);
+        for y in 0..4 {
+            let pixel = |x: usize| &smoothed[(y * 8 + x) * 4..(y * 8 + x) * 4 + 4];
+            // far from the edge nothing changes
+            assert_eq!(pixel(0), &[0, 0, 0, 200]);
+            assert_eq!(pixel(7), &[255, 255, 255, 200]);
+            // on the edge the colors move towards each other, the alpha stays
+            assert!(pixel(3)[0] > 0 && pixel(3)[0] < 128);
+            assert!(pixel(4)[0] > 128 && pixel(4)[0] < 255);
+            assert_eq!(pixel(3)[3], 200);
+            assert_eq!(pixel(4)[3], 200);
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use crate::constants::{
+    PIC_MIN_COMPLEXITY, PIC_RANDOM_TREE_MAX, PIC_RANDOM_TREE_MAX_DEPTH, PIC_RANDOM_TREE_MAX_NODES,
+    PIC_RANDOM_TREE_MIN,
+};
+use crate::pic::color::Color;
+
+/// The relative chance of every operation and leaf to be picked when a random tree is generated.
+/// A weight of 0.0 means the node is never picked, the weights don't have to add up to anything.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OperatorWeights {
+    pub add: f32,
+    pub sub: f32,
+    pub mul: f32,
+    pub div: f32,
+    pub modulo: f32,
+    pub fbm: f32,
+    pub ridge: f32,
+    pub turbulence: f32,
+    pub cell1: f32,
+    pub cell2: f32,
+    pub sqrt: f32,
+    pub sin: f32,
+    pub atan: f32,
+    pub atan2: f32,
+    pub tan: f32,
+    pub sinh: f32,
+    pub tanh: f32,
+    pub log: f32,
+    pub abs: f32,
+    pub floor: f32,
+    pub ceil: f32,
+    pub fract: f32,
+    pub clamp: f32,
+    pub wrap: f32,
+    pub square: f32,
+    pub max: f32,
+    pub min: f32,
+    pub mandelbrot: f32,
+    pub warp: f32,
+    pub smoothstep: f32,
+    pub mix: f32,
+    pub step: f32,
+    pub voronoi: f32,
+    pub ridged_fbm: f32,
+    pub dx: f32,
+    pub dy: f32,
+    pub picture: f32,
+    pub x: f32,
+    pub y: f32,
+    pub t: f32,
+    pub constant: f32,
+}
+
+impl Default for OperatorWeights {
+    /// Plain arithmetic is the most common, the noise functions are rarer so they don't
+    /// turn every picture into noise soup, and Mandelbrot is a No-Op for now.
+    fn default() -> Self {
+        OperatorWeights {
+            add: 2.0,
+            sub: 2.0,
+            mul: 2.0,
+            div: 1.0,
+            modulo: 1.0,
+            fbm: 0.5,
+            ridge: 0.5,
+            turbulence: 0.5,
+            cell1: 0.5,
+            cell2: 0.5,
+            sqrt: 1.0,
+            sin: 1.5,
+            atan: 1.0,
+            atan2: 1.0,
+            tan: 0.5,
+            sinh: 0.5,
+            tanh: 0.5,
+            log: 1.0,
+            abs: 1.0,
+            floor: 0.5,
+            ceil: 0.5,
+            fract: 0.5,
+            clamp: 1.0,
+            wrap: 1.0,
+            square: 1.0,
+            max: 1.0,
+            min: 1.0,
+            mandelbrot: 0.0,
+            warp: 0.5,
+            smoothstep: 0.5,
+            mix: 1.0,
+            step: 0.5,
+            voronoi: 0.5,
+            ridged_fbm: 0.5,
+            dx: 0.5,
+            dy: 0.5,
+            picture: 1.0,
+            x: 1.0,
+            y: 1.0,
+            t: 1.0,
+            constant: 1.0,
+        }
+    }
+}
+
+impl OperatorWeights {
+    /// Every weight is 1.0, so all the nodes are equally likely
+    pub fn uniform() -> Self {
+        OperatorWeights {
+            add: 1.0,
+            sub: 1.0,
+            mul: 1.0,
+            div: 1.0,
+            modulo: 1.0,
+            fbm: 1.0,
+            ridge: 1.0,
+            turbulence: 1.0,
+            cell1: 1.0,
+            cell2: 1.0,
+            sqrt: 1.0,
+            sin: 1.0,
+            atan: 1.0,
+            atan2: 1.0,
+            tan: 1.0,
+            sinh: 1.0,
+            tanh: 1.0,
+            log: 1.0,
+            abs: 1.0,
+            floor: 1.0,
+            ceil: 1.0,
+            fract: 1.0,
+            clamp: 1.0,
+            wrap: 1.0,
+            square: 1.0,
+            max: 1.0,
+            min: 1.0,
+            mandelbrot: 1.0,
+            warp: 1.0,
+            smoothstep: 1.0,
+            mix: 1.0,
+            step: 1.0,
+            voronoi: 1.0,
+            ridged_fbm: 1.0,
+            dx: 1.0,
+            dy: 1.0,
+            picture: 1.0,
+            x: 1.0,
+            y: 1.0,
+            t: 1.0,
+            constant: 1.0,
+        }
+    }
+
+    /// The weights of the operations, in the order of APTNode::pick_random_node
+    pub fn node_weights(&self) -> Vec<f32> {
+        vec![
+            self.add,
+            self.sub,
+            self.mul,
+            self.div,
+            self.modulo,
+            self.fbm,
+            self.ridge,
+            self.turbulence,
+            self.cell1,
+            self.cell2,
+            self.sqrt,
+            self.sin,
+            self.atan,
+            self.atan2,
+            self.tan,
+            self.sinh,
+            self.tanh,
+            self.log,
+            self.abs,
+            self.floor,
+            self.ceil,
+            self.fract,
+            self.clamp,
+            self.wrap,
+            self.square,
+            self.max,
+            self.min,
+            self.mandelbrot,
+            self.warp,
+            self.smoothstep,
+            self.mix,
+            self.step,
+            self.voronoi,
+            self.ridged_fbm,
+            self.dx,
+            self.dy,
+            self.picture,
+        ]
+    }
+
+    /// The weights of X, Y, Constant and, for videos, T
+    pub fn leaf_weights(&self, video: bool) -> Vec<f32> {
+        let t = if video { self.t } else { 0.0 };
+        vec![self.x, self.y, self.constant, t]
+    }
+}
+
+/// How Pic::new generates the random trees of a picture
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenerationConfig {
+    /// The number of operations in a tree is picked from tree_min..tree_max
+    pub tree_min: usize,
+    pub tree_max: usize,
+    /// A tree stops growing before it gets deeper than max_depth levels or more than max_nodes nodes
+    pub max_depth: usize,
+    pub max_nodes: usize,
+    /// Pics with fewer nodes, see Pic::complexity, are generated again
+    pub min_complexity: usize,
+    pub weights: OperatorWeights,
+    /// The colors of the gradient pics, random colors when there is none
+    pub palette: Option<Vec<Color>>,
+}
+
+impl GenerationConfig {
+    pub fn new(tree_min: usize, tree_max: usize) -> Self {
+        GenerationConfig {
+            tree_min,
+            tree_max,
+            max_depth: PIC_RANDOM_TREE_MAX_DEPTH,
+            max_nodes: PIC_RANDOM_TREE_MAX_NODES,
+            min_complexity: PIC_MIN_COMPLEXITY,
+            weights: OperatorWeights::default(),
+            palette: None,
+        }
+    }
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        GenerationConfig::new(PIC_RANDOM_TREE_MIN, PIC_RANDOM_TREE_MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::aptnode::APTNode;
+
+    #[test]
+    fn test_operator_weights_node_weights() {
+        // all variants, except the leaves and Empty
+        let operations = APTNode::VARIANT_COUNT - 11;
+        assert_eq!(OperatorWeights::default().node_weights().len(), operations);
+        assert_eq!(
+            OperatorWeights::uniform().node_weights(),
+            vec![1.0; operations]
+        );
+        let weights = OperatorWeights {
+            picture: 3.0,
+            ..OperatorWeights::uniform()
+        };
+        assert_eq!(weights.node_weights().last(), Some(&3.0));
+    }
+
+    #[test]
+    fn test_operator_weights_leaf_weights() {
+        let weights = OperatorWeights {
+            constant: 2.0,
+            t: 3.0,
+            ..OperatorWeights::uniform()
+        };
+        assert_eq!(weights.leaf_weights(true), vec![1.0, 1.0, 2.0, 3.0]);
+        assert_eq!(weights.leaf_weights(false), vec![1.0, 1.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn test_generation_config_default() {
+        let config = GenerationConfig::default();
+        assert_eq!(config.tree_min, PIC_RANDOM_TREE_MIN);
+        assert_eq!(config.tree_max, PIC_RANDOM_TREE_MAX);
+        assert_eq!(config.max_depth, PIC_RANDOM_TREE_MAX_DEPTH);
+        assert_eq!(config.max_nodes, PIC_RANDOM_TREE_MAX_NODES);
+        assert_eq!(config.min_complexity, PIC_MIN_COMPLEXITY);
+        assert_eq!(config.weights, OperatorWeights::default());
+        assert_eq!(config.palette, None);
+    }
+}
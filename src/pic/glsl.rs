@@ -0,0 +1,557 @@
+use std::collections::BTreeSet;
+
+use crate::constants::PIC_GRADIENT_SIZE;
+use crate::parser::aptnode::APTNode;
+use crate::pic::actual_picture::Sampling;
+use crate::pic::coordinatesystem::CoordinateSystem;
+use crate::pic::data::gradient::GradientData;
+use crate::pic::gradientwrap::GradientWrap;
+use crate::pic::noisebasis::NoiseBasis;
+use crate::pic::normalization::Normalization;
+use crate::pic::pic::Pic;
+
+/// The helpers that the translated expressions call, they follow the math of the StackMachine.
+/// The noise is simplex, perlin, value and cellular noise like the StackMachine makes, but
+/// not the same values.
+const GLSL_PRELUDE: &'static str = r#"#ifdef GL_ES
+precision highp float;
+#endif
+
+uniform float t;
+uniform vec2 resolution;
+
+const float EVO_PI = 3.14159265;
+const float EVO_E = 2.71828183;
+
+// the remainder of a truncated division, like % in Rust
+float evo_rem(float a, float b) {
+    float q = a / b;
+    return a - b * (q < 0.0 ? ceil(q) : floor(q));
+}
+
+float evo_mod(float a, float b) {
+    return b == 0.0 ? a : evo_rem(a, b);
+}
+
+// a division by zero gives -1.0, 0.0 or 1.0 instead of infinity or NaN
+float evo_div(float a, float b) {
+    return b == 0.0 ? sign(a) : a / b;
+}
+
+float evo_sqrt(float v) {
+    return v >= 0.0 ? sqrt(v) : -sqrt(-v);
+}
+
+float evo_log(float v) {
+    float l = log(abs(v * 4.0));
+    return (v >= 0.0 ? l : -l) / EVO_E;
+}
+
+float evo_sinh(float v) {
+    if (abs(v) > 89.0) {
+        return sign(v);
+    }
+    return (exp(v) - exp(-v)) * 0.5;
+}
+
+float evo_tanh(float v) {
+    float e = exp(-2.0 * abs(v));
+    return sign(v) * (1.0 - e) / (1.0 + e);
+}
+
+float evo_wrap(float v) {
+    if (v < -1.0 || v > 1.0) {
+        float w = (v + 1.0) / 2.0;
+        return -1.0 + 2.0 * (w - floor(w));
+    }
+    return v;
+}
+
+// equal edges are a step at edge0, smoothstep of glsl leaves them undefined
+float evo_smoothstep(float edge0, float edge1, float x) {
+    float d = edge1 - edge0;
+    float t = d == 0.0 ? (x > edge0 ? 1.0 : 0.0) : clamp((x - edge0) / d, 0.0, 1.0);
+    return t * t * (3.0 - 2.0 * t) * 2.0 - 1.0;
+}
+
+float evo_square(float v) {
+    return v * v;
+}
+
+vec3 evo_permute(vec3 v) {
+    return mod(((v * 34.0) + 1.0) * v, 289.0);
+}
+
+// 2D simplex noise by Ian McEwan and Stefan Gustavson, MIT license
+float evo_simplex(vec2 v) {
+    const vec4 C = vec4(0.211324865405187, 0.366025403784439, -0.577350269189626, 0.024390243902439);
+    vec2 i = floor(v + dot(v, C.yy));
+    vec2 x0 = v - i + dot(i, C.xx);
+    vec2 i1 = (x0.x > x0.y) ? vec2(1.0, 0.0) : vec2(0.0, 1.0);
+    vec4 x12 = x0.xyxy + C.xxzz;
+    x12.xy -= i1;
+    i = mod(i, 289.0);
+    vec3 p = evo_permute(evo_permute(i.y + vec3(0.0, i1.y, 1.0)) + i.x + vec3(0.0, i1.x, 1.0));
+    vec3 m = max(0.5 - vec3(dot(x0, x0), dot(x12.xy, x12.xy), dot(x12.zw, x12.zw)), 0.0);
+    m = m * m;
+    m = m * m;
+    vec3 x = 2.0 * fract(p * C.www) - 1.0;
+    vec3 h = abs(x) - 0.5;
+    vec3 ox = floor(x + 0.5);
+    vec3 a0 = x - ox;
+    m *= 1.79284291400159 - 0.85373472095314 * (a0 * a0 + h * h);
+    vec3 g;
+    g.x = a0.x * x0.x + h.x * x0.y;
+    g.yz = a0.yz * x12.xz + h.yz * x12.yw;
+    return 130.0 * dot(m, g);
+}
+
+float evo_ridge(vec2 p, float lacunarity, float gain) {
+    float amp = 1.0;
+    float result = 1.0 - abs(evo_simplex(p));
+    for (int i = 1; i < 3; i++) {
+        p *= lacunarity;
+        amp *= gain;
+        result += (1.0 - abs(evo_simplex(p))) * amp;
+    }
+    return result;
+}
+
+// ridged multifractal, every octave is weighted by the one before it
+float evo_ridged_fbm(vec2 p, float lacunarity, float gain) {
+    float amp = 1.0;
+    float weight = 1.0;
+    float result = 0.0;
+    float total = 0.0;
+    for (int i = 0; i < 3; i++) {
+        float folded = 1.0 - abs(evo_simplex(p));
+        float signal = folded * folded * weight;
+        weight = clamp(signal * 2.0, 0.0, 1.0);
+        result += signal * amp;
+        total += amp;
+        amp *= abs(gain);
+        p *= lacunarity;
+    }
+    return result / total * 2.0 - 1.0;
+}
+
+float evo_turbulence(vec2 p, float lacunarity, float gain) {
+    float amp = 1.0;
+    float result = abs(evo_simplex(p));
+    for (int i = 1; i < 3; i++) {
+        p *= lacunarity;
+        amp *= gain;
+        result += abs(evo_simplex(p) * amp);
+    }
+    return result;
+}
+
+vec2 evo_cell_point(vec2 cell) {
+    return fract(sin(vec2(dot(cell, vec2(127.1, 311.7)), dot(cell, vec2(269.5, 183.3)))) * 43758.5453);
+}
+
+// the random unit gradient at a corner of the grid
+vec2 evo_gradient(vec2 cell) {
+    float angle = EVO_PI * (evo_cell_point(cell).x * 2.0 - 1.0);
+    return vec2(cos(angle), sin(angle));
+}
+
+vec2 evo_fade(vec2 f) {
+    return f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+}
+
+float evo_perlin(vec2 p) {
+    vec2 cell = floor(p);
+    vec2 f = p - cell;
+    vec2 u = evo_fade(f);
+    float a = dot(evo_gradient(cell), f);
+    float b = dot(evo_gradient(cell + vec2(1.0, 0.0)), f - vec2(1.0, 0.0));
+    float c = dot(evo_gradient(cell + vec2(0.0, 1.0)), f - vec2(0.0, 1.0));
+    float d = dot(evo_gradient(cell + vec2(1.0, 1.0)), f - vec2(1.0, 1.0));
+    return mix(mix(a, b, u.x), mix(c, d, u.x), u.y) * 1.41421356;
+}
+
+float evo_value_noise(vec2 p) {
+    vec2 cell = floor(p);
+    vec2 u = evo_fade(p - cell);
+    float a = evo_cell_point(cell).x;
+    float b = evo_cell_point(cell + vec2(1.0, 0.0)).x;
+    float c = evo_cell_point(cell + vec2(0.0, 1.0)).x;
+    float d = evo_cell_point(cell + vec2(1.0, 1.0)).x;
+    return mix(mix(a, b, u.x), mix(c, d, u.x), u.y) * 2.0 - 1.0;
+}
+
+const int EVO_SIMPLEX = 0;
+const int EVO_PERLIN = 1;
+const int EVO_VALUE = 2;
+
+float evo_noise(vec2 p, int basis) {
+    if (basis == EVO_PERLIN) {
+        return evo_perlin(p);
+    } else if (basis == EVO_VALUE) {
+        return evo_value_noise(p);
+    }
+    return evo_simplex(p);
+}
+
+float evo_fbm(vec2 p, float lacunarity, float gain, float octaves, int basis) {
+    float amp = 1.0;
+    float result = evo_noise(p, basis);
+    float count = octaves * 8.0;
+    for (int i = 1; i < 8; i++) {
+        if (count < float(i) + 0.5) {
+            break;
+        }
+        p *= lacunarity;
+        amp *= gain;
+        result += evo_noise(p, basis) * amp;
+    }
+    return result;
+}
+
+// the distance to the nearest of the jittered points and the value of its cell
+vec2 evo_cell(vec2 p, float jitter) {
+    vec2 cell = floor(p);
+    float nearest = 1e10;
+    vec2 nearest_cell = cell;
+    for (int j = -1; j <= 1; j++) {
+        for (int i = -1; i <= 1; i++) {
+            vec2 c = cell + vec2(float(i), float(j));
+            vec2 point = c + 0.5 + (evo_cell_point(c) - 0.5) * jitter * 2.0;
+            float d = distance(p, point);
+            if (d < nearest) {
+                nearest = d;
+                nearest_cell = c;
+            }
+        }
+    }
+    return vec2(nearest, fract(sin(dot(nearest_cell, vec2(12.9898, 78.233))) * 43758.5453) * 2.0 - 1.0);
+}
+
+// the distance to the nearest point, the points circle around within their cell with t
+float evo_voronoi(vec2 p, float t) {
+    vec2 cell = floor(p);
+    float nearest = 1e10;
+    for (int j = -1; j <= 1; j++) {
+        for (int i = -1; i <= 1; i++) {
+            vec2 c = cell + vec2(float(i), float(j));
+            vec2 h = evo_cell_point(c) * 2.0 - 1.0;
+            vec2 point = c + 0.5 + 0.5 * sin(EVO_PI * (h + h + t));
+            nearest = min(nearest, distance(p, point));
+        }
+    }
+    return min(nearest, 1.0) * 2.0 - 1.0;
+}
+
+float evo_brightness(vec4 color) {
+    return (color.r + color.g + color.b) / 3.0 * 2.0 - 1.0;
+}
+
+// the sampler wraps around and filters linearly
+float evo_picture(sampler2D picture, float x, float y) {
+    vec2 p = vec2(evo_rem((x + 1.0) / 2.0, 1.0), evo_rem((y + 1.0) / 2.0, 1.0));
+    return evo_brightness(texture2D(picture, p));
+}
+
+float evo_picture_nearest(sampler2D picture, vec2 size, float x, float y) {
+    vec2 p = vec2(evo_rem((x + 1.0) / 2.0, 1.0), evo_rem((y + 1.0) / 2.0, 1.0));
+    return evo_brightness(texture2D(picture, (floor(p * size + 0.5) + 0.5) / size));
+}
+
+vec2 evo_polar(vec2 p) {
+    float adjust = p.x >= 0.0 ? (p.y < 0.0 ? 2.0 * EVO_PI : 0.0) : EVO_PI;
+    return vec2(length(p), atan(p.y / p.x) + adjust);
+}
+
+vec3 evo_hsv(float h, float s, float v) {
+    float hi = floor(h * 6.0);
+    float f = h * 6.0 - hi;
+    float p = v * (1.0 - s);
+    float q = v * (1.0 - f * s);
+    float u = v * (1.0 - (1.0 - f) * s);
+    float sector = evo_rem(hi, 6.0);
+    if (sector == 0.0) {
+        return vec3(v, u, p);
+    } else if (sector == 1.0) {
+        return vec3(q, v, p);
+    } else if (sector == 2.0) {
+        return vec3(p, v, u);
+    } else if (sector == 3.0) {
+        return vec3(p, q, v);
+    } else if (sector == 4.0) {
+        return vec3(u, p, v);
+    }
+    return vec3(v, p, q);
+}
+
+vec3 evo_cmyk(vec4 ink) {
+    ink = clamp(ink, 0.0, 1.0);
+    return (1.0 - ink.rgb) * (1.0 - ink.a);
+}
+
+float evo_lab_f_inv(float v) {
+    float delta = 6.0 / 29.0;
+    return v > delta ? v * v * v : 3.0 * delta * delta * (v - 4.0 / 29.0);
+}
+
+float evo_srgb_gamma(float c) {
+    c = clamp(c, 0.0, 1.0);
+    return c > 0.0031308 ? 1.055 * pow(c, 1.0 / 2.4) - 0.055 : 12.92 * c;
+}
+
+vec3 evo_lab(float l, float a, float b) {
+    float fy = (l + 16.0) / 116.0;
+    vec3 xyz = vec3(
+        evo_lab_f_inv(fy + a / 500.0) * 0.95047,
+        evo_lab_f_inv(fy),
+        evo_lab_f_inv(fy - b / 200.0) * 1.08883
+    );
+    vec3 rgb = vec3(
+        dot(vec3(3.2406, -1.5372, -0.4986), xyz),
+        dot(vec3(-0.9689, 1.8758, 0.0415), xyz),
+        dot(vec3(0.0557, -0.2040, 1.0570), xyz)
+    );
+    return vec3(evo_srgb_gamma(rgb.r), evo_srgb_gamma(rgb.g), evo_srgb_gamma(rgb.b));
+}
+"#;
+
+/// A float literal that GLSL accepts, negative values are wrapped in parentheses
+pub fn glsl_float(v: f32) -> String {
+    let v = if v.is_nan() {
+        0.0
+    } else {
+        v.max(f32::MIN).min(f32::MAX)
+    };
+    if v < 0.0 {
+        format!("({:?})", v)
+    } else {
+        format!("{:?}", v)
+    }
+}
+
+/// The constant of the prelude that selects `basis` in evo_fbm
+pub fn glsl_noise_basis(basis: &NoiseBasis) -> &'static str {
+    match basis {
+        NoiseBasis::Simplex => "EVO_SIMPLEX",
+        NoiseBasis::Perlin => "EVO_PERLIN",
+        NoiseBasis::Value => "EVO_VALUE",
+    }
+}
+
+/// The name of the sampler uniform of a picture file, e.g. pic_eye_jpg for eye.jpg
+pub fn sampler_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("pic_{}", name)
+}
+
+/// The pictures that the trees sample, with the ones that are sampled without filtering
+fn pictures(trees: &[&APTNode]) -> (BTreeSet<String>, BTreeSet<String>) {
+    let mut all = BTreeSet::new();
+    let mut nearest = BTreeSet::new();
+    for tree in trees {
+        for i in 0..tree.count_nodes() {
+            if let Some(APTNode::Picture(name, sampling, _)) = tree.get_subtree(i) {
+                all.insert(name.clone());
+                if *sampling == Sampling::Nearest {
+                    nearest.insert(name.clone());
+                }
+            }
+        }
+    }
+    (all, nearest)
+}
+
+/// A function that picks the color of a gradient like GradientData does, `pct` is 0.0..1.0
+fn gradient_function(data: &GradientData) -> String {
+    let color = |i: usize| {
+        let c = data.colors[i].0;
+        format!(
+            "vec3({}, {}, {})",
+            glsl_float(c.r),
+            glsl_float(c.g),
+            glsl_float(c.b)
+        )
+    };
+    let positions = data.positions();
+    let mut function = String::from("vec3 evo_gradient(float pct) {\n");
+    function += &format!(
+        "    if (pct <= 0.0) {{\n        return {};\n    }}\n",
+        color(0)
+    );
+    for i in 1..positions.len() {
+        function += &format!(
+            "    if (pct <= {}) {{\n        return mix({}, {}, (pct - {}) / ({} - {}));\n    }}\n",
+            glsl_float(positions[i]),
+            color(i - 1),
+            color(i),
+            glsl_float(positions[i - 1]),
+            glsl_float(positions[i]),
+            glsl_float(positions[i - 1])
+        );
+    }
+    function += &format!("    return {};\n}}\n", color(positions.len() - 1));
+    function
+}
+
+/// A fragment shader that renders the pic like get_rgba8 does, for the uniforms `t`,
+/// `resolution` (the width and height in pixels), and a sampler2D per picture file
+pub fn pic_to_glsl(pic: &Pic) -> String {
+    let trees = pic.to_tree();
+    let channels: Vec<String> = trees.iter().map(|tree| tree.to_glsl()).collect();
+    let color = match pic {
+        Pic::Mono(_) => "vec4(vec3(step(0.0, c0)), 1.0)".to_string(),
+        Pic::Grayscale(data) => match data.normalization {
+            Normalization::Wrap => {
+                "vec4(vec3(mod(floor((c0 + 1.0) * 127.5), 256.0) / 255.0), 1.0)".to_string()
+            }
+            // Auto needs the range of the first row, that is not known to a single pixel
+            Normalization::Clamp | Normalization::Auto => {
+                "vec4(vec3(clamp((c0 + 1.0) * 0.5, 0.0, 1.0)), 1.0)".to_string()
+            }
+        },
+        Pic::Gradient(data) => {
+            let size = glsl_float(PIC_GRADIENT_SIZE as f32);
+            let shift = if data.cycle { "t + 1.0" } else { "1.0" };
+            let index = format!("floor((c0 + {}) * 0.5 * {} + 0.5)", shift, size);
+            let index = match data.wrap {
+                GradientWrap::Wrap => format!("mod({}, {})", index, size),
+                GradientWrap::Clamp => format!("clamp({}, 0.0, {} - 1.0)", index, size),
+                GradientWrap::Mirror => format!(
+                    "({size} - 0.5 - abs(mod({}, 2.0 * {size}) - {size} + 0.5))",
+                    index,
+                    size = size
+                ),
+            };
+            format!("vec4(evo_gradient({} / {}), 1.0)", index, size)
+        }
+        Pic::RGB(_) => "vec4(clamp((vec3(c0, c1, c2) + 1.0) * 128.0 / 255.0, 0.0, 1.0), 1.0)".to_string(),
+        Pic::RGBA(_) => "clamp((vec4(c0, c1, c2, c3) + 1.0) * 128.0 / 255.0, 0.0, 1.0)".to_string(),
+        Pic::HSV(_) => "vec4(clamp(evo_hsv(evo_rem((c0 + 1.0) * 0.5, 1.0001), evo_rem((c1 + 1.0) * 0.5, 1.0001), evo_rem((c2 + 1.0) * 0.5, 1.0001)), 0.0, 1.0), 1.0)".to_string(),
+        Pic::CMYK(_) => "vec4(evo_cmyk((vec4(c0, c1, c2, c3) + 1.0) * 0.5), 1.0)".to_string(),
+        Pic::LAB(_) => "vec4(evo_lab((c0 + 1.0) * 50.0, c1 * 128.0, c2 * 128.0), 1.0)".to_string(),
+    };
+
+    let mut shader = String::from(GLSL_PRELUDE);
+    let (all, nearest) = pictures(&trees);
+    for name in &all {
+        shader += &format!("uniform sampler2D {};\n", sampler_name(name));
+    }
+    for name in &nearest {
+        shader += &format!("uniform vec2 {}_size;\n", sampler_name(name));
+    }
+    if let Pic::Gradient(data) = pic {
+        shader += "\n";
+        shader += &gradient_function(data);
+    }
+    shader += "\nvoid main() {\n";
+    shader += "    // the same pixels as the CPU renders, with the first row at the top\n";
+    shader += "    float column = gl_FragCoord.x - 0.5;\n";
+    shader += "    float row = resolution.y - gl_FragCoord.y - 0.5;\n";
+    shader += "    vec2 uv = vec2(column * 2.0 / (resolution.x - 1.0) - 1.0, row / resolution.y * 2.0 - 1.0);\n";
+    if *pic.coord() == CoordinateSystem::Polar {
+        shader += "    uv = evo_polar(uv);\n";
+    }
+    for (i, channel) in channels.iter().enumerate() {
+        shader += &format!("    float c{} = {};\n", i, channel);
+    }
+    shader += &format!("    gl_FragColor = {};\n}}\n", color);
+    shader
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lexer::lisp_to_pic;
+    use std::process::Command;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SHADER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn test_glsl_float() {
+        assert_eq!(glsl_float(1.0), "1.0");
+        assert_eq!(glsl_float(0.25), "0.25");
+        assert_eq!(glsl_float(-0.5), "(-0.5)");
+        assert_eq!(glsl_float(f32::NAN), "0.0");
+        assert!(!glsl_float(f32::INFINITY).contains("inf"));
+    }
+
+    #[test]
+    fn test_sampler_name() {
+        assert_eq!(sampler_name("eye.jpg"), "pic_eye_jpg");
+        assert_eq!(sampler_name("my-pic 2.png"), "pic_my_pic_2_png");
+    }
+
+    #[test]
+    fn test_pic_to_glsl_x() {
+        let pic = lisp_to_pic(
+            "( MONO CARTESIAN ( X ) )".to_string(),
+            CoordinateSystem::Polar,
+        )
+        .unwrap();
+        let shader = pic_to_glsl(&pic);
+        assert!(shader.contains("float c0 = uv.x;"));
+        assert!(shader.contains("gl_FragColor = "));
+        assert!(!shader.contains("evo_polar(uv)"));
+        assert_glsl_compiles(&shader);
+    }
+
+    #[test]
+    fn test_pic_to_glsl_modes() {
+        for source in &[
+            "( MONO POLAR ( FBM X Y 0.5 0.5 0.5 0.5 ) )",
+            "( GRAYSCALE CARTESIAN CLAMP ( CELL1 X Y 0.5 0.5 0.5 ) )",
+            "( GRADIENT CARTESIAN ( COLORS ( COLOR 0.1 0.2 0.3 ) ( STOPCOLOR 0.4 0.5 0.6 ) ( COLOR 0.9 0.8 0.7 ) ) ( Y ) )",
+            "( RGB CARTESIAN ( PIC-eye.jpg X Y ) ( PICN-eye.jpg Y X ) ( WARP 0.1 0.2 ( * X Y ) ) )",
+            "( RGBA POLAR ( X ) ( Y ) ( % X -0.5 ) ( T ) )",
+            "( HSV CARTESIAN ( X ) ( Y ) ( / WIDTH HEIGHT ) )",
+            "( CMYK CARTESIAN ( X ) ( Y ) ( SINH X ) ( TANH Y ) )",
+            "( LAB CARTESIAN ( X ) ( ATAN2 X Y ) ( LOG Y ) )",
+        ] {
+            let pic = lisp_to_pic(source.to_string(), CoordinateSystem::Polar).unwrap();
+            let shader = pic_to_glsl(&pic);
+            for i in 0..pic.to_tree().len() {
+                assert!(shader.contains(&format!("float c{} = ", i)), "{}", source);
+            }
+            assert!(shader.contains("gl_FragColor = "), "{}", source);
+            assert_glsl_compiles(&shader);
+        }
+    }
+
+    #[test]
+    fn test_pic_to_glsl_pictures() {
+        let pic = lisp_to_pic(
+            "( RGB CARTESIAN ( PIC-eye.jpg X Y ) ( PICN-eye.jpg Y X ) ( PIC-other.png X Y ) )"
+                .to_string(),
+            CoordinateSystem::Polar,
+        )
+        .unwrap();
+        let shader = pic_to_glsl(&pic);
+        assert_eq!(shader.matches("uniform sampler2D pic_eye_jpg;").count(), 1);
+        assert!(shader.contains("uniform sampler2D pic_other_png;"));
+        assert!(shader.contains("uniform vec2 pic_eye_jpg_size;"));
+        assert!(!shader.contains("uniform vec2 pic_other_png_size;"));
+    }
+
+    /// Checks the shader with glslangValidator, when it is installed
+    fn assert_glsl_compiles(shader: &str) {
+        let path = std::env::temp_dir().join(format!(
+            "evolution_{}_{}.frag",
+            std::process::id(),
+            SHADER_COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::write(&path, shader).unwrap();
+        if let Ok(output) = Command::new("glslangValidator").arg(&path).output() {
+            assert!(
+                output.status.success(),
+                "{}\n{}",
+                String::from_utf8_lossy(&output.stdout),
+                shader
+            );
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+}
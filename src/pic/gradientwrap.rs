@@ -0,0 +1,112 @@
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// How a gradient index outside of 0..size picks a color of the gradient
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GradientWrap {
+    /// The gradient repeats, this is how gradients were always rendered
+    Wrap,
+    /// Indices below or above the gradient get its first or its last color
+    Clamp,
+    /// The gradient repeats, every other time backwards, so there are no hard edges
+    Mirror,
+}
+
+impl GradientWrap {
+    pub fn list_all<'a>() -> Vec<String> {
+        vec![
+            GradientWrap::Wrap.to_string(),
+            GradientWrap::Clamp.to_string(),
+            GradientWrap::Mirror.to_string(),
+        ]
+    }
+
+    /// The index in a gradient of `size` colors for `index`
+    #[inline(always)]
+    pub fn index(&self, index: i32, size: usize) -> usize {
+        let size = size as i32;
+        let index = match self {
+            GradientWrap::Wrap => index.rem_euclid(size),
+            GradientWrap::Clamp => index.clamp(0, size - 1),
+            GradientWrap::Mirror => {
+                let index = index.rem_euclid(2 * size);
+                if index < size {
+                    index
+                } else {
+                    2 * size - 1 - index
+                }
+            }
+        };
+        index as usize
+    }
+}
+
+impl Display for GradientWrap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        let x = match self {
+            GradientWrap::Wrap => "wrap",
+            GradientWrap::Clamp => "clamp",
+            GradientWrap::Mirror => "mirror",
+        };
+        write!(f, "{}", x)
+    }
+}
+
+impl FromStr for GradientWrap {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_ref() {
+            "wrap" => Ok(GradientWrap::Wrap),
+            "clamp" => Ok(GradientWrap::Clamp),
+            "mirror" => Ok(GradientWrap::Mirror),
+            _ => Err(format!("Cannot parse {}. Not a known gradient wrap", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gradient_wrap_parse() {
+        assert_eq!("Wrap".parse(), Ok(GradientWrap::Wrap));
+        assert_eq!("CLAMP".parse(), Ok(GradientWrap::Clamp));
+        assert_eq!("mirror".parse(), Ok(GradientWrap::Mirror));
+        assert_eq!(
+            "auto".parse::<GradientWrap>(),
+            Err("Cannot parse auto. Not a known gradient wrap".to_string())
+        );
+    }
+
+    #[test]
+    fn test_gradient_wrap_index() {
+        for wrap in [
+            GradientWrap::Wrap,
+            GradientWrap::Clamp,
+            GradientWrap::Mirror,
+        ] {
+            assert_eq!(wrap.index(0, 512), 0);
+            assert_eq!(wrap.index(300, 512), 300);
+            assert_eq!(wrap.index(511, 512), 511);
+        }
+        assert_eq!(GradientWrap::Wrap.index(512, 512), 0);
+        assert_eq!(GradientWrap::Wrap.index(640, 512), 128);
+        assert_eq!(GradientWrap::Wrap.index(-1, 512), 511);
+        assert_eq!(GradientWrap::Clamp.index(640, 512), 511);
+        assert_eq!(GradientWrap::Clamp.index(-1, 512), 0);
+        assert_eq!(GradientWrap::Mirror.index(512, 512), 511);
+        assert_eq!(GradientWrap::Mirror.index(640, 512), 383);
+        assert_eq!(GradientWrap::Mirror.index(-1, 512), 0);
+        assert_eq!(GradientWrap::Mirror.index(1024, 512), 0);
+    }
+
+    #[test]
+    fn test_gradient_wrap_display() {
+        assert_eq!(&GradientWrap::Wrap.to_string(), "wrap");
+        assert_eq!(&GradientWrap::Clamp.to_string(), "clamp");
+        assert_eq!(&GradientWrap::Mirror.to_string(), "mirror");
+    }
+}
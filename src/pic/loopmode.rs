@@ -0,0 +1,72 @@
+use std::fmt::{Display, Formatter, Result as FResult};
+
+use clap::ValueEnum;
+
+/// How the frames of an animation are played back
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LoopMode {
+    /// Repeat the frames from the first to the last
+    Forward,
+    /// Play the frames forward and then backward, so the animation doesn't jump when it repeats
+    #[value(name = "pingpong")]
+    PingPong,
+    /// Play the frames once
+    Once,
+}
+
+impl LoopMode {
+    /// Put the frames in playback order, `PingPong` appends the frames in reverse without the
+    /// first and the last frame, as those are shown already when the animation turns around
+    pub fn arrange<T: Clone>(&self, mut frames: Vec<T>) -> Vec<T> {
+        if *self == LoopMode::PingPong && frames.len() > 2 {
+            let backward: Vec<T> = frames[1..frames.len() - 1].iter().rev().cloned().collect();
+            frames.extend(backward);
+        }
+        frames
+    }
+
+    pub fn repeats(&self) -> bool {
+        *self != LoopMode::Once
+    }
+}
+
+impl Display for LoopMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        let x = match self {
+            LoopMode::Forward => "forward",
+            LoopMode::PingPong => "pingpong",
+            LoopMode::Once => "once",
+        };
+        write!(f, "{}", x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loop_mode_arrange() {
+        let frames = vec![0, 1, 2, 3];
+        assert_eq!(LoopMode::Forward.arrange(frames.clone()), vec![0, 1, 2, 3]);
+        assert_eq!(LoopMode::Once.arrange(frames.clone()), vec![0, 1, 2, 3]);
+        assert_eq!(LoopMode::PingPong.arrange(frames), vec![0, 1, 2, 3, 2, 1]);
+        // nothing is left to play backward
+        assert_eq!(LoopMode::PingPong.arrange(vec![0, 1]), vec![0, 1]);
+        assert_eq!(LoopMode::PingPong.arrange(vec![0]), vec![0]);
+        assert_eq!(
+            LoopMode::PingPong.arrange(Vec::<u8>::new()),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[test]
+    fn test_loop_mode_parse() {
+        assert_eq!(LoopMode::from_str("pingpong", true), Ok(LoopMode::PingPong));
+        assert_eq!(LoopMode::from_str("once", true), Ok(LoopMode::Once));
+        assert!(LoopMode::from_str("backward", true).is_err());
+        assert_eq!(LoopMode::PingPong.to_string(), "pingpong");
+        assert!(LoopMode::Forward.repeats());
+        assert!(!LoopMode::Once.repeats());
+    }
+}
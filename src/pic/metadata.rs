@@ -0,0 +1,208 @@
+use png::{BitDepth, ColorType, Decoder, Encoder};
+
+use crate::pic::coordinatesystem::CoordinateSystem;
+use crate::pic::pic::Pic;
+
+const KEYWORD_SOFTWARE: &str = "Software";
+const KEYWORD_EXPRESSION: &str = "evolution:expression";
+const KEYWORD_COORDINATE_SYSTEM: &str = "evolution:coordinate-system";
+const KEYWORD_WIDTH: &str = "evolution:width";
+const KEYWORD_HEIGHT: &str = "evolution:height";
+const KEYWORD_T: &str = "evolution:t";
+const KEYWORD_SEED: &str = "evolution:seed";
+
+/// What is needed to render a png file again, it is stored in the text chunks of the png
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderMetadata {
+    /// The lisp of the pic
+    pub expression: String,
+    pub coordinate_system: CoordinateSystem,
+    pub width: u32,
+    pub height: u32,
+    /// The value of T the image was rendered at
+    pub t: f32,
+    /// The seed of the random generator that made the pic, when it was generated
+    pub seed: Option<u64>,
+}
+
+impl RenderMetadata {
+    pub fn new(pic: &Pic, width: u32, height: u32, t: f32, seed: Option<u64>) -> Self {
+        RenderMetadata {
+            expression: pic.to_lisp(),
+            coordinate_system: pic.coord().clone(),
+            width,
+            height,
+            t,
+            seed,
+        }
+    }
+
+    fn text_chunks(&self) -> Vec<(&'static str, String)> {
+        let mut chunks = vec![
+            (KEYWORD_SOFTWARE, "evolution".to_string()),
+            (KEYWORD_EXPRESSION, self.expression.clone()),
+            (
+                KEYWORD_COORDINATE_SYSTEM,
+                self.coordinate_system.to_string(),
+            ),
+            (KEYWORD_WIDTH, self.width.to_string()),
+            (KEYWORD_HEIGHT, self.height.to_string()),
+            (KEYWORD_T, self.t.to_string()),
+        ];
+        if let Some(seed) = self.seed {
+            chunks.push((KEYWORD_SEED, seed.to_string()));
+        }
+        chunks
+    }
+}
+
+/// Encode rgba pixels as a png with the metadata in its text chunks, `data` holds the
+/// channels as bytes of `depth` bits, 16 bits big endian
+fn encode_png(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    depth: BitDepth,
+    metadata: &RenderMetadata,
+) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut encoder = Encoder::new(&mut bytes, width, height);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(depth);
+    for (keyword, text) in metadata.text_chunks() {
+        encoder
+            .add_text_chunk(keyword.to_string(), text)
+            .map_err(|e| format!("Cannot add the {} metadata. {}", keyword, e))?;
+    }
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| format!("Cannot write the png header. {}", e))?;
+    writer
+        .write_image_data(data)
+        .map_err(|e| format!("Cannot write the png pixels. {}", e))?;
+    writer
+        .finish()
+        .map_err(|e| format!("Cannot finish the png. {}", e))?;
+    Ok(bytes)
+}
+
+/// Encode w x h rgba pixels of 8 bits as a png that holds `metadata`
+pub fn encode_png_with_metadata(
+    rgba8: &[u8],
+    width: u32,
+    height: u32,
+    metadata: &RenderMetadata,
+) -> Result<Vec<u8>, String> {
+    encode_png(rgba8, width, height, BitDepth::Eight, metadata)
+}
+
+/// Encode w x h rgba pixels of 16 bits as a png that holds `metadata`
+pub fn encode_png16_with_metadata(
+    rgba16: &[u16],
+    width: u32,
+    height: u32,
+    metadata: &RenderMetadata,
+) -> Result<Vec<u8>, String> {
+    let data: Vec<u8> = rgba16.iter().flat_map(|c| c.to_be_bytes()).collect();
+    encode_png(&data, width, height, BitDepth::Sixteen, metadata)
+}
+
+/// The metadata of a png that was written by `encode_png_with_metadata`
+pub fn read_png_metadata(png: &[u8]) -> Result<RenderMetadata, String> {
+    let reader = Decoder::new(png)
+        .read_info()
+        .map_err(|e| format!("Cannot read the png. {}", e))?;
+    let chunks = &reader.info().uncompressed_latin1_text;
+    let text = |keyword: &str| -> Result<&str, String> {
+        chunks
+            .iter()
+            .find(|chunk| chunk.keyword == keyword)
+            .map(|chunk| chunk.text.as_str())
+            .ok_or_else(|| format!("The png has no {} metadata", keyword))
+    };
+    let number = |keyword: &str| -> Result<u32, String> {
+        let value = text(keyword)?;
+        value
+            .parse()
+            .map_err(|_| format!("The {} metadata '{}' is not a number", keyword, value))
+    };
+    let expression = text(KEYWORD_EXPRESSION)?.to_string();
+    let t = text(KEYWORD_T)?;
+    let seed = match text(KEYWORD_SEED) {
+        Ok(seed) => Some(
+            seed.parse()
+                .map_err(|_| format!("The {} metadata '{}' is not a seed", KEYWORD_SEED, seed))?,
+        ),
+        Err(_) => None,
+    };
+    Ok(RenderMetadata {
+        expression,
+        coordinate_system: text(KEYWORD_COORDINATE_SYSTEM)?.parse()?,
+        width: number(KEYWORD_WIDTH)?,
+        height: number(KEYWORD_HEIGHT)?,
+        t: t.parse()
+            .map_err(|_| format!("The {} metadata '{}' is not a number", KEYWORD_T, t))?,
+        seed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lexer::lisp_to_pic;
+    use crate::pic::pic::pic_get_rgba8_runtime_select;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_png_metadata_roundtrip() {
+        let sexpr = "( RGB CARTESIAN ( X ) ( Y ) ( * T 0.5 ) )";
+        let pic = lisp_to_pic(sexpr.to_string(), CoordinateSystem::Polar).unwrap();
+        let (width, height) = (12, 8);
+        let rgba8 = pic_get_rgba8_runtime_select(
+            &pic,
+            false,
+            Arc::new(HashMap::new()),
+            width,
+            height,
+            0.25,
+            1,
+        );
+        let metadata = RenderMetadata::new(&pic, width, height, 0.25, Some(42));
+        let png = encode_png_with_metadata(&rgba8, width, height, &metadata).unwrap();
+
+        // the pixels are a plain png
+        let img = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(img.into_raw(), rgba8);
+
+        let read = read_png_metadata(&png).unwrap();
+        assert_eq!(read, metadata);
+        assert_eq!(read.coordinate_system, CoordinateSystem::Cartesian);
+        let reparsed = lisp_to_pic(read.expression, CoordinateSystem::Polar).unwrap();
+        assert_eq!(reparsed.to_lisp(), pic.to_lisp());
+    }
+
+    #[test]
+    fn test_png16_metadata_roundtrip() {
+        let pic = lisp_to_pic("( MONO POLAR ( X ) )".to_string(), CoordinateSystem::Polar).unwrap();
+        let rgba16: Vec<u16> = (0..4 * 3 * 4).map(|i| i * 1000 + 7).collect();
+        let metadata = RenderMetadata::new(&pic, 4, 3, 0.0, None);
+        let png = encode_png16_with_metadata(&rgba16, 4, 3, &metadata).unwrap();
+        let img = image::load_from_memory(&png).unwrap().to_rgba16();
+        assert_eq!(img.into_raw(), rgba16);
+        assert_eq!(read_png_metadata(&png).unwrap(), metadata);
+    }
+
+    #[test]
+    fn test_png_without_metadata() {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbaImage::new(2, 2)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        assert_eq!(
+            read_png_metadata(png.get_ref()),
+            Err("The png has no evolution:expression metadata".to_string())
+        );
+        assert!(read_png_metadata(b"not a png").is_err());
+    }
+}
@@ -1,5 +1,28 @@
 pub mod actual_picture;
+pub mod benchmark;
+pub mod blend;
 pub mod color;
+pub mod colorspace;
+pub mod contactsheet;
 pub mod coordinatesystem;
 pub mod data;
+pub mod downscalefilter;
+pub mod edges;
+pub mod generation;
+pub mod glsl;
+pub mod gradientwrap;
+pub mod loopmode;
+pub mod metadata;
+pub mod noisebasis;
+pub mod normalization;
+pub mod palette;
 pub mod pic;
+pub mod population;
+pub mod renderoptions;
+pub mod simdbackend;
+pub mod simdcheck;
+pub mod stats;
+pub mod subsample;
+pub mod symmetry;
+pub mod timerange;
+pub mod viewport;
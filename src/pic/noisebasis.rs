@@ -0,0 +1,76 @@
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::str::FromStr;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// The noise that every octave of FBM is made of, in lisp it follows FBM as in FBM-PERLIN
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NoiseBasis {
+    /// Smooth blobs without a grid, this is how FBM was always rendered
+    Simplex,
+    /// Gradients on a square grid, the features line up a bit with the axes
+    Perlin,
+    /// Random values on a square grid that are blended, soft and blocky, the fastest
+    Value,
+}
+
+impl NoiseBasis {
+    pub fn list_all<'a>() -> Vec<String> {
+        vec![
+            NoiseBasis::Simplex.to_string(),
+            NoiseBasis::Perlin.to_string(),
+            NoiseBasis::Value.to_string(),
+        ]
+    }
+
+    pub fn pick_random(rng: &mut impl Rng) -> NoiseBasis {
+        match rng.gen_range(0..3) {
+            0 => NoiseBasis::Simplex,
+            1 => NoiseBasis::Perlin,
+            _ => NoiseBasis::Value,
+        }
+    }
+}
+
+impl Display for NoiseBasis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        let x = match self {
+            NoiseBasis::Simplex => "simplex",
+            NoiseBasis::Perlin => "perlin",
+            NoiseBasis::Value => "value",
+        };
+        write!(f, "{}", x)
+    }
+}
+
+impl FromStr for NoiseBasis {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_ref() {
+            "simplex" => Ok(NoiseBasis::Simplex),
+            "perlin" => Ok(NoiseBasis::Perlin),
+            "value" => Ok(NoiseBasis::Value),
+            _ => Err(format!("Cannot parse {}. Not a known noise basis", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise_basis_parse() {
+        assert_eq!("Simplex".parse(), Ok(NoiseBasis::Simplex));
+        assert_eq!("PERLIN".parse(), Ok(NoiseBasis::Perlin));
+        assert_eq!("value".parse(), Ok(NoiseBasis::Value));
+        assert_eq!(
+            "worley".parse::<NoiseBasis>(),
+            Err("Cannot parse worley. Not a known noise basis".to_string())
+        );
+        for name in NoiseBasis::list_all() {
+            assert_eq!(name.parse::<NoiseBasis>().unwrap().to_string(), name);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::pic::data::Channel;
+
+/// How a channel value, scaled to 0.0..C::FULL, is turned into a channel
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Normalization {
+    /// Values outside of the range wrap around, this is how pictures were always rendered
+    Wrap,
+    /// Values outside of the range are clamped to black or white
+    Clamp,
+    /// Values are rescaled with the lowest and highest value of the first row
+    Auto,
+}
+
+impl Normalization {
+    pub fn list_all<'a>() -> Vec<String> {
+        vec![
+            Normalization::Wrap.to_string(),
+            Normalization::Clamp.to_string(),
+            Normalization::Auto.to_string(),
+        ]
+    }
+
+    /// Convert `c` to a channel, `range` is the (min, max) that is used by `Normalization::Auto`
+    #[inline(always)]
+    pub fn to_channel<C: Channel>(&self, c: f32, range: (f32, f32)) -> C {
+        C::from_f32(self.normalize::<C>(c, range))
+    }
+
+    /// The value of `c` in 0.0..=C::FULL, before it is turned into a channel by to_channel
+    #[inline(always)]
+    pub fn normalize<C: Channel>(&self, c: f32, range: (f32, f32)) -> f32 {
+        match self {
+            Normalization::Wrap => {
+                // the fraction is kept, to_channel drops it the way wrap does
+                let wrapped: u32 = C::wrap(c).into();
+                wrapped as f32 + c.fract().abs()
+            }
+            Normalization::Clamp => c,
+            Normalization::Auto => {
+                let (min, max) = range;
+                if max > min {
+                    (c - min) / (max - min) * C::FULL
+                } else {
+                    c
+                }
+            }
+        }
+    }
+}
+
+impl Display for Normalization {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        let x = match self {
+            Normalization::Wrap => "wrap",
+            Normalization::Clamp => "clamp",
+            Normalization::Auto => "auto",
+        };
+        write!(f, "{}", x)
+    }
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_ref() {
+            "wrap" => Ok(Normalization::Wrap),
+            "clamp" => Ok(Normalization::Clamp),
+            "auto" => Ok(Normalization::Auto),
+            _ => Err(format!("Cannot parse {}. Not a known normalization", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalization_parse() {
+        assert_eq!("Wrap".parse(), Ok(Normalization::Wrap));
+        assert_eq!("CLAMP".parse(), Ok(Normalization::Clamp));
+        assert_eq!("auto".parse(), Ok(Normalization::Auto));
+        assert_eq!(
+            "stretch".parse::<Normalization>(),
+            Err("Cannot parse stretch. Not a known normalization".to_string())
+        );
+    }
+
+    #[test]
+    fn test_normalization_normalize() {
+        assert_eq!(Normalization::Wrap.normalize::<u8>(300.5, (0.0, 0.0)), 44.5);
+        assert_eq!(Normalization::Clamp.normalize::<u8>(0.25, (0.0, 0.0)), 0.25);
+        assert_eq!(
+            Normalization::Auto.normalize::<u8>(150.0, (100.0, 200.0)),
+            127.5
+        );
+    }
+
+    #[test]
+    fn test_normalization_to_channel() {
+        assert_eq!(Normalization::Wrap.to_channel::<u8>(100.0, (0.0, 0.0)), 100);
+        assert_eq!(Normalization::Wrap.to_channel::<u8>(256.0, (0.0, 0.0)), 0);
+        assert_eq!(Normalization::Wrap.to_channel::<u8>(300.0, (0.0, 0.0)), 44);
+
+        assert_eq!(
+            Normalization::Clamp.to_channel::<u8>(100.0, (0.0, 0.0)),
+            100
+        );
+        assert_eq!(
+            Normalization::Clamp.to_channel::<u8>(256.0, (0.0, 0.0)),
+            255
+        );
+        assert_eq!(Normalization::Clamp.to_channel::<u8>(-20.0, (0.0, 0.0)), 0);
+
+        assert_eq!(
+            Normalization::Auto.to_channel::<u8>(100.0, (100.0, 200.0)),
+            0
+        );
+        assert_eq!(
+            Normalization::Auto.to_channel::<u8>(200.0, (100.0, 200.0)),
+            255
+        );
+        assert_eq!(
+            Normalization::Auto.to_channel::<u8>(150.0, (100.0, 200.0)),
+            127
+        );
+        assert_eq!(
+            Normalization::Auto.to_channel::<u8>(250.0, (100.0, 200.0)),
+            255
+        );
+        // a flat first row falls back to clamping
+        assert_eq!(
+            Normalization::Auto.to_channel::<u8>(300.0, (100.0, 100.0)),
+            255
+        );
+    }
+
+    #[test]
+    fn test_normalization_display() {
+        assert_eq!(&Normalization::Wrap.to_string(), "wrap");
+        assert_eq!(&Normalization::Clamp.to_string(), "clamp");
+        assert_eq!(&Normalization::Auto.to_string(), "auto");
+    }
+}
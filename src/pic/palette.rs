@@ -0,0 +1,107 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::pic::color::Color;
+
+/// The colors of a palette, either a GIMP palette (.gpl) or a file with one hex color
+/// (`#rrggbb` or `rrggbb`) per line. Empty lines and lines starting with `#` and a space
+/// are comments. A gradient needs at least 2 colors.
+pub fn parse_palette(source: &str) -> Result<Vec<Color>, String> {
+    let mut colors = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty()
+            || line == "GIMP Palette"
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+            || line == "#"
+            || line.starts_with("# ")
+        {
+            continue;
+        }
+        let color = if line.starts_with(|c: char| c.is_ascii_digit()) && line.contains(' ') {
+            parse_gpl_color(line)
+        } else {
+            line.parse::<Color>()
+        };
+        colors.push(color.map_err(|e| format!("{} on line {}", e, i + 1))?);
+    }
+    if colors.len() < 2 {
+        return Err(format!(
+            "A palette needs at least 2 colors, found {}",
+            colors.len()
+        ));
+    }
+    Ok(colors)
+}
+
+/// The colors of the palette file `path`, see parse_palette
+pub fn load_palette(path: &Path) -> Result<Vec<Color>, String> {
+    let source = read_to_string(path)
+        .map_err(|e| format!("Cannot read palette {}. {}", path.display(), e))?;
+    parse_palette(&source).map_err(|e| format!("Cannot parse palette {}. {}", path.display(), e))
+}
+
+/// `R G B name` with the channels from 0 to 255, the name is optional
+fn parse_gpl_color(line: &str) -> Result<Color, String> {
+    let channels = line
+        .split_whitespace()
+        .take(3)
+        .map(|channel| {
+            channel
+                .parse::<u8>()
+                .map_err(|_| format!("Invalid color channel '{}'", channel))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    if channels.len() < 3 {
+        return Err(format!("Expected 'R G B', not '{}'", line));
+    }
+    Ok(Color::new(
+        channels[0] as f32 / 255.0,
+        channels[1] as f32 / 255.0,
+        channels[2] as f32 / 255.0,
+        1.0,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_palette_hex() {
+        let colors = parse_palette("#ff0000\n\n# green\n00ff00\n#0000FF\n").unwrap();
+        assert_eq!(
+            colors,
+            vec![
+                Color::new(1.0, 0.0, 0.0, 1.0),
+                Color::new(0.0, 1.0, 0.0, 1.0),
+                Color::new(0.0, 0.0, 1.0, 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_palette_gpl() {
+        let source =
+            "GIMP Palette\nName: Fire\nColumns: 2\n#\n255   0   0\tRed\n255 255 0 Yellow\n";
+        let colors = parse_palette(source).unwrap();
+        assert_eq!(
+            colors,
+            vec![
+                Color::new(1.0, 0.0, 0.0, 1.0),
+                Color::new(1.0, 1.0, 0.0, 1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_palette_errors() {
+        assert!(parse_palette("").is_err());
+        assert!(parse_palette("#ff0000").is_err());
+        assert!(parse_palette("#ff0000\n#ff00").is_err());
+        assert!(parse_palette("#ff0000\nred").is_err());
+        assert!(parse_palette("255 0 0\n256 0 0").is_err());
+        assert!(parse_palette("255 0 0\n255 0").is_err());
+    }
+}
@@ -1,53 +1,307 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
-use crate::constants::{PIC_RANDOM_TREE_MAX, PIC_RANDOM_TREE_MIN};
+use crate::constants::{
+    DEFAULT_RENDER_OPTIONS, DEFAULT_VIEWPORT, PIC_COMPLEXITY_ATTEMPTS, PIC_MAX_STACK_DEPTH,
+    PIC_UNSEEN_ATTEMPTS,
+};
 use crate::parser::aptnode::APTNode;
+use crate::parser::parseerror::ParseError;
 use crate::pic::actual_picture::ActualPicture;
+use crate::pic::color::Color;
+use crate::pic::colorspace::ColorSpace;
 use crate::pic::coordinatesystem::CoordinateSystem;
+use crate::pic::data::cmyk::CMYKData;
 use crate::pic::data::gradient::GradientData;
 use crate::pic::data::grayscale::GrayscaleData;
 use crate::pic::data::hsv::HSVData;
+use crate::pic::data::lab::LABData;
 use crate::pic::data::mono::MonoData;
 use crate::pic::data::rgb::RGBData;
-use crate::pic::data::PicData;
+use crate::pic::data::rgba::RGBAData;
+use crate::pic::data::{Channel, PicData};
+use crate::pic::generation::GenerationConfig;
+use crate::pic::glsl::pic_to_glsl;
+use crate::pic::gradientwrap::GradientWrap;
+use crate::pic::loopmode::LoopMode;
+use crate::pic::normalization::Normalization;
+use crate::pic::renderoptions::RenderOptions;
+use crate::pic::simdbackend::{selected_simd_backend, SimdBackend};
+use crate::pic::stats::PicStats;
+use crate::pic::timerange::TimeRange;
+use crate::pic::viewport::Viewport;
+use crate::vm::cache::StackMachineCache;
 
 use rand::prelude::*;
-use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 use simdeez::avx2::*;
 use simdeez::scalar::*;
 use simdeez::sse2::*;
 use simdeez::sse41::*;
 use simdeez::Simd;
 
+/// Call the version of a simd_runtime_generate function for a SimdBackend that the cpu supports,
+/// Auto goes through the runtime dispatch of simdeez
+macro_rules! simd_select {
+    ($backend:expr, [$auto:ident, $scalar:ident, $sse2:ident, $sse41:ident, $avx2:ident]($($arg:expr),* $(,)?)) => {
+        match $backend {
+            SimdBackend::Auto => $auto($($arg),*),
+            // a backend is only selected or resolved when the cpu supports it
+            SimdBackend::Scalar => unsafe { $scalar($($arg),*) },
+            SimdBackend::Sse2 => unsafe { $sse2($($arg),*) },
+            SimdBackend::Sse41 => unsafe { $sse41($($arg),*) },
+            SimdBackend::Avx2 => unsafe { $avx2($($arg),*) },
+        }
+    };
+}
+
 simd_runtime_generate!(
-    pub fn pic_get_rgba8(
+    fn simd_pic_get_rgba8(
         pic: &Pic,
         threaded: bool,
         pictures: Arc<HashMap<String, ActualPicture>>,
         width: u32,
         height: u32,
         t: f32,
+        supersample: u32,
     ) -> Vec<u8> {
-        pic.get_rgba8::<S>(threaded, pictures, width, height, t)
+        get_rgba8_supersampled::<S>(
+            pic,
+            threaded,
+            pictures,
+            width,
+            height,
+            t,
+            supersample,
+            DEFAULT_VIEWPORT,
+            DEFAULT_RENDER_OPTIONS,
+        )
     }
 );
 
 simd_runtime_generate!(
-    pub fn pic_get_video(
+    fn simd_pic_get_rgba8_viewport(
+        pic: &Pic,
+        threaded: bool,
+        pictures: Arc<HashMap<String, ActualPicture>>,
+        width: u32,
+        height: u32,
+        t: f32,
+        supersample: u32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        get_rgba8_supersampled::<S>(
+            pic,
+            threaded,
+            pictures,
+            width,
+            height,
+            t,
+            supersample,
+            viewport,
+            options,
+        )
+    }
+);
+
+/// Render the pixels of `viewport`, with `supersample` x `supersample` samples per pixel
+#[inline(always)]
+fn get_rgba8_supersampled<S: Simd>(
+    pic: &Pic,
+    threaded: bool,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    width: u32,
+    height: u32,
+    t: f32,
+    supersample: u32,
+    viewport: Viewport,
+    options: RenderOptions,
+) -> Vec<u8> {
+    if supersample <= 1 {
+        return pic.get_rgba8::<S>(threaded, pictures, width, height, t, viewport, options);
+    }
+    let rgba8 = pic.get_rgba8::<S>(
+        threaded,
+        pictures,
+        width * supersample,
+        height * supersample,
+        t,
+        viewport,
+        options,
+    );
+    box_downsample(&rgba8, width, height, supersample, options.color_space)
+}
+
+simd_runtime_generate!(
+    fn simd_pic_get_rgba8_tiled(
+        pic: &Pic,
+        threaded: bool,
+        pictures: Arc<HashMap<String, ActualPicture>>,
+        width: u32,
+        height: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        tile_height: u32,
+        sink: &mut dyn FnMut(u32, &[u8]),
+    ) {
+        pic.get_rgba8_tiled::<S>(
+            threaded,
+            pictures,
+            width,
+            height,
+            t,
+            viewport,
+            options,
+            tile_height,
+            sink,
+        )
+    }
+);
+
+simd_runtime_generate!(
+    fn simd_pic_get_rgba16(
+        pic: &Pic,
+        threaded: bool,
+        pictures: Arc<HashMap<String, ActualPicture>>,
+        width: u32,
+        height: u32,
+        t: f32,
+        supersample: u32,
+    ) -> Vec<u16> {
+        get_rgba16_supersampled::<S>(
+            pic,
+            threaded,
+            pictures,
+            width,
+            height,
+            t,
+            supersample,
+            DEFAULT_VIEWPORT,
+            DEFAULT_RENDER_OPTIONS,
+        )
+    }
+);
+
+simd_runtime_generate!(
+    fn simd_pic_get_rgba16_viewport(
+        pic: &Pic,
+        threaded: bool,
+        pictures: Arc<HashMap<String, ActualPicture>>,
+        width: u32,
+        height: u32,
+        t: f32,
+        supersample: u32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u16> {
+        get_rgba16_supersampled::<S>(
+            pic,
+            threaded,
+            pictures,
+            width,
+            height,
+            t,
+            supersample,
+            viewport,
+            options,
+        )
+    }
+);
+
+/// Like get_rgba8_supersampled, with 16 bits per channel
+#[inline(always)]
+fn get_rgba16_supersampled<S: Simd>(
+    pic: &Pic,
+    threaded: bool,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    width: u32,
+    height: u32,
+    t: f32,
+    supersample: u32,
+    viewport: Viewport,
+    options: RenderOptions,
+) -> Vec<u16> {
+    if supersample <= 1 {
+        return pic.get_rgba16::<S>(threaded, pictures, width, height, t, viewport, options);
+    }
+    let rgba16 = pic.get_rgba16::<S>(
+        threaded,
+        pictures,
+        width * supersample,
+        height * supersample,
+        t,
+        viewport,
+        options,
+    );
+    box_downsample(&rgba16, width, height, supersample, options.color_space)
+}
+
+/// Shrink an rgba buffer of (n * w) x (n * h) pixels to w x h pixels,
+/// every channel (alpha included) of a pixel is the average of its n x n block,
+/// in linear light for the `color_space` Linear
+pub fn box_downsample<C: Channel>(
+    rgba8: &[C],
+    w: u32,
+    h: u32,
+    n: u32,
+    color_space: ColorSpace,
+) -> Vec<C> {
+    let (w, h, n) = (w as usize, h as usize, n as usize);
+    let src_w = w * n;
+    let block = (n * n) as u32;
+    let mut result = Vec::with_capacity(w * h * 4);
+    for y in 0..h {
+        for x in 0..w {
+            let mut sums = [0f32; 4];
+            for sy in y * n..(y + 1) * n {
+                for sx in x * n..(x + 1) * n {
+                    let offset = (sy * src_w + sx) * 4;
+                    for c in 0..4 {
+                        sums[c] += color_space.rgba_channel(c).decode(rgba8[offset + c]);
+                    }
+                }
+            }
+            for c in 0..4 {
+                result.push(color_space.rgba_channel(c).encode_mean(sums[c], block));
+            }
+        }
+    }
+    result
+}
+
+simd_runtime_generate!(
+    fn simd_pic_get_video(
         pic: &Pic,
         pictures: Arc<HashMap<String, ActualPicture>>,
         width: u32,
         height: u32,
         fps: u16,
         duration_ms: f32,
+        time_range: TimeRange,
+        loop_mode: LoopMode,
+        viewport: Viewport,
+        options: RenderOptions,
+        progress: Option<&mut dyn FnMut(usize, usize)>,
     ) -> Vec<Vec<u8>> {
-        pic.get_video::<S>(pictures, width, height, fps, duration_ms)
+        pic.get_video::<S>(
+            pictures,
+            width,
+            height,
+            fps,
+            duration_ms,
+            time_range,
+            loop_mode,
+            viewport,
+            options,
+            progress,
+        )
     }
 );
 
 simd_runtime_generate!(
-    pub fn pic_simplify(
+    fn simd_pic_simplify(
         pic: &mut Pic,
         pictures: Arc<HashMap<String, ActualPicture>>,
         width: u32,
@@ -58,55 +312,355 @@ simd_runtime_generate!(
     }
 );
 
-#[derive(Clone, Debug, PartialEq)]
+/// Like pic_get_rgba8_runtime_select, with `backend` instead of the selected one
+pub(crate) fn pic_get_rgba8_with_backend(
+    backend: SimdBackend,
+    pic: &Pic,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    width: u32,
+    height: u32,
+    t: f32,
+) -> Result<Vec<u8>, String> {
+    let backend = backend.resolve()?;
+    Ok(simd_select!(
+        backend,
+        [
+            simd_pic_get_rgba8_runtime_select,
+            simd_pic_get_rgba8_scalar,
+            simd_pic_get_rgba8_sse2,
+            simd_pic_get_rgba8_sse41,
+            simd_pic_get_rgba8_avx2
+        ](pic, false, pictures, width, height, t, 1)
+    ))
+}
+
+// The functions that render with the SimdBackend of select_simd_backend
+
+pub fn pic_get_rgba8_runtime_select(
+    pic: &Pic,
+    threaded: bool,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    width: u32,
+    height: u32,
+    t: f32,
+    supersample: u32,
+) -> Vec<u8> {
+    simd_select!(
+        selected_simd_backend(),
+        [
+            simd_pic_get_rgba8_runtime_select,
+            simd_pic_get_rgba8_scalar,
+            simd_pic_get_rgba8_sse2,
+            simd_pic_get_rgba8_sse41,
+            simd_pic_get_rgba8_avx2
+        ](pic, threaded, pictures, width, height, t, supersample)
+    )
+}
+
+pub fn pic_get_rgba8_viewport_runtime_select(
+    pic: &Pic,
+    threaded: bool,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    width: u32,
+    height: u32,
+    t: f32,
+    supersample: u32,
+    viewport: Viewport,
+    options: RenderOptions,
+) -> Vec<u8> {
+    simd_select!(
+        selected_simd_backend(),
+        [
+            simd_pic_get_rgba8_viewport_runtime_select,
+            simd_pic_get_rgba8_viewport_scalar,
+            simd_pic_get_rgba8_viewport_sse2,
+            simd_pic_get_rgba8_viewport_sse41,
+            simd_pic_get_rgba8_viewport_avx2
+        ](
+            pic,
+            threaded,
+            pictures,
+            width,
+            height,
+            t,
+            supersample,
+            viewport,
+            options
+        )
+    )
+}
+
+pub fn pic_get_rgba8_tiled_runtime_select(
+    pic: &Pic,
+    threaded: bool,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    width: u32,
+    height: u32,
+    t: f32,
+    viewport: Viewport,
+    options: RenderOptions,
+    tile_height: u32,
+    sink: &mut dyn FnMut(u32, &[u8]),
+) {
+    simd_select!(
+        selected_simd_backend(),
+        [
+            simd_pic_get_rgba8_tiled_runtime_select,
+            simd_pic_get_rgba8_tiled_scalar,
+            simd_pic_get_rgba8_tiled_sse2,
+            simd_pic_get_rgba8_tiled_sse41,
+            simd_pic_get_rgba8_tiled_avx2
+        ](
+            pic,
+            threaded,
+            pictures,
+            width,
+            height,
+            t,
+            viewport,
+            options,
+            tile_height,
+            sink
+        )
+    )
+}
+
+pub fn pic_get_rgba16_runtime_select(
+    pic: &Pic,
+    threaded: bool,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    width: u32,
+    height: u32,
+    t: f32,
+    supersample: u32,
+) -> Vec<u16> {
+    simd_select!(
+        selected_simd_backend(),
+        [
+            simd_pic_get_rgba16_runtime_select,
+            simd_pic_get_rgba16_scalar,
+            simd_pic_get_rgba16_sse2,
+            simd_pic_get_rgba16_sse41,
+            simd_pic_get_rgba16_avx2
+        ](pic, threaded, pictures, width, height, t, supersample)
+    )
+}
+
+pub fn pic_get_rgba16_viewport_runtime_select(
+    pic: &Pic,
+    threaded: bool,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    width: u32,
+    height: u32,
+    t: f32,
+    supersample: u32,
+    viewport: Viewport,
+    options: RenderOptions,
+) -> Vec<u16> {
+    simd_select!(
+        selected_simd_backend(),
+        [
+            simd_pic_get_rgba16_viewport_runtime_select,
+            simd_pic_get_rgba16_viewport_scalar,
+            simd_pic_get_rgba16_viewport_sse2,
+            simd_pic_get_rgba16_viewport_sse41,
+            simd_pic_get_rgba16_viewport_avx2
+        ](
+            pic,
+            threaded,
+            pictures,
+            width,
+            height,
+            t,
+            supersample,
+            viewport,
+            options
+        )
+    )
+}
+
+pub fn pic_get_video_runtime_select(
+    pic: &Pic,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    width: u32,
+    height: u32,
+    fps: u16,
+    duration_ms: f32,
+    time_range: TimeRange,
+    loop_mode: LoopMode,
+    viewport: Viewport,
+    options: RenderOptions,
+    progress: Option<&mut dyn FnMut(usize, usize)>,
+) -> Vec<Vec<u8>> {
+    simd_select!(
+        selected_simd_backend(),
+        [
+            simd_pic_get_video_runtime_select,
+            simd_pic_get_video_scalar,
+            simd_pic_get_video_sse2,
+            simd_pic_get_video_sse41,
+            simd_pic_get_video_avx2
+        ](
+            pic,
+            pictures,
+            width,
+            height,
+            fps,
+            duration_ms,
+            time_range,
+            loop_mode,
+            viewport,
+            options,
+            progress
+        )
+    )
+}
+
+pub fn pic_simplify_runtime_select(
+    pic: &mut Pic,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    width: u32,
+    height: u32,
+    t: f32,
+) {
+    simd_select!(
+        selected_simd_backend(),
+        [
+            simd_pic_simplify_runtime_select,
+            simd_pic_simplify_scalar,
+            simd_pic_simplify_sse2,
+            simd_pic_simplify_sse41,
+            simd_pic_simplify_avx2
+        ](pic, pictures, width, height, t)
+    )
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Pic {
     Mono(MonoData),
     Grayscale(GrayscaleData),
     RGB(RGBData),
+    RGBA(RGBAData),
     HSV(HSVData),
+    CMYK(CMYKData),
+    LAB(LABData),
     Gradient(GradientData),
 }
 
 impl Pic {
-    pub fn new(rng: &mut StdRng, pic_names: &Vec<&String>) -> Self {
-        let pic_type = rng.gen_range(0..5);
+    /// A random pic with at least `config.min_complexity` nodes, when none of the attempts
+    /// is complex enough the most complex one is returned
+    pub fn new(rng: &mut impl Rng, pic_names: &Vec<&String>, config: &GenerationConfig) -> Self {
+        let mut best = Pic::new_random(rng, pic_names, config);
+        let mut attempts = 1;
+        while best.complexity() < config.min_complexity && attempts < PIC_COMPLEXITY_ATTEMPTS {
+            let pic = Pic::new_random(rng, pic_names, config);
+            if pic.complexity() > best.complexity() {
+                best = pic;
+            }
+            attempts += 1;
+        }
+        best
+    }
+
+    /// A pic of `new_pic` whose fingerprint is not in `seen` yet, the fingerprint is added to it.
+    /// After PIC_UNSEEN_ATTEMPTS duplicates the last one is returned anyway.
+    pub fn unseen(seen: &mut HashSet<u64>, mut new_pic: impl FnMut() -> Pic) -> Self {
+        let mut pic = new_pic();
+        let mut attempts = 1;
+        while seen.contains(&pic.fingerprint()) && attempts < PIC_UNSEEN_ATTEMPTS {
+            pic = new_pic();
+            attempts += 1;
+        }
+        seen.insert(pic.fingerprint());
+        pic
+    }
+
+    // Pics of trees that were built by hand, the options that the lisp can leave out have
+    // their default
+
+    pub fn mono_from(c: APTNode, coord: CoordinateSystem) -> Self {
+        Pic::Mono(MonoData { c, coord })
+    }
+
+    pub fn grayscale_from(c: APTNode, coord: CoordinateSystem) -> Self {
+        Pic::Grayscale(GrayscaleData {
+            c,
+            coord,
+            normalization: Normalization::Wrap,
+        })
+    }
+
+    /// `colors` are the colors of the gradient and whether they are a stop, at least 2 of them
+    pub fn gradient_from(
+        colors: Vec<(Color, bool)>,
+        index: APTNode,
+        coord: CoordinateSystem,
+    ) -> Self {
+        Pic::Gradient(GradientData {
+            colors,
+            index,
+            coord,
+            wrap: GradientWrap::Wrap,
+            cycle: false,
+        })
+    }
+
+    pub fn rgb_from(r: APTNode, g: APTNode, b: APTNode, coord: CoordinateSystem) -> Self {
+        Pic::RGB(RGBData { r, g, b, coord })
+    }
+
+    pub fn rgba_from(
+        r: APTNode,
+        g: APTNode,
+        b: APTNode,
+        a: APTNode,
+        coord: CoordinateSystem,
+    ) -> Self {
+        Pic::RGBA(RGBAData { r, g, b, a, coord })
+    }
+
+    pub fn hsv_from(h: APTNode, s: APTNode, v: APTNode, coord: CoordinateSystem) -> Self {
+        Pic::HSV(HSVData { h, s, v, coord })
+    }
+
+    pub fn lab_from(l: APTNode, a: APTNode, b: APTNode, coord: CoordinateSystem) -> Self {
+        Pic::LAB(LABData { l, a, b, coord })
+    }
+
+    pub fn cmyk_from(
+        c: APTNode,
+        m: APTNode,
+        y: APTNode,
+        k: APTNode,
+        coord: CoordinateSystem,
+    ) -> Self {
+        Pic::CMYK(CMYKData { c, m, y, k, coord })
+    }
+
+    /// A hash of the lisp of the pic, pics with the same lisp have the same fingerprint.
+    /// It is the same on every run and platform (64 bit FNV-1a), so it can be stored.
+    pub fn fingerprint(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+        self.to_lisp().bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+        })
+    }
+
+    /// A random picture of a random type, the trees are generated following the config
+    fn new_random(rng: &mut impl Rng, pic_names: &Vec<&String>, config: &GenerationConfig) -> Self {
+        let pic_type = rng.gen_range(0..8);
 
         let pic = match pic_type {
-            0 => MonoData::new(
-                PIC_RANDOM_TREE_MIN,
-                PIC_RANDOM_TREE_MAX,
-                false,
-                rng,
-                pic_names,
-            ),
-            1 => GradientData::new(
-                PIC_RANDOM_TREE_MIN,
-                PIC_RANDOM_TREE_MAX,
-                false,
-                rng,
-                pic_names,
-            ),
-            2 => RGBData::new(
-                PIC_RANDOM_TREE_MIN,
-                PIC_RANDOM_TREE_MAX,
-                false,
-                rng,
-                pic_names,
-            ),
-            3 => HSVData::new(
-                PIC_RANDOM_TREE_MIN,
-                PIC_RANDOM_TREE_MAX,
-                false,
-                rng,
-                pic_names,
-            ),
-            4 => GrayscaleData::new(
-                PIC_RANDOM_TREE_MIN,
-                PIC_RANDOM_TREE_MAX,
-                false,
-                rng,
-                pic_names,
-            ),
+            0 => MonoData::new(config, false, rng, pic_names),
+            1 => GradientData::new(config, false, rng, pic_names),
+            2 => RGBData::new(config, false, rng, pic_names),
+            3 => HSVData::new(config, false, rng, pic_names),
+            4 => GrayscaleData::new(config, false, rng, pic_names),
+            5 => RGBAData::new(config, false, rng, pic_names),
+            6 => CMYKData::new(config, false, rng, pic_names),
+            7 => LABData::new(config, false, rng, pic_names),
             _ => panic!("invalid"),
         };
         pic
@@ -124,7 +678,10 @@ impl Pic {
             Pic::Mono(data) => data.simplify::<S>(pics, w, h, t),
             Pic::Gradient(data) => data.simplify::<S>(pics, w, h, t),
             Pic::RGB(data) => data.simplify::<S>(pics, w, h, t),
+            Pic::RGBA(data) => data.simplify::<S>(pics, w, h, t),
             Pic::HSV(data) => data.simplify::<S>(pics, w, h, t),
+            Pic::LAB(data) => data.simplify::<S>(pics, w, h, t),
+            Pic::CMYK(data) => data.simplify::<S>(pics, w, h, t),
         }
     }
 
@@ -134,8 +691,131 @@ impl Pic {
             Pic::Mono(data) => vec![&data.c],
             Pic::Gradient(data) => vec![&data.index],
             Pic::RGB(data) => vec![&data.r, &data.g, &data.b],
+            Pic::RGBA(data) => vec![&data.r, &data.g, &data.b, &data.a],
             Pic::HSV(data) => vec![&data.h, &data.s, &data.v],
+            Pic::LAB(data) => vec![&data.l, &data.a, &data.b],
+            Pic::CMYK(data) => vec![&data.c, &data.m, &data.y, &data.k],
+        }
+    }
+
+    /// The number of nodes in the trees of all the channels
+    pub fn complexity(&self) -> usize {
+        self.to_tree().iter().map(|tree| tree.count_nodes()).sum()
+    }
+
+    /// An error when a channel needs a stack of more than `limit` values, see stack_depth. The
+    /// stack of every pixel is allocated before the first instruction runs, a sexpr that is
+    /// deep enough would take all the memory.
+    pub fn check_stack_depth(&self, limit: usize) -> Result<(), ParseError> {
+        let depth = self
+            .to_tree()
+            .iter()
+            .map(|tree| tree.stack_depth())
+            .max()
+            .unwrap_or(0);
+        if depth > limit {
+            return Err(ParseError::TooDeep { depth, limit });
         }
+        Ok(())
+    }
+
+    /// Point the Pic- nodes to the loaded `pictures`, a picture that was moved or converted is
+    /// found by its name, see resolve_picture_name. The names that were replaced are returned
+    /// with their replacements, a picture that cannot be found is an error.
+    pub fn resolve_pictures(
+        &mut self,
+        pictures: &HashMap<String, ActualPicture>,
+    ) -> Result<Vec<(String, String)>, String> {
+        let names: Vec<&String> = pictures.keys().collect();
+        let mut replaced = Vec::new();
+        for tree in self.to_tree_mut() {
+            tree.resolve_pictures(&names, &mut replaced)?;
+        }
+        Ok(replaced)
+    }
+
+    /// Replace the T of every channel by TSIN, see APTNode::seamless_time
+    pub fn seamless_time(&mut self) {
+        for tree in self.to_tree_mut() {
+            tree.seamless_time();
+        }
+    }
+
+    /// The names of the channels, in the order of to_tree
+    pub fn channel_names(&self) -> Vec<&'static str> {
+        match self {
+            Pic::Grayscale(_) | Pic::Mono(_) => vec!["C"],
+            Pic::Gradient(_) => vec!["INDEX"],
+            Pic::RGB(_) => vec!["R", "G", "B"],
+            Pic::RGBA(_) => vec!["R", "G", "B", "A"],
+            Pic::HSV(_) => vec!["H", "S", "V"],
+            Pic::LAB(_) => vec!["L", "A", "B"],
+            Pic::CMYK(_) => vec!["C", "M", "Y", "K"],
+        }
+    }
+
+    /// The depth of every channel, the number of nodes and how often every operation is used
+    pub fn stats(&self) -> PicStats {
+        let trees = self.to_tree();
+        let mut operations = BTreeMap::new();
+        for tree in &trees {
+            tree.count_operations(&mut operations);
+        }
+        PicStats {
+            mode: self.mode_name(),
+            depths: self
+                .channel_names()
+                .into_iter()
+                .zip(trees.iter().map(|tree| tree.depth()))
+                .collect(),
+            nodes: self.complexity(),
+            operations,
+        }
+    }
+
+    pub fn to_tree_mut(&mut self) -> Vec<&mut APTNode> {
+        match self {
+            Pic::Grayscale(data) => vec![&mut data.c],
+            Pic::Mono(data) => vec![&mut data.c],
+            Pic::Gradient(data) => vec![&mut data.index],
+            Pic::RGB(data) => vec![&mut data.r, &mut data.g, &mut data.b],
+            Pic::RGBA(data) => vec![&mut data.r, &mut data.g, &mut data.b, &mut data.a],
+            Pic::HSV(data) => vec![&mut data.h, &mut data.s, &mut data.v],
+            Pic::LAB(data) => vec![&mut data.l, &mut data.a, &mut data.b],
+            Pic::CMYK(data) => vec![&mut data.c, &mut data.m, &mut data.y, &mut data.k],
+        }
+    }
+
+    /// Breed a child that has the color mode of one of the parents and subtrees of both.
+    /// When the modes of the parents differ, the subtrees are grafted from a random channel.
+    pub fn crossover(&self, other: &Pic, rng: &mut impl Rng) -> Pic {
+        let same_mode = std::mem::discriminant(self) == std::mem::discriminant(other);
+        let (mut child, donor) = if rng.gen_bool(0.5) {
+            (self.clone(), other)
+        } else {
+            (other.clone(), self)
+        };
+        let donor_trees = donor.to_tree();
+        for (i, tree) in child.to_tree_mut().into_iter().enumerate() {
+            let donor_tree = if same_mode {
+                donor_trees[i]
+            } else {
+                donor_trees[rng.gen_range(0..donor_trees.len())]
+            };
+            tree.crossover(donor_tree, rng);
+        }
+        child
+    }
+
+    /// A variation of self where every node of every channel has a chance of `rate` to mutate.
+    /// Regrown subtrees can use any of the loaded `pic_names`.
+    pub fn mutate(&self, rng: &mut impl Rng, rate: f32, pic_names: &Vec<&String>) -> Pic {
+        let video = self.can_animate();
+        let mut child = self.clone();
+        for tree in child.to_tree_mut() {
+            tree.mutate(rng, rate, video, pic_names);
+        }
+        child
     }
 
     pub fn to_lisp(&self) -> String {
@@ -146,9 +826,16 @@ impl Pic {
                 data.c.to_lisp()
             ),
             Pic::Grayscale(data) => {
+                // wrapping is the default, leave it out so older pictures keep their lisp
+                let normalization = if data.normalization == Normalization::Wrap {
+                    String::new()
+                } else {
+                    format!(" {}", data.normalization.to_string().to_uppercase())
+                };
                 format!(
-                    "( GRAYSCALE {}\n\t( {} )\n)",
+                    "( GRAYSCALE {}{}\n\t( {} )\n)",
                     data.coord.to_string().to_uppercase(),
+                    normalization,
                     data.c.to_lisp()
                 )
             }
@@ -162,9 +849,18 @@ impl Pic {
                         colors += &format!("\n\t\t( COLOR {} {} {} )", color.r, color.g, color.b);
                     }
                 }
+                // wrapping is the default, leave it out so older pictures keep their lisp
+                let wrap = if data.wrap == GradientWrap::Wrap {
+                    String::new()
+                } else {
+                    format!(" {}", data.wrap.to_string().to_uppercase())
+                };
+                let cycle = if data.cycle { " CYCLE" } else { "" };
                 format!(
-                    "( GRADIENT {}\n\t( COLORS{}\n\t)\n\t{}\n)",
+                    "( GRADIENT {}{}{}\n\t( COLORS{}\n\t)\n\t( {} )\n)",
                     data.coord.to_string().to_uppercase(),
+                    wrap,
+                    cycle,
                     colors,
                     data.index.to_lisp()
                 )
@@ -176,6 +872,14 @@ impl Pic {
                 data.g.to_lisp(),
                 data.b.to_lisp()
             ),
+            Pic::RGBA(data) => format!(
+                "( RGBA {}\n\t( {} )\n\t( {} )\n\t( {} )\n\t( {} )\n)",
+                data.coord.to_string().to_uppercase(),
+                data.r.to_lisp(),
+                data.g.to_lisp(),
+                data.b.to_lisp(),
+                data.a.to_lisp()
+            ),
             Pic::HSV(data) => format!(
                 "( HSV {}\n\t( {} )\n\t( {} )\n\t( {} )\n)",
                 data.coord.to_string().to_uppercase(),
@@ -183,9 +887,92 @@ impl Pic {
                 data.s.to_lisp(),
                 data.v.to_lisp()
             ),
+            Pic::CMYK(data) => format!(
+                "( CMYK {}\n\t( {} )\n\t( {} )\n\t( {} )\n\t( {} )\n)",
+                data.coord.to_string().to_uppercase(),
+                data.c.to_lisp(),
+                data.m.to_lisp(),
+                data.y.to_lisp(),
+                data.k.to_lisp()
+            ),
+            Pic::LAB(data) => format!(
+                "( LAB {}\n\t( {} )\n\t( {} )\n\t( {} )\n)",
+                data.coord.to_string().to_uppercase(),
+                data.l.to_lisp(),
+                data.a.to_lisp(),
+                data.b.to_lisp()
+            ),
         }
     }
 
+    /// The lisp of the picture in a canonical layout that is easy to diff and to edit by hand:
+    /// every channel on a line of its own and every nested operation indented `indent` spaces
+    /// deeper than its parent. lisp_to_pic reads it back as the same picture.
+    pub fn to_lisp_pretty(&self, indent: usize) -> String {
+        let pad = " ".repeat(indent);
+        let mut lisp = format!(
+            "( {} {}",
+            self.mode_name(),
+            self.coord().to_string().to_uppercase()
+        );
+        match self {
+            Pic::Grayscale(data) if data.normalization != Normalization::Wrap => {
+                lisp += &format!(" {}", data.normalization.to_string().to_uppercase());
+            }
+            Pic::Gradient(data) => {
+                if data.wrap != GradientWrap::Wrap {
+                    lisp += &format!(" {}", data.wrap.to_string().to_uppercase());
+                }
+                if data.cycle {
+                    lisp += " CYCLE";
+                }
+                lisp += &format!("\n{}( COLORS", pad);
+                for (color, stop) in &data.colors {
+                    lisp += &format!(
+                        "\n{}{}( {} {} {} {} )",
+                        pad,
+                        pad,
+                        if *stop { "STOPCOLOR" } else { "COLOR" },
+                        color.r,
+                        color.g,
+                        color.b
+                    );
+                }
+                lisp += &format!("\n{})", pad);
+            }
+            _ => {}
+        }
+        for tree in self.to_tree() {
+            if tree.is_leaf() {
+                lisp += &format!("\n{}( {} )", pad, tree.to_lisp());
+            } else {
+                lisp += &format!("\n{}{}", pad, tree.to_lisp_pretty(indent, 1));
+            }
+        }
+        lisp + "\n)"
+    }
+
+    /// A GLSL fragment shader that renders the picture on the GPU, see pic::glsl for its uniforms
+    pub fn to_glsl(&self) -> String {
+        pic_to_glsl(self)
+    }
+
+    /// The picture as JSON, with the color mode, the coordinate system and every node of the trees.
+    /// Lisp stays the format to read and write by hand, this one is for other programs.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Cannot convert the picture to json")
+    }
+
+    /// Parse a picture written by to_json, trees with missing or extra children are refused
+    pub fn from_json(json: &str) -> Result<Pic, String> {
+        let pic: Pic = serde_json::from_str(json).map_err(|e| format!("Invalid json: {}", e))?;
+        if !pic.to_tree().iter().all(|tree| tree.is_complete()) {
+            return Err("The json has an operation with the wrong number of children".to_string());
+        }
+        pic.check_stack_depth(PIC_MAX_STACK_DEPTH)?;
+        Ok(pic)
+    }
+
     pub fn get_video<S: Simd>(
         &self,
         pics: Arc<HashMap<String, ActualPicture>>,
@@ -193,130 +980,1113 @@ impl Pic {
         h: u32,
         fps: u16,
         d_ms: f32,
+        time_range: TimeRange,
+        loop_mode: LoopMode,
+        viewport: Viewport,
+        options: RenderOptions,
+        progress: Option<&mut dyn FnMut(usize, usize)>,
     ) -> Vec<Vec<u8>> {
         // todo investigate if we can return an iterator instead of a vec
-        let frames = (fps as f32 * (d_ms / 1000.0)) as i32;
-        let frame_dt = 2.0 / frames as f32;
-        let mut t = -1.0;
-        let mut result = Vec::new();
-        for _i in 0..frames {
-            let frame_buffer = self.get_rgba8::<S>(true, pics.clone(), w, h, t);
-            result.push(frame_buffer);
-            t += frame_dt;
+        let frames = match self {
+            Pic::Mono(data) => data.get_video::<S>(
+                pics, w, h, fps, d_ms, time_range, viewport, options, progress,
+            ),
+            Pic::Grayscale(data) => data.get_video::<S>(
+                pics, w, h, fps, d_ms, time_range, viewport, options, progress,
+            ),
+            Pic::Gradient(data) => data.get_video::<S>(
+                pics, w, h, fps, d_ms, time_range, viewport, options, progress,
+            ),
+            Pic::RGB(data) => data.get_video::<S>(
+                pics, w, h, fps, d_ms, time_range, viewport, options, progress,
+            ),
+            Pic::RGBA(data) => data.get_video::<S>(
+                pics, w, h, fps, d_ms, time_range, viewport, options, progress,
+            ),
+            Pic::HSV(data) => data.get_video::<S>(
+                pics, w, h, fps, d_ms, time_range, viewport, options, progress,
+            ),
+            Pic::LAB(data) => data.get_video::<S>(
+                pics, w, h, fps, d_ms, time_range, viewport, options, progress,
+            ),
+            Pic::CMYK(data) => data.get_video::<S>(
+                pics, w, h, fps, d_ms, time_range, viewport, options, progress,
+            ),
+        };
+        loop_mode.arrange(frames)
+    }
+
+    /// The color mode, as it is written in lisp
+    pub fn mode_name(&self) -> &'static str {
+        match self {
+            Pic::Mono(_) => "MONO",
+            Pic::Grayscale(_) => "GRAYSCALE",
+            Pic::Gradient(_) => "GRADIENT",
+            Pic::RGB(_) => "RGB",
+            Pic::RGBA(_) => "RGBA",
+            Pic::HSV(_) => "HSV",
+            Pic::LAB(_) => "LAB",
+            Pic::CMYK(_) => "CMYK",
+        }
+    }
+
+    pub fn coord(&self) -> &CoordinateSystem {
+        match self {
+            Pic::Mono(data) => &data.coord,
+            Pic::Grayscale(data) => &data.coord,
+            Pic::Gradient(data) => &data.coord,
+            Pic::RGB(data) => &data.coord,
+            Pic::RGBA(data) => &data.coord,
+            Pic::HSV(data) => &data.coord,
+            Pic::LAB(data) => &data.coord,
+            Pic::CMYK(data) => &data.coord,
+        }
+    }
+
+    pub fn get_rgba8<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        match self {
+            Pic::Mono(data) => data.get_rgba8::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::Grayscale(data) => data.get_rgba8::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::Gradient(data) => data.get_rgba8::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::RGB(data) => data.get_rgba8::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::RGBA(data) => data.get_rgba8::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::HSV(data) => data.get_rgba8::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::LAB(data) => data.get_rgba8::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::CMYK(data) => data.get_rgba8::<S>(threaded, pics, w, h, t, viewport, options),
+        }
+    }
+
+    /// Like get_rgba8, but only `tile_h` rows are rendered at a time, every strip of rows
+    /// is passed to `sink` with the index of its first row, so the whole image never has to be in memory
+    pub fn get_rgba8_tiled<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+        tile_h: u32,
+        sink: &mut dyn FnMut(u32, &[u8]),
+    ) {
+        match self {
+            Pic::Mono(data) => {
+                data.get_rgba8_tiled::<S>(threaded, pics, w, h, t, viewport, options, tile_h, sink)
+            }
+            Pic::Grayscale(data) => {
+                data.get_rgba8_tiled::<S>(threaded, pics, w, h, t, viewport, options, tile_h, sink)
+            }
+            Pic::Gradient(data) => {
+                data.get_rgba8_tiled::<S>(threaded, pics, w, h, t, viewport, options, tile_h, sink)
+            }
+            Pic::RGB(data) => {
+                data.get_rgba8_tiled::<S>(threaded, pics, w, h, t, viewport, options, tile_h, sink)
+            }
+            Pic::RGBA(data) => {
+                data.get_rgba8_tiled::<S>(threaded, pics, w, h, t, viewport, options, tile_h, sink)
+            }
+            Pic::HSV(data) => {
+                data.get_rgba8_tiled::<S>(threaded, pics, w, h, t, viewport, options, tile_h, sink)
+            }
+            Pic::LAB(data) => {
+                data.get_rgba8_tiled::<S>(threaded, pics, w, h, t, viewport, options, tile_h, sink)
+            }
+            Pic::CMYK(data) => {
+                data.get_rgba8_tiled::<S>(threaded, pics, w, h, t, viewport, options, tile_h, sink)
+            }
+        }
+    }
+
+    /// Like get_rgba8, with 16 bits per channel
+    pub fn get_rgba16<S: Simd>(
+        &self,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u16> {
+        match self {
+            Pic::Mono(data) => data.get_rgba16::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::Grayscale(data) => {
+                data.get_rgba16::<S>(threaded, pics, w, h, t, viewport, options)
+            }
+            Pic::Gradient(data) => data.get_rgba16::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::RGB(data) => data.get_rgba16::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::RGBA(data) => data.get_rgba16::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::HSV(data) => data.get_rgba16::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::LAB(data) => data.get_rgba16::<S>(threaded, pics, w, h, t, viewport, options),
+            Pic::CMYK(data) => data.get_rgba16::<S>(threaded, pics, w, h, t, viewport, options),
+        }
+    }
+
+    /// Like get_rgba8, but the stack machines are taken from (and added to) the cache
+    pub fn get_rgba8_cached<S: Simd>(
+        &self,
+        cache: &mut StackMachineCache<S>,
+        threaded: bool,
+        pics: Arc<HashMap<String, ActualPicture>>,
+        w: u32,
+        h: u32,
+        t: f32,
+        viewport: Viewport,
+        options: RenderOptions,
+    ) -> Vec<u8> {
+        match self {
+            Pic::Mono(data) => {
+                data.get_rgba8_cached::<S>(cache, threaded, pics, w, h, t, viewport, options)
+            }
+            Pic::Grayscale(data) => {
+                data.get_rgba8_cached::<S>(cache, threaded, pics, w, h, t, viewport, options)
+            }
+            Pic::Gradient(data) => {
+                data.get_rgba8_cached::<S>(cache, threaded, pics, w, h, t, viewport, options)
+            }
+            Pic::RGB(data) => {
+                data.get_rgba8_cached::<S>(cache, threaded, pics, w, h, t, viewport, options)
+            }
+            Pic::RGBA(data) => {
+                data.get_rgba8_cached::<S>(cache, threaded, pics, w, h, t, viewport, options)
+            }
+            Pic::HSV(data) => {
+                data.get_rgba8_cached::<S>(cache, threaded, pics, w, h, t, viewport, options)
+            }
+            Pic::LAB(data) => {
+                data.get_rgba8_cached::<S>(cache, threaded, pics, w, h, t, viewport, options)
+            }
+            Pic::CMYK(data) => {
+                data.get_rgba8_cached::<S>(cache, threaded, pics, w, h, t, viewport, options)
+            }
+        }
+    }
+
+    /// True when the frames differ, some channel uses T or the gradient cycles
+    pub fn can_animate(&self) -> bool {
+        if let Pic::Gradient(GradientData { cycle: true, .. }) = self {
+            return true;
+        }
+        self.to_tree().iter().any(|tree| tree.uses_t())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{
+        DEFAULT_COORDINATE_SYSTEM, DEFAULT_IMAGE_HEIGHT, DEFAULT_IMAGE_WIDTH, DEFAULT_TIME_RANGE,
+        SEAMLESS_TIME_RANGE,
+    };
+    use crate::parser::lexer::lisp_to_pic;
+    use crate::pic::data::{average_sub_frames, frame_times};
+    use crate::pic::symmetry::Symmetry;
+    use image::io::Reader as ImageReader;
+    use image::{
+        save_buffer_with_format, ColorType, DynamicImage, GenericImageView, ImageBuffer,
+        ImageFormat,
+    };
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_pic_to_lisp_mono() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let pic = MonoData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
+        let sexpr = pic.to_lisp();
+
+        assert!(
+            sexpr.starts_with("( MONO POLAR\n\t(") || sexpr.starts_with("( MONO CARTESIAN\n\t(")
+        );
+        assert!(sexpr.ends_with("\n)"));
+        assert!(sexpr.lines().collect::<Vec<_>>().len() > 1);
+    }
+
+    #[test]
+    fn test_pic_to_lisp_grayscale() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let pic = GrayscaleData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
+        let sexpr = pic.to_lisp();
+        assert!(
+            sexpr.starts_with("( GRAYSCALE POLAR\n\t(")
+                || sexpr.starts_with("( GRAYSCALE CARTESIAN\n\t(")
+        );
+        assert!(sexpr.ends_with("\n)"));
+        assert!(sexpr.lines().collect::<Vec<_>>().len() > 1);
+    }
+
+    #[test]
+    fn test_pic_to_lisp_gradient() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let pic = GradientData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
+        let sexpr = pic.to_lisp();
+        assert!(
+            sexpr.starts_with("( GRADIENT POLAR\n\t(")
+                || sexpr.starts_with("( GRADIENT CARTESIAN\n\t(")
+        );
+        assert!(sexpr.ends_with("\n)"));
+        assert!(sexpr.contains("\n\t( COLORS\n\t"));
+        assert!(sexpr.contains("\n\t)\n\t"));
+        assert!(sexpr.contains("\n\t\t( COLOR ") || sexpr.contains("\n\t\t( STOPCOLOR "));
+        assert!(sexpr.lines().collect::<Vec<_>>().len() > 0);
+    }
+
+    #[test]
+    fn test_pic_to_lisp_rgba() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let pic = RGBAData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
+        let sexpr = pic.to_lisp();
+        assert!(
+            sexpr.starts_with("( RGBA POLAR\n\t(") || sexpr.starts_with("( RGBA CARTESIAN\n\t(")
+        );
+        assert!(sexpr.ends_with("\n)"));
+        assert_eq!(sexpr.lines().collect::<Vec<_>>().len(), 6);
+        let reparsed = lisp_to_pic(sexpr, DEFAULT_COORDINATE_SYSTEM).unwrap();
+        assert_eq!(reparsed, pic);
+    }
+
+    #[test]
+    fn test_pic_to_lisp_pretty() {
+        let sexpr = "( GRAYSCALE CARTESIAN CLAMP ( + X ( * ( SIN Y ) 0.5 ) ) )";
+        let pic = lisp_to_pic(sexpr.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        assert_eq!(
+            pic.to_lisp_pretty(2),
+            "( GRAYSCALE CARTESIAN CLAMP\n  ( +\n    X\n    ( *\n      ( SIN Y )\n      0.5\n    )\n  )\n)"
+        );
+
+        let sexpr = "( GRADIENT POLAR ( COLORS ( COLOR 1 0 0 ) ( STOPCOLOR 0 0 1 ) ) ( T ) )";
+        let pic = lisp_to_pic(sexpr.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        assert_eq!(
+            pic.to_lisp_pretty(2),
+            "( GRADIENT POLAR\n  ( COLORS\n    ( COLOR 1 0 0 )\n    ( STOPCOLOR 0 0 1 )\n  )\n  ( T )\n)"
+        );
+    }
+
+    #[test]
+    fn test_pic_to_lisp_pretty_round_trip() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let name = "eye.jpg".to_string();
+        let pic_names = vec![&name];
+        let config = GenerationConfig::new(0, 60);
+        let pics = vec![
+            MonoData::new(&config, false, &mut rng, &pic_names),
+            GrayscaleData::new(&config, true, &mut rng, &pic_names),
+            GradientData::new(&config, false, &mut rng, &pic_names),
+            RGBData::new(&config, true, &mut rng, &pic_names),
+            RGBAData::new(&config, false, &mut rng, &pic_names),
+            HSVData::new(&config, false, &mut rng, &pic_names),
+            CMYKData::new(&config, false, &mut rng, &pic_names),
+            LABData::new(&config, false, &mut rng, &pic_names),
+        ];
+        for pic in pics {
+            for indent in [0, 4] {
+                let sexpr = pic.to_lisp_pretty(indent);
+                let reparsed = lisp_to_pic(sexpr.clone(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+                assert_eq!(reparsed, pic, "{}", sexpr);
+                // canonical, so formatting it again changes nothing
+                assert_eq!(reparsed.to_lisp_pretty(indent), sexpr);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pic_resolve_pictures() {
+        let pictures = crate::load_pictures(std::path::Path::new("pictures")).unwrap();
+        let sexpr = "( RGB ( PIC-eye.jpg X Y ) ( PICN-old/TREE.png Y X ) ( PIC-eye.jpg Y Y ) )";
+        let mut pic = lisp_to_pic(sexpr.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        assert_eq!(
+            pic.resolve_pictures(&pictures),
+            Ok(vec![("old/tree.png".to_string(), "tree.jpg".to_string())])
+        );
+        assert!(pic.to_lisp().contains("( PICN-tree.jpg Y X )"));
+        assert_eq!(pic.resolve_pictures(&pictures), Ok(vec![]));
+
+        let sexpr = "( MONO ( + X ( PIC-moon.jpg X Y ) ) )";
+        let mut pic = lisp_to_pic(sexpr.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let err = pic.resolve_pictures(&pictures).err().unwrap();
+        assert!(
+            err.starts_with("The picture moon.jpg is missing, the pictures are barn.jpg, eye.jpg"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_pic_simd_backends() {
+        let pictures = Arc::new(HashMap::new());
+        let pic = lisp_to_pic(
+            "( RGB POLAR ( SIN ( * X 3.1 ) ) ( SQRT ( ABS Y ) ) ( ATAN2 X ( - Y 0.3 ) ) )"
+                .to_string(),
+            DEFAULT_COORDINATE_SYSTEM,
+        )
+        .unwrap();
+        let auto = simd_pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 37, 11, 0.0, 1);
+        let scalar =
+            unsafe { simd_pic_get_rgba8_scalar(&pic, false, pictures.clone(), 37, 11, 0.0, 1) };
+        assert_eq!(auto.len(), scalar.len());
+        // the lanes may round differently, the colors must be about the same
+        for (i, (a, s)) in auto.iter().zip(scalar.iter()).enumerate() {
+            assert!((*a as i32 - *s as i32).abs() <= 1, "{}: {} {}", i, a, s);
+        }
+    }
+
+    #[test]
+    fn test_pic_fingerprint() {
+        let pic = lisp_to_pic(
+            "( MONO CARTESIAN ( X ) )".to_string(),
+            CoordinateSystem::Polar,
+        )
+        .unwrap();
+        // stable across runs
+        assert_eq!(pic.fingerprint(), 0x616db6a511b52c41);
+
+        let sexpr = "( RGB CARTESIAN ( + X ( SIN Y ) ) ( Y ) ( 0.5 ) )";
+        let a = lisp_to_pic(sexpr.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let b = lisp_to_pic(
+            "( RGB CARTESIAN\n  ( +   X ( sin y ) )\n  ( Y )\n  ( 0.5 ) )".to_string(),
+            DEFAULT_COORDINATE_SYSTEM,
+        )
+        .unwrap();
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        for other in [
+            "( RGB CARTESIAN ( + Y ( SIN Y ) ) ( Y ) ( 0.5 ) )",
+            "( RGB POLAR ( + X ( SIN Y ) ) ( Y ) ( 0.5 ) )",
+            "( RGB CARTESIAN ( + X ( SIN Y ) ) ( Y ) ( 0.6 ) )",
+            "( HSV CARTESIAN ( + X ( SIN Y ) ) ( Y ) ( 0.5 ) )",
+        ] {
+            let c = lisp_to_pic(other.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            assert_ne!(a.fingerprint(), c.fingerprint(), "{}", other);
+        }
+    }
+
+    #[test]
+    fn test_pic_unseen() {
+        let x = lisp_to_pic("( MONO ( X ) )".to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let y = lisp_to_pic("( MONO ( Y ) )".to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let mut seen = HashSet::new();
+        assert_eq!(Pic::unseen(&mut seen, || x.clone()), x);
+        assert!(seen.contains(&x.fingerprint()));
+
+        let mut calls = 0;
+        let pic = Pic::unseen(&mut seen, || {
+            calls += 1;
+            if calls < 3 {
+                x.clone()
+            } else {
+                y.clone()
+            }
+        });
+        assert_eq!((pic, calls), (y.clone(), 3));
+
+        // when everything is a duplicate the last attempt is taken
+        let mut calls = 0;
+        let pic = Pic::unseen(&mut seen, || {
+            calls += 1;
+            x.clone()
+        });
+        assert_eq!((pic, calls), (x, PIC_UNSEEN_ATTEMPTS));
+    }
+
+    #[test]
+    fn test_pic_stats() {
+        let sexpr = "( RGB CARTESIAN ( + X ( * ( SIN Y ) ( SIN T ) ) ) ( Y ) ( FBM X Y T 1 2 3 ) )";
+        let pic = lisp_to_pic(sexpr.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let stats = pic.stats();
+        assert_eq!(stats.mode, "RGB");
+        assert_eq!(stats.depths, vec![("R", 4), ("G", 1), ("B", 2)]);
+        assert_eq!(stats.nodes, 7 + 1 + 7);
+        assert_eq!(
+            stats.operations,
+            BTreeMap::from([
+                ("*".to_string(), 1),
+                ("+".to_string(), 1),
+                ("FBM".to_string(), 1),
+                ("SIN".to_string(), 2),
+            ])
+        );
+        assert_eq!(stats.nodes, pic.complexity());
+    }
+
+    #[test]
+    fn test_box_downsample() {
+        #[rustfmt::skip]
+        let rgba8: Vec<u8> = vec![
+            0, 10, 255, 255,   4, 10, 255, 255,
+            2, 10, 255, 0,     6, 11, 255, 0,
+        ];
+        assert_eq!(
+            box_downsample(&rgba8, 1, 1, 2, ColorSpace::Srgb),
+            vec![3, 10, 255, 128]
+        );
+        assert_eq!(box_downsample(&rgba8, 2, 2, 1, ColorSpace::Srgb), rgba8);
+        // the average of black and white is half of the light, the alpha is averaged as it is
+        #[rustfmt::skip]
+        let checkers: Vec<u8> = vec![
+            0, 0, 0, 255,      255, 255, 255, 0,
+            255, 255, 255, 0,  0, 0, 0, 255,
+        ];
+        assert_eq!(
+            box_downsample(&checkers, 1, 1, 2, ColorSpace::Linear),
+            vec![188, 188, 188, 128]
+        );
+    }
+
+    #[test]
+    fn test_pic_get_rgba8_tiled() {
+        let pictures = Arc::new(HashMap::new());
+        for source in &[
+            "( MONO CARTESIAN ( X ) )",
+            "( GRAYSCALE POLAR AUTO ( Y ) )",
+            "( RGB CARTESIAN ( X ) ( Y ) ( * X Y ) )",
+            "( RGBA CARTESIAN ( X ) ( Y ) ( 0.5 ) ( Y ) )",
+            "( HSV POLAR ( X ) ( Y ) ( 0.8 ) )",
+            "( CMYK POLAR ( X ) ( Y ) ( 0.8 ) ( * X Y ) )",
+            "( LAB CARTESIAN ( X ) ( Y ) ( * X Y ) )",
+            "( GRADIENT CARTESIAN ( COLORS ( COLOR 0.1 0.2 0.3 ) ( COLOR 0.9 0.8 0.7 ) ) ( Y ) )",
+        ] {
+            let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            let whole = pic_get_rgba8_runtime_select(&pic, true, pictures.clone(), 16, 7, 0.0, 1);
+            let mut tiled = Vec::new();
+            let mut first_rows = Vec::new();
+            let mut sink = |y: u32, strip: &[u8]| {
+                first_rows.push(y);
+                tiled.extend_from_slice(strip);
+            };
+            pic_get_rgba8_tiled_runtime_select(
+                &pic,
+                true,
+                pictures.clone(),
+                16,
+                7,
+                0.0,
+                DEFAULT_VIEWPORT,
+                DEFAULT_RENDER_OPTIONS,
+                3,
+                &mut sink,
+            );
+            assert_eq!(first_rows, vec![0, 3, 6], "{}", source);
+            assert_eq!(tiled, whole, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_pic_get_rgba8_viewport() {
+        let pictures = Arc::new(HashMap::new());
+        let pic = lisp_to_pic(
+            "( RGB CARTESIAN ( X ) ( Y ) ( 0 ) )".to_string(),
+            DEFAULT_COORDINATE_SYSTEM,
+        )
+        .unwrap();
+        let red_spread = |rgba8: &[u8]| {
+            let reds = rgba8.chunks_exact(4).map(|pixel| pixel[0]);
+            (reds.clone().min().unwrap(), reds.max().unwrap())
+        };
+        let whole = pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 16, 8, 0.0, 1);
+        assert_eq!(
+            pic_get_rgba8_viewport_runtime_select(
+                &pic,
+                false,
+                pictures.clone(),
+                16,
+                8,
+                0.0,
+                1,
+                DEFAULT_VIEWPORT,
+                DEFAULT_RENDER_OPTIONS
+            ),
+            whole
+        );
+        assert_eq!(red_spread(&whole), (0, 255));
+
+        // the middle half of the X axis
+        let shrunken = pic_get_rgba8_viewport_runtime_select(
+            &pic,
+            false,
+            pictures.clone(),
+            16,
+            8,
+            0.0,
+            1,
+            Viewport::new(-0.5, -1.0, 0.5, 1.0),
+            DEFAULT_RENDER_OPTIONS,
+        );
+        let (min, max) = red_spread(&shrunken);
+        assert_eq!(min, 64);
+        assert!((191..=192).contains(&max), "{}", max);
+        // Y is not affected
+        assert_eq!(
+            shrunken
+                .chunks_exact(4)
+                .map(|pixel| pixel[1])
+                .collect::<Vec<_>>(),
+            whole
+                .chunks_exact(4)
+                .map(|pixel| pixel[1])
+                .collect::<Vec<_>>()
+        );
+
+        // the bottom right quarter
+        let quarter = pic_get_rgba8_viewport_runtime_select(
+            &pic,
+            false,
+            pictures,
+            16,
+            8,
+            0.0,
+            1,
+            Viewport::new(0.0, 0.0, 1.0, 1.0),
+            DEFAULT_RENDER_OPTIONS,
+        );
+        assert_eq!(red_spread(&quarter), (128, 255));
+        assert_eq!(quarter[1], 128);
+    }
+
+    #[test]
+    fn test_pic_symmetry() {
+        let pictures = Arc::new(HashMap::new());
+        // the rows are as far apart as the columns and centered, so the pixels of a quarter
+        // turn land on pixels as well
+        let n = 24;
+        let viewport = Viewport::new(-1.0, -1.0, 1.0, -1.0 + 2.0 * n as f32 / (n - 1) as f32);
+        let symmetric = DEFAULT_RENDER_OPTIONS.with_symmetry(Some(Symmetry::new(4).unwrap()));
+        let quarter_turn_diff = |rgba8: &[u8]| {
+            let pixel = |x: usize, y: usize| &rgba8[(y * n + x) * 4..(y * n + x) * 4 + 4];
+            let mut diff = 0;
+            for y in 0..n {
+                for x in 0..n {
+                    // (x, y) turns to (-y, x)
+                    for (a, b) in pixel(x, y).iter().zip(pixel(n - 1 - y, x)) {
+                        diff = diff.max((*a as i32 - *b as i32).abs());
+                    }
+                }
+            }
+            diff
+        };
+        for source in &[
+            "( MONO CARTESIAN ( X ) )",
+            "( GRAYSCALE POLAR AUTO ( Y ) )",
+            "( RGB CARTESIAN ( X ) ( Y ) ( * X Y ) )",
+            "( RGBA CARTESIAN ( X ) ( Y ) ( 0.5 ) ( Y ) )",
+            "( HSV POLAR ( X ) ( Y ) ( 0.8 ) )",
+            "( CMYK POLAR ( X ) ( Y ) ( 0.8 ) ( * X Y ) )",
+            "( LAB CARTESIAN ( X ) ( Y ) ( * X Y ) )",
+            "( GRADIENT CARTESIAN ( COLORS ( COLOR 0.1 0.2 0.3 ) ( COLOR 0.9 0.8 0.7 ) ) ( Y ) )",
+        ] {
+            let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            let render = |options| {
+                pic_get_rgba8_viewport_runtime_select(
+                    &pic,
+                    true,
+                    pictures.clone(),
+                    n as u32,
+                    n as u32,
+                    0.0,
+                    1,
+                    viewport,
+                    options,
+                )
+            };
+            assert!(
+                quarter_turn_diff(&render(DEFAULT_RENDER_OPTIONS)) > 2,
+                "{}",
+                source
+            );
+            assert!(quarter_turn_diff(&render(symmetric)) <= 2, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_pic_max_render_time() {
+        let pictures = Arc::new(HashMap::new());
+        // noise of noise of noise with many octaves, every pixel takes a while
+        let fbm = "( FBM X Y 0.9 0.5 0.5 0.9 )";
+        let sexpr = format!(
+            "( RGB CARTESIAN ( FBM {} {} 0.9 0.5 0.5 0.9 ) ( Y ) ( X ) )",
+            fbm, fbm
+        );
+        let pic = lisp_to_pic(sexpr, DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let (w, h) = (512, 512);
+        for threaded in [false, true] {
+            let options = DEFAULT_RENDER_OPTIONS
+                .with_deadline(Some(Instant::now() + Duration::from_millis(1)));
+            let rgba8 = pic_get_rgba8_viewport_runtime_select(
+                &pic,
+                threaded,
+                pictures.clone(),
+                w,
+                h,
+                0.0,
+                1,
+                DEFAULT_VIEWPORT,
+                options,
+            );
+            assert!(options.check_deadline().is_err());
+            assert_eq!(rgba8.len(), (w * h * 4) as usize);
+            // the rows that were not started are not even opaque
+            assert!(rgba8.chunks_exact(4).any(|pixel| pixel == [0, 0, 0, 0]));
+        }
+        // without a deadline every row is rendered
+        let rgba8 = pic_get_rgba8_viewport_runtime_select(
+            &pic,
+            true,
+            pictures,
+            8,
+            8,
+            0.0,
+            1,
+            DEFAULT_VIEWPORT,
+            DEFAULT_RENDER_OPTIONS,
+        );
+        assert!(rgba8.chunks_exact(4).all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn test_pic_subsample() {
+        let pictures = Arc::new(HashMap::new());
+        let (w, h) = (33, 17);
+        let render = |source: &str, subsample: &str, threaded: bool| {
+            let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            let options = DEFAULT_RENDER_OPTIONS.with_subsample(subsample.parse().unwrap());
+            pic_get_rgba8_viewport_runtime_select(
+                &pic,
+                threaded,
+                pictures.clone(),
+                w,
+                h,
+                0.0,
+                1,
+                DEFAULT_VIEWPORT,
+                options,
+            )
+        };
+        let max_diff = |a: &[u8], b: &[u8], channel: usize| {
+            a.chunks_exact(4)
+                .zip(b.chunks_exact(4))
+                .map(|(a, b)| (a[channel] as i32 - b[channel] as i32).abs())
+                .max()
+                .unwrap()
+        };
+        // a hue and saturation that change slowly look the same at half the resolution
+        let smooth = "( HSV CARTESIAN ( * 0.5 ( + X Y ) ) ( 0.8 ) ( SIN ( * 12 X ) ) )";
+        for threaded in [false, true] {
+            let full = render(smooth, "R", threaded);
+            let half = render(smooth, "H,S", threaded);
+            assert_eq!(half.len(), full.len());
+            for channel in 0..3 {
+                assert!(max_diff(&full, &half, channel) <= 2);
+            }
+        }
+        // a channel with fine detail loses it, the other channels are untouched
+        let fine = "( RGB CARTESIAN ( SIN ( * 100 X ) ) ( Y ) ( X ) )";
+        let full = render(fine, "H", true);
+        let half = render(fine, "R", true);
+        assert!(max_diff(&full, &half, 0) > 16);
+        assert_eq!(max_diff(&full, &half, 1), 0);
+        assert_eq!(max_diff(&full, &half, 2), 0);
+    }
+
+    #[test]
+    fn test_pic_color_space() {
+        let pictures = Arc::new(HashMap::new());
+        let pic = lisp_to_pic(
+            "( RGB CARTESIAN ( -0.99 ) ( -0.999 ) ( 0.0 ) )".to_string(),
+            DEFAULT_COORDINATE_SYSTEM,
+        )
+        .unwrap();
+        let render = |color_space: ColorSpace, supersample: u32| {
+            pic_get_rgba8_viewport_runtime_select(
+                &pic,
+                true,
+                pictures.clone(),
+                4,
+                4,
+                0.0,
+                supersample,
+                DEFAULT_VIEWPORT,
+                DEFAULT_RENDER_OPTIONS.with_color_space(color_space),
+            )
+        };
+        assert_eq!(render(ColorSpace::Srgb, 1)[0..4], [1, 0, 128, 255]);
+        // the shadows are encoded before they are quantized, not from the bytes 1 and 0
+        let linear = render(ColorSpace::Linear, 1);
+        assert_eq!(linear[0..4], [16, 2, 188, 255]);
+        assert_eq!(render(ColorSpace::Linear, 2), linear);
+    }
+
+    #[test]
+    fn test_pic_rgba16() {
+        let pictures = Arc::new(HashMap::new());
+        let source = "( GRAYSCALE CARTESIAN ( * X 0.01 ) )";
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let (w, h) = (512, 2);
+        let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), w, h, 0.0, 1);
+        let rgba16 = pic_get_rgba16_runtime_select(&pic, false, pictures.clone(), w, h, 0.0, 1);
+        assert_eq!(rgba16.len(), rgba8.len());
+        let mut grays8: Vec<u8> = rgba8.chunks(4).map(|rgba| rgba[0]).collect();
+        let mut grays16: Vec<u16> = rgba16.chunks(4).map(|rgba| rgba[0]).collect();
+        grays8.dedup();
+        grays16.dedup();
+        assert!(grays16.len() > grays8.len());
+        // the high byte of the 16 bit channel matches the 8 bit channel
+        for (c8, c16) in rgba8.iter().zip(rgba16.iter()) {
+            assert!((*c8 as i32 - (*c16 >> 8) as i32).abs() <= 1);
+        }
+
+        let supersampled = pic_get_rgba16_runtime_select(&pic, false, pictures, 16, 8, 0.0, 2);
+        assert_eq!(supersampled.len(), 16 * 8 * 4);
+    }
+
+    #[test]
+    fn test_pic_supersample() {
+        let pictures = Arc::new(HashMap::new());
+        let source = "( RGBA CARTESIAN ( 0.2 ) ( -0.4 ) ( 0.6 ) ( 0.1 ) )";
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let once = pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 16, 8, 0.0, 1);
+        let twice = pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 16, 8, 0.0, 2);
+        assert_eq!(twice.len(), 16 * 8 * 4);
+        assert_eq!(twice, once);
+
+        let source = "( GRAYSCALE CARTESIAN ( X ) )";
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures, 16, 8, 0.0, 3);
+        assert_eq!(rgba8.len(), 16 * 8 * 4);
+    }
+
+    #[test]
+    fn test_pic_rgb_white() {
+        let pictures = Arc::new(HashMap::new());
+        for source in &[
+            "( RGB CARTESIAN ( 1.0 ) ( 1.0 ) ( 1.0 ) )",
+            "( HSV CARTESIAN ( 0.0 ) ( -1.0 ) ( 1.0 ) )",
+        ] {
+            let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 16, 8, 0.0, 1);
+            assert_eq!(rgba8.len(), 16 * 8 * 4);
+            assert!(rgba8.iter().all(|c| *c == 255), "{} is not white", source);
+        }
+    }
+
+    #[test]
+    fn test_pic_from_tree() {
+        let pictures = Arc::new(HashMap::new());
+        let pic = Pic::rgb_from(
+            APTNode::Constant(1.0),
+            APTNode::Constant(-1.0),
+            APTNode::Constant(0.0),
+            CoordinateSystem::Cartesian,
+        );
+        let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 4, 2, 0.0, 1);
+        assert_eq!(rgba8.len(), 4 * 2 * 4);
+        assert!(rgba8.chunks(4).all(|rgba| rgba == [255, 0, 127, 255]));
+
+        let x = APTNode::X;
+        let sin_y = APTNode::Sin(vec![APTNode::Y]);
+        for pic in [
+            Pic::mono_from(x.clone(), CoordinateSystem::Polar),
+            Pic::grayscale_from(sin_y.clone(), CoordinateSystem::Cartesian),
+            Pic::gradient_from(
+                vec![
+                    (Color::RED, false),
+                    (Color::CYAN, true),
+                    (Color::WHITE, false),
+                ],
+                x.clone(),
+                CoordinateSystem::Polar,
+            ),
+            pic,
+            Pic::rgba_from(
+                x.clone(),
+                sin_y.clone(),
+                APTNode::T,
+                APTNode::Constant(0.5),
+                CoordinateSystem::Polar,
+            ),
+            Pic::hsv_from(
+                x.clone(),
+                sin_y.clone(),
+                x.clone(),
+                CoordinateSystem::Cartesian,
+            ),
+            Pic::lab_from(sin_y.clone(), x.clone(), x.clone(), CoordinateSystem::Polar),
+            Pic::cmyk_from(
+                x.clone(),
+                x.clone(),
+                sin_y.clone(),
+                APTNode::Constant(-1.0),
+                CoordinateSystem::Cartesian,
+            ),
+        ] {
+            let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 8, 4, 0.0, 1);
+            assert_eq!(rgba8.len(), 8 * 4 * 4);
+            let parsed = lisp_to_pic(pic.to_lisp(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            assert_eq!(parsed, pic, "{}", pic.to_lisp());
         }
-        result
     }
 
-    pub fn coord(&self) -> &CoordinateSystem {
-        match self {
-            Pic::Mono(data) => &data.coord,
-            Pic::Grayscale(data) => &data.coord,
-            Pic::Gradient(data) => &data.coord,
-            Pic::RGB(data) => &data.coord,
-            Pic::HSV(data) => &data.coord,
+    #[test]
+    fn test_pic_step_two_tones() {
+        let pictures = Arc::new(HashMap::new());
+        for source in &[
+            "( MONO CARTESIAN ( STEP ( SIN Y ) X ) )",
+            "( GRAYSCALE CARTESIAN CLAMP ( STEP ( * X Y ) ( FBM X Y 0.5 0.5 0.5 0.5 ) ) )",
+        ] {
+            let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 32, 16, 0.0, 1);
+            assert!(
+                rgba8.iter().all(|c| *c == 0 || *c == 255),
+                "{} has gray pixels",
+                source
+            );
+            // both regions are there
+            assert!(rgba8.chunks(4).any(|rgba| rgba[0] == 0), "{}", source);
+            assert!(rgba8.chunks(4).any(|rgba| rgba[0] == 255), "{}", source);
         }
     }
 
-    pub fn get_rgba8<S: Simd>(
-        &self,
-        threaded: bool,
-        pics: Arc<HashMap<String, ActualPicture>>,
-        w: u32,
-        h: u32,
-        t: f32,
-    ) -> Vec<u8> {
-        match self {
-            Pic::Mono(data) => data.get_rgba8::<S>(threaded, pics, w, h, t),
-            Pic::Grayscale(data) => data.get_rgba8::<S>(threaded, pics, w, h, t),
-            Pic::Gradient(data) => data.get_rgba8::<S>(threaded, pics, w, h, t),
-            Pic::RGB(data) => data.get_rgba8::<S>(threaded, pics, w, h, t),
-            Pic::HSV(data) => data.get_rgba8::<S>(threaded, pics, w, h, t),
-        }
+    #[test]
+    fn test_pic_cmyk_inks() {
+        let pictures = Arc::new(HashMap::new());
+        // no cyan, full magenta, half yellow and no black
+        let source = "( CMYK CARTESIAN ( -1.0 ) ( 1.0 ) ( 0.0 ) ( -1.0 ) )";
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 4, 2, 0.0, 1);
+        assert!(rgba8.chunks(4).all(|rgba| rgba == [255, 0, 127, 255]));
+
+        // full black ink, whatever the other inks are
+        let source = "( CMYK CARTESIAN ( X ) ( Y ) ( 0.3 ) ( 1.0 ) )";
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures, 4, 2, 0.0, 1);
+        assert!(rgba8.chunks(4).all(|rgba| rgba == [0, 0, 0, 255]));
     }
 
-    pub fn can_animate(&self) -> bool {
-        let mut children = match self {
-            Pic::Mono(data) => vec![&data.c],
-            Pic::Grayscale(data) => vec![&data.c],
-            Pic::Gradient(data) => vec![&data.index],
-            Pic::RGB(data) => vec![&data.r, &data.g, &data.b],
-            Pic::HSV(data) => vec![&data.h, &data.s, &data.v],
-        };
-        while children.len() > 0 {
-            if let Some(child) = children.pop() {
-                if child.is_leaf() {
-                    if *child == APTNode::T {
-                        return true;
-                    }
-                } else {
-                    for kid in child.get_children().unwrap() {
-                        children.push(kid);
-                    }
-                }
-            };
+    #[test]
+    fn test_pic_lab_lightness() {
+        let pictures = Arc::new(HashMap::new());
+        for (source, low, high) in &[
+            ("( LAB CARTESIAN ( 1.0 ) ( 0.0 ) ( 0.0 ) )", 254, 255),
+            ("( LAB CARTESIAN ( -1.0 ) ( 0.0 ) ( 0.0 ) )", 0, 0),
+            // L* 50 is a mid gray, much lighter than half the linear light
+            ("( LAB CARTESIAN ( 0.0 ) ( 0.0 ) ( 0.0 ) )", 118, 119),
+        ] {
+            let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 4, 2, 0.0, 1);
+            for rgba in rgba8.chunks(4) {
+                assert!(
+                    rgba[..3].iter().all(|c| c >= low && c <= high),
+                    "{} gives {:?}",
+                    source,
+                    rgba
+                );
+                assert_eq!(rgba[3], 255);
+            }
         }
-        false
+
+        // far out of gamut, clamped instead of wrapped
+        let source = "( LAB CARTESIAN ( 0.0 ) ( 1.0 ) ( -1.0 ) )";
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures, 4, 2, 0.0, 1);
+        assert!(rgba8
+            .chunks(4)
+            .all(|rgba| rgba[0] > 150 && rgba[1] == 0 && rgba[2] >= 254));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::constants::{DEFAULT_COORDINATE_SYSTEM, DEFAULT_IMAGE_HEIGHT, DEFAULT_IMAGE_WIDTH};
-    use crate::parser::lexer::lisp_to_pic;
-    use crate::pic::color::Color;
-    use image::io::Reader as ImageReader;
-    use image::{
-        save_buffer_with_format, ColorType, DynamicImage, GenericImageView, ImageBuffer,
-        ImageFormat,
-    };
+    #[test]
+    fn test_handle_lab_round_trip() {
+        let sexpr = "( LAB POLAR ( X ) ( Y ) ( T ) )";
+        let pic = lisp_to_pic(sexpr.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        assert_eq!(
+            pic,
+            Pic::LAB(LABData {
+                l: APTNode::X,
+                a: APTNode::Y,
+                b: APTNode::T,
+                coord: CoordinateSystem::Polar
+            })
+        );
+        let resexpr = pic.to_lisp();
+        assert_eq!(resexpr, "( LAB POLAR\n\t( X )\n\t( Y )\n\t( T )\n)");
+        assert_eq!(
+            lisp_to_pic(resexpr, DEFAULT_COORDINATE_SYSTEM).unwrap(),
+            pic
+        );
+    }
 
     #[test]
-    fn test_pic_to_lisp_mono() {
+    fn test_pic_json_round_trip() {
+        let pictures = Arc::new(crate::load_pictures(std::path::Path::new("pictures")).unwrap());
         let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
-        let pic = MonoData::new(0, 60, false, &mut rng, &vec![&"eye.jpg".to_string()]);
-        let sexpr = pic.to_lisp();
+        let name = "eye.jpg".to_string();
+        let pic_names = vec![&name];
+        let mut pics = vec![
+            lisp_to_pic(
+                "( GRAYSCALE CARTESIAN AUTO ( WARP 0.1 -0.2 ( PICN-eye.jpg X Y ) ) )".to_string(),
+                DEFAULT_COORDINATE_SYSTEM,
+            )
+            .unwrap(),
+            lisp_to_pic(
+                "( GRADIENT POLAR ( COLORS ( COLOR 0.1 0.2 0.3 ) ( STOPCOLOR 0.4 0.5 0.6 ) ( COLOR 0.9 0.8 0.7 ) ) ( FBM X Y 0.5 0.5 0.5 0.5 ) )".to_string(),
+                DEFAULT_COORDINATE_SYSTEM,
+            )
+            .unwrap(),
+        ];
+        for _i in 0..10 {
+            pics.push(Pic::new(
+                &mut rng,
+                &pic_names,
+                &GenerationConfig::new(1, 20),
+            ));
+        }
+        for pic in pics {
+            let json = pic.to_json();
+            let loaded = Pic::from_json(&json).unwrap();
+            assert_eq!(loaded, pic);
+            assert_eq!(
+                pic_get_rgba8_runtime_select(&loaded, false, pictures.clone(), 16, 8, 0.3, 1),
+                pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 16, 8, 0.3, 1),
+                "{}",
+                json
+            );
+        }
+    }
 
-        assert!(
-            sexpr.starts_with("( MONO POLAR\n\t(") || sexpr.starts_with("( MONO CARTESIAN\n\t(")
+    #[test]
+    fn test_pic_from_json_invalid() {
+        assert!(Pic::from_json("( MONO ( X ) )").is_err());
+        assert!(Pic::from_json(r#"{"Mono":{"c":"X","coord":"Sideways"}}"#).is_err());
+        assert!(Pic::from_json(r#"{"Mono":{"c":{"Add":["X"]},"coord":"Polar"}}"#).is_err());
+        assert_eq!(
+            Pic::from_json(r#"{"Mono":{"c":{"Add":["X","Y"]},"coord":"Polar"}}"#).unwrap(),
+            lisp_to_pic(
+                "( MONO POLAR ( + X Y ) )".to_string(),
+                CoordinateSystem::Cartesian
+            )
+            .unwrap()
         );
-        assert!(sexpr.ends_with("\n)"));
-        assert!(sexpr.lines().collect::<Vec<_>>().len() > 1);
     }
 
     #[test]
-    fn test_pic_to_lisp_grayscale() {
-        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
-        let pic = GrayscaleData::new(0, 60, false, &mut rng, &vec![&"eye.jpg".to_string()]);
-        let sexpr = pic.to_lisp();
-        assert!(
-            sexpr.starts_with("( GRAYSCALE POLAR\n\t(")
-                || sexpr.starts_with("( GRAYSCALE CARTESIAN\n\t(")
+    fn test_handle_cmyk_round_trip() {
+        let sexpr = "( CMYK CARTESIAN ( X ) ( Y ) ( T ) ( 0.5 ) )";
+        let pic = lisp_to_pic(sexpr.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        assert_eq!(
+            pic,
+            Pic::CMYK(CMYKData {
+                c: APTNode::X,
+                m: APTNode::Y,
+                y: APTNode::T,
+                k: APTNode::Constant(0.5),
+                coord: CoordinateSystem::Cartesian
+            })
+        );
+        let resexpr = pic.to_lisp();
+        assert_eq!(
+            resexpr,
+            "( CMYK CARTESIAN\n\t( X )\n\t( Y )\n\t( T )\n\t( 0.5 )\n)"
+        );
+        assert_eq!(
+            lisp_to_pic(resexpr, DEFAULT_COORDINATE_SYSTEM).unwrap(),
+            pic
         );
-        assert!(sexpr.ends_with("\n)"));
-        assert!(sexpr.lines().collect::<Vec<_>>().len() > 1);
     }
 
     #[test]
-    fn test_pic_to_lisp_gradient() {
+    fn test_pic_rgba_alpha() {
+        let pictures = Arc::new(HashMap::new());
+        let source = "( RGBA CARTESIAN ( X ) ( Y ) ( 0.5 ) ( X ) )";
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures, 5, 1, 0.0, 1);
+        let alphas: Vec<u8> = rgba8.chunks(4).map(|rgba| rgba[3]).collect();
+        // x runs from -1.0 to 1.0 and is scaled just like the color channels
+        assert_eq!(alphas[0], 0);
+        assert_eq!(alphas[2], 128);
+        assert_eq!(alphas[4], 255);
+        assert!(alphas.windows(2).all(|pair| pair[0] < pair[1]));
+        let reds: Vec<u8> = rgba8.chunks(4).map(|rgba| rgba[0]).collect();
+        assert_eq!(reds, alphas);
+    }
+
+    #[test]
+    fn test_pic_gradient_round_trip() {
+        let pictures = Arc::new(HashMap::new());
         let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
-        let pic = GradientData::new(0, 60, false, &mut rng, &vec![&"eye.jpg".to_string()]);
-        let sexpr = pic.to_lisp();
-        assert!(
-            sexpr.starts_with("( GRADIENT POLAR\n\t(")
-                || sexpr.starts_with("( GRADIENT CARTESIAN\n\t(")
-        );
-        assert!(sexpr.ends_with("\n)"));
-        assert!(sexpr.contains("\n\t( COLORS\n\t"));
-        assert!(sexpr.contains("\n\t)\n\t"));
-        assert!(sexpr.contains("\n\t\t( COLOR ") || sexpr.contains("\n\t\t( STOPCOLOR "));
-        assert!(sexpr.lines().collect::<Vec<_>>().len() > 0);
+        let mut pics = vec![Pic::Gradient(GradientData {
+            colors: vec![
+                (Color::new(0.3690771, 0.7165854, 0.075644374, 1.0), false),
+                (Color::new(0.39675784, 0.10509944, 0.82246256, 1.0), true),
+                (Color::new(0.1, 0.2, 0.3, 1.0), false),
+            ],
+            index: APTNode::X,
+            coord: CoordinateSystem::Cartesian,
+            wrap: GradientWrap::Wrap,
+            cycle: false,
+        })];
+        for _i in 0..5 {
+            pics.push(GradientData::new(
+                &GenerationConfig::new(1, 20),
+                false,
+                &mut rng,
+                &vec![],
+            ));
+        }
+        for wrap in [GradientWrap::Clamp, GradientWrap::Mirror] {
+            if let Pic::Gradient(data) = &pics[0] {
+                pics.push(Pic::Gradient(GradientData {
+                    index: APTNode::Mul(vec![APTNode::X, APTNode::Constant(2.0)]),
+                    wrap,
+                    cycle: true,
+                    ..data.clone()
+                }));
+            }
+        }
+        for pic in pics {
+            let reparsed = lisp_to_pic(pic.to_lisp(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            assert_eq!(reparsed, pic);
+            let expected =
+                pic_get_rgba8_runtime_select(&pic, false, pictures.clone(), 32, 16, 0.0, 1);
+            let actual =
+                pic_get_rgba8_runtime_select(&reparsed, false, pictures.clone(), 32, 16, 0.0, 1);
+            assert_eq!(actual, expected);
+        }
     }
 
     #[test]
     fn test_pic_to_lisp_rgb() {
         let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
-        let pic = RGBData::new(0, 60, false, &mut rng, &vec![&"eye.jpg".to_string()]);
+        let pic = RGBData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
         let sexpr = pic.to_lisp();
         assert!(sexpr.starts_with("( RGB POLAR\n\t(") || sexpr.starts_with("( RGB CARTESIAN\n\t("));
         assert!(sexpr.ends_with("\n)"));
@@ -326,13 +2096,122 @@ mod tests {
     #[test]
     fn test_pic_to_lisp_hsv() {
         let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
-        let pic = HSVData::new(0, 60, false, &mut rng, &vec![&"eye.jpg".to_string()]);
+        let pic = HSVData::new(
+            &GenerationConfig::new(0, 60),
+            false,
+            &mut rng,
+            &vec![&"eye.jpg".to_string()],
+        );
         let sexpr = pic.to_lisp();
         assert!(sexpr.starts_with("( HSV POLAR\n\t(") || sexpr.starts_with("( HSV CARTESIAN\n\t("));
         assert!(sexpr.ends_with("\n)"));
         assert!(sexpr.lines().collect::<Vec<_>>().len() > 1);
     }
 
+    #[test]
+    fn test_pic_new_seeded() {
+        let name = "eye.jpg".to_string();
+        let pic_names = vec![&name];
+        let mut rng_a = StdRng::seed_from_u64(42);
+        let mut rng_b = StdRng::seed_from_u64(42);
+        for _i in 0..10 {
+            assert_eq!(
+                Pic::new(&mut rng_a, &pic_names, &GenerationConfig::default()),
+                Pic::new(&mut rng_b, &pic_names, &GenerationConfig::default())
+            );
+        }
+    }
+
+    #[test]
+    fn test_pic_complexity() {
+        let cases = vec![
+            ("( MONO CARTESIAN ( X ) )", 1),
+            ("( GRAYSCALE CARTESIAN ( + X ( * Y 0.5 ) ) )", 5),
+            ("( RGB CARTESIAN ( X ) ( Y ) ( SIN T ) )", 4),
+            ("( HSV CARTESIAN ( X ) ( ATAN2 X Y ) ( WARP 0.1 Y X ) )", 8),
+            (
+                "( GRADIENT CARTESIAN ( COLORS ( STOPCOLOR 0 0 0 ) ( COLOR 1 1 1 ) ) ( / X Y ) )",
+                3,
+            ),
+        ];
+        for (source, expected) in cases {
+            let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            assert_eq!(pic.complexity(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_pic_new_min_complexity() {
+        let name = "eye.jpg".to_string();
+        let pic_names = vec![&name];
+        let mut rng = StdRng::seed_from_u64(7);
+        let config = GenerationConfig {
+            min_complexity: 12,
+            ..GenerationConfig::default()
+        };
+        // the odds that none of the attempts reaches 12 nodes are slim
+        for _i in 0..50 {
+            assert!(Pic::new(&mut rng, &pic_names, &config).complexity() >= 12);
+        }
+
+        // an impossible minimum still gives a pic
+        let config = GenerationConfig {
+            min_complexity: usize::MAX,
+            ..GenerationConfig::default()
+        };
+        assert!(Pic::new(&mut rng, &pic_names, &config).complexity() > 0);
+    }
+
+    #[test]
+    fn test_pic_mutate() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let name = "eye.jpg".to_string();
+        let pic_names = vec![&name];
+        let pic = RGBData::new(&GenerationConfig::new(1, 20), false, &mut rng, &pic_names);
+        assert_eq!(pic.mutate(&mut rng, 0.0, &pic_names), pic);
+        for _i in 0..20 {
+            let child = pic.mutate(&mut rng, 1.0, &pic_names);
+            match &child {
+                Pic::RGB(_) => {}
+                _ => panic!("wrong type"),
+            };
+            let reparsed = lisp_to_pic(child.to_lisp(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            assert_eq!(reparsed, child);
+        }
+    }
+
+    #[test]
+    fn test_pic_crossover_same_mode() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let name = "eye.jpg".to_string();
+        let pic_names = vec![&name];
+        let mother = RGBData::new(&GenerationConfig::new(1, 20), false, &mut rng, &pic_names);
+        let father = RGBData::new(&GenerationConfig::new(1, 20), false, &mut rng, &pic_names);
+        let child = mother.crossover(&father, &mut rng);
+        match &child {
+            Pic::RGB(_) => {}
+            _ => panic!("wrong type"),
+        };
+        let reparsed = lisp_to_pic(child.to_lisp(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        assert_eq!(reparsed, child);
+    }
+
+    #[test]
+    fn test_pic_crossover_different_mode() {
+        let mut rng = StdRng::from_rng(rand::thread_rng()).unwrap();
+        let name = "eye.jpg".to_string();
+        let pic_names = vec![&name];
+        let mother = HSVData::new(&GenerationConfig::new(1, 20), false, &mut rng, &pic_names);
+        let father = GrayscaleData::new(&GenerationConfig::new(1, 20), false, &mut rng, &pic_names);
+        let child = mother.crossover(&father, &mut rng);
+        match &child {
+            Pic::HSV(_) | Pic::Grayscale(_) => {}
+            _ => panic!("wrong type"),
+        };
+        let reparsed = lisp_to_pic(child.to_lisp(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        assert_eq!(reparsed, child);
+    }
+
     #[test]
     fn test_pic_coord() {
         assert_eq!(
@@ -358,6 +2237,65 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_pic_mode_name() {
+        for mode in ["MONO", "GRAYSCALE", "RGB", "RGBA", "HSV", "LAB", "CMYK"] {
+            let channels = match mode {
+                "MONO" | "GRAYSCALE" => 1,
+                "RGBA" | "CMYK" => 4,
+                _ => 3,
+            };
+            let source = format!("( {} CARTESIAN{} )", mode, " ( X )".repeat(channels));
+            let pic = lisp_to_pic(source.clone(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            assert_eq!(pic.mode_name(), mode, "{}", source);
+            assert!(pic.to_lisp().starts_with(&format!("( {} ", mode)));
+        }
+        let pic = lisp_to_pic(
+            "( GRADIENT CARTESIAN ( COLORS ( COLOR 1 1 1 ) ) ( X ) )".to_string(),
+            DEFAULT_COORDINATE_SYSTEM,
+        )
+        .unwrap();
+        assert_eq!(pic.mode_name(), "GRADIENT");
+    }
+
+    #[test]
+    fn test_pic_coord_round_trip() {
+        for source in &[
+            "( MONO POLAR ( X ) )",
+            "( GRAYSCALE POLAR ( X ) )",
+            "( RGB POLAR ( X ) ( Y ) ( X ) )",
+            "( HSV POLAR ( X ) ( Y ) ( X ) )",
+            "( GRADIENT POLAR ( COLORS ( COLOR 0.1 0.2 0.3 ) ) ( X ) )",
+        ] {
+            let pic = lisp_to_pic(source.to_string(), CoordinateSystem::Cartesian).unwrap();
+            assert_eq!(pic.coord(), &CoordinateSystem::Polar);
+            let reparsed = lisp_to_pic(pic.to_lisp(), CoordinateSystem::Cartesian).unwrap();
+            assert_eq!(reparsed.coord(), &CoordinateSystem::Polar);
+        }
+    }
+
+    #[test]
+    fn test_pic_polar_radial_symmetry() {
+        // with 5x4 pixels, x runs from -1.0 to 1.0 in steps of 0.5 and y from -1.0 to 0.5
+        let (w, h) = (5, 4);
+        let pixel = |rgba8: &Vec<u8>, c: usize, r: usize| rgba8[(r * w + c) * 4];
+        let pictures = Arc::new(HashMap::new());
+        let pic = lisp_to_pic(
+            "( GRAYSCALE POLAR ( X ) )".to_string(),
+            CoordinateSystem::Polar,
+        )
+        .unwrap();
+        let rgba8 = pic_get_rgba8_runtime_select(&pic, false, pictures, w as u32, h as u32, 0.0, 1);
+        for c in 0..w {
+            assert_eq!(pixel(&rgba8, c, 1), pixel(&rgba8, w - 1 - c, 1));
+            assert_eq!(pixel(&rgba8, c, 1), pixel(&rgba8, c, 3));
+        }
+        assert_eq!(pixel(&rgba8, 3, 2), pixel(&rgba8, 2, 3));
+        assert_eq!(pixel(&rgba8, 1, 2), pixel(&rgba8, 2, 1));
+        assert!(pixel(&rgba8, 2, 2) < pixel(&rgba8, 3, 2));
+        assert!(pixel(&rgba8, 3, 2) < pixel(&rgba8, 4, 2));
+    }
+
     #[test]
     //todo Currently wrong CoordinateSystems are still accepted, but ignored
     fn test_pic_coord_fail() {
@@ -375,7 +2313,8 @@ mod tests {
                     pic,
                     Pic::Grayscale(GrayscaleData {
                         c: APTNode::Div(vec![APTNode::X, APTNode::Width]),
-                        coord: CoordinateSystem::Polar
+                        coord: CoordinateSystem::Polar,
+                        normalization: Normalization::Wrap,
                     })
                 );
                 let resexpr = pic.to_lisp();
@@ -396,7 +2335,8 @@ mod tests {
                     pic,
                     Pic::Grayscale(GrayscaleData {
                         c: APTNode::Div(vec![APTNode::Y, APTNode::Height]),
-                        coord: CoordinateSystem::Polar
+                        coord: CoordinateSystem::Polar,
+                        normalization: Normalization::Wrap,
                     })
                 );
                 let resexpr = pic.to_lisp();
@@ -417,7 +2357,8 @@ mod tests {
                     pic,
                     Pic::Grayscale(GrayscaleData {
                         c: APTNode::Sin(vec![APTNode::Div(vec![APTNode::X, APTNode::PI,])]),
-                        coord: CoordinateSystem::Polar
+                        coord: CoordinateSystem::Polar,
+                        normalization: Normalization::Wrap,
                     })
                 );
                 let resexpr = pic.to_lisp();
@@ -438,7 +2379,8 @@ mod tests {
                     pic,
                     Pic::Grayscale(GrayscaleData {
                         c: APTNode::Log(vec![APTNode::Div(vec![APTNode::X, APTNode::E,])]),
-                        coord: CoordinateSystem::Polar
+                        coord: CoordinateSystem::Polar,
+                        normalization: Normalization::Wrap,
                     })
                 );
                 let resexpr = pic.to_lisp();
@@ -594,7 +2536,8 @@ mod tests {
                     pic,
                     Pic::Grayscale(GrayscaleData {
                         c: APTNode::X,
-                        coord: CoordinateSystem::Cartesian
+                        coord: CoordinateSystem::Cartesian,
+                        normalization: Normalization::Wrap,
                     })
                 );
                 let resexpr = pic.to_lisp();
@@ -621,6 +2564,7 @@ mod tests {
             crashes_at_dim.0,
             crashes_at_dim.1,
             0.0,
+            1,
         );
     }
 
@@ -631,6 +2575,7 @@ mod tests {
         let pic = Pic::Grayscale(GrayscaleData {
             c: APTNode::X,
             coord: CoordinateSystem::Polar,
+            normalization: Normalization::Wrap,
         });
         let _x = pic_get_rgba8_runtime_select(
             &pic,
@@ -639,6 +2584,7 @@ mod tests {
             crashes_at_dim.0,
             crashes_at_dim.1,
             0.0,
+            1,
         );
     }
 
@@ -669,6 +2615,8 @@ mod tests {
             ],
             index: APTNode::X,
             coord: CoordinateSystem::Polar,
+            wrap: GradientWrap::Wrap,
+            cycle: false,
         });
         let _x = pic_get_rgba8_runtime_select(
             &pic,
@@ -677,6 +2625,7 @@ mod tests {
             crashes_at_dim.0,
             crashes_at_dim.1,
             0.0,
+            1,
         );
     }
 
@@ -697,6 +2646,7 @@ mod tests {
             crashes_at_dim.0,
             crashes_at_dim.1,
             0.0,
+            1,
         );
     }
 
@@ -717,6 +2667,7 @@ mod tests {
             crashes_at_dim.0,
             crashes_at_dim.1,
             0.0,
+            1,
         );
     }
 
@@ -734,6 +2685,7 @@ mod tests {
             DEFAULT_IMAGE_WIDTH,
             DEFAULT_IMAGE_HEIGHT,
             0.0,
+            1,
         );
         if overwrite {
             save_buffer_with_format(
@@ -834,6 +2786,286 @@ mod tests {
         assert_eq!(generated.as_bytes(), read.as_bytes());
     }
 
+    #[test]
+    fn test_get_video_frames() {
+        let pictures = Arc::new(HashMap::new());
+        let source = r#"( RGB CARTESIAN ( X ) ( Y ) ( T ) )"#;
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let frames = pic_get_video_runtime_select(
+            &pic,
+            pictures.clone(),
+            16,
+            8,
+            3,
+            1000.0,
+            DEFAULT_TIME_RANGE,
+            LoopMode::Forward,
+            DEFAULT_VIEWPORT,
+            DEFAULT_RENDER_OPTIONS,
+            None,
+        );
+        assert_eq!(frames.len(), 3);
+        for (frame, t) in frames
+            .iter()
+            .zip(frame_times(3, 1000.0, DEFAULT_TIME_RANGE))
+        {
+            assert_eq!(frame.len(), 16 * 8 * 4);
+            let still = pic_get_rgba8_runtime_select(&pic, true, pictures.clone(), 16, 8, t, 1);
+            assert_eq!(frame, &still);
+        }
+        assert_ne!(frames[0], frames[2]);
+    }
+
+    #[test]
+    fn test_get_video_motion_blur() {
+        let pictures = Arc::new(HashMap::new());
+        let source = r#"( RGB CARTESIAN ( SIN ( * 8 ( + X T ) ) ) ( Y ) ( T ) )"#;
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let video = |fps: u16, motion_blur: u32| {
+            pic_get_video_runtime_select(
+                &pic,
+                pictures.clone(),
+                16,
+                8,
+                fps,
+                1000.0,
+                DEFAULT_TIME_RANGE,
+                LoopMode::Forward,
+                DEFAULT_VIEWPORT,
+                DEFAULT_RENDER_OPTIONS.with_motion_blur(motion_blur),
+                None,
+            )
+        };
+        // a single moment per frame is the still at the T of the frame
+        let sharp = video(3, 1);
+        for (frame, t) in sharp.iter().zip(frame_times(3, 1000.0, DEFAULT_TIME_RANGE)) {
+            let still = pic_get_rgba8_runtime_select(&pic, true, pictures.clone(), 16, 8, t, 1);
+            assert_eq!(frame, &still);
+        }
+        // every frame is the average of the frames of a video with 4 times the frame rate
+        let blurred = video(3, 4);
+        let fast = video(12, 1);
+        assert_eq!(blurred.len(), 3);
+        assert_ne!(blurred, sharp);
+        for (frame, sub_frames) in blurred.iter().zip(fast.chunks(4)) {
+            let average = average_sub_frames(sub_frames.to_vec(), ColorSpace::Srgb);
+            for (a, b) in frame.iter().zip(average) {
+                assert!((*a as i32 - b as i32).abs() <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_video_time_range() {
+        let pictures = Arc::new(HashMap::new());
+        let source = r#"( MONO CARTESIAN ( T ) )"#;
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let time_range = TimeRange::new(0.5, 1.0);
+        let frames = pic_get_video_runtime_select(
+            &pic,
+            pictures.clone(),
+            16,
+            8,
+            2,
+            1000.0,
+            time_range,
+            LoopMode::Forward,
+            DEFAULT_VIEWPORT,
+            DEFAULT_RENDER_OPTIONS,
+            None,
+        );
+        assert_eq!(frames.len(), 2);
+        // the first frame starts at the min of the range, not at -1.0
+        let first = pic_get_rgba8_runtime_select(&pic, true, pictures.clone(), 16, 8, 0.5, 1);
+        assert_eq!(frames[0], first);
+        let default = pic_get_rgba8_runtime_select(&pic, true, pictures, 16, 8, -1.0, 1);
+        assert_ne!(frames[0], default);
+    }
+
+    #[test]
+    fn test_get_video_seamless_time() {
+        let pictures = Arc::new(HashMap::new());
+        let source = r#"( RGB CARTESIAN ( SIN ( + X ( * 3.0 T ) ) ) ( * Y TCOS ) ( X ) )"#;
+        let plain = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let mut seamless = plain.clone();
+        seamless.seamless_time();
+        for (pic, loops) in [(&plain, false), (&seamless, true)] {
+            let frames = pic_get_video_runtime_select(
+                pic,
+                pictures.clone(),
+                16,
+                8,
+                8,
+                1000.0,
+                SEAMLESS_TIME_RANGE,
+                LoopMode::Forward,
+                DEFAULT_VIEWPORT,
+                DEFAULT_RENDER_OPTIONS,
+                None,
+            );
+            assert_eq!(frames.len(), 8);
+            assert_ne!(frames[0], frames[4]);
+            // the frame after the last one closes the loop
+            let end = SEAMLESS_TIME_RANGE.max;
+            let after_last =
+                pic_get_rgba8_runtime_select(pic, true, pictures.clone(), 16, 8, end, 1);
+            assert_eq!(frames[0] == after_last, loops, "{}", pic.to_lisp());
+        }
+    }
+
+    #[test]
+    fn test_pic_division_by_zero() {
+        let pictures = Arc::new(HashMap::new());
+        let source = r#"( RGB CARTESIAN ( / 1 ( - X X ) ) ( / ( - X X ) ( - X X ) ) ( - 0 ( / 1 ( - X X ) ) ) )"#;
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let rgba8 = pic_get_rgba8_runtime_select(&pic, true, pictures.clone(), 17, 9, 0.0, 1);
+        assert_eq!(rgba8.len(), 17 * 9 * 4);
+        // the division by zero gives 1.0 and 0 / 0 gives 0.0, for every pixel
+        for pixel in rgba8.chunks(4) {
+            assert_eq!(pixel, &[255, 128, 0, 255]);
+        }
+        let rgba16 = pic_get_rgba16_runtime_select(&pic, true, pictures, 17, 9, 0.0, 1);
+        for pixel in rgba16.chunks(4) {
+            assert_eq!(pixel, &[65535, 32768, 0, 65535]);
+        }
+    }
+
+    #[test]
+    fn test_pic_t_differs_over_time() {
+        let pictures = Arc::new(HashMap::new());
+        let source = r#"( RGB CARTESIAN ( T ) ( T ) ( T ) )"#;
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let early = DEFAULT_TIME_RANGE.at_ms(1000.0, 5000.0);
+        let late = DEFAULT_TIME_RANGE.at_ms(3000.0, 5000.0);
+        let early_rgba8 =
+            pic_get_rgba8_runtime_select(&pic, true, pictures.clone(), 16, 8, early, 1);
+        let late_rgba8 = pic_get_rgba8_runtime_select(&pic, true, pictures, 16, 8, late, 1);
+        assert_ne!(early_rgba8, late_rgba8);
+        assert!(early_rgba8[0] < late_rgba8[0]);
+    }
+
+    #[test]
+    fn test_get_video_progress() {
+        let pictures = Arc::new(HashMap::new());
+        let source = r#"( MONO CARTESIAN ( T ) )"#;
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let mut done = Vec::new();
+        let mut progress = |frame: usize, total: usize| done.push((frame, total));
+        let frames = pic_get_video_runtime_select(
+            &pic,
+            pictures,
+            16,
+            8,
+            3,
+            1000.0,
+            DEFAULT_TIME_RANGE,
+            LoopMode::Forward,
+            DEFAULT_VIEWPORT,
+            DEFAULT_RENDER_OPTIONS,
+            Some(&mut progress),
+        );
+        assert_eq!(frames.len(), 3);
+        assert_eq!(done, vec![(1, 3), (2, 3), (3, 3)]);
+    }
+
+    #[test]
+    fn test_get_video_pingpong() {
+        let pictures = Arc::new(HashMap::new());
+        let source = r#"( MONO CARTESIAN ( T ) )"#;
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        let mut done = Vec::new();
+        let mut progress = |frame: usize, total: usize| done.push((frame, total));
+        // 5 frames forward, then 3 frames back without the first and the last one
+        let frames = pic_get_video_runtime_select(
+            &pic,
+            pictures.clone(),
+            16,
+            8,
+            5,
+            1000.0,
+            DEFAULT_TIME_RANGE,
+            LoopMode::PingPong,
+            DEFAULT_VIEWPORT,
+            DEFAULT_RENDER_OPTIONS,
+            Some(&mut progress),
+        );
+        assert_eq!(frames.len(), 8);
+        assert_eq!(done.len(), 5);
+        assert_eq!(frames[3], frames[5]);
+        assert_eq!(frames[1], frames[7]);
+        let once = pic_get_video_runtime_select(
+            &pic,
+            pictures,
+            16,
+            8,
+            5,
+            1000.0,
+            DEFAULT_TIME_RANGE,
+            LoopMode::Once,
+            DEFAULT_VIEWPORT,
+            DEFAULT_RENDER_OPTIONS,
+            None,
+        );
+        assert_eq!(once.len(), 5);
+        assert_eq!(once[..], frames[..5]);
+    }
+
+    simd_runtime_generate!(
+        fn impl_pic_get_rgba8_cached() {
+            let pictures = Arc::new(HashMap::new());
+            let source = r#"( RGB CARTESIAN ( X ) ( Y ) ( T ) )"#;
+            let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            let mut cache = StackMachineCache::<S>::new();
+            let frames = frame_times(10, 1000.0, DEFAULT_TIME_RANGE);
+            for t in &frames {
+                let cached = pic.get_rgba8_cached::<S>(
+                    &mut cache,
+                    true,
+                    pictures.clone(),
+                    16,
+                    8,
+                    *t,
+                    DEFAULT_VIEWPORT,
+                    DEFAULT_RENDER_OPTIONS,
+                );
+                let expected = pic.get_rgba8::<S>(
+                    true,
+                    pictures.clone(),
+                    16,
+                    8,
+                    *t,
+                    DEFAULT_VIEWPORT,
+                    DEFAULT_RENDER_OPTIONS,
+                );
+                assert_eq!(cached, expected);
+            }
+            // one build for each channel, not for each frame
+            assert_eq!(frames.len(), 10);
+            assert_eq!(cache.builds(), 3);
+
+            let source = r#"( GRAYSCALE CARTESIAN ( + X T ) )"#;
+            let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+            for t in &frames {
+                pic.get_rgba8_cached::<S>(
+                    &mut cache,
+                    true,
+                    pictures.clone(),
+                    16,
+                    8,
+                    *t,
+                    DEFAULT_VIEWPORT,
+                    DEFAULT_RENDER_OPTIONS,
+                );
+            }
+            assert_eq!(cache.builds(), 4);
+        }
+    );
+
+    #[test]
+    fn test_pic_get_rgba8_cached() {
+        impl_pic_get_rgba8_cached_runtime_select();
+    }
+
     #[test]
     fn test_has_t_apt() {
         let source = r#"( MONO POLAR ( MAX X Y ) )"#;
@@ -847,5 +3079,22 @@ mod tests {
         let source = r#"( RGB CARTESIAN ( ( x ) ( Y )  ( T ) ) )"#;
         let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
         assert_eq!(pic.can_animate(), true);
+
+        let source = r#"( HSV POLAR ( SIN X ) ( ABS ( + X ( * Y ( COS ( MIN T X ) ) ) ) ) ( Y ) )"#;
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        assert_eq!(pic.can_animate(), true);
+
+        let source = r#"( CMYK CARTESIAN ( X ) ( Y ) ( SIN ( + X Y ) ) ( 0.5 ) )"#;
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        assert_eq!(pic.can_animate(), false);
+
+        let source = r#"( GRADIENT POLAR ( COLORS ( COLOR 0 0 0 ) ( COLOR 1 1 1 ) ) ( SIN X ) )"#;
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        assert_eq!(pic.can_animate(), false);
+
+        let source =
+            r#"( GRADIENT POLAR CYCLE ( COLORS ( COLOR 0 0 0 ) ( COLOR 1 1 1 ) ) ( SIN X ) )"#;
+        let pic = lisp_to_pic(source.to_string(), DEFAULT_COORDINATE_SYSTEM).unwrap();
+        assert_eq!(pic.can_animate(), true);
     }
 }
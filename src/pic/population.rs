@@ -0,0 +1,76 @@
+use crate::constants::POPULATION_SEPARATOR;
+use crate::parser::lexer::lisp_to_pic;
+use crate::pic::coordinatesystem::CoordinateSystem;
+use crate::pic::pic::Pic;
+
+/// All pics as one text, the records are separated by a POPULATION_SEPARATOR line.
+/// Every record starts with the picture type and coordinate system, so it loads as it was saved.
+pub fn population_to_lisp(pics: &[Pic]) -> String {
+    pics.iter()
+        .map(|pic| pic.to_lisp())
+        .collect::<Vec<String>>()
+        .join(&format!("\n{}\n", POPULATION_SEPARATOR))
+}
+
+/// Parse a text written by population_to_lisp, `coord` is only used for records without a coordinate system
+pub fn lisp_to_population(contents: &str, coord: CoordinateSystem) -> Result<Vec<Pic>, String> {
+    let mut records = vec![String::new()];
+    for line in contents.lines() {
+        if line.trim() == POPULATION_SEPARATOR {
+            records.push(String::new());
+        } else {
+            let record = records.last_mut().unwrap();
+            record.push_str(line);
+            record.push('\n');
+        }
+    }
+    records
+        .into_iter()
+        .filter(|record| !record.trim().is_empty())
+        .enumerate()
+        .map(|(i, record)| {
+            lisp_to_pic(record, coord.clone())
+                .map_err(|e| format!("Cannot parse record {}. {}", i, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_population_roundtrip() {
+        let pics = vec![
+            lisp_to_pic(
+                "( MONO CARTESIAN ( X ) )".to_string(),
+                CoordinateSystem::Polar,
+            )
+            .unwrap(),
+            lisp_to_pic(
+                "( RGB POLAR ( Y ) ( X ) ( + X Y ) )".to_string(),
+                CoordinateSystem::Cartesian,
+            )
+            .unwrap(),
+        ];
+        let contents = population_to_lisp(&pics);
+        assert_eq!(contents.matches(POPULATION_SEPARATOR).count(), 1);
+        // the stored coordinate systems win over the default
+        let loaded = lisp_to_population(&contents, CoordinateSystem::Cartesian).unwrap();
+        assert_eq!(loaded, pics);
+        let loaded = lisp_to_population(&contents, CoordinateSystem::Polar).unwrap();
+        assert_eq!(loaded, pics);
+    }
+
+    #[test]
+    fn test_lisp_to_population() {
+        assert_eq!(
+            lisp_to_population("", CoordinateSystem::Polar).unwrap(),
+            vec![]
+        );
+        let err = lisp_to_population("( MONO ( X ) )\n---\n( garbage", CoordinateSystem::Polar)
+            .err()
+            .unwrap();
+        assert!(err.starts_with("Cannot parse record 1."));
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::Instant;
+
+use simdeez::Simd;
+
+use crate::pic::colorspace::ColorSpace;
+use crate::pic::subsample::Subsample;
+use crate::pic::symmetry::Symmetry;
+
+/// How a render is done, besides the region of the plane of its Viewport
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderOptions {
+    /// The positions are folded into the wedges of the symmetry before they are evaluated
+    pub symmetry: Option<Symmetry>,
+    /// The rows that did not start by then are left empty, see check_deadline
+    pub deadline: Option<Instant>,
+    /// The channels that are evaluated at half the resolution and interpolated
+    pub subsample: Subsample,
+    /// Every frame of a video is the average of this many sub-frames, 1 renders it as is
+    pub motion_blur: u32,
+    /// What the rendered values stand for, linear light is sRGB encoded before it is quantized
+    pub color_space: ColorSpace,
+}
+
+impl RenderOptions {
+    /// A render as it was always done
+    pub const fn new() -> Self {
+        RenderOptions {
+            symmetry: None,
+            deadline: None,
+            subsample: Subsample::NONE,
+            motion_blur: 1,
+            color_space: ColorSpace::Srgb,
+        }
+    }
+
+    /// The same options, rendered with `symmetry`
+    pub fn with_symmetry(self, symmetry: Option<Symmetry>) -> Self {
+        RenderOptions { symmetry, ..self }
+    }
+
+    /// The same options, rendered until `deadline`
+    pub fn with_deadline(self, deadline: Option<Instant>) -> Self {
+        RenderOptions { deadline, ..self }
+    }
+
+    /// The same options, with the `subsample` channels at half the resolution
+    pub fn with_subsample(self, subsample: Subsample) -> Self {
+        RenderOptions { subsample, ..self }
+    }
+
+    /// The same options, with every frame of a video blurred over `motion_blur` sub-frames
+    pub fn with_motion_blur(self, motion_blur: u32) -> Self {
+        RenderOptions {
+            motion_blur,
+            ..self
+        }
+    }
+
+    /// The same options, with the rendered values in `color_space`
+    pub fn with_color_space(self, color_space: ColorSpace) -> Self {
+        RenderOptions {
+            color_space,
+            ..self
+        }
+    }
+
+    /// Whether the deadline has passed, the rows that start from now on are skipped
+    pub fn expired(&self) -> bool {
+        self.deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
+    }
+
+    /// An error when the deadline has passed, after a render the rows that started later are empty
+    pub fn check_deadline(&self) -> Result<(), String> {
+        if self.expired() {
+            return Err(
+                "The render took longer than --max-render-time and was aborted".to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// The position that is evaluated for the lanes at `x`, `y`
+    #[inline(always)]
+    pub fn fold<S: Simd>(&self, x: S::Vf32, y: S::Vf32) -> (S::Vf32, S::Vf32) {
+        match self.symmetry {
+            Some(symmetry) => symmetry.fold_ps::<S>(x, y),
+            None => (x, y),
+        }
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::DEFAULT_RENDER_OPTIONS;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render_options() {
+        assert_eq!(RenderOptions::default(), DEFAULT_RENDER_OPTIONS);
+
+        let symmetry = Some(Symmetry::new(6).unwrap());
+        let folded = DEFAULT_RENDER_OPTIONS.with_symmetry(symmetry);
+        assert_eq!(folded.symmetry, symmetry);
+        assert_eq!(folded.with_symmetry(None), DEFAULT_RENDER_OPTIONS);
+
+        let subsample = "H,S".parse().unwrap();
+        let halved = DEFAULT_RENDER_OPTIONS.with_subsample(subsample);
+        assert_eq!(halved.subsample, subsample);
+        assert_eq!(
+            halved.with_subsample(Subsample::NONE),
+            DEFAULT_RENDER_OPTIONS
+        );
+
+        let blurred = DEFAULT_RENDER_OPTIONS.with_motion_blur(4);
+        assert_eq!(blurred.motion_blur, 4);
+        assert_eq!(blurred.with_motion_blur(1), DEFAULT_RENDER_OPTIONS);
+
+        let linear = DEFAULT_RENDER_OPTIONS.with_color_space(ColorSpace::Linear);
+        assert_eq!(linear.color_space, ColorSpace::Linear);
+        assert_eq!(
+            linear.with_color_space(ColorSpace::Srgb),
+            DEFAULT_RENDER_OPTIONS
+        );
+    }
+
+    #[test]
+    fn test_render_options_deadline() {
+        assert!(!DEFAULT_RENDER_OPTIONS.expired());
+        assert_eq!(DEFAULT_RENDER_OPTIONS.check_deadline(), Ok(()));
+        let later =
+            DEFAULT_RENDER_OPTIONS.with_deadline(Some(Instant::now() + Duration::from_secs(60)));
+        assert!(!later.expired());
+        let passed = DEFAULT_RENDER_OPTIONS.with_deadline(Some(Instant::now()));
+        assert!(passed.expired());
+        assert!(passed.check_deadline().is_err());
+        assert_eq!(passed.with_deadline(None), DEFAULT_RENDER_OPTIONS);
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use clap::ValueEnum;
+
+/// The instruction set the pics are rendered with
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum SimdBackend {
+    /// The widest instruction set the cpu supports
+    Auto,
+    /// One value at a time, without simd
+    Scalar,
+    #[value(name = "sse2")]
+    Sse2,
+    #[value(name = "sse41")]
+    Sse41,
+    #[value(name = "avx2")]
+    Avx2,
+}
+
+// Auto until select_simd_backend is called, only backends the cpu supports are stored
+static SELECTED: AtomicU8 = AtomicU8::new(0);
+
+impl SimdBackend {
+    const ALL: [SimdBackend; 5] = [
+        SimdBackend::Auto,
+        SimdBackend::Scalar,
+        SimdBackend::Sse2,
+        SimdBackend::Sse41,
+        SimdBackend::Avx2,
+    ];
+
+    pub fn is_supported(&self) -> bool {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            match self {
+                SimdBackend::Auto | SimdBackend::Scalar => true,
+                SimdBackend::Sse2 => is_x86_feature_detected!("sse2"),
+                SimdBackend::Sse41 => is_x86_feature_detected!("sse4.1"),
+                SimdBackend::Avx2 => is_x86_feature_detected!("avx2"),
+            }
+        }
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+        {
+            matches!(self, SimdBackend::Auto | SimdBackend::Scalar)
+        }
+    }
+
+    /// The instruction sets this cpu can render with, from the narrowest to the widest
+    pub fn list_supported() -> Vec<SimdBackend> {
+        SimdBackend::ALL
+            .iter()
+            .filter(|backend| **backend != SimdBackend::Auto && backend.is_supported())
+            .copied()
+            .collect()
+    }
+
+    /// The backend that does the rendering, for Auto that is the widest one the cpu supports
+    pub fn resolve(&self) -> Result<SimdBackend, String> {
+        match self {
+            SimdBackend::Auto => Ok(*[SimdBackend::Avx2, SimdBackend::Sse41, SimdBackend::Sse2]
+                .iter()
+                .find(|backend| backend.is_supported())
+                .unwrap_or(&SimdBackend::Scalar)),
+            backend if backend.is_supported() => Ok(*backend),
+            backend => Err(format!("This cpu does not support {}", backend)),
+        }
+    }
+}
+
+impl Display for SimdBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        let x = match self {
+            SimdBackend::Auto => "auto",
+            SimdBackend::Scalar => "scalar",
+            SimdBackend::Sse2 => "sse2",
+            SimdBackend::Sse41 => "sse41",
+            SimdBackend::Avx2 => "avx2",
+        };
+        write!(f, "{}", x)
+    }
+}
+
+/// Render with `backend` from now on, the backend that is used is returned. Auto leaves the
+/// choice to the runtime dispatch of simdeez, a backend the cpu does not support is an error.
+pub fn select_simd_backend(backend: SimdBackend) -> Result<SimdBackend, String> {
+    let resolved = backend.resolve()?;
+    SELECTED.store(backend as u8, Ordering::Relaxed);
+    Ok(resolved)
+}
+
+pub fn selected_simd_backend() -> SimdBackend {
+    SimdBackend::ALL[SELECTED.load(Ordering::Relaxed) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simd_backend_parse() {
+        assert_eq!(SimdBackend::from_str("sse41", true), Ok(SimdBackend::Sse41));
+        assert_eq!(SimdBackend::from_str("avx2", true), Ok(SimdBackend::Avx2));
+        assert!(SimdBackend::from_str("neon", true).is_err());
+        for backend in SimdBackend::ALL {
+            assert_eq!(
+                SimdBackend::from_str(&backend.to_string(), true),
+                Ok(backend)
+            );
+            assert_eq!(SimdBackend::ALL[backend as usize], backend);
+        }
+    }
+
+    #[test]
+    fn test_simd_backend_resolve() {
+        assert_eq!(SimdBackend::Scalar.resolve(), Ok(SimdBackend::Scalar));
+        let auto = SimdBackend::Auto.resolve().unwrap();
+        assert_ne!(auto, SimdBackend::Auto);
+        assert!(auto.is_supported());
+        for backend in SimdBackend::ALL {
+            assert_eq!(backend.resolve().is_ok(), backend.is_supported());
+        }
+        let supported = SimdBackend::list_supported();
+        assert_eq!(supported[0], SimdBackend::Scalar);
+        assert!(supported.contains(&auto));
+        assert!(!supported.contains(&SimdBackend::Auto));
+    }
+}
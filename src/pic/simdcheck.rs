@@ -0,0 +1,229 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::sync::Arc;
+
+use crate::constants::SIMD_DIVERGENCE_THRESHOLD;
+use crate::parser::aptnode::APTNode;
+use crate::pic::actual_picture::ActualPicture;
+use crate::pic::coordinatesystem::CoordinateSystem;
+use crate::pic::data::grayscale::GrayscaleData;
+use crate::pic::normalization::Normalization;
+use crate::pic::pic::{pic_get_rgba8_with_backend, Pic};
+use crate::pic::simdbackend::SimdBackend;
+
+/// How much a pic changes when it is rendered with `backend` instead of `reference`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimdDivergence {
+    pub reference: SimdBackend,
+    pub backend: SimdBackend,
+    /// The largest difference of a channel of a pixel
+    pub max_difference: u8,
+    /// The number of pixels with a channel that differs more than SIMD_DIVERGENCE_THRESHOLD
+    pub pixels: usize,
+    /// The operations, or the color mode, that differ while their inputs do not
+    pub suspects: Vec<String>,
+}
+
+impl SimdDivergence {
+    pub fn diverges(&self) -> bool {
+        self.max_difference > SIMD_DIVERGENCE_THRESHOLD
+    }
+}
+
+impl Display for SimdDivergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        write!(
+            f,
+            "{} and {} differ by at most {}",
+            self.reference, self.backend, self.max_difference
+        )?;
+        if self.diverges() {
+            write!(
+                f,
+                ", {} pixels differ by more than {}",
+                self.pixels, SIMD_DIVERGENCE_THRESHOLD
+            )?;
+        }
+        if !self.suspects.is_empty() {
+            write!(f, "\nlane sensitive: {}", self.suspects.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The largest difference between the channels of `a` and `b`, and the number of pixels that
+/// differ by more than SIMD_DIVERGENCE_THRESHOLD
+fn compare_rgba8(a: &[u8], b: &[u8]) -> (u8, usize) {
+    let mut max_difference = 0;
+    let mut pixels = 0;
+    for (pa, pb) in a.chunks_exact(4).zip(b.chunks_exact(4)) {
+        let difference = pa
+            .iter()
+            .zip(pb.iter())
+            .map(|(ca, cb)| ca.abs_diff(*cb))
+            .max()
+            .unwrap_or(0);
+        max_difference = max_difference.max(difference);
+        if difference > SIMD_DIVERGENCE_THRESHOLD {
+            pixels += 1;
+        }
+    }
+    (max_difference, pixels)
+}
+
+struct Verifier {
+    backend: SimdBackend,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    w: u32,
+    h: u32,
+    t: f32,
+}
+
+impl Verifier {
+    fn compare(&self, pic: &Pic) -> Result<(u8, usize), String> {
+        let render = |backend| {
+            pic_get_rgba8_with_backend(backend, pic, self.pictures.clone(), self.w, self.h, self.t)
+        };
+        Ok(compare_rgba8(
+            &render(SimdBackend::Scalar)?,
+            &render(self.backend)?,
+        ))
+    }
+
+    /// Whether the tree differs between the backends, the operations that differ while their
+    /// children do not are added to `suspects`
+    fn find_suspects(
+        &self,
+        tree: &APTNode,
+        coord: &CoordinateSystem,
+        suspects: &mut BTreeSet<String>,
+    ) -> Result<bool, String> {
+        let children = match tree.get_children() {
+            Some(children) => children,
+            None => return Ok(false),
+        };
+        let mut children_diverge = false;
+        for child in children {
+            children_diverge |= self.find_suspects(child, coord, suspects)?;
+        }
+        let pic = Pic::Grayscale(GrayscaleData {
+            c: tree.clone(),
+            coord: coord.clone(),
+            normalization: Normalization::Clamp,
+        });
+        let diverges = self.compare(&pic)?.0 > SIMD_DIVERGENCE_THRESHOLD;
+        if diverges && !children_diverge {
+            suspects.insert(tree.lisp_name());
+        }
+        Ok(diverges)
+    }
+}
+
+/// Render the pic at `w` x `h` with scalar and with `backend` and compare the two. When they
+/// differ, the operations of the pic are rendered on their own to find the ones that cause it.
+pub fn verify_simd(
+    pic: &Pic,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    backend: SimdBackend,
+    w: u32,
+    h: u32,
+    t: f32,
+) -> Result<SimdDivergence, String> {
+    let backend = backend.resolve()?;
+    if backend == SimdBackend::Scalar {
+        return Err(
+            "Cannot compare scalar with itself, this cpu or --simd has no other instruction set"
+                .to_string(),
+        );
+    }
+    let verifier = Verifier {
+        backend,
+        pictures,
+        w,
+        h,
+        t,
+    };
+    let (max_difference, pixels) = verifier.compare(pic)?;
+    let mut suspects = BTreeSet::new();
+    if max_difference > SIMD_DIVERGENCE_THRESHOLD {
+        let mut trees_diverge = false;
+        for tree in pic.to_tree() {
+            trees_diverge |= verifier.find_suspects(tree, pic.coord(), &mut suspects)?;
+        }
+        if !trees_diverge {
+            // the channels are the same, so it is the conversion to colors
+            suspects.insert(pic.mode_name().to_string());
+        }
+    }
+    Ok(SimdDivergence {
+        reference: SimdBackend::Scalar,
+        backend,
+        max_difference,
+        pixels,
+        suspects: suspects.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::DEFAULT_COORDINATE_SYSTEM;
+    use crate::parser::lexer::lisp_to_pic;
+
+    #[test]
+    fn test_compare_rgba8() {
+        assert_eq!(compare_rgba8(&[1, 2, 3, 255], &[1, 2, 3, 255]), (0, 0));
+        assert_eq!(
+            compare_rgba8(&[1, 2, 3, 255, 0, 0, 0, 255], &[2, 2, 3, 255, 0, 9, 0, 255]),
+            (9, 1)
+        );
+    }
+
+    #[test]
+    fn test_simd_divergence_display() {
+        let mut divergence = SimdDivergence {
+            reference: SimdBackend::Scalar,
+            backend: SimdBackend::Avx2,
+            max_difference: 1,
+            pixels: 0,
+            suspects: vec![],
+        };
+        assert!(!divergence.diverges());
+        assert_eq!(
+            divergence.to_string(),
+            "scalar and avx2 differ by at most 1"
+        );
+        divergence.max_difference = 40;
+        divergence.pixels = 12;
+        divergence.suspects = vec!["%".to_string(), "HSV".to_string()];
+        assert!(divergence.diverges());
+        assert_eq!(
+            divergence.to_string(),
+            "scalar and avx2 differ by at most 40, 12 pixels differ by more than 1\nlane sensitive: %, HSV"
+        );
+    }
+
+    #[test]
+    fn test_verify_simd() {
+        let pictures = Arc::new(HashMap::new());
+        let pic = lisp_to_pic(
+            "( RGB CARTESIAN ( X ) ( Y ) ( * X Y ) )".to_string(),
+            DEFAULT_COORDINATE_SYSTEM,
+        )
+        .unwrap();
+        assert!(verify_simd(&pic, pictures.clone(), SimdBackend::Scalar, 32, 16, 0.0).is_err());
+        match SimdBackend::Auto.resolve() {
+            Ok(SimdBackend::Scalar) => {
+                assert!(verify_simd(&pic, pictures, SimdBackend::Auto, 32, 16, 0.0).is_err())
+            }
+            Ok(backend) => {
+                let divergence =
+                    verify_simd(&pic, pictures, SimdBackend::Auto, 32, 16, 0.0).unwrap();
+                assert_eq!(divergence.backend, backend);
+                assert!(!divergence.diverges(), "{}", divergence);
+                assert!(divergence.suspects.is_empty());
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result as FResult};
+
+/// The size of the trees of a picture. Every pixel runs through the whole stack of a channel,
+/// so deep and large trees are slow to render.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PicStats {
+    pub mode: &'static str,
+    /// The name and the depth of the tree of every channel
+    pub depths: Vec<(&'static str, usize)>,
+    /// The number of nodes in the trees of all the channels, leaves included
+    pub nodes: usize,
+    /// How often every operation is used, leaves are not counted
+    pub operations: BTreeMap<String, usize>,
+}
+
+impl Display for PicStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        writeln!(f, "mode: {}", self.mode)?;
+        let depths: Vec<String> = self
+            .depths
+            .iter()
+            .map(|(name, depth)| format!("{} {}", name, depth))
+            .collect();
+        writeln!(f, "depth: {}", depths.join(", "))?;
+        write!(f, "nodes: {}", self.nodes)?;
+        if !self.operations.is_empty() {
+            write!(f, "\noperations:")?;
+        }
+        let width = self.operations.keys().map(|op| op.len()).max().unwrap_or(0);
+        for (op, count) in &self.operations {
+            write!(f, "\n  {:width$} {}", op, count, width = width)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pic_stats_display() {
+        let stats = PicStats {
+            mode: "RGB",
+            depths: vec![("R", 3), ("G", 1), ("B", 2)],
+            nodes: 9,
+            operations: BTreeMap::from([("+".to_string(), 2), ("SIN".to_string(), 1)]),
+        };
+        assert_eq!(
+            stats.to_string(),
+            "mode: RGB\ndepth: R 3, G 1, B 2\nnodes: 9\noperations:\n  +   2\n  SIN 1"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::str::FromStr;
+
+// the channels of RGB and HSV pics, a channel is a bit of Subsample
+const CHANNELS: [&str; 6] = ["R", "G", "B", "H", "S", "V"];
+
+/// The channels of RGB and HSV pics that are evaluated at every other pixel of every other
+/// row only, the pixels in between are interpolated. A channel that changes slowly, like the
+/// hue of many HSV pics, looks the same in a quarter of the time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Subsample(u8);
+
+impl Subsample {
+    /// Every channel at full resolution
+    pub const NONE: Subsample = Subsample(0);
+
+    pub fn contains(&self, channel: &str) -> bool {
+        CHANNELS
+            .iter()
+            .position(|c| *c == channel)
+            .map_or(false, |i| self.0 & (1 << i) != 0)
+    }
+}
+
+impl Display for Subsample {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        let channels: Vec<&str> = CHANNELS
+            .iter()
+            .copied()
+            .filter(|c| self.contains(c))
+            .collect();
+        write!(f, "{}", channels.join(","))
+    }
+}
+
+impl FromStr for Subsample {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut bits = 0;
+        for channel in s.split(',') {
+            let channel = channel.trim().to_uppercase();
+            let i = CHANNELS.iter().position(|c| *c == channel).ok_or_else(|| {
+                format!(
+                    "Cannot parse {}. Only the channels {} can be subsampled",
+                    s,
+                    CHANNELS.join(", ")
+                )
+            })?;
+            bits |= 1 << i;
+        }
+        Ok(Subsample(bits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subsample_parse() {
+        let subsample: Subsample = "h, s".parse().unwrap();
+        assert!(subsample.contains("H"));
+        assert!(subsample.contains("S"));
+        assert!(!subsample.contains("V"));
+        assert!(!subsample.contains("R"));
+        assert_eq!(subsample.to_string(), "H,S");
+        assert_eq!("B,R".parse::<Subsample>().unwrap().to_string(), "R,B");
+        assert!(!Subsample::NONE.contains("H"));
+        assert_eq!(Subsample::NONE.to_string(), "");
+        assert_eq!(
+            "H,L".parse::<Subsample>(),
+            Err(
+                "Cannot parse H,L. Only the channels R, G, B, H, S, V can be subsampled"
+                    .to_string()
+            )
+        );
+        assert!("".parse::<Subsample>().is_err());
+    }
+}
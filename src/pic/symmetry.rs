@@ -0,0 +1,121 @@
+use std::f32::consts::PI;
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::str::FromStr;
+
+use simdeez::Simd;
+
+use crate::constants::MAX_SYMMETRY;
+
+/// Folds the plane into N wedges around the origin, every wedge is the mirror image of the
+/// ones next to it so the image repeats N times around the center, like a kaleidoscope
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Symmetry(u32);
+
+impl Symmetry {
+    pub fn new(n: u32) -> Result<Self, String> {
+        if n == 0 || n > MAX_SYMMETRY {
+            return Err(format!(
+                "A symmetry of {} is not possible, it is 1 to {}",
+                n, MAX_SYMMETRY
+            ));
+        }
+        Ok(Symmetry(n))
+    }
+
+    pub fn n(&self) -> u32 {
+        self.0
+    }
+
+    /// The position in the first half of the first wedge that `(x, y)` is folded onto, at the
+    /// same distance from the origin
+    pub fn fold(&self, x: f32, y: f32) -> (f32, f32) {
+        let wedge = 2.0 * PI / self.0 as f32;
+        let angle = y.atan2(x).rem_euclid(wedge);
+        // the second half of a wedge is mirrored, so the wedges meet without a seam
+        let angle = if angle > wedge / 2.0 {
+            wedge - angle
+        } else {
+            angle
+        };
+        let r = x.hypot(y);
+        let (sin, cos) = angle.sin_cos();
+        (r * cos, r * sin)
+    }
+
+    /// Like fold, for every lane
+    #[inline(always)]
+    pub fn fold_ps<S: Simd>(&self, x: S::Vf32, y: S::Vf32) -> (S::Vf32, S::Vf32) {
+        unsafe {
+            let mut fx = S::setzero_ps();
+            let mut fy = S::setzero_ps();
+            for i in 0..S::VF32_WIDTH {
+                let (a, b) = self.fold(x[i], y[i]);
+                fx[i] = a;
+                fy[i] = b;
+            }
+            (fx, fy)
+        }
+    }
+}
+
+impl Display for Symmetry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Symmetry {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let n = s
+            .parse()
+            .map_err(|e| format!("Cannot parse {}. Not a symmetry, {}", s, e))?;
+        Symmetry::new(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close((ax, ay): (f32, f32), (bx, by): (f32, f32)) {
+        assert!(
+            (ax - bx).abs() < 1e-5 && (ay - by).abs() < 1e-5,
+            "({}, {}) is not ({}, {})",
+            ax,
+            ay,
+            bx,
+            by
+        );
+    }
+
+    #[test]
+    fn test_symmetry_parse() {
+        assert_eq!("6".parse(), Ok(Symmetry(6)));
+        assert_eq!("6".parse::<Symmetry>().unwrap().to_string(), "6");
+        assert_eq!(
+            "0".parse::<Symmetry>(),
+            Err("A symmetry of 0 is not possible, it is 1 to 64".to_string())
+        );
+        assert!("65".parse::<Symmetry>().is_err());
+        assert!("-4".parse::<Symmetry>().is_err());
+        assert!("many".parse::<Symmetry>().is_err());
+    }
+
+    #[test]
+    fn test_symmetry_fold() {
+        let four = Symmetry::new(4).unwrap();
+        // the first half of the first wedge stays where it is
+        assert_close(four.fold(0.5, 0.25), (0.5, 0.25));
+        assert_close(four.fold(0.0, 0.0), (0.0, 0.0));
+        // a quarter turn lands on the same position
+        assert_close(four.fold(-0.25, 0.5), (0.5, 0.25));
+        assert_close(four.fold(0.25, -0.5), (0.5, 0.25));
+        // the second half of a wedge is mirrored
+        assert_close(four.fold(0.25, 0.5), (0.5, 0.25));
+        // one wedge only mirrors the bottom onto the top
+        let one = Symmetry::new(1).unwrap();
+        assert_close(one.fold(0.5, -0.25), (0.5, 0.25));
+        assert_close(one.fold(-0.5, 0.25), (-0.5, 0.25));
+    }
+}
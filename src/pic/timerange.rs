@@ -0,0 +1,90 @@
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::str::FromStr;
+
+/// The values the `T` operation sweeps through during an animation, from `min` towards `max`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl TimeRange {
+    pub const fn new(min: f32, max: f32) -> Self {
+        TimeRange { min, max }
+    }
+
+    /// The `T` value at `fraction` of an animation, the animation loops so 1.25 is the same as 0.25
+    pub fn at(&self, fraction: f32) -> f32 {
+        self.min + (self.max - self.min) * fraction.rem_euclid(1.0)
+    }
+
+    /// The `T` value at `ms` milliseconds in an animation of `duration_ms` milliseconds
+    pub fn at_ms(&self, ms: f32, duration_ms: f32) -> f32 {
+        if duration_ms <= 0.0 {
+            return self.min;
+        }
+        self.at(ms / duration_ms)
+    }
+}
+
+impl Display for TimeRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        write!(f, "{}:{}", self.min, self.max)
+    }
+}
+
+impl FromStr for TimeRange {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (min, max) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Cannot parse {}. A time range is written as min:max", s))?;
+        let min: f32 = min
+            .trim()
+            .parse()
+            .map_err(|e| format!("Cannot parse {}. {}", s, e))?;
+        let max: f32 = max
+            .trim()
+            .parse()
+            .map_err(|e| format!("Cannot parse {}. {}", s, e))?;
+        if !min.is_finite() || !max.is_finite() || min == max {
+            return Err(format!(
+                "Cannot parse {}. The min and max of a time range must be different numbers",
+                s
+            ));
+        }
+        Ok(TimeRange::new(min, max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_range_at() {
+        let range = TimeRange::new(-1.0, 1.0);
+        assert_eq!(range.at(0.0), -1.0);
+        assert_eq!(range.at(0.5), 0.0);
+        assert_eq!(range.at(0.75), 0.5);
+        assert_eq!(range.at(1.0), -1.0);
+        assert_eq!(range.at(1.25), -0.5);
+        assert_eq!(range.at_ms(2500.0, 5000.0), 0.0);
+        assert_eq!(range.at_ms(2500.0, 0.0), -1.0);
+
+        let reversed = TimeRange::new(4.0, 2.0);
+        assert_eq!(reversed.at(0.5), 3.0);
+    }
+
+    #[test]
+    fn test_time_range_parse() {
+        assert_eq!("-1:1".parse(), Ok(TimeRange::new(-1.0, 1.0)));
+        assert_eq!(" 0.5 : 3 ".parse(), Ok(TimeRange::new(0.5, 3.0)));
+        assert_eq!("2:-2".parse(), Ok(TimeRange::new(2.0, -2.0)));
+        assert!("1".parse::<TimeRange>().is_err());
+        assert!("1:1".parse::<TimeRange>().is_err());
+        assert!("a:1".parse::<TimeRange>().is_err());
+        assert!("0:inf".parse::<TimeRange>().is_err());
+        assert_eq!(TimeRange::new(-1.0, 0.5).to_string(), "-1:0.5");
+    }
+}
@@ -0,0 +1,78 @@
+/// The region of the plane that is rendered. The first column of pixels is at `x_min` and the
+/// last one at `x_max`, the first row is at `y_min` and the rows run towards `y_max`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x_min: f32,
+    pub y_min: f32,
+    pub x_max: f32,
+    pub y_max: f32,
+}
+
+impl Viewport {
+    pub const fn new(x_min: f32, y_min: f32, x_max: f32, y_max: f32) -> Self {
+        Viewport {
+            x_min,
+            y_min,
+            x_max,
+            y_max,
+        }
+    }
+
+    /// The distance in X between two neighbouring pixels of a row of `w` pixels
+    pub fn x_step(&self, w: u32) -> f32 {
+        (self.x_max - self.x_min) / (w - 1) as f32
+    }
+
+    /// The Y of the row `y_pixel` of an image of `h` rows
+    pub fn y_at(&self, y_pixel: u32, h: u32) -> f32 {
+        (y_pixel as f32 / h as f32) * (self.y_max - self.y_min) + self.y_min
+    }
+
+    /// The viewport grown around its center along the longer side of an image of `w` x `h`
+    /// pixels, so a unit of X is as many pixels as a unit of Y
+    pub fn keep_aspect_ratio(&self, w: u32, h: u32) -> Viewport {
+        let (x_scale, y_scale) = if w >= h {
+            (w as f32 / h as f32, 1.0)
+        } else {
+            (1.0, h as f32 / w as f32)
+        };
+        let x_center = (self.x_min + self.x_max) / 2.0;
+        let y_center = (self.y_min + self.y_max) / 2.0;
+        let x_half = (self.x_max - self.x_min) / 2.0 * x_scale;
+        let y_half = (self.y_max - self.y_min) / 2.0 * y_scale;
+        Viewport::new(
+            x_center - x_half,
+            y_center - y_half,
+            x_center + x_half,
+            y_center + y_half,
+        )
+    }
+}
+
+impl From<(f32, f32, f32, f32)> for Viewport {
+    fn from((x_min, y_min, x_max, y_max): (f32, f32, f32, f32)) -> Self {
+        Viewport::new(x_min, y_min, x_max, y_max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::DEFAULT_VIEWPORT;
+
+    #[test]
+    fn test_viewport() {
+        assert_eq!(DEFAULT_VIEWPORT.x_step(5), 0.5);
+        assert_eq!(DEFAULT_VIEWPORT.y_at(0, 4), -1.0);
+        assert_eq!(DEFAULT_VIEWPORT.y_at(2, 4), 0.0);
+        assert_eq!(DEFAULT_VIEWPORT.y_at(3, 4), 0.5);
+
+        let viewport = Viewport::from((0.0, 0.5, 2.0, 1.5));
+        assert_eq!(viewport, Viewport::new(0.0, 0.5, 2.0, 1.5));
+        assert_eq!(viewport.x_step(3), 1.0);
+        assert_eq!(viewport.y_at(2, 4), 1.0);
+
+        let zoomed = Viewport::new(0.0, 0.0, 1.0, 1.0).keep_aspect_ratio(400, 200);
+        assert_eq!(zoomed, Viewport::new(-0.5, 0.0, 1.5, 1.0));
+    }
+}
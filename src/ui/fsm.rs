@@ -1,11 +1,8 @@
 use crate::ui::state::State;
-use crate::{
-    keep_aspect_ratio, pic_get_rgba8_runtime_select, Pic, EXEC_NAME, EXEC_UI_THUMB_COLS,
-    EXEC_UI_THUMB_HEIGHT, EXEC_UI_THUMB_ROWS, EXEC_UI_THUMB_WIDTH,
-};
+use crate::{info, pic_get_rgba8_runtime_select, Pic, EXEC_NAME};
 
 use image::{imageops::overlay, ImageBuffer};
-use minifb::{Key, MouseButton, MouseMode, Window};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window};
 
 pub type FsmCbt = for<'a, 'b> fn(&'a mut State, &'b Window, Option<Pic>) -> FSM;
 
@@ -26,7 +23,7 @@ impl<'c> Default for FSM {
 }
 
 fn _fsm_regenerate<'a, 'b>(state: &'a mut State, _window: &'b Window, _pic: Option<Pic>) -> FSM {
-    println!("repopulating, please be patient");
+    info("repopulating, please be patient");
     state.generate_buttons();
     FSM {
         cb: _fsm_select_prep,
@@ -34,34 +31,41 @@ fn _fsm_regenerate<'a, 'b>(state: &'a mut State, _window: &'b Window, _pic: Opti
     }
 }
 
+fn _fsm_crossover<'a, 'b>(state: &'a mut State, _window: &'b Window, _pic: Option<Pic>) -> FSM {
+    info("cross breeding, please be patient");
+    state.crossover_buttons();
+    FSM {
+        cb: _fsm_select_prep,
+        ..FSM::default()
+    }
+}
+
+fn _fsm_breed<'a, 'b>(state: &'a mut State, _window: &'b Window, _pic: Option<Pic>) -> FSM {
+    info(format!(
+        "breeding from {} pinned thumbnail(s), please be patient",
+        state.pinned.len()
+    ));
+    state.breed_buttons();
+    FSM {
+        cb: _fsm_select_prep,
+        ..FSM::default()
+    }
+}
+
+fn _fsm_mutate<'a, 'b>(state: &'a mut State, _window: &'b Window, pic: Option<Pic>) -> FSM {
+    info("mutating, please be patient");
+    state.mutate_buttons(&pic.unwrap());
+    FSM {
+        cb: _fsm_select_prep,
+        ..FSM::default()
+    }
+}
+
 fn _fsm_select_prep<'a, 'b>(state: &'a mut State, _window: &'b Window, pic: Option<Pic>) -> FSM {
     assert!(pic.is_none());
-    assert_eq!(state.buttons.len(), EXEC_UI_THUMB_ROWS);
-    assert_eq!(state.buttons.get(0).unwrap().len(), EXEC_UI_THUMB_COLS);
-    let (twidth, theight) = keep_aspect_ratio(
-        state.dimensions,
-        (EXEC_UI_THUMB_WIDTH, EXEC_UI_THUMB_HEIGHT),
-    );
-    //todo: rayon par_iter
-    for (r, row) in state.buttons.iter().enumerate() {
-        for (c, button) in row.iter().enumerate() {
-            let generated_buffer = pic_get_rgba8_runtime_select(
-                &button.pic,
-                false,
-                state.pictures.clone(),
-                twidth,
-                theight,
-                state.frame_elapsed(),
-            );
-            let img = ImageBuffer::from_raw(twidth, theight, &generated_buffer[0..]).unwrap();
-            overlay(
-                &mut state.image,
-                &img,
-                (c as u32 * twidth) as i64,
-                (r as u32 * theight) as i64,
-            );
-        }
-    }
+    assert_eq!(state.buttons.len(), state.grid.rows);
+    assert_eq!(state.buttons.get(0).unwrap().len(), state.grid.cols);
+    state.render_thumbnails();
     FSM {
         cb: _fsm_select_show,
         pic,
@@ -71,6 +75,7 @@ fn _fsm_select_prep<'a, 'b>(state: &'a mut State, _window: &'b Window, pic: Opti
 
 fn _fsm_select_show<'a, 'b>(state: &'a mut State, window: &'b Window, pic: Option<Pic>) -> FSM {
     assert!(pic.is_none());
+    state.receive_thumbnails();
     if window.is_key_down(Key::Escape) {
         return FSM {
             cb: _fsm_exit,
@@ -83,9 +88,56 @@ fn _fsm_select_show<'a, 'b>(state: &'a mut State, window: &'b Window, pic: Optio
             ..FSM::default()
         };
     }
+    if window.is_key_pressed(Key::U, KeyRepeat::No) && !state.undo() {
+        info("nothing to undo");
+    }
+    if window.is_key_pressed(Key::R, KeyRepeat::No) && !state.redo() {
+        info("nothing to redo");
+    }
+    if window.is_key_pressed(Key::S, KeyRepeat::No) {
+        state.save_population(EXEC_NAME);
+    }
+    if window.is_key_pressed(Key::E, KeyRepeat::No) {
+        let hovered = window
+            .get_mouse_pos(MouseMode::Discard)
+            .and_then(|(x, y)| state.slot_at(x as u32, y as u32));
+        match hovered {
+            Some(slot) => state.edit_slot(slot, EXEC_NAME),
+            None => info("point at a thumbnail to edit its expression"),
+        }
+    }
+    if window.is_key_pressed(Key::P, KeyRepeat::No) {
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        let hovered = window
+            .get_mouse_pos(MouseMode::Discard)
+            .and_then(|(x, y)| state.slot_at(x as u32, y as u32));
+        match hovered {
+            _ if shift => state.clear_pins(),
+            Some(slot) => state.toggle_pin(slot),
+            None => info("point at a thumbnail to pin it"),
+        }
+    }
+    if window.is_key_pressed(Key::B, KeyRepeat::No) {
+        if !state.pinned.is_empty() {
+            return FSM {
+                cb: _fsm_breed,
+                ..FSM::default()
+            };
+        }
+        info("pin thumbnails with P to breed from them");
+    }
+    if window.is_key_pressed(Key::C, KeyRepeat::No) && state.parents.len() == 2 {
+        return FSM {
+            cb: _fsm_crossover,
+            ..FSM::default()
+        };
+    }
     let right = window.get_mouse_down(MouseButton::Right);
     let left = window.get_mouse_down(MouseButton::Left);
-    if right || left {
+    let middle = window.get_mouse_down(MouseButton::Middle);
+    let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+    let mut parent = None;
+    if right || left || middle {
         if let Some((x, y)) = window.get_mouse_pos(MouseMode::Discard) {
             //todo: rayon par_iter
             for row in &state.buttons {
@@ -98,7 +150,16 @@ fn _fsm_select_show<'a, 'b>(state: &'a mut State, window: &'b Window, pic: Optio
                                 ..FSM::default()
                             };
                         }
-                        if left {
+                        if middle {
+                            return FSM {
+                                cb: _fsm_mutate,
+                                pic: Some(button.pic.clone()),
+                                ..FSM::default()
+                            };
+                        }
+                        if left && shift {
+                            parent = Some(button.pic.clone());
+                        } else if left {
                             state.save_to_files(&button.pic, EXEC_NAME);
                         }
                     }
@@ -106,6 +167,9 @@ fn _fsm_select_show<'a, 'b>(state: &'a mut State, window: &'b Window, pic: Optio
             }
         }
     }
+    if let Some(pic) = parent {
+        state.select_parent(pic);
+    }
     FSM {
         cb: _fsm_select_show,
         pic,
@@ -122,6 +186,7 @@ fn _fsm_zoom_prep<'a, 'b>(state: &'a mut State, window: &'b Window, wpic: Option
             ..FSM::default()
         };
     }
+    state.reset_zoom();
     let (width, height) = state.dimensions;
     let generated_buffer = pic_get_rgba8_runtime_select(
         pic,
@@ -129,7 +194,8 @@ fn _fsm_zoom_prep<'a, 'b>(state: &'a mut State, window: &'b Window, wpic: Option
         state.pictures.clone(),
         width,
         height,
-        state.frame_elapsed(),
+        state.frame_t(),
+        state.supersample,
     );
     let img = ImageBuffer::from_raw(width, height, &generated_buffer[0..]).unwrap();
     overlay(&mut state.image, &img, 0, 0);
@@ -143,14 +209,28 @@ fn _fsm_zoom_prep<'a, 'b>(state: &'a mut State, window: &'b Window, wpic: Option
 fn _fsm_zoom_show<'a, 'b>(state: &'a mut State, window: &'b Window, wpic: Option<Pic>) -> FSM {
     assert!(wpic.is_some());
     let pic = wpic.as_ref().unwrap();
+    state.receive_zoom();
     if window.is_key_down(Key::Escape) {
         return FSM {
             cb: _fsm_exit,
             ..FSM::default()
         };
     }
+    // '+' shares its key with '='
+    let sharper = window.is_key_pressed(Key::Equal, KeyRepeat::No)
+        || window.is_key_pressed(Key::NumPadPlus, KeyRepeat::No);
+    let coarser = window.is_key_pressed(Key::Minus, KeyRepeat::No)
+        || window.is_key_pressed(Key::NumPadMinus, KeyRepeat::No);
+    if sharper || coarser {
+        if state.change_zoom_level(if sharper { 1 } else { -1 }) {
+            state.render_zoom(pic);
+        } else {
+            info("the zoom resolution is at its limit");
+        }
+    }
 
     if window.get_mouse_down(MouseButton::Right) {
+        state.reset_zoom();
         return FSM {
             cb: _fsm_select_prep,
             ..FSM::default()
@@ -159,6 +239,9 @@ fn _fsm_zoom_show<'a, 'b>(state: &'a mut State, window: &'b Window, wpic: Option
     if window.get_mouse_down(MouseButton::Left) {
         state.save_to_files(pic, EXEC_NAME);
     }
+    if window.is_key_pressed(Key::W, KeyRepeat::No) {
+        state.save_zoomed(pic, EXEC_NAME);
+    }
     FSM {
         cb: _fsm_zoom_show,
         pic: wpic,
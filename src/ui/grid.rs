@@ -0,0 +1,155 @@
+use image::math::Rect;
+
+use crate::{keep_aspect_ratio, EXEC_UI_GRID_CELLS_MAX};
+
+/// Where the thumbnails of the population are drawn in the window of the Ui
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Grid {
+    pub cols: usize,
+    pub rows: usize,
+    /// The size of a thumbnail, it has the aspect ratio of the window
+    pub thumb: (u32, u32),
+}
+
+impl Grid {
+    /// `cols` x `rows` thumbnails of at most `thumb_size` pixels wide in a window of `window`
+    /// pixels. The thumbnails shrink until they all fit in the window next to each other.
+    pub fn new(
+        cols: usize,
+        rows: usize,
+        thumb_size: u32,
+        window: (u32, u32),
+    ) -> Result<Grid, String> {
+        if cols == 0 || rows == 0 || cols * rows > EXEC_UI_GRID_CELLS_MAX {
+            return Err(format!(
+                "A grid of {}x{} thumbnails is not possible, it takes 1 to {} thumbnails",
+                cols, rows, EXEC_UI_GRID_CELLS_MAX
+            ));
+        }
+        let (width, height) = window;
+        let too_small = || {
+            format!(
+                "A window of {}x{} pixels is too small for a grid of {}x{} thumbnails",
+                width, height, cols, rows
+            )
+        };
+        let max_width = thumb_size.min(width / cols as u32);
+        if max_width == 0 {
+            return Err(too_small());
+        }
+        let (mut twidth, mut theight) = keep_aspect_ratio(window, (max_width, max_width));
+        if theight * rows as u32 > height {
+            theight = height / rows as u32;
+            twidth = (theight as f32 * width as f32 / height as f32).floor() as u32;
+        }
+        if twidth == 0 || theight == 0 {
+            return Err(too_small());
+        }
+        Ok(Grid {
+            cols,
+            rows,
+            thumb: (twidth, theight),
+        })
+    }
+
+    /// The size of the window for thumbnails of `thumb_size` pixels wide, `window` is shrunk
+    /// to the grid but never grows
+    pub fn fit_window(cols: usize, rows: usize, thumb_size: u32, window: (u32, u32)) -> (u32, u32) {
+        let (width, height) = window;
+        let theight = (thumb_size as f32 * height as f32 / width as f32).floor() as u32;
+        (
+            width.min(cols as u32 * thumb_size),
+            height.min(rows as u32 * theight.max(1)),
+        )
+    }
+
+    /// The number of thumbnails
+    pub fn len(&self) -> usize {
+        self.cols * self.rows
+    }
+
+    /// The area of the thumbnail of `slot`, the slots go row by row
+    pub fn rect(&self, slot: usize) -> Rect {
+        let (twidth, theight) = self.thumb;
+        Rect {
+            x: twidth * (slot % self.cols) as u32,
+            y: theight * (slot / self.cols) as u32,
+            width: twidth,
+            height: theight,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid_new() {
+        let grid = Grid::new(4, 3, 100, (800, 600)).unwrap();
+        assert_eq!(grid.thumb, (100, 75));
+        assert_eq!(grid.len(), 12);
+        // too wide for the thumbnails
+        assert_eq!(Grid::new(10, 3, 100, (800, 600)).unwrap().thumb, (80, 60));
+        // too high for the thumbnails
+        assert_eq!(Grid::new(2, 10, 100, (800, 600)).unwrap().thumb, (80, 60));
+        assert!(Grid::new(0, 3, 100, (800, 600)).is_err());
+        assert!(Grid::new(3, 0, 100, (800, 600)).is_err());
+        assert!(Grid::new(EXEC_UI_GRID_CELLS_MAX, 2, 100, (800, 600)).is_err());
+        assert!(Grid::new(100, 2, 100, (50, 50)).is_err());
+    }
+
+    #[test]
+    fn test_grid_rect() {
+        let grid = Grid::new(4, 3, 100, (800, 600)).unwrap();
+        assert_eq!(
+            grid.rect(0),
+            Rect {
+                x: 0,
+                y: 0,
+                width: 100,
+                height: 75
+            }
+        );
+        assert_eq!(
+            grid.rect(6),
+            Rect {
+                x: 200,
+                y: 75,
+                width: 100,
+                height: 75
+            }
+        );
+    }
+
+    #[test]
+    fn test_grid_no_overlap() {
+        for (cols, rows, thumb_size, window) in [
+            (14, 15, 128, (1920, 1080)),
+            (3, 2, 300, (640, 480)),
+            (20, 20, 64, (1000, 300)),
+            (1, 1, 1024, (300, 1000)),
+        ] {
+            let grid = Grid::new(cols, rows, thumb_size, window).unwrap();
+            for slot in 0..grid.len() {
+                let a = grid.rect(slot);
+                assert!(a.x + a.width <= window.0 && a.y + a.height <= window.1);
+                for other in slot + 1..grid.len() {
+                    let b = grid.rect(other);
+                    let apart = a.x + a.width <= b.x
+                        || b.x + b.width <= a.x
+                        || a.y + a.height <= b.y
+                        || b.y + b.height <= a.y;
+                    assert!(apart, "{} and {} overlap", slot, other);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_grid_fit_window() {
+        assert_eq!(Grid::fit_window(14, 15, 128, (1920, 1080)), (1792, 1080));
+        assert_eq!(Grid::fit_window(4, 3, 100, (1600, 900)), (400, 168));
+        assert_eq!(Grid::fit_window(4, 3, 100, (300, 200)), (300, 198));
+    }
+}
@@ -1,3 +1,4 @@
 pub mod button;
 pub mod fsm;
+pub mod grid;
 pub mod state;
@@ -1,87 +1,602 @@
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::env::{temp_dir, var};
+use std::fs::{read_to_string, remove_file, write, File};
 use std::io::Write;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::path::Path;
+use std::process::{id, Command};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use rand::rngs::StdRng;
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
 
+use image::imageops::{overlay, resize, FilterType};
 use image::math::Rect;
-use image::{save_buffer_with_format, ColorType, ImageFormat, RgbaImage};
+use image::RgbaImage;
+use image::{ImageBuffer, Rgba};
+use rayon::prelude::*;
 
 use crate::filename_to_copy_to;
 use crate::ui::button::Button;
+use crate::ui::grid::Grid;
 use crate::{
-    get_picture_path, keep_aspect_ratio, load_pictures, pic_get_rgba8_runtime_select,
-    pic_simplify_runtime_select, ActualPicture, Args, Pic, EXEC_UI_THUMB_COLS,
-    EXEC_UI_THUMB_HEIGHT, EXEC_UI_THUMB_ROWS, EXEC_UI_THUMB_WIDTH,
+    encode_png_with_metadata, get_generation_config, get_picture_path, info, lisp_to_pic,
+    lisp_to_population, load_optional_pictures, pic_get_rgba8_runtime_select,
+    pic_get_rgba8_viewport_runtime_select, pic_simplify_runtime_select, population_to_lisp, warn,
+    ActualPicture, Args, CoordinateSystem, GenerationConfig, Pic, RenderMetadata, TimeRange,
+    DEFAULT_RENDER_OPTIONS, DEFAULT_VIDEO_DURATION, DEFAULT_VIEWPORT, EXEC_UI_HISTORY_SIZE,
+    EXEC_UI_PIN_BORDER, EXEC_UI_PIN_COLOR, EXEC_UI_PLACEHOLDER_GRAY, EXEC_UI_ZOOM_LEVEL_MAX,
+    EXEC_UI_ZOOM_LEVEL_MIN,
 };
 
+/// A population that was replaced, with the thumbnails as they were rendered at that moment
+struct Generation {
+    buttons: Vec<Vec<Button>>,
+    image: RgbaImage,
+    rendered: bool,
+}
+
+/// Renders that run on other threads, of which only the one that was started last counts.
+/// A slow render that was started earlier must not replace the result of a newer one.
+struct LatestRender<T> {
+    generation: u64,
+    sender: Sender<(u64, T)>,
+    receiver: Receiver<(u64, T)>,
+}
+
+impl<T> LatestRender<T> {
+    fn new() -> Self {
+        let (sender, receiver) = channel();
+        LatestRender {
+            generation: 0,
+            sender,
+            receiver,
+        }
+    }
+
+    /// Start a render, it sends its result tagged with the returned generation
+    fn start(&mut self) -> (u64, Sender<(u64, T)>) {
+        self.generation += 1;
+        (self.generation, self.sender.clone())
+    }
+
+    /// Ignore the results of all the renders that were started until now
+    fn cancel(&mut self) {
+        self.generation += 1;
+    }
+
+    /// The result of the render that was started last, when it arrived since the last call
+    fn take(&self) -> Option<T> {
+        let mut latest = None;
+        while let Ok((generation, result)) = self.receiver.try_recv() {
+            if generation == self.generation {
+                latest = Some(result);
+            }
+        }
+        latest
+    }
+}
+
 pub struct State {
     pub buttons: Vec<Vec<Button>>,
     pub pictures: Arc<HashMap<String, ActualPicture>>,
     pub dimensions: (u32, u32),
+    pub grid: Grid,
+    pub parents: Vec<Pic>,
+    pub supersample: u32,
+    pub thumb_quality: u32,
+    pub generation: GenerationConfig,
+    /// The chance that a node changes when a pic is mutated or bred
+    pub mutation_rate: f32,
+    /// A thumbnail that takes longer to render stays empty
+    pub max_render_time: Option<Duration>,
     rng: StdRng,
     offset: f32,
+    time_range: TimeRange,
     start_time: Duration,
     pub image: RgbaImage,
+    thumbnails: Option<Receiver<(usize, Vec<u8>)>>,
+    zoom_level: i32,
+    zoomed: LatestRender<RgbaImage>,
+    loaded_population: Vec<Pic>,
+    /// The fingerprints of the pics that were shown in this session, they are not generated again
+    seen: HashSet<u64>,
+    undo_history: VecDeque<Generation>,
+    redo_history: Vec<Generation>,
+    /// The slots of which the pic is kept when the grid is filled again
+    pub pinned: BTreeSet<usize>,
 }
 
 impl State {
     pub fn new(args: &Args) -> Result<State, String> {
         let dimensions = (args.width, args.height);
+        let grid = Grid::new(args.grid_cols, args.grid_rows, args.thumb_size, dimensions)?;
         let pic_path = get_picture_path(&args);
         let pictures = Arc::new(
-            load_pictures(pic_path.as_path())
-                .map_err(|e| format!("Cannot load picture folder. {:?}", e))?,
+            load_optional_pictures(pic_path.as_path())
+                .map_err(|e| format!("Cannot load picture folder. {}", e))?,
         );
+        if pictures.is_empty() {
+            warn(format!(
+                "there are no pictures in {}, the Pic- operation is not used",
+                pic_path.display()
+            ));
+        }
+
+        let loaded_population = match &args.load_population {
+            Some(file_name) => {
+                let contents = read_to_string(file_name)
+                    .map_err(|e| format!("Cannot read population {}. {}", file_name, e))?;
+                let mut population = lisp_to_population(&contents, args.coordinate_system.clone())
+                    .map_err(|e| format!("Cannot parse population {}. {}", file_name, e))?;
+                for pic in population.iter_mut() {
+                    let replaced = pic
+                        .resolve_pictures(&pictures)
+                        .map_err(|e| format!("Cannot load population {}. {}", file_name, e))?;
+                    for (name, resolved) in replaced {
+                        warn(format!(
+                            "the picture {} is missing, using {}",
+                            name, resolved
+                        ));
+                    }
+                }
+                population
+            }
+            None => Vec::new(),
+        };
 
+        let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        info(format!("using seed {}", seed));
         let state = State {
             buttons: Vec::new(), //this will be overridden by generate_buttons() during _fsm_regenerate_
             pictures,
             dimensions,
-            rng: StdRng::from_rng(rand::thread_rng()).unwrap(),
+            grid,
+            parents: Vec::new(),
+            supersample: args.supersample,
+            thumb_quality: args.thumb_quality,
+            generation: get_generation_config(args)?,
+            mutation_rate: args.mutation_rate,
+            max_render_time: args.max_render_time,
+            rng: StdRng::seed_from_u64(seed),
             offset: args.time,
+            time_range: args.time_range,
             start_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap(),
             image: RgbaImage::new(args.width, args.height),
+            thumbnails: None,
+            zoom_level: 0,
+            zoomed: LatestRender::new(),
+            loaded_population,
+            seen: HashSet::new(),
+            undo_history: VecDeque::with_capacity(EXEC_UI_HISTORY_SIZE),
+            redo_history: Vec::new(),
+            pinned: BTreeSet::new(),
         };
         Ok(state)
     }
 
+    /// Fill the grid with random pics, the first time the loaded population (if any) is used
+    /// and the slots it doesn't cover are random.
     pub fn generate_buttons(&mut self) {
-        let pic_names: Vec<&String> = self.pictures.keys().collect();
-        let mut rows = Vec::with_capacity(EXEC_UI_THUMB_ROWS);
-        let (twidth, theight) =
-            keep_aspect_ratio(self.dimensions, (EXEC_UI_THUMB_WIDTH, EXEC_UI_THUMB_HEIGHT));
+        let loaded = std::mem::take(&mut self.loaded_population);
+        let config = self.generation.clone();
+        let mut seen = std::mem::take(&mut self.seen);
+        self.populate(|slot, rng, pic_names| match loaded.get(slot) {
+            Some(pic) => {
+                seen.insert(pic.fingerprint());
+                pic.clone()
+            }
+            None => Pic::unseen(&mut seen, || Pic::new(rng, pic_names, &config)),
+        });
+        self.seen = seen;
+    }
+
+    pub fn crossover_buttons(&mut self) {
+        assert_eq!(self.parents.len(), 2);
+        let parents = std::mem::take(&mut self.parents);
+        let mut seen = std::mem::take(&mut self.seen);
+        self.populate(|_slot, rng, _pic_names| {
+            Pic::unseen(&mut seen, || parents[0].crossover(&parents[1], rng))
+        });
+        self.seen = seen;
+    }
+
+    /// Fill the grid with mutations of the pic, the original stays in the first slot.
+    pub fn mutate_buttons(&mut self, pic: &Pic) {
+        let rate = self.mutation_rate;
+        let mut seen = std::mem::take(&mut self.seen);
+        seen.insert(pic.fingerprint());
+        self.populate(|slot, rng, pic_names| match slot {
+            0 => pic.clone(),
+            _ => Pic::unseen(&mut seen, || pic.mutate(rng, rate, pic_names)),
+        });
+        self.seen = seen;
+    }
+
+    /// Fill the slots that are not pinned with offspring of the pinned pics, returns false
+    /// when nothing is pinned to breed from.
+    pub fn breed_buttons(&mut self) -> bool {
+        let cols = self.grid.cols;
+        let favorites: Vec<Pic> = self
+            .pinned
+            .iter()
+            .map(|&slot| self.buttons[slot / cols][slot % cols].pic.clone())
+            .collect();
+        if favorites.is_empty() {
+            return false;
+        }
+        let rate = self.mutation_rate;
+        let mut seen = std::mem::take(&mut self.seen);
+        self.populate(|_slot, rng, pic_names| {
+            Pic::unseen(&mut seen, || breed(&favorites, rng, rate, pic_names))
+        });
+        self.seen = seen;
+        true
+    }
+
+    pub fn select_parent(&mut self, pic: Pic) {
+        if self.parents.contains(&pic) {
+            return;
+        }
+        self.parents.push(pic);
+        if self.parents.len() > 2 {
+            self.parents.remove(0);
+        }
+        info(format!(
+            "{} parent(s) selected for cross breeding",
+            self.parents.len()
+        ));
+    }
+
+    fn populate<F>(&mut self, mut new_pic: F)
+    where
+        F: FnMut(usize, &mut StdRng, &Vec<&String>) -> Pic,
+    {
+        // the order of a HashMap differs between runs, the seed should pick the same pictures
+        let mut pic_names: Vec<&String> = self.pictures.keys().collect();
+        pic_names.sort();
+        let mut rows = Vec::with_capacity(self.grid.rows);
+        let (twidth, theight) = self.grid.thumb;
         //todo: rayon par_iter
-        for r in 0..EXEC_UI_THUMB_ROWS {
-            let mut cols = Vec::with_capacity(EXEC_UI_THUMB_COLS);
-            for c in 0..EXEC_UI_THUMB_COLS {
-                let rect = Rect {
-                    x: twidth * c as u32,
-                    y: theight * r as u32,
-                    width: twidth,
-                    height: theight,
+        for r in 0..self.grid.rows {
+            let mut cols = Vec::with_capacity(self.grid.cols);
+            for c in 0..self.grid.cols {
+                let slot = r * self.grid.cols + c;
+                let rect = self.grid.rect(slot);
+                let pinned = if self.pinned.contains(&slot) {
+                    self.buttons.get(r).and_then(|row| row.get(c))
+                } else {
+                    None
+                };
+                let mut pic = match pinned {
+                    Some(button) => button.pic.clone(),
+                    None => new_pic(slot, &mut self.rng, &pic_names),
                 };
-                let mut pic = Pic::new(&mut self.rng, &pic_names);
                 pic_simplify_runtime_select(
                     &mut pic,
                     self.pictures.clone(),
                     twidth,
                     theight,
-                    self.frame_elapsed(),
+                    self.frame_t(),
                 );
                 let button = Button::new(pic, rect);
                 cols.push(button);
             }
             rows.push(cols);
         }
+        if !self.buttons.is_empty() {
+            let previous = self.take_generation();
+            if self.undo_history.len() == EXEC_UI_HISTORY_SIZE {
+                // dropping the oldest population frees its thumbnails
+                self.undo_history.pop_front();
+            }
+            self.undo_history.push_back(previous);
+            self.redo_history.clear();
+        }
         self.buttons = rows;
         self.start_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
     }
 
+    /// Go back to the previous population, returns false when there is none
+    pub fn undo(&mut self) -> bool {
+        match self.undo_history.pop_back() {
+            Some(generation) => {
+                let current = self.take_generation();
+                self.redo_history.push(current);
+                self.restore_generation(generation);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Go forward to the population that was undone, returns false when there is none
+    pub fn redo(&mut self) -> bool {
+        match self.redo_history.pop() {
+            Some(generation) => {
+                let current = self.take_generation();
+                self.undo_history.push_back(current);
+                self.restore_generation(generation);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn take_generation(&mut self) -> Generation {
+        Generation {
+            buttons: std::mem::take(&mut self.buttons),
+            image: self.image.clone(),
+            rendered: self.thumbnails.is_none(),
+        }
+    }
+
+    /// Show a population again, only thumbnails that were still rendering are rendered again
+    fn restore_generation(&mut self, generation: Generation) {
+        self.buttons = generation.buttons;
+        self.image = generation.image;
+        if generation.rendered {
+            // thumbnails of the replaced population must not be drawn over the restored ones
+            self.thumbnails = None;
+        } else {
+            self.render_thumbnails();
+        }
+    }
+
+    /// Render the thumbnails on the thread pool, pending slots are shown as gray tiles.
+    /// The finished thumbnails are picked up by receive_thumbnails.
+    pub fn render_thumbnails(&mut self) {
+        let (twidth, theight) = self.grid.thumb;
+        for pixel in self.image.pixels_mut() {
+            *pixel = Rgba([
+                EXEC_UI_PLACEHOLDER_GRAY,
+                EXEC_UI_PLACEHOLDER_GRAY,
+                EXEC_UI_PLACEHOLDER_GRAY,
+                255,
+            ]);
+        }
+        for &slot in &self.pinned {
+            draw_border(&mut self.image, self.grid.rect(slot));
+        }
+        let pics: Vec<(usize, Pic)> = self
+            .buttons
+            .iter()
+            .flatten()
+            .map(|button| button.pic.clone())
+            .enumerate()
+            .collect();
+        let pictures = self.pictures.clone();
+        let t = self.frame_t();
+        let quality = self.thumb_quality;
+        let max_render_time = self.max_render_time;
+        let (sender, receiver) = channel();
+        // replacing the receiver makes a previous render, that is still busy, send into the void
+        self.thumbnails = Some(receiver);
+        thread::spawn(move || {
+            pics.into_par_iter()
+                .for_each_with(sender, |sender, (slot, pic)| {
+                    let rendered = catch_unwind(AssertUnwindSafe(|| {
+                        render_thumbnail(
+                            &pic,
+                            pictures.clone(),
+                            (twidth, theight),
+                            quality,
+                            t,
+                            max_render_time,
+                        )
+                    }));
+                    match rendered {
+                        Ok(Ok(buffer)) => {
+                            let _ = sender.send((slot, buffer));
+                        }
+                        Ok(Err(e)) => warn(format!("thumbnail {} stays empty. {}", slot, e)),
+                        Err(_) => warn(format!("rendering thumbnail {} failed", slot)),
+                    }
+                });
+        });
+    }
+
+    /// Draw the thumbnails that finished rendering since the last call
+    pub fn receive_thumbnails(&mut self) {
+        while let Some(receiver) = &self.thumbnails {
+            match receiver.try_recv() {
+                Ok((slot, buffer)) => draw_thumbnail(
+                    &mut self.image,
+                    self.grid.rect(slot),
+                    buffer,
+                    self.pinned.contains(&slot),
+                ),
+                Err(TryRecvError::Empty) => break,
+                // all done, or the render thread died
+                Err(TryRecvError::Disconnected) => self.thumbnails = None,
+            }
+        }
+    }
+
+    /// The slot of the thumbnail at `x`, `y` in the window
+    pub fn slot_at(&self, x: u32, y: u32) -> Option<usize> {
+        self.buttons
+            .iter()
+            .flatten()
+            .position(|button| button.hit(x, y))
+    }
+
+    /// Edit the expression of the thumbnail in `slot` in $VISUAL or $EDITOR, the window waits
+    /// until the editor is closed. Only the pic of that slot is replaced, also by a pic of
+    /// another color mode. When the expression cannot be parsed the error is printed and the
+    /// pic stays as it was.
+    pub fn edit_slot(&mut self, slot: usize, exec_name: &str) {
+        let (cols, (twidth, theight)) = (self.grid.cols, self.grid.thumb);
+        let pic = self.buttons[slot / cols][slot % cols].pic.clone();
+        let path = temp_dir().join(format!("{}_{}_{}.sexpr", exec_name, id(), slot));
+        if let Err(e) = write(&path, pic.to_lisp_pretty(4)) {
+            warn(format!("cannot write {}. {}", path.display(), e));
+            return;
+        }
+        let editor = var("VISUAL")
+            .or_else(|_| var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string());
+        // the editor can come with arguments, like "code --wait"
+        let mut words = editor.split_whitespace();
+        let program = words.next().unwrap_or("vi");
+        info(format!("editing thumbnail {} with {}", slot, editor));
+        let edited = Command::new(program)
+            .args(words)
+            .arg(&path)
+            .status()
+            .map_err(|e| format!("Cannot start the editor {}. {}", editor, e))
+            .and_then(|status| {
+                if status.success() {
+                    read_to_string(&path)
+                        .map_err(|e| format!("Cannot read {}. {}", path.display(), e))
+                } else {
+                    Err(format!("The editor {} failed, {}", editor, status))
+                }
+            })
+            .and_then(|contents| parse_edited(contents, pic.coord(), &self.pictures));
+        let _ = remove_file(&path);
+        let mut edited = match edited {
+            Ok(edited) => edited,
+            Err(e) => {
+                warn(format!("thumbnail {} stays as it was. {}", slot, e));
+                return;
+            }
+        };
+        if edited.mode_name() != pic.mode_name() {
+            info(format!(
+                "thumbnail {} changed from {} to {}",
+                slot,
+                pic.mode_name(),
+                edited.mode_name()
+            ));
+        }
+        let t = self.frame_t();
+        pic_simplify_runtime_select(&mut edited, self.pictures.clone(), twidth, theight, t);
+        self.seen.insert(edited.fingerprint());
+        self.buttons[slot / cols][slot % cols].pic = edited;
+        if self.thumbnails.is_some() {
+            // the render that is still busy would draw the old pic in the slot
+            self.render_thumbnails();
+        } else {
+            self.redraw_slot(slot);
+        }
+    }
+
+    /// Render the thumbnail of `slot` again, on this thread
+    fn redraw_slot(&mut self, slot: usize) {
+        let cols = self.grid.cols;
+        let rendered = render_thumbnail(
+            &self.buttons[slot / cols][slot % cols].pic,
+            self.pictures.clone(),
+            self.grid.thumb,
+            self.thumb_quality,
+            self.frame_t(),
+            self.max_render_time,
+        );
+        let buffer = match rendered {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                warn(format!("thumbnail {} stays empty. {}", slot, e));
+                placeholder_thumbnail(self.grid.thumb)
+            }
+        };
+        draw_thumbnail(
+            &mut self.image,
+            self.grid.rect(slot),
+            buffer,
+            self.pinned.contains(&slot),
+        );
+    }
+
+    /// Pin the pic of `slot`, so it stays when the grid is filled again, or unpin it
+    pub fn toggle_pin(&mut self, slot: usize) {
+        if self.pinned.remove(&slot) {
+            info(format!("thumbnail {} is not pinned anymore", slot));
+            // the border is drawn over the thumbnail
+            self.redraw_slot(slot);
+        } else {
+            self.pinned.insert(slot);
+            info(format!(
+                "thumbnail {} is pinned, {} thumbnail(s) are kept when the grid is filled again",
+                slot,
+                self.pinned.len()
+            ));
+            draw_border(&mut self.image, self.grid.rect(slot));
+        }
+    }
+
+    /// Unpin all the thumbnails
+    pub fn clear_pins(&mut self) {
+        let pinned = std::mem::take(&mut self.pinned);
+        for &slot in &pinned {
+            self.redraw_slot(slot);
+        }
+        info(format!(
+            "{} thumbnail(s) are not pinned anymore",
+            pinned.len()
+        ));
+    }
+
+    /// Render the zoomed pic again in the background at the zoom level: above 0 every pixel gets
+    /// more samples, below 0 fewer pixels are rendered and scaled up to the window.
+    /// The result is picked up by receive_zoom.
+    pub fn render_zoom(&mut self, pic: &Pic) {
+        let (width, height) = self.dimensions;
+        let (w, h, supersample) =
+            zoom_render_size(self.dimensions, self.zoom_level, self.supersample);
+        info(format!(
+            "rendering {}x{} pixels with {}x{} samples each",
+            w, h, supersample, supersample
+        ));
+        let pic = pic.clone();
+        let pictures = self.pictures.clone();
+        let t = self.frame_t();
+        let (generation, sender) = self.zoomed.start();
+        thread::spawn(move || {
+            let rendered = catch_unwind(AssertUnwindSafe(|| {
+                pic_get_rgba8_runtime_select(&pic, true, pictures, w, h, t, supersample)
+            }));
+            match rendered {
+                Ok(buffer) => {
+                    let img: RgbaImage = ImageBuffer::from_raw(w, h, buffer).unwrap();
+                    let img = if (w, h) == (width, height) {
+                        img
+                    } else {
+                        resize(&img, width, height, FilterType::Nearest)
+                    };
+                    let _ = sender.send((generation, img));
+                }
+                Err(_) => warn("rendering the zoomed pic failed"),
+            }
+        });
+    }
+
+    /// Draw the zoomed pic when the render that was started last finished since the last call
+    pub fn receive_zoom(&mut self) {
+        if let Some(img) = self.zoomed.take() {
+            overlay(&mut self.image, &img, 0, 0);
+        }
+    }
+
+    /// Change the zoom level by `delta`, returns false when it is at its limit already
+    pub fn change_zoom_level(&mut self, delta: i32) -> bool {
+        let level = (self.zoom_level + delta).clamp(EXEC_UI_ZOOM_LEVEL_MIN, EXEC_UI_ZOOM_LEVEL_MAX);
+        if level == self.zoom_level {
+            return false;
+        }
+        self.zoom_level = level;
+        true
+    }
+
+    /// Back to the window resolution, a render that is still busy is not drawn anymore
+    pub fn reset_zoom(&mut self) {
+        self.zoom_level = 0;
+        self.zoomed.cancel();
+    }
+
     pub fn frame_elapsed(&self) -> f32 {
         let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
         let diff = now - self.start_time;
@@ -89,28 +604,86 @@ impl State {
         offset_from_start //% VIDEO_DURATION
     }
 
-    pub fn save_to_files(&self, pic: &Pic, exec_name: &str) {
+    /// The value of T for the current frame, the animation loops every DEFAULT_VIDEO_DURATION ms
+    pub fn frame_t(&self) -> f32 {
+        self.time_range
+            .at_ms(self.frame_elapsed(), DEFAULT_VIDEO_DURATION)
+    }
+
+    /// Save all the pics of the grid in one population file, that can be loaded with --load-population
+    pub fn save_population(&self, exec_name: &str) {
         let target_dir = Path::new(".");
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
+        let pics: Vec<Pic> = self
+            .buttons
+            .iter()
+            .flatten()
+            .map(|button| button.pic.clone())
+            .collect();
+        let tfn = format!("{}_{}.population", exec_name, self.frame_elapsed());
+        let dest = filename_to_copy_to(&target_dir, now, &tfn);
+        info(format!("writing {} pics to {:?}", pics.len(), dest));
+        File::create(dest)
+            .unwrap()
+            .write_all(population_to_lisp(&pics).as_bytes())
+            .unwrap();
+    }
 
+    fn save_sexpr(&self, pic: &Pic, exec_name: &str, target_dir: &Path, now: u64, ts: f32) {
         let sexpr = pic.to_lisp();
-        //let's save this to a sexpr_file
-        let ts = self.frame_elapsed();
         let tfn = format!("{}_{}.sexpr", exec_name, ts);
         let sexpr_filename = Path::new(&tfn);
         let dest = filename_to_copy_to(
-            &target_dir,
+            target_dir,
             now,
             &sexpr_filename.file_name().unwrap().to_string_lossy(),
         );
-        println!("writing to {:?}", dest);
+        info(format!("writing to {:?}", dest));
         File::create(dest)
             .unwrap()
             .write_all(sexpr.as_bytes())
             .unwrap();
+    }
+
+    /// Write a png that holds the sexpr of `pic` in its metadata, so it can be rendered again
+    fn save_png(&self, pic: &Pic, dest: &Path, rgba8: &[u8], width: u32, height: u32) {
+        let metadata = RenderMetadata::new(pic, width, height, self.frame_t(), None);
+        write(
+            dest,
+            encode_png_with_metadata(rgba8, width, height, &metadata).unwrap(),
+        )
+        .unwrap();
+    }
+
+    /// Save the zoomed pic as it is shown, at the resolution of the zoom level, with its sexpr
+    pub fn save_zoomed(&self, pic: &Pic, exec_name: &str) {
+        let target_dir = Path::new(".");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let ts = self.frame_elapsed();
+        self.save_sexpr(pic, exec_name, target_dir, now, ts);
+        let tfn = format!("{}_{}_zoom.png", exec_name, ts);
+        let dest = filename_to_copy_to(target_dir, now, &tfn);
+        info(format!("writing to {:?}", dest));
+        let (width, height) = self.dimensions;
+        self.save_png(pic, dest.as_path(), self.image.as_raw(), width, height);
+    }
+
+    pub fn save_to_files(&self, pic: &Pic, exec_name: &str) {
+        let target_dir = Path::new(".");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        //let's save this to a sexpr_file
+        let ts = self.frame_elapsed();
+        self.save_sexpr(pic, exec_name, target_dir, now, ts);
         //let's save this to a png file
         let tfn = format!("{}_{}.png", exec_name, ts);
         let png_filename = Path::new(&tfn);
@@ -120,16 +693,369 @@ impl State {
             &png_filename.file_name().unwrap().to_string_lossy(),
         );
         let (width, height) = self.dimensions;
-        let rgba8 =
-            pic_get_rgba8_runtime_select(&pic, false, self.pictures.clone(), width, height, ts);
-        save_buffer_with_format(
-            dest,
-            &rgba8[..],
+        let rgba8 = pic_get_rgba8_runtime_select(
+            &pic,
+            false,
+            self.pictures.clone(),
             width,
             height,
-            ColorType::Rgba8,
-            ImageFormat::Png,
+            self.frame_t(),
+            self.supersample,
+        );
+        self.save_png(pic, dest.as_path(), &rgba8[..], width, height);
+    }
+}
+
+/// Parse an expression that was edited by hand, in the coordinate system of the pic it
+/// replaces when it has none of its own, with the pictures it uses resolved
+fn parse_edited(
+    contents: String,
+    coord: &CoordinateSystem,
+    pictures: &HashMap<String, ActualPicture>,
+) -> Result<Pic, String> {
+    let mut pic = lisp_to_pic(contents, coord.clone())?;
+    for (name, resolved) in pic.resolve_pictures(pictures)? {
+        warn(format!(
+            "the picture {} is missing, using {}",
+            name, resolved
+        ));
+    }
+    Ok(pic)
+}
+
+/// The indices of the parents of a child of `favorites` pics: two different ones to cross,
+/// or only one to mutate when there are fewer than two favorites
+fn pick_parents(favorites: usize, rng: &mut impl Rng) -> (usize, Option<usize>) {
+    assert!(favorites > 0, "there is nothing to breed from");
+    let first = rng.gen_range(0..favorites);
+    if favorites < 2 {
+        return (first, None);
+    }
+    // skip over the first parent so a pic is not crossed with itself
+    let second = rng.gen_range(0..favorites - 1);
+    (first, Some(second + (second >= first) as usize))
+}
+
+/// A child of two random favorites that is mutated at `rate`, with one favorite it is only
+/// a mutation
+fn breed(favorites: &[Pic], rng: &mut StdRng, rate: f32, pic_names: &Vec<&String>) -> Pic {
+    let child = match pick_parents(favorites.len(), rng) {
+        (first, Some(second)) => favorites[first].crossover(&favorites[second], rng),
+        (first, None) => favorites[first].clone(),
+    };
+    child.mutate(rng, rate, pic_names)
+}
+
+/// Draw the rendered thumbnail in `rect` of the window, with a border when it is pinned
+fn draw_thumbnail(image: &mut RgbaImage, rect: Rect, buffer: Vec<u8>, pinned: bool) {
+    let img: RgbaImage = ImageBuffer::from_raw(rect.width, rect.height, buffer).unwrap();
+    overlay(image, &img, rect.x as i64, rect.y as i64);
+    if pinned {
+        draw_border(image, rect);
+    }
+}
+
+/// Mark a pinned thumbnail with a border along the inside of `rect`
+fn draw_border(image: &mut RgbaImage, rect: Rect) {
+    let border = EXEC_UI_PIN_BORDER.min(rect.width / 2).min(rect.height / 2);
+    for y in rect.y..rect.y + rect.height {
+        for x in rect.x..rect.x + rect.width {
+            let inside = x >= rect.x + border
+                && x < rect.x + rect.width - border
+                && y >= rect.y + border
+                && y < rect.y + rect.height - border;
+            if !inside && x < image.width() && y < image.height() {
+                image.put_pixel(x, y, Rgba(EXEC_UI_PIN_COLOR));
+            }
+        }
+    }
+}
+
+/// Render a thumbnail of `thumb` pixels at `quality` times its size and scale it down. A render
+/// that takes longer than `max_render_time` is aborted.
+fn render_thumbnail(
+    pic: &Pic,
+    pictures: Arc<HashMap<String, ActualPicture>>,
+    thumb: (u32, u32),
+    quality: u32,
+    t: f32,
+    max_render_time: Option<Duration>,
+) -> Result<Vec<u8>, String> {
+    let (twidth, theight) = thumb;
+    let options =
+        DEFAULT_RENDER_OPTIONS.with_deadline(max_render_time.map(|budget| Instant::now() + budget));
+    let buffer = pic_get_rgba8_viewport_runtime_select(
+        pic,
+        false,
+        pictures,
+        twidth * quality,
+        theight * quality,
+        t,
+        1,
+        DEFAULT_VIEWPORT,
+        options,
+    );
+    options.check_deadline()?;
+    Ok(downsample(buffer, twidth, theight, quality))
+}
+
+/// The gray of a thumbnail that is not rendered
+fn placeholder_thumbnail(thumb: (u32, u32)) -> Vec<u8> {
+    let (twidth, theight) = thumb;
+    [
+        EXEC_UI_PLACEHOLDER_GRAY,
+        EXEC_UI_PLACEHOLDER_GRAY,
+        EXEC_UI_PLACEHOLDER_GRAY,
+        255,
+    ]
+    .repeat((twidth * theight) as usize)
+}
+
+/// Scale the RGBA8 pixels of an image that was rendered at `quality` times `width` x `height`
+/// down to that size. The filter averages the pixels, so fine details don't alias.
+fn downsample(buffer: Vec<u8>, width: u32, height: u32, quality: u32) -> Vec<u8> {
+    if quality <= 1 {
+        return buffer;
+    }
+    let img: RgbaImage = ImageBuffer::from_raw(width * quality, height * quality, buffer).unwrap();
+    resize(&img, width, height, FilterType::Lanczos3).into_raw()
+}
+
+/// The width, height and samples per pixel to render a window of `dimensions` at `zoom_level`
+fn zoom_render_size(dimensions: (u32, u32), zoom_level: i32, supersample: u32) -> (u32, u32, u32) {
+    let (width, height) = dimensions;
+    if zoom_level >= 0 {
+        // the same limit as --supersample
+        let supersample = (supersample << zoom_level).min(16);
+        (width, height, supersample)
+    } else {
+        let shift = -zoom_level;
+        (
+            (width >> shift).max(2),
+            (height >> shift).max(1),
+            supersample,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_latest_render() {
+        let mut renders = LatestRender::new();
+        assert_eq!(renders.take(), None);
+        let (older, older_sender) = renders.start();
+        let (newer, newer_sender) = renders.start();
+        // the newer render finishes first, the older one is slower
+        newer_sender.send((newer, "newer")).unwrap();
+        older_sender.send((older, "older")).unwrap();
+        assert_eq!(renders.take(), Some("newer"));
+        assert_eq!(renders.take(), None);
+
+        let (generation, sender) = renders.start();
+        renders.cancel();
+        sender.send((generation, "cancelled")).unwrap();
+        assert_eq!(renders.take(), None);
+
+        // renders that finish on other threads
+        let mut renders = LatestRender::<u64>::new();
+        let mut handles = vec![];
+        for i in 0..4u64 {
+            let (generation, sender) = renders.start();
+            handles.push(thread::spawn(move || {
+                thread::sleep(Duration::from_millis(40 - i * 10));
+                sender.send((generation, i)).unwrap();
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(renders.take(), Some(3));
+    }
+
+    #[test]
+    fn test_downsample() {
+        let buffer = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(downsample(buffer.clone(), 2, 1, 1), buffer);
+
+        // a checkerboard of black and white pixels averages to gray
+        let mut checkers = Vec::new();
+        for y in 0..8 {
+            for x in 0..8 {
+                let v = if (x + y) % 2 == 0 { 0 } else { 255 };
+                checkers.extend_from_slice(&[v, v, v, 255]);
+            }
+        }
+        let small = downsample(checkers, 4, 4, 2);
+        assert_eq!(small.len(), 4 * 4 * 4);
+        for pixel in small.chunks_exact(4) {
+            assert!((pixel[0] as i32 - 128).abs() <= 16, "{:?}", pixel);
+            assert_eq!(pixel[3], 255);
+        }
+    }
+
+    #[test]
+    fn test_parse_edited() {
+        let pictures = HashMap::new();
+        // the color mode may change, the coordinate system is kept when it is left out
+        let pic = parse_edited(
+            "( RGB ( X ) ( Y ) ( * X Y ) )".to_string(),
+            &CoordinateSystem::Cartesian,
+            &pictures,
+        )
+        .unwrap();
+        assert_eq!(pic.mode_name(), "RGB");
+        assert_eq!(pic.coord(), &CoordinateSystem::Cartesian);
+        let pic = parse_edited(
+            "( MONO POLAR ( X ) )".to_string(),
+            &CoordinateSystem::Cartesian,
+            &pictures,
         )
         .unwrap();
+        assert_eq!(pic.coord(), &CoordinateSystem::Polar);
+        assert!(parse_edited(
+            "( RGB ( X ) ( Y )".to_string(),
+            &CoordinateSystem::Cartesian,
+            &pictures
+        )
+        .is_err());
+        assert!(parse_edited(
+            "( MONO ( PIC-missing.png X Y ) )".to_string(),
+            &CoordinateSystem::Cartesian,
+            &pictures
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_render_thumbnail() {
+        let pic = parse_edited(
+            "( MONO ( X ) )".to_string(),
+            &CoordinateSystem::Cartesian,
+            &HashMap::new(),
+        )
+        .unwrap();
+        let buffer =
+            render_thumbnail(&pic, Arc::new(HashMap::new()), (6, 4), 2, 0.0, None).unwrap();
+        assert_eq!(buffer.len(), 6 * 4 * 4);
+        let budget = Some(Duration::from_secs(60));
+        assert!(render_thumbnail(&pic, Arc::new(HashMap::new()), (6, 4), 2, 0.0, budget).is_ok());
+        assert!(render_thumbnail(
+            &pic,
+            Arc::new(HashMap::new()),
+            (6, 4),
+            2,
+            0.0,
+            Some(Duration::ZERO)
+        )
+        .is_err());
+        assert_eq!(placeholder_thumbnail((6, 4)).len(), 6 * 4 * 4);
+    }
+
+    #[test]
+    fn test_generate_buttons_seed() {
+        let lisp = || {
+            let args = Args::try_parse_from(["evolution", "--seed", "42"]).unwrap();
+            let mut state = State::new(&args).unwrap();
+            state.generate_buttons();
+            state
+                .buttons
+                .iter()
+                .flatten()
+                .map(|button| button.pic.to_lisp())
+                .collect::<Vec<String>>()
+        };
+        // every State loads the pictures into a HashMap of its own
+        assert_eq!(lisp(), lisp());
+    }
+
+    #[test]
+    fn test_pick_parents() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..20 {
+            assert_eq!(pick_parents(1, &mut rng), (0, None));
+        }
+        let mut picked = [0; 4];
+        for _ in 0..400 {
+            let (first, second) = pick_parents(4, &mut rng);
+            let second = second.unwrap();
+            assert_ne!(first, second);
+            assert!(first < 4 && second < 4);
+            picked[first] += 1;
+            picked[second] += 1;
+        }
+        // every favorite gets to be a parent
+        assert!(picked.iter().all(|&n| n > 100), "{:?}", picked);
+        let (first, second) = pick_parents(2, &mut rng);
+        assert_eq!(first + second.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_breed() {
+        let favorites = vec![
+            lisp_to_pic("( MONO POLAR ( X ) )".to_string(), CoordinateSystem::Polar).unwrap(),
+            lisp_to_pic(
+                "( RGB CARTESIAN ( Y ) ( X ) ( T ) )".to_string(),
+                CoordinateSystem::Polar,
+            )
+            .unwrap(),
+        ];
+        let mut rng = StdRng::seed_from_u64(11);
+        // without mutations a child only has the color modes of the parents
+        for _ in 0..20 {
+            let child = breed(&favorites, &mut rng, 0.0, &vec![]);
+            assert!(["MONO", "RGB"].contains(&child.mode_name()));
+        }
+        // with one favorite and no mutations it is copied
+        let child = breed(&favorites[..1], &mut rng, 0.0, &vec![]);
+        assert_eq!(child.to_lisp(), favorites[0].to_lisp());
+        // with one favorite the children are mutations of it
+        let mutated = (0..20)
+            .map(|_| breed(&favorites[..1], &mut rng, 1.0, &vec![]))
+            .filter(|child| child.to_lisp() != favorites[0].to_lisp())
+            .count();
+        assert!(mutated > 0);
+    }
+
+    #[test]
+    fn test_draw_border() {
+        let mut image = RgbaImage::new(20, 20);
+        let rect = Rect {
+            x: 4,
+            y: 2,
+            width: 10,
+            height: 8,
+        };
+        draw_border(&mut image, rect);
+        let pin = Rgba(EXEC_UI_PIN_COLOR);
+        assert_eq!(image.get_pixel(4, 2), &pin);
+        assert_eq!(image.get_pixel(13, 9), &pin);
+        assert_eq!(image.get_pixel(4 + EXEC_UI_PIN_BORDER - 1, 5), &pin);
+        // the inside of the thumbnail and the outside of the rect stay as they were
+        assert_eq!(
+            image.get_pixel(4 + EXEC_UI_PIN_BORDER, 5),
+            &Rgba([0, 0, 0, 0])
+        );
+        assert_eq!(image.get_pixel(3, 2), &Rgba([0, 0, 0, 0]));
+        assert_eq!(image.get_pixel(14, 10), &Rgba([0, 0, 0, 0]));
+
+        // a thumbnail that is drawn again over the border only gets it when it is pinned
+        let gray = vec![128; 10 * 8 * 4];
+        draw_thumbnail(&mut image, rect, gray.clone(), false);
+        assert_eq!(image.get_pixel(4, 2), &Rgba([128, 128, 128, 128]));
+        draw_thumbnail(&mut image, rect, gray, true);
+        assert_eq!(image.get_pixel(4, 2), &pin);
+    }
+
+    #[test]
+    fn test_zoom_render_size() {
+        assert_eq!(zoom_render_size((800, 600), 0, 1), (800, 600, 1));
+        assert_eq!(zoom_render_size((800, 600), 2, 2), (800, 600, 8));
+        assert_eq!(zoom_render_size((800, 600), 2, 8), (800, 600, 16));
+        assert_eq!(zoom_render_size((800, 600), -3, 1), (100, 75, 1));
+        assert_eq!(zoom_render_size((4, 4), -3, 1), (2, 1, 1));
     }
 }
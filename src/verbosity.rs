@@ -0,0 +1,133 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// How much is printed besides the output that was asked for, like the report of --check
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    /// Only the errors
+    Quiet,
+    /// The progress, the files that were written and the warnings
+    Normal,
+    /// How long the renders take as well
+    Verbose,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Level {
+    Report,
+    Warn,
+    Info,
+    Debug,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Target {
+    Stdout,
+    Stderr,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+// when the image is written to stdout, nothing else may be
+static STDOUT_IS_IMAGE: AtomicBool = AtomicBool::new(false);
+
+/// Set how much is printed for the rest of the run, with `stdout_is_image` the messages go
+/// to stderr so they don't end up in the image
+pub fn set_verbosity(verbosity: Verbosity, stdout_is_image: bool) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+    STDOUT_IS_IMAGE.store(stdout_is_image, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+/// Where a message of `level` is printed, None when it is not printed at all
+fn target(verbosity: Verbosity, level: Level, stdout_is_image: bool) -> Option<Target> {
+    let needed = match level {
+        Level::Report => Verbosity::Quiet,
+        Level::Warn | Level::Info => Verbosity::Normal,
+        Level::Debug => Verbosity::Verbose,
+    };
+    if verbosity < needed {
+        None
+    } else if level == Level::Warn || stdout_is_image {
+        Some(Target::Stderr)
+    } else {
+        Some(Target::Stdout)
+    }
+}
+
+fn print(level: Level, message: impl Display) {
+    match target(verbosity(), level, STDOUT_IS_IMAGE.load(Ordering::Relaxed)) {
+        Some(Target::Stdout) => println!("{}", message),
+        Some(Target::Stderr) => eprintln!("{}", message),
+        None => {}
+    }
+}
+
+/// The output that was asked for, like the stats of --stats, printed even when --quiet
+pub fn report(message: impl Display) {
+    print(Level::Report, message);
+}
+
+/// Something that probably does not turn out as expected, printed as "warning: ..."
+pub fn warn(message: impl Display) {
+    print(Level::Warn, format!("warning: {}", message));
+}
+
+/// The progress and the files that were written
+pub fn info(message: impl Display) {
+    print(Level::Info, message);
+}
+
+/// Details like how long a render took, only printed with --verbose
+pub fn debug(message: impl Display) {
+    print(Level::Debug, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verbosity_quiet() {
+        assert_eq!(
+            target(Verbosity::Quiet, Level::Report, false),
+            Some(Target::Stdout)
+        );
+        for level in [Level::Warn, Level::Info, Level::Debug] {
+            assert_eq!(target(Verbosity::Quiet, level, false), None);
+            assert_eq!(target(Verbosity::Quiet, level, true), None);
+        }
+    }
+
+    #[test]
+    fn test_verbosity_levels() {
+        assert_eq!(
+            target(Verbosity::Normal, Level::Info, false),
+            Some(Target::Stdout)
+        );
+        assert_eq!(
+            target(Verbosity::Normal, Level::Warn, false),
+            Some(Target::Stderr)
+        );
+        assert_eq!(target(Verbosity::Normal, Level::Debug, false), None);
+        assert_eq!(
+            target(Verbosity::Verbose, Level::Debug, false),
+            Some(Target::Stdout)
+        );
+    }
+
+    #[test]
+    fn test_verbosity_stdout_is_image() {
+        for verbosity in [Verbosity::Quiet, Verbosity::Normal, Verbosity::Verbose] {
+            for level in [Level::Report, Level::Warn, Level::Info, Level::Debug] {
+                assert_ne!(target(verbosity, level, true), Some(Target::Stdout));
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::parser::aptnode::APTNode;
+use crate::vm::stackmachine::StackMachine;
+
+use simdeez::Simd;
+
+/// Keeps the stack machines that were built for a tree, keyed on the lisp of that tree,
+/// so rendering the same tree again (e.g. for another t) skips the build
+pub struct StackMachineCache<S: Simd> {
+    machines: HashMap<String, Arc<StackMachine<S>>>,
+    builds: usize,
+}
+
+impl<S: Simd> StackMachineCache<S> {
+    pub fn new() -> Self {
+        StackMachineCache {
+            machines: HashMap::new(),
+            builds: 0,
+        }
+    }
+
+    pub fn get(&mut self, node: &APTNode) -> Arc<StackMachine<S>> {
+        let key = node.to_lisp();
+        if let Some(sm) = self.machines.get(&key) {
+            return sm.clone();
+        }
+        let sm = Arc::new(StackMachine::<S>::build(node));
+        self.builds += 1;
+        self.machines.insert(key, sm.clone());
+        sm
+    }
+
+    /// The number of stack machines that had to be built
+    pub fn builds(&self) -> usize {
+        self.builds
+    }
+
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+
+    /// Forget every stack machine, the builds are counted from 0 again
+    pub fn clear(&mut self) {
+        self.machines.clear();
+        self.builds = 0;
+    }
+}
+
+impl<S: Simd> Default for StackMachineCache<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simdeez::avx2::*;
+    use simdeez::scalar::*;
+    use simdeez::sse2::*;
+    use simdeez::sse41::*;
+
+    simd_runtime_generate!(
+        fn impl_stackmachinecache_get() {
+            let mut cache = StackMachineCache::<S>::new();
+            let node = APTNode::Add(vec![APTNode::X, APTNode::Y]);
+            let first = cache.get(&node);
+            let second = cache.get(&node.clone());
+            assert!(Arc::ptr_eq(&first, &second));
+            assert_eq!(cache.builds(), 1);
+            assert_eq!(cache.len(), 1);
+
+            cache.get(&APTNode::Sub(vec![APTNode::X, APTNode::Y]));
+            assert_eq!(cache.builds(), 2);
+            assert_eq!(cache.len(), 2);
+
+            cache.clear();
+            assert!(cache.is_empty());
+            assert_eq!(cache.builds(), 0);
+            cache.get(&node);
+            assert_eq!(cache.builds(), 1);
+        }
+    );
+
+    #[test]
+    fn test_stackmachinecache_get() {
+        impl_stackmachinecache_get_runtime_select();
+    }
+}
@@ -1,3 +1,6 @@
+use crate::pic::actual_picture::Sampling;
+use crate::pic::noisebasis::NoiseBasis;
+
 use simdeez::Simd;
 
 use std::fmt;
@@ -9,7 +12,7 @@ pub enum Instruction<S: Simd> {
     Mul,
     Div,
     Mod,
-    FBM,
+    FBM(NoiseBasis),
     Ridge,
     Turbulence,
     Cell1,
@@ -19,17 +22,29 @@ pub enum Instruction<S: Simd> {
     Atan,
     Atan2,
     Tan,
+    Sinh,
+    Tanh,
     Log,
     Abs,
     Floor,
     Ceil,
+    Fract,
     Clamp,
     Wrap,
     Square,
     Max,
     Min,
     Mandelbrot,
-    Picture(String),
+    WarpPush,
+    WarpPop,
+    Smoothstep,
+    Mix,
+    Step,
+    Voronoi,
+    RidgedFBM,
+    DX,
+    DY,
+    Picture(String, Sampling),
     Constant(S::Vf32),
     Width,
     Height,
@@ -38,6 +53,8 @@ pub enum Instruction<S: Simd> {
     X,
     Y,
     T,
+    TSin,
+    TCos,
 }
 
 impl<S> fmt::Debug for Instruction<S>
@@ -51,7 +68,7 @@ where
             Instruction::Mul => "Mul".to_string(),
             Instruction::Div => "Div".to_string(),
             Instruction::Mod => "Mod".to_string(),
-            Instruction::FBM => "FBM".to_string(),
+            Instruction::FBM(basis) => format!("FBM({:?})", basis),
             Instruction::Ridge => "Ridge".to_string(),
             Instruction::Turbulence => "Turbulence".to_string(),
             Instruction::Cell1 => "Cell1".to_string(),
@@ -61,17 +78,31 @@ where
             Instruction::Atan => "Atan".to_string(),
             Instruction::Atan2 => "Atan2".to_string(),
             Instruction::Tan => "Tan".to_string(),
+            Instruction::Sinh => "Sinh".to_string(),
+            Instruction::Tanh => "Tanh".to_string(),
             Instruction::Log => "Log".to_string(),
             Instruction::Abs => "Abs".to_string(),
             Instruction::Floor => "Floor".to_string(),
             Instruction::Ceil => "Ceil".to_string(),
+            Instruction::Fract => "Fract".to_string(),
             Instruction::Clamp => "Clamp".to_string(),
             Instruction::Wrap => "Wrap".to_string(),
             Instruction::Square => "Square".to_string(),
             Instruction::Max => "Max".to_string(),
             Instruction::Min => "Min".to_string(),
             Instruction::Mandelbrot => "Mandelbrot".to_string(),
-            Instruction::Picture(pic_name) => format!("Picture({})", pic_name),
+            Instruction::WarpPush => "WarpPush".to_string(),
+            Instruction::WarpPop => "WarpPop".to_string(),
+            Instruction::Smoothstep => "Smoothstep".to_string(),
+            Instruction::Mix => "Mix".to_string(),
+            Instruction::Step => "Step".to_string(),
+            Instruction::Voronoi => "Voronoi".to_string(),
+            Instruction::RidgedFBM => "RidgedFBM".to_string(),
+            Instruction::DX => "DX".to_string(),
+            Instruction::DY => "DY".to_string(),
+            Instruction::Picture(pic_name, sampling) => {
+                format!("Picture({}, {:?})", pic_name, sampling)
+            }
             Instruction::Constant(vf32) => format!("Constant({:?}", vf32),
             Instruction::Width => "Width".to_string(),
             Instruction::Height => "Height".to_string(),
@@ -80,6 +111,8 @@ where
             Instruction::X => "X".to_string(),
             Instruction::Y => "Y".to_string(),
             Instruction::T => "T".to_string(),
+            Instruction::TSin => "TSin".to_string(),
+            Instruction::TCos => "TCos".to_string(),
         };
 
         write!(f, "{}", name)
@@ -105,7 +138,10 @@ mod test {
         assert_eq!(&format!("{:?}", Instruction::Mul::<Avx2>), "Mul");
         assert_eq!(&format!("{:?}", Instruction::Div::<Avx2>), "Div");
         assert_eq!(&format!("{:?}", Instruction::Mod::<Avx2>), "Mod");
-        assert_eq!(&format!("{:?}", Instruction::FBM::<Avx2>), "FBM");
+        assert_eq!(
+            &format!("{:?}", Instruction::FBM::<Avx2>(NoiseBasis::Perlin)),
+            "FBM(Perlin)"
+        );
         assert_eq!(&format!("{:?}", Instruction::Ridge::<Avx2>), "Ridge");
         assert_eq!(
             &format!("{:?}", Instruction::Turbulence::<Avx2>),
@@ -118,10 +154,13 @@ mod test {
         assert_eq!(&format!("{:?}", Instruction::Atan::<Avx2>), "Atan");
         assert_eq!(&format!("{:?}", Instruction::Atan2::<Avx2>), "Atan2");
         assert_eq!(&format!("{:?}", Instruction::Tan::<Avx2>), "Tan");
+        assert_eq!(&format!("{:?}", Instruction::Sinh::<Avx2>), "Sinh");
+        assert_eq!(&format!("{:?}", Instruction::Tanh::<Avx2>), "Tanh");
         assert_eq!(&format!("{:?}", Instruction::Log::<Avx2>), "Log");
         assert_eq!(&format!("{:?}", Instruction::Abs::<Avx2>), "Abs");
         assert_eq!(&format!("{:?}", Instruction::Floor::<Avx2>), "Floor");
         assert_eq!(&format!("{:?}", Instruction::Ceil::<Avx2>), "Ceil");
+        assert_eq!(&format!("{:?}", Instruction::Fract::<Avx2>), "Fract");
         assert_eq!(&format!("{:?}", Instruction::Clamp::<Avx2>), "Clamp");
         assert_eq!(&format!("{:?}", Instruction::Wrap::<Avx2>), "Wrap");
         assert_eq!(&format!("{:?}", Instruction::Square::<Avx2>), "Square");
@@ -131,9 +170,27 @@ mod test {
             &format!("{:?}", Instruction::Mandelbrot::<Avx2>),
             "Mandelbrot"
         );
+        assert_eq!(&format!("{:?}", Instruction::WarpPush::<Avx2>), "WarpPush");
+        assert_eq!(&format!("{:?}", Instruction::WarpPop::<Avx2>), "WarpPop");
+        assert_eq!(&format!("{:?}", Instruction::Mix::<Avx2>), "Mix");
+        assert_eq!(&format!("{:?}", Instruction::Step::<Avx2>), "Step");
+        assert_eq!(&format!("{:?}", Instruction::Voronoi::<Avx2>), "Voronoi");
+        assert_eq!(
+            &format!("{:?}", Instruction::Smoothstep::<Avx2>),
+            "Smoothstep"
+        );
+        assert_eq!(
+            &format!("{:?}", Instruction::RidgedFBM::<Avx2>),
+            "RidgedFBM"
+        );
+        assert_eq!(&format!("{:?}", Instruction::DX::<Avx2>), "DX");
+        assert_eq!(&format!("{:?}", Instruction::DY::<Avx2>), "DY");
         assert_eq!(
-            &format!("{:?}", Instruction::Picture::<Avx2>("cat.png".to_string())),
-            "Picture(cat.png)"
+            &format!(
+                "{:?}",
+                Instruction::Picture::<Avx2>("cat.png".to_string(), Sampling::Bilinear)
+            ),
+            "Picture(cat.png, Bilinear)"
         );
         /*
         assert_eq!(
@@ -148,5 +205,7 @@ mod test {
         assert_eq!(&format!("{:?}", Instruction::X::<Avx2>), "X");
         assert_eq!(&format!("{:?}", Instruction::Y::<Avx2>), "Y");
         assert_eq!(&format!("{:?}", Instruction::T::<Avx2>), "T");
+        assert_eq!(&format!("{:?}", Instruction::TSin::<Avx2>), "TSin");
+        assert_eq!(&format!("{:?}", Instruction::TCos::<Avx2>), "TCos");
     }
 }
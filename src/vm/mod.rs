@@ -1,2 +1,3 @@
+pub mod cache;
 mod instruction;
 pub mod stackmachine;
@@ -1,19 +1,23 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use crate::constants::{PIC_DERIVATIVE_STEP, PIC_FBM_OCTAVES_MAX};
 use crate::parser::aptnode::APTNode;
-use crate::pic::actual_picture::ActualPicture;
+use crate::pic::actual_picture::{ActualPicture, Sampling};
+use crate::pic::noisebasis::NoiseBasis;
 use crate::vm::instruction::Instruction;
 
 use simdeez::Simd;
 use simdnoise::{
     cellular::cellular_2d,
-    simplex::{fbm_2d, ridge_2d, turbulence_2d},
+    simplex::{ridge_2d, simplex_2d, turbulence_2d},
     CellDistanceFunction, CellReturnType,
 };
 
 pub struct StackMachine<S: Simd> {
     pub instructions: Vec<Instruction<S>>,
+    /// The length of the stacks that execute needs, the stack_depth of the tree it was built from
+    pub stack_depth: usize,
 }
 
 impl<S: Simd> StackMachine<S> {
@@ -24,7 +28,7 @@ impl<S: Simd> StackMachine<S> {
             APTNode::Mul(_) => Instruction::Mul,
             APTNode::Div(_) => Instruction::Div,
             APTNode::Mod(_) => Instruction::Mod,
-            APTNode::FBM(_) => Instruction::FBM,
+            APTNode::FBM(basis, _) => Instruction::FBM(*basis),
             APTNode::Ridge(_) => Instruction::Ridge,
             APTNode::Turbulence(_) => Instruction::Turbulence,
             APTNode::Cell1(_) => Instruction::Cell1,
@@ -34,17 +38,30 @@ impl<S: Simd> StackMachine<S> {
             APTNode::Atan(_) => Instruction::Atan,
             APTNode::Atan2(_) => Instruction::Atan2,
             APTNode::Tan(_) => Instruction::Tan,
+            APTNode::Sinh(_) => Instruction::Sinh,
+            APTNode::Tanh(_) => Instruction::Tanh,
             APTNode::Log(_) => Instruction::Log,
             APTNode::Abs(_) => Instruction::Abs,
             APTNode::Floor(_) => Instruction::Floor,
             APTNode::Ceil(_) => Instruction::Ceil,
+            APTNode::Fract(_) => Instruction::Fract,
             APTNode::Clamp(_) => Instruction::Clamp,
             APTNode::Wrap(_) => Instruction::Wrap,
             APTNode::Square(_) => Instruction::Square,
             APTNode::Max(_) => Instruction::Max,
             APTNode::Min(_) => Instruction::Min,
             APTNode::Mandelbrot(_) => Instruction::Mandelbrot,
-            APTNode::Picture(name, _) => Instruction::Picture(name.to_string()),
+            APTNode::Warp(_) => Instruction::WarpPop,
+            APTNode::Smoothstep(_) => Instruction::Smoothstep,
+            APTNode::Mix(_) => Instruction::Mix,
+            APTNode::Step(_) => Instruction::Step,
+            APTNode::Voronoi(_) => Instruction::Voronoi,
+            APTNode::RidgedFBM(_) => Instruction::RidgedFBM,
+            APTNode::DX(_) => Instruction::DX,
+            APTNode::DY(_) => Instruction::DY,
+            APTNode::Picture(name, sampling, _) => {
+                Instruction::Picture(name.to_string(), *sampling)
+            }
             APTNode::Constant(v) => Instruction::Constant(unsafe { S::set1_ps(*v) }),
             APTNode::Width => Instruction::Width,
             APTNode::Height => Instruction::Height,
@@ -53,15 +70,56 @@ impl<S: Simd> StackMachine<S> {
             APTNode::X => Instruction::X,
             APTNode::Y => Instruction::Y,
             APTNode::T => Instruction::T,
+            APTNode::TSin => Instruction::TSin,
+            APTNode::TCos => Instruction::TCos,
             APTNode::Empty => panic!("got empty building stack machine"),
         }
     }
 
-    fn build_helper(&mut self, node: &APTNode) {
+    fn build_helper(&mut self, node: &APTNode, fold: bool) {
+        if fold && !node.is_leaf() && node.is_constant() {
+            // the subtree results in the same value for every pixel, compute it once
+            let mut sm = StackMachine::<S> {
+                instructions: Vec::new(),
+                stack_depth: node.stack_depth(),
+            };
+            sm.build_helper(node, false);
+            let v = sm.execute_constant();
+            self.instructions.push(Instruction::Constant(v));
+            return;
+        }
+        if let APTNode::Warp(children) = node {
+            // the offsets have to be known before the input is evaluated at the warped position
+            self.build_helper(&children[1], fold);
+            self.build_helper(&children[0], fold);
+            self.instructions.push(Instruction::WarpPush);
+            self.build_helper(&children[2], fold);
+            self.instructions.push(StackMachine::get_instruction(node));
+            return;
+        }
+        if let APTNode::DX(children) | APTNode::DY(children) = node {
+            // the child is evaluated a step before and a step after the position, shifted the
+            // way Warp shifts its input
+            let (dx, dy) = match node {
+                APTNode::DX(_) => (PIC_DERIVATIVE_STEP, 0.0),
+                _ => (0.0, PIC_DERIVATIVE_STEP),
+            };
+            for sign in [-1.0, 1.0] {
+                self.instructions
+                    .push(Instruction::Constant(unsafe { S::set1_ps(sign * dy) }));
+                self.instructions
+                    .push(Instruction::Constant(unsafe { S::set1_ps(sign * dx) }));
+                self.instructions.push(Instruction::WarpPush);
+                self.build_helper(&children[0], fold);
+                self.instructions.push(Instruction::WarpPop);
+            }
+            self.instructions.push(StackMachine::get_instruction(node));
+            return;
+        }
         match node.get_children() {
             Some(children) => {
                 for child in children.iter().rev() {
-                    self.build_helper(child);
+                    self.build_helper(child, fold);
                 }
             }
             None => (),
@@ -72,13 +130,32 @@ impl<S: Simd> StackMachine<S> {
     }
 
     pub fn build(node: &APTNode) -> StackMachine<S> {
+        // folding a subtree into a constant only makes the stack shallower
         let mut sm = StackMachine {
             instructions: Vec::new(),
+            stack_depth: node.stack_depth(),
         };
-        sm.build_helper(node);
+        sm.build_helper(node, true);
         sm
     }
 
+    fn execute_constant(&self) -> S::Vf32 {
+        unsafe {
+            let zero = S::setzero_ps();
+            let mut stack = Vec::with_capacity(self.stack_depth);
+            stack.set_len(self.stack_depth);
+            self.execute(
+                &mut stack,
+                Arc::new(HashMap::new()),
+                zero,
+                zero,
+                zero,
+                zero,
+                zero,
+            )
+        }
+    }
+
     #[inline(always)]
     pub fn deal_with_nan(mut a: S::Vf32) -> S::Vf32 {
         for i in 0..S::VF32_WIDTH {
@@ -93,18 +170,281 @@ impl<S: Simd> StackMachine<S> {
         a
     }
 
+    /// sin(PI * T), T is wrapped into 0..2 first so the end of a loop is exactly its start
+    #[inline(always)]
+    pub fn time_sin(t: S::Vf32) -> S::Vf32 {
+        unsafe {
+            let two = S::set1_ps(2.0);
+            let phase = t - two * S::fast_floor_ps(t / two);
+            S::fast_sin_ps(phase * S::set1_ps(std::f32::consts::PI))
+        }
+    }
+
+    /// Reads the pixel of `picture` that is closest to `xpct`, `ypct` (0..1).
+    /// Lanes that fall outside of the brightness data keep the value of `fallback`
+    #[inline(always)]
+    pub fn sample_nearest(
+        picture: &ActualPicture,
+        fallback: S::Vf32,
+        xpct: S::Vf32,
+        ypct: S::Vf32,
+    ) -> S::Vf32 {
+        unsafe {
+            let w = S::set1_epi32(picture.w as i32);
+            let h = S::set1_epi32(picture.h as i32);
+            let wf = S::cvtepi32_ps(w);
+            let hf = S::cvtepi32_ps(h);
+            let xi = S::cvtps_epi32(xpct * wf);
+            let yi = S::cvtps_epi32(ypct * hf);
+            let index = xi + w * yi;
+
+            let mut v = fallback;
+            let brightness_len = picture.brightness.len();
+            for i in 0..S::VF32_WIDTH {
+                let slot: usize = index[i] as usize % (picture.w as usize * picture.h as usize);
+                if slot >= brightness_len {
+                    break;
+                }
+                v[i] = picture.brightness[slot];
+            }
+            v
+        }
+    }
+
+    /// Blends the four pixels of `picture` around `xpct`, `ypct` (0..1), wrapping around the edges
+    #[inline(always)]
+    pub fn sample_bilinear(picture: &ActualPicture, xpct: S::Vf32, ypct: S::Vf32) -> S::Vf32 {
+        unsafe {
+            let w = picture.w as usize;
+            let h = picture.h as usize;
+            let mut v = S::setzero_ps();
+            if w == 0 || h == 0 {
+                return v;
+            }
+            let brightness = |px: usize, py: usize| -> f32 {
+                *picture.brightness.get(px + w * py).unwrap_or(&0.0)
+            };
+            for i in 0..S::VF32_WIDTH {
+                // pixel centers are at half coordinates
+                let fx = xpct[i].rem_euclid(1.0) * w as f32 - 0.5;
+                let fy = ypct[i].rem_euclid(1.0) * h as f32 - 0.5;
+                let x0 = fx.floor();
+                let y0 = fy.floor();
+                let tx = fx - x0;
+                let ty = fy - y0;
+                let x0 = (x0 as i64).rem_euclid(w as i64) as usize;
+                let y0 = (y0 as i64).rem_euclid(h as i64) as usize;
+                let x1 = (x0 + 1) % w;
+                let y1 = (y0 + 1) % h;
+
+                let top = brightness(x0, y0) * (1.0 - tx) + brightness(x1, y0) * tx;
+                let bottom = brightness(x0, y1) * (1.0 - tx) + brightness(x1, y1) * tx;
+                v[i] = top * (1.0 - ty) + bottom * ty;
+            }
+            v
+        }
+    }
+
+    /// A hash of the cells `cx`, `cy` between -1.0 and 1.0, `seed` gives another hash of the same cells
+    #[inline(always)]
+    fn hash_cell(cx: S::Vi32, cy: S::Vi32, seed: i32) -> S::Vf32 {
+        unsafe {
+            let mut h = (cx * S::set1_epi32(501125321)) ^ (cy * S::set1_epi32(1136930381));
+            h = h ^ S::set1_epi32(seed);
+            h = h * h * h * S::set1_epi32(60493);
+            h = S::srai_epi32(h, 13) ^ h;
+            S::cvtepi32_ps(h) * S::set1_ps(1.0 / 2147483648.0)
+        }
+    }
+
+    /// The distance from `x`, `y` to the nearest feature point, one point per cell of 1 by 1,
+    /// scaled to -1.0 .. 1.0. The points circle around within their cell as `t` changes.
+    #[inline(always)]
+    pub fn voronoi(x: S::Vf32, y: S::Vf32, t: S::Vf32) -> S::Vf32 {
+        unsafe {
+            let cell_x = S::fast_floor_ps(x);
+            let cell_y = S::fast_floor_ps(y);
+            let cx = S::cvtps_epi32(cell_x);
+            let cy = S::cvtps_epi32(cell_y);
+            let half = S::set1_ps(0.5);
+            let pi = S::set1_ps(std::f32::consts::PI);
+            let mut nearest = S::set1_ps(f32::MAX);
+            for j in -1..=1 {
+                for i in -1..=1 {
+                    let nx = cx + S::set1_epi32(i);
+                    let ny = cy + S::set1_epi32(j);
+                    let hx = StackMachine::<S>::hash_cell(nx, ny, 0);
+                    let hy = StackMachine::<S>::hash_cell(nx, ny, 1013904223);
+                    // 0.0 .. 1.0 within the cell
+                    let px = half + half * S::fast_sin_ps(pi * (hx + hx + t));
+                    let py = half + half * S::fast_sin_ps(pi * (hy + hy + t));
+                    let dx = cell_x + S::set1_ps(i as f32) + px - x;
+                    let dy = cell_y + S::set1_ps(j as f32) + py - y;
+                    nearest = S::min_ps(nearest, dx * dx + dy * dy);
+                }
+            }
+            // the nearest point is at most sqrt(2) away, nearly always less than 1.0
+            let d = S::min_ps(S::sqrt_ps(nearest), S::set1_ps(1.0));
+            d + d - S::set1_ps(1.0)
+        }
+    }
+
+    /// The quintic curve that blends between the corners of a cell, its slope is 0 at the corners
+    #[inline(always)]
+    fn fade(f: S::Vf32) -> S::Vf32 {
+        unsafe { f * f * f * (f * (f * S::set1_ps(6.0) - S::set1_ps(15.0)) + S::set1_ps(10.0)) }
+    }
+
+    #[inline(always)]
+    fn lerp(a: S::Vf32, b: S::Vf32, t: S::Vf32) -> S::Vf32 {
+        a + (b - a) * t
+    }
+
+    /// Perlin noise between -1.0 and 1.0: a random unit gradient at every corner of a grid of
+    /// 1 by 1, the dot products with the offsets to the corners are blended
+    #[inline(always)]
+    pub fn perlin(x: S::Vf32, y: S::Vf32) -> S::Vf32 {
+        unsafe {
+            let cell_x = S::fast_floor_ps(x);
+            let cell_y = S::fast_floor_ps(y);
+            let cx = S::cvtps_epi32(cell_x);
+            let cy = S::cvtps_epi32(cell_y);
+            let fx = x - cell_x;
+            let fy = y - cell_y;
+            let one = S::set1_ps(1.0);
+            let pi = S::set1_ps(std::f32::consts::PI);
+            let quarter_turn = S::set1_ps(std::f32::consts::FRAC_PI_2);
+            let corner = |i: i32, j: i32| -> S::Vf32 {
+                let angle = pi
+                    * StackMachine::<S>::hash_cell(cx + S::set1_epi32(i), cy + S::set1_epi32(j), 0);
+                let dx = fx - S::set1_ps(i as f32);
+                let dy = fy - S::set1_ps(j as f32);
+                S::fast_sin_ps(angle + quarter_turn) * dx + S::fast_sin_ps(angle) * dy
+            };
+            let u = StackMachine::<S>::fade(fx);
+            let v = StackMachine::<S>::fade(fy);
+            let bottom = StackMachine::<S>::lerp(corner(0, 0), corner(1, 0), u);
+            let top = StackMachine::<S>::lerp(corner(0, 1), corner(1, 1), u);
+            // unit gradients reach at most sqrt(0.5)
+            let n = StackMachine::<S>::lerp(bottom, top, v) * S::set1_ps(std::f32::consts::SQRT_2);
+            S::max_ps(S::set1_ps(-1.0), S::min_ps(one, n))
+        }
+    }
+
+    /// Value noise between -1.0 and 1.0: a random value at every corner of a grid of 1 by 1,
+    /// blended over the cell
+    #[inline(always)]
+    pub fn value_noise(x: S::Vf32, y: S::Vf32) -> S::Vf32 {
+        unsafe {
+            let cell_x = S::fast_floor_ps(x);
+            let cell_y = S::fast_floor_ps(y);
+            let cx = S::cvtps_epi32(cell_x);
+            let cy = S::cvtps_epi32(cell_y);
+            let corner = |i: i32, j: i32| -> S::Vf32 {
+                StackMachine::<S>::hash_cell(cx + S::set1_epi32(i), cy + S::set1_epi32(j), 0)
+            };
+            let u = StackMachine::<S>::fade(x - cell_x);
+            let v = StackMachine::<S>::fade(y - cell_y);
+            let bottom = StackMachine::<S>::lerp(corner(0, 0), corner(1, 0), u);
+            let top = StackMachine::<S>::lerp(corner(0, 1), corner(1, 1), u);
+            StackMachine::<S>::lerp(bottom, top, v)
+        }
+    }
+
+    /// One octave of `basis` noise at `x`, `y`
+    #[inline(always)]
+    pub fn noise(basis: NoiseBasis, x: S::Vf32, y: S::Vf32) -> S::Vf32 {
+        match basis {
+            NoiseBasis::Simplex => simplex_2d::<S>(x, y, 3),
+            NoiseBasis::Perlin => StackMachine::<S>::perlin(x, y),
+            NoiseBasis::Value => StackMachine::<S>::value_noise(x, y),
+        }
+    }
+
+    /// Fractal brownian motion of `basis` noise where every lane has its own number of octaves,
+    /// `octaves` from 0.0 up to 1.0 is rounded to 1 up to PIC_FBM_OCTAVES_MAX octaves
+    #[inline(always)]
+    pub fn fbm(
+        basis: NoiseBasis,
+        x: S::Vf32,
+        y: S::Vf32,
+        lacunarity: S::Vf32,
+        gain: S::Vf32,
+        octaves: S::Vf32,
+    ) -> S::Vf32 {
+        unsafe {
+            let count = octaves * S::set1_ps(PIC_FBM_OCTAVES_MAX as f32);
+            let zero = S::setzero_ps();
+            let mut x = x;
+            let mut y = y;
+            let mut amplitude = S::set1_ps(1.0);
+            let mut result = StackMachine::<S>::noise(basis, x, y);
+            for octave in 1..PIC_FBM_OCTAVES_MAX {
+                // the lanes with a count that rounds to more than this octave
+                let threshold = octave as f32 + 0.5;
+                if (0..S::VF32_WIDTH).all(|i| count[i] < threshold) {
+                    break;
+                }
+                x = x * lacunarity;
+                y = y * lacunarity;
+                amplitude = amplitude * gain;
+                let mask = S::cmpge_ps(count, S::set1_ps(threshold));
+                let octave = StackMachine::<S>::noise(basis, x, y) * amplitude;
+                result = result + S::blendv_ps(zero, octave, mask);
+            }
+            result
+        }
+    }
+
+    /// Ridged multifractal noise: every octave of simplex noise is folded to `1 - |noise|` and
+    /// squared, and weighted by the octave before it, so the ridges get sharper where they meet.
+    /// The sum is scaled to -1.0 .. 1.0, the crests of the ridges are at 1.0.
+    #[inline(always)]
+    pub fn ridged_fbm(
+        x: S::Vf32,
+        y: S::Vf32,
+        lacunarity: S::Vf32,
+        gain: S::Vf32,
+        octaves: usize,
+    ) -> S::Vf32 {
+        unsafe {
+            let one = S::set1_ps(1.0);
+            let zero = S::setzero_ps();
+            let gain = S::abs_ps(gain);
+            let mut x = x;
+            let mut y = y;
+            let mut amplitude = one;
+            let mut weight = one;
+            let mut sum = zero;
+            let mut total = zero;
+            for _ in 0..octaves {
+                let folded = one - S::abs_ps(simplex_2d::<S>(x, y, 3));
+                let signal = folded * folded * weight;
+                weight = S::max_ps(zero, S::min_ps(one, signal * S::set1_ps(2.0)));
+                sum = sum + signal * amplitude;
+                total = total + amplitude;
+                amplitude = amplitude * gain;
+                x = x * lacunarity;
+                y = y * lacunarity;
+            }
+            let v = sum / total;
+            v + v - one
+        }
+    }
+
     pub fn execute(
         &self,
         stack: &mut Vec<S::Vf32>,
         pics: Arc<HashMap<String, ActualPicture>>,
-        x: S::Vf32,
-        y: S::Vf32,
+        mut x: S::Vf32,
+        mut y: S::Vf32,
         t: S::Vf32,
         w: S::Vf32,
         h: S::Vf32,
     ) -> S::Vf32 {
         unsafe {
             let mut sp = 0;
+            let mut frames: Vec<(S::Vf32, S::Vf32)> = Vec::new();
             for ins in &self.instructions {
                 match ins {
                     Instruction::Add => {
@@ -129,24 +469,24 @@ impl<S: Simd> StackMachine<S> {
                         let b = stack[sp];
                         let mut r = S::setzero_ps();
                         for i in 0..S::VF32_WIDTH {
-                            r[i] = a[i] % b[i];
+                            // a zero divisor keeps the dividend instead of making a NaN
+                            r[i] = if b[i] == 0.0 { a[i] } else { a[i] % b[i] };
                         }
                         stack[sp - 1] = StackMachine::<S>::deal_with_nan(r);
                     }
-                    Instruction::FBM => {
-                        sp -= 5;
-                        let xfreq = stack[sp - 1] * S::set1_ps(15.0);
-                        let yfreq = stack[sp + 4] * S::set1_ps(15.0);
-                        let lacunarity = stack[sp + 2] * S::set1_ps(5.0);
-                        let gain = stack[sp + 3] * S::set1_ps(0.5);
-                        let octaves = 3;
-                        stack[sp - 1] = fbm_2d::<S>(
-                            stack[sp + 1] * xfreq,
-                            stack[sp] * yfreq,
+                    Instruction::FBM(basis) => {
+                        sp -= 6;
+                        let xfreq = stack[sp] * S::set1_ps(15.0);
+                        let yfreq = stack[sp + 5] * S::set1_ps(15.0);
+                        let lacunarity = stack[sp + 3] * S::set1_ps(5.0);
+                        let gain = stack[sp + 4] * S::set1_ps(0.5);
+                        stack[sp - 1] = StackMachine::<S>::fbm(
+                            *basis,
+                            stack[sp + 2] * xfreq,
+                            stack[sp + 1] * yfreq,
                             lacunarity,
                             gain,
-                            octaves,
-                            3,
+                            stack[sp - 1],
                         );
                     }
                     Instruction::Ridge => {
@@ -232,16 +572,36 @@ impl<S: Simd> StackMachine<S> {
                             S::fast_atan2_ps(y, x) * S::set1_ps(std::f32::consts::FRAC_1_PI);
                     }
                     Instruction::Tan => {
-                        stack[sp - 1] =
-                            S::fast_tan_ps(stack[sp - 1] * S::set1_ps(std::f32::consts::FRAC_PI_2));
+                        // the asymptotes would give huge values or NaN, keep it in range
+                        let v = StackMachine::<S>::deal_with_nan(S::fast_tan_ps(
+                            stack[sp - 1] * S::set1_ps(std::f32::consts::FRAC_PI_2),
+                        ));
+                        stack[sp - 1] = S::max_ps(S::set1_ps(-1.0), S::min_ps(S::set1_ps(1.0), v));
+                    }
+                    Instruction::Sinh => {
+                        let mut v = stack[sp - 1];
+                        for i in 0..S::VF32_WIDTH {
+                            v[i] = v[i].sinh();
+                        }
+                        stack[sp - 1] = StackMachine::<S>::deal_with_nan(v);
+                    }
+                    Instruction::Tanh => {
+                        let mut v = stack[sp - 1];
+                        for i in 0..S::VF32_WIDTH {
+                            v[i] = v[i].tanh();
+                        }
+                        stack[sp - 1] = v;
                     }
                     Instruction::Log => {
                         let v = stack[sp - 1] * S::set1_ps(4.0);
                         let positive = S::fast_ln_ps(v);
                         let negative = S::mul_ps(S::set1_ps(-1.0), S::fast_ln_ps(S::abs_ps(v)));
                         let mask = S::cmpge_ps(v, S::setzero_ps());
-                        stack[sp - 1] = S::blendv_ps(negative, positive, mask)
-                            * S::set1_ps(1.0 / std::f32::consts::E);
+                        // the log of 0 is -inf
+                        stack[sp - 1] = StackMachine::<S>::deal_with_nan(
+                            S::blendv_ps(negative, positive, mask)
+                                * S::set1_ps(1.0 / std::f32::consts::E),
+                        );
                     }
                     Instruction::Abs => {
                         stack[sp - 1] = S::abs_ps(stack[sp - 1]);
@@ -252,16 +612,16 @@ impl<S: Simd> StackMachine<S> {
                     Instruction::Ceil => {
                         stack[sp - 1] = S::fast_ceil_ps(stack[sp - 1]);
                     }
+                    Instruction::Fract => {
+                        let v = stack[sp - 1];
+                        stack[sp - 1] = v - S::fast_floor_ps(v);
+                    }
                     Instruction::Clamp => {
-                        let mut v = stack[sp - 1];
-                        for i in 0..S::VF32_WIDTH {
-                            if v[i] > 1.0 {
-                                v[i] = 1.0
-                            } else if v[i] < -1.0 {
-                                v[i] = -1.0
-                            }
-                        }
-                        stack[sp - 1] = v;
+                        sp -= 2;
+                        let v = stack[sp + 1];
+                        let lo = stack[sp];
+                        let hi = stack[sp - 1];
+                        stack[sp - 1] = S::min_ps(S::max_ps(v, lo), hi);
                     }
                     Instruction::Wrap => {
                         let mut v = stack[sp - 1];
@@ -289,37 +649,96 @@ impl<S: Simd> StackMachine<S> {
                         sp -= 1;
                         //todo do
                     }
-                    Instruction::Picture(name) => {
+                    Instruction::WarpPush => {
+                        sp -= 2;
+                        let dx = stack[sp + 1];
+                        let dy = stack[sp];
+                        frames.push((x, y));
+                        x = x + dx;
+                        y = y + dy;
+                    }
+                    Instruction::WarpPop => {
+                        let (prev_x, prev_y) = frames.pop().unwrap();
+                        x = prev_x;
+                        y = prev_y;
+                    }
+                    Instruction::DX | Instruction::DY => {
+                        // the child after the position is on top of the one before it
+                        sp -= 1;
+                        let slope =
+                            (stack[sp] - stack[sp - 1]) * S::set1_ps(0.5 / PIC_DERIVATIVE_STEP);
+                        stack[sp - 1] = StackMachine::<S>::deal_with_nan(slope);
+                    }
+                    Instruction::Smoothstep => {
+                        sp -= 2;
+                        let edge0 = stack[sp + 1];
+                        let edge1 = stack[sp];
+                        let x = stack[sp - 1];
+                        // equal edges divide by zero: beyond the edge is 1.0, on it is NaN and 0.0
+                        let t = StackMachine::<S>::deal_with_nan((x - edge0) / (edge1 - edge0));
+                        let t = S::max_ps(S::setzero_ps(), S::min_ps(S::set1_ps(1.0), t));
+                        let curve = t * t * (S::set1_ps(3.0) - S::set1_ps(2.0) * t);
+                        stack[sp - 1] = curve * S::set1_ps(2.0) - S::set1_ps(1.0);
+                    }
+                    Instruction::Mix => {
+                        sp -= 2;
+                        let a = stack[sp + 1];
+                        let b = stack[sp];
+                        let t =
+                            S::max_ps(S::setzero_ps(), S::min_ps(S::set1_ps(1.0), stack[sp - 1]));
+                        stack[sp - 1] = a * (S::set1_ps(1.0) - t) + b * t;
+                    }
+                    Instruction::Step => {
+                        sp -= 1;
+                        let edge = stack[sp];
+                        let mask = S::cmplt_ps(stack[sp - 1], edge);
+                        stack[sp - 1] = S::blendv_ps(S::set1_ps(1.0), S::set1_ps(-1.0), mask);
+                    }
+                    Instruction::Voronoi => {
+                        sp -= 2;
+                        let x = stack[sp + 1] * S::set1_ps(4.0);
+                        let y = stack[sp] * S::set1_ps(4.0);
+                        stack[sp - 1] = StackMachine::<S>::voronoi(x, y, stack[sp - 1]);
+                    }
+                    Instruction::RidgedFBM => {
+                        sp -= 5;
+                        let xfreq = stack[sp - 1] * S::set1_ps(15.0);
+                        let yfreq = stack[sp + 4] * S::set1_ps(15.0);
+                        let lacunarity = stack[sp + 2] * S::set1_ps(5.0);
+                        let gain = stack[sp + 3] * S::set1_ps(0.5);
+                        let octaves = 3;
+                        stack[sp - 1] = StackMachine::<S>::ridged_fbm(
+                            stack[sp + 1] * xfreq,
+                            stack[sp] * yfreq,
+                            lacunarity,
+                            gain,
+                            octaves,
+                        );
+                    }
+                    Instruction::Picture(name, sampling) => {
                         sp -= 1;
 
                         let y = stack[sp - 1];
                         let x = stack[sp];
 
                         let picture = &pics[name];
-                        let w = S::set1_epi32(picture.w as i32);
-                        let h = S::set1_epi32(picture.h as i32);
-                        let wf = S::cvtepi32_ps(w);
-                        let hf = S::cvtepi32_ps(h);
                         let mut xpct = (x + S::set1_ps(1.0)) / S::set1_ps(2.0);
                         let mut ypct = (y + S::set1_ps(1.0)) / S::set1_ps(2.0);
                         for i in 0..S::VF32_WIDTH {
                             xpct[i] = xpct[i] % 1.0;
                             ypct[i] = ypct[i] % 1.0;
                         }
-                        let xi = S::cvtps_epi32(xpct * wf);
-                        let yi = S::cvtps_epi32(ypct * hf);
-                        let index = xi + w * yi;
-
-                        // println!("w:{:?} h{:?} xpct:{:?} ypct:{:?} index:{},{}",w[0],h[0],xpct[0],ypct[0],index[0],index[1]);
-                        let brightness_len = picture.brightness.len();
-                        for i in 0..S::VF32_WIDTH {
-                            let slot: usize =
-                                index[i] as usize % (picture.w as usize * picture.h as usize);
-                            if slot >= brightness_len {
-                                break;
+                        stack[sp - 1] = match sampling {
+                            Sampling::Bilinear => {
+                                StackMachine::<S>::sample_bilinear(picture, xpct, ypct)
                             }
-                            stack[sp - 1][i] = picture.brightness[slot];
-                        }
+                            Sampling::Nearest => StackMachine::<S>::sample_nearest(
+                                picture,
+                                stack[sp - 1],
+                                xpct,
+                                ypct,
+                            ),
+                        };
                     }
                     Instruction::Constant(v) => {
                         stack[sp] = *v;
@@ -355,16 +774,27 @@ impl<S: Simd> StackMachine<S> {
                         stack[sp] = t;
                         sp += 1;
                     }
+                    Instruction::TSin => {
+                        stack[sp] = StackMachine::<S>::time_sin(t);
+                        sp += 1;
+                    }
+                    Instruction::TCos => {
+                        // cos(PI * T) is sin(PI * (T + 0.5))
+                        stack[sp] = StackMachine::<S>::time_sin(t + S::set1_ps(0.5));
+                        sp += 1;
+                    }
                 }
             }
-            stack[sp - 1]
+            // overflows and NaNs of the other operations end up here, instead of as random colors
+            StackMachine::<S>::deal_with_nan(stack[sp - 1])
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::aptnode::mock;
+    use crate::constants::PIC_FBM_OCTAVES_DEFAULT;
+    use crate::parser::aptnode::{mock, time_cos, time_sin};
 
     use super::*;
     use simdeez::avx2::*;
@@ -398,8 +828,11 @@ mod tests {
                     panic!("Unexpected result");
                 }
             }
-            match StackMachine::<S>::get_instruction(&APTNode::FBM(mock::mock_params_fbm(true))) {
-                Instruction::FBM => {}
+            match StackMachine::<S>::get_instruction(&APTNode::FBM(
+                NoiseBasis::Value,
+                mock::mock_params_fbm(true),
+            )) {
+                Instruction::FBM(NoiseBasis::Value) => {}
                 _ => {
                     panic!("Unexpected result");
                 }
@@ -464,6 +897,18 @@ mod tests {
                     panic!("Unexpected result");
                 }
             }
+            match StackMachine::<S>::get_instruction(&APTNode::Sinh(mock::mock_params_sinh(true))) {
+                Instruction::Sinh => {}
+                _ => {
+                    panic!("Unexpected result");
+                }
+            }
+            match StackMachine::<S>::get_instruction(&APTNode::Tanh(mock::mock_params_tanh(true))) {
+                Instruction::Tanh => {}
+                _ => {
+                    panic!("Unexpected result");
+                }
+            }
             match StackMachine::<S>::get_instruction(&APTNode::Log(mock::mock_params_log(true))) {
                 Instruction::Log => {}
                 _ => {
@@ -489,6 +934,13 @@ mod tests {
                     panic!("Unexpected result");
                 }
             }
+            match StackMachine::<S>::get_instruction(&APTNode::Fract(mock::mock_params_fract(true)))
+            {
+                Instruction::Fract => {}
+                _ => {
+                    panic!("Unexpected result");
+                }
+            }
             match StackMachine::<S>::get_instruction(&APTNode::Clamp(mock::mock_params_clamp(true)))
             {
                 Instruction::Clamp => {}
@@ -536,14 +988,70 @@ mod tests {
                     panic!("Unexpected result");
                 }
             }
+            match StackMachine::<S>::get_instruction(&APTNode::Warp(mock::mock_params_warp(true))) {
+                Instruction::WarpPop => {}
+                _ => {
+                    panic!("Unexpected result");
+                }
+            }
+            match StackMachine::<S>::get_instruction(&APTNode::Smoothstep(
+                mock::mock_params_smoothstep(true),
+            )) {
+                Instruction::Smoothstep => {}
+                _ => {
+                    panic!("Unexpected result");
+                }
+            }
+            match StackMachine::<S>::get_instruction(&APTNode::Mix(mock::mock_params_mix(true))) {
+                Instruction::Mix => {}
+                _ => {
+                    panic!("Unexpected result");
+                }
+            }
+            match StackMachine::<S>::get_instruction(&APTNode::Step(mock::mock_params_step(true))) {
+                Instruction::Step => {}
+                _ => {
+                    panic!("Unexpected result");
+                }
+            }
+            match StackMachine::<S>::get_instruction(&APTNode::Voronoi(mock::mock_params_voronoi(
+                true,
+            ))) {
+                Instruction::Voronoi => {}
+                _ => {
+                    panic!("Unexpected result");
+                }
+            }
+            match StackMachine::<S>::get_instruction(&APTNode::RidgedFBM(
+                mock::mock_params_ridgedfbm(true),
+            )) {
+                Instruction::RidgedFBM => {}
+                _ => {
+                    panic!("Unexpected result");
+                }
+            }
+            match StackMachine::<S>::get_instruction(&APTNode::DX(mock::mock_params_dx(true))) {
+                Instruction::DX => {}
+                _ => {
+                    panic!("Unexpected result");
+                }
+            }
+            match StackMachine::<S>::get_instruction(&APTNode::DY(mock::mock_params_dy(true))) {
+                Instruction::DY => {}
+                _ => {
+                    panic!("Unexpected result");
+                }
+            }
 
             let name = "eye.jpg".to_string();
             match StackMachine::<S>::get_instruction(&APTNode::Picture(
                 name.clone(),
+                Sampling::Nearest,
                 mock::mock_params_picture(true),
             )) {
-                Instruction::Picture(got) => {
+                Instruction::Picture(got, sampling) => {
                     assert_eq!(got, name);
+                    assert_eq!(sampling, Sampling::Nearest);
                 }
                 _ => {
                     panic!("Unexpected result");
@@ -597,6 +1105,12 @@ mod tests {
                     panic!("Unexpected result");
                 }
             }
+            match StackMachine::<S>::get_instruction(&APTNode::TSin) {
+                Instruction::TSin => {}
+                _ => {
+                    panic!("Unexpected result");
+                }
+            }
         }
     );
 
@@ -626,6 +1140,601 @@ mod tests {
         impl_stackmachine_build_runtime_select();
     }
 
+    simd_runtime_generate!(
+        fn impl_stackmachine_stack_depth() {
+            unsafe {
+                // ( + X ( + X ( + X ... ) ) ) has many instructions, but needs a stack of 2
+                let levels = 2000;
+                let node =
+                    (0..levels).fold(APTNode::X, |inner, _| APTNode::Add(vec![APTNode::X, inner]));
+                let sm = StackMachine::<S>::build(&node);
+                assert_eq!(sm.instructions.len(), 2 * levels + 1);
+                assert_eq!(sm.stack_depth, 2);
+                let mut stack = Vec::with_capacity(sm.stack_depth);
+                stack.set_len(sm.stack_depth);
+                let zero = S::setzero_ps();
+                let v = sm.execute(
+                    &mut stack,
+                    Arc::new(HashMap::new()),
+                    S::set1_ps(1.0),
+                    zero,
+                    zero,
+                    zero,
+                    zero,
+                );
+                assert_eq!(v[0], (levels + 1) as f32);
+            }
+        }
+    );
+
+    #[test]
+    fn test_stackmachine_stack_depth() {
+        impl_stackmachine_stack_depth_runtime_select();
+    }
+
+    simd_runtime_generate!(
+        fn impl_stackmachine_build_constant_fold() {
+            let folded = StackMachine::<S>::build(&APTNode::Add(vec![
+                APTNode::Mul(vec![APTNode::Constant(2.0), APTNode::Constant(3.0)]),
+                APTNode::X,
+            ]));
+            let expected =
+                StackMachine::<S>::build(&APTNode::Add(vec![APTNode::Constant(6.0), APTNode::X]));
+            assert_eq!(folded.instructions.len(), expected.instructions.len());
+            assert_eq!(folded.instructions.len(), 3);
+            match folded.instructions.get(1).unwrap() {
+                Instruction::Constant(v) => assert_eq!(v[0], 6.0),
+                _ => panic!("Unexpected result"),
+            }
+
+            let sm = StackMachine::<S>::build(&APTNode::Sin(vec![APTNode::Add(vec![
+                APTNode::PI,
+                APTNode::Constant(1.0),
+            ])]));
+            assert_eq!(sm.instructions.len(), 1);
+
+            // nodes that depend on the pixel, time, dimensions or pictures are kept
+            for node in vec![
+                APTNode::X,
+                APTNode::Y,
+                APTNode::T,
+                APTNode::Width,
+                APTNode::Height,
+            ] {
+                let sm = StackMachine::<S>::build(&APTNode::Sin(vec![APTNode::Add(vec![
+                    node,
+                    APTNode::Constant(1.0),
+                ])]));
+                assert_eq!(sm.instructions.len(), 4);
+            }
+            let sm = StackMachine::<S>::build(&APTNode::Picture(
+                "eye.jpg".to_string(),
+                Sampling::Bilinear,
+                vec![APTNode::Constant(0.5), APTNode::Constant(0.5)],
+            ));
+            assert_eq!(sm.instructions.len(), 3);
+        }
+    );
+
+    #[test]
+    fn test_stackmachine_build_constant_fold() {
+        impl_stackmachine_build_constant_fold_runtime_select();
+    }
+
+    simd_runtime_generate!(
+        fn impl_stackmachine_execute_warp() {
+            unsafe {
+                let sm = StackMachine::<S>::build(&APTNode::Warp(vec![
+                    APTNode::Constant(0.25),
+                    APTNode::Y,
+                    APTNode::Sub(vec![APTNode::X, APTNode::Y]),
+                ]));
+                // dy, dx, push, y, x, sub, pop
+                assert_eq!(sm.instructions.len(), 7);
+                let mut stack = Vec::with_capacity(sm.stack_depth);
+                stack.set_len(sm.stack_depth);
+                let zero = S::setzero_ps();
+                let v = sm.execute(
+                    &mut stack,
+                    Arc::new(HashMap::new()),
+                    S::set1_ps(0.5),
+                    S::set1_ps(0.125),
+                    zero,
+                    zero,
+                    zero,
+                );
+                // (0.5 + 0.25) - (0.125 + 0.125)
+                assert_eq!(v[0], 0.5);
+
+                // outside of the warp the coordinates are restored
+                let sm = StackMachine::<S>::build(&APTNode::Add(vec![
+                    APTNode::Warp(vec![APTNode::Constant(0.25), APTNode::Y, APTNode::X]),
+                    APTNode::X,
+                ]));
+                let mut stack = Vec::with_capacity(sm.stack_depth);
+                stack.set_len(sm.stack_depth);
+                let v = sm.execute(
+                    &mut stack,
+                    Arc::new(HashMap::new()),
+                    S::set1_ps(0.5),
+                    S::set1_ps(0.125),
+                    zero,
+                    zero,
+                    zero,
+                );
+                assert_eq!(v[0], 1.25);
+            }
+        }
+    );
+
+    #[test]
+    fn test_stackmachine_execute_warp() {
+        impl_stackmachine_execute_warp_runtime_select();
+    }
+
+    simd_runtime_generate!(
+        fn impl_stackmachine_execute_derivative() {
+            unsafe {
+                let zero = S::setzero_ps();
+                let slope = |node: APTNode, x: f32, y: f32| -> f32 {
+                    let sm = StackMachine::<S>::build(&node);
+                    let mut stack = Vec::with_capacity(sm.stack_depth);
+                    stack.set_len(sm.stack_depth);
+                    sm.execute(
+                        &mut stack,
+                        Arc::new(HashMap::new()),
+                        S::set1_ps(x),
+                        S::set1_ps(y),
+                        zero,
+                        zero,
+                        zero,
+                    )[0]
+                };
+                // twice dy, dx, push, x, pop and then the difference
+                let sm = StackMachine::<S>::build(&APTNode::DX(vec![APTNode::X]));
+                assert_eq!(sm.instructions.len(), 11);
+                // the result before the position waits under the offsets after it
+                assert_eq!(sm.stack_depth, 3);
+                for x in [-0.75, 0.0, 0.5] {
+                    assert!((slope(APTNode::DX(vec![APTNode::X]), x, 0.25) - 1.0).abs() < 1e-4);
+                    assert_eq!(slope(APTNode::DY(vec![APTNode::X]), x, 0.25), 0.0);
+                    let square = APTNode::Mul(vec![APTNode::X, APTNode::X]);
+                    assert!((slope(APTNode::DX(vec![square]), x, 0.25) - 2.0 * x).abs() < 1e-3);
+                }
+                assert!((slope(APTNode::DY(vec![APTNode::Y]), 0.0, -0.5) - 1.0).abs() < 1e-4);
+                // a constant does not change
+                assert_eq!(
+                    slope(APTNode::DX(vec![APTNode::Constant(0.3)]), 0.5, 0.5),
+                    0.0
+                );
+            }
+        }
+    );
+
+    #[test]
+    fn test_stackmachine_execute_derivative() {
+        impl_stackmachine_execute_derivative_runtime_select();
+    }
+
+    simd_runtime_generate!(
+        fn impl_stackmachine_execute_clamp_smoothstep() {
+            unsafe {
+                let clamp = StackMachine::<S>::build(&APTNode::Clamp(vec![
+                    APTNode::X,
+                    APTNode::Constant(-0.5),
+                    APTNode::Y,
+                ]));
+                let smoothstep = StackMachine::<S>::build(&APTNode::Smoothstep(vec![
+                    APTNode::Constant(0.0),
+                    APTNode::Y,
+                    APTNode::X,
+                ]));
+                let len = clamp.stack_depth.max(smoothstep.stack_depth);
+                let mut stack = Vec::with_capacity(len);
+                stack.set_len(len);
+                let zero = S::setzero_ps();
+                let run = |sm: &StackMachine<S>, stack: &mut Vec<S::Vf32>, x: f32, y: f32| {
+                    sm.execute(
+                        stack,
+                        Arc::new(HashMap::new()),
+                        S::set1_ps(x),
+                        S::set1_ps(y),
+                        zero,
+                        zero,
+                        zero,
+                    )[0]
+                };
+                // X between -0.5 and Y, with -0.5 above Y the result is Y
+                for (x, y, expected) in [
+                    (0.25, 0.5, 0.25),
+                    (0.75, 0.5, 0.5),
+                    (-1.0, 0.5, -0.5),
+                    (0.0, -0.75, -0.75),
+                ] {
+                    assert_eq!(run(&clamp, &mut stack, x, y), expected, "{} {}", x, y);
+                }
+                // X from 0.0 up to Y, a Y of 0.0 is a step
+                for (x, y, expected) in [
+                    (0.5, 1.0, 0.0),
+                    (0.25, 1.0, -0.6875),
+                    (0.25, 0.0, 1.0),
+                    (0.0, 0.0, -1.0),
+                    (0.25, -1.0, -1.0),
+                ] {
+                    assert_eq!(run(&smoothstep, &mut stack, x, y), expected, "{} {}", x, y);
+                }
+            }
+        }
+    );
+
+    #[test]
+    fn test_stackmachine_execute_clamp_smoothstep() {
+        impl_stackmachine_execute_clamp_smoothstep_runtime_select();
+    }
+
+    simd_runtime_generate!(
+        fn impl_stackmachine_execute_mix() {
+            unsafe {
+                let sm = StackMachine::<S>::build(&APTNode::Mix(vec![
+                    APTNode::Constant(-1.0),
+                    APTNode::Y,
+                    APTNode::X,
+                ]));
+                let mut stack = Vec::with_capacity(sm.stack_depth);
+                stack.set_len(sm.stack_depth);
+                let zero = S::setzero_ps();
+                // X is t, it is clamped to 0.0 .. 1.0
+                for (x, expected) in [(0.25, -0.5), (-2.0, -1.0), (2.0, 1.0)] {
+                    let v = sm.execute(
+                        &mut stack,
+                        Arc::new(HashMap::new()),
+                        S::set1_ps(x),
+                        S::set1_ps(1.0),
+                        zero,
+                        zero,
+                        zero,
+                    );
+                    assert_eq!(v[0], expected, "{}", x);
+                }
+            }
+        }
+    );
+
+    #[test]
+    fn test_stackmachine_execute_mix() {
+        impl_stackmachine_execute_mix_runtime_select();
+    }
+
+    simd_runtime_generate!(
+        fn impl_stackmachine_execute_voronoi() {
+            unsafe {
+                let sm = StackMachine::<S>::build(&APTNode::Voronoi(vec![
+                    APTNode::X,
+                    APTNode::Y,
+                    APTNode::T,
+                ]));
+                let mut stack = Vec::with_capacity(sm.stack_depth);
+                stack.set_len(sm.stack_depth);
+                let zero = S::setzero_ps();
+                let mut values = Vec::new();
+                for t in [-1.0, 0.0, 0.37, 1.0] {
+                    for yi in -10..=10 {
+                        for xi in -10..=10 {
+                            let v = sm.execute(
+                                &mut stack,
+                                Arc::new(HashMap::new()),
+                                S::set1_ps(xi as f32 * 0.1),
+                                S::set1_ps(yi as f32 * 0.1),
+                                S::set1_ps(t),
+                                zero,
+                                zero,
+                            );
+                            values.push(v[0]);
+                        }
+                    }
+                }
+                assert!(values.iter().all(|v| (-1.0..=1.0).contains(v)));
+                // near and far from the points
+                assert!(values.iter().any(|v| *v < -0.5));
+                assert!(values.iter().any(|v| *v > 0.0));
+            }
+        }
+    );
+
+    #[test]
+    fn test_stackmachine_execute_voronoi() {
+        impl_stackmachine_execute_voronoi_runtime_select();
+    }
+
+    simd_runtime_generate!(
+        fn impl_stackmachine_execute_fbm() {
+            unsafe {
+                let render = |basis: NoiseBasis, gain: f32, octaves: f32| {
+                    let sm = StackMachine::<S>::build(&APTNode::FBM(
+                        basis,
+                        vec![
+                            APTNode::Constant(0.5),
+                            APTNode::Constant(gain),
+                            APTNode::Constant(0.4),
+                            APTNode::X,
+                            APTNode::Y,
+                            APTNode::Constant(0.5),
+                            APTNode::Constant(octaves),
+                        ],
+                    ));
+                    let mut stack = Vec::with_capacity(sm.stack_depth);
+                    stack.set_len(sm.stack_depth);
+                    let zero = S::setzero_ps();
+                    let mut values = Vec::new();
+                    for yi in -10..=10 {
+                        for xi in -10..=10 {
+                            values.push(
+                                sm.execute(
+                                    &mut stack,
+                                    Arc::new(HashMap::new()),
+                                    S::set1_ps(xi as f32 * 0.1),
+                                    S::set1_ps(yi as f32 * 0.1),
+                                    zero,
+                                    zero,
+                                    zero,
+                                )[0],
+                            );
+                        }
+                    }
+                    values
+                };
+                let rms = |values: &Vec<f32>| {
+                    (values.iter().map(|v| v * v).sum::<f32>() / values.len() as f32).sqrt()
+                };
+                let low = render(NoiseBasis::Simplex, 0.0, PIC_FBM_OCTAVES_DEFAULT);
+                let high = render(NoiseBasis::Simplex, 2.0, PIC_FBM_OCTAVES_DEFAULT);
+                assert!(rms(&high) > rms(&low) * 1.2);
+                // a single octave does not depend on the gain
+                assert_eq!(render(NoiseBasis::Simplex, 2.0, -1.0), low);
+
+                // the bases look different at the same coordinates
+                let simplex = render(NoiseBasis::Simplex, 0.5, PIC_FBM_OCTAVES_DEFAULT);
+                let perlin = render(NoiseBasis::Perlin, 0.5, PIC_FBM_OCTAVES_DEFAULT);
+                let value = render(NoiseBasis::Value, 0.5, PIC_FBM_OCTAVES_DEFAULT);
+                let differ = |a: &Vec<f32>, b: &Vec<f32>| {
+                    a.iter()
+                        .zip(b)
+                        .filter(|(a, b)| (*a - *b).abs() > 0.01)
+                        .count()
+                        > a.len() / 2
+                };
+                assert!(differ(&simplex, &perlin));
+                assert!(differ(&simplex, &value));
+                assert!(differ(&perlin, &value));
+                for values in [&perlin, &value] {
+                    assert!(rms(values) > 0.05);
+                    assert!(values.iter().all(|v| v.is_finite()));
+                }
+            }
+        }
+    );
+
+    simd_runtime_generate!(
+        fn impl_stackmachine_noise_bases() {
+            unsafe {
+                let mut perlin = Vec::new();
+                let mut value = Vec::new();
+                for yi in -20..=20 {
+                    for xi in -20..=20 {
+                        let x = S::set1_ps(xi as f32 * 0.27);
+                        let y = S::set1_ps(yi as f32 * 0.31);
+                        perlin.push(StackMachine::<S>::noise(NoiseBasis::Perlin, x, y)[0]);
+                        value.push(StackMachine::<S>::noise(NoiseBasis::Value, x, y)[0]);
+                    }
+                }
+                for v in perlin.iter().chain(&value) {
+                    assert!((-1.0..=1.0).contains(v), "{}", v);
+                }
+                assert!(perlin.iter().any(|v| *v < -0.2) && perlin.iter().any(|v| *v > 0.2));
+                assert!(value.iter().any(|v| *v < -0.2) && value.iter().any(|v| *v > 0.2));
+                for (i, j) in [(0, 0), (3, -2), (-7, 5)] {
+                    let x = S::set1_ps(i as f32);
+                    let y = S::set1_ps(j as f32);
+                    // the gradients of perlin noise cross zero at the corners of the grid
+                    assert_eq!(StackMachine::<S>::perlin(x, y)[0], 0.0);
+                    // value noise is the value of the corner there
+                    let cell = StackMachine::<S>::hash_cell(S::set1_epi32(i), S::set1_epi32(j), 0);
+                    assert_eq!(StackMachine::<S>::value_noise(x, y)[0], cell[0]);
+                }
+            }
+        }
+    );
+
+    #[test]
+    fn test_stackmachine_noise_bases() {
+        impl_stackmachine_noise_bases_runtime_select();
+    }
+
+    #[test]
+    fn test_stackmachine_execute_fbm() {
+        impl_stackmachine_execute_fbm_runtime_select();
+    }
+
+    simd_runtime_generate!(
+        fn impl_stackmachine_execute_ridgedfbm() {
+            unsafe {
+                let sm = StackMachine::<S>::build(&APTNode::RidgedFBM(vec![
+                    APTNode::Constant(0.5),
+                    APTNode::Constant(0.5),
+                    APTNode::Constant(0.4),
+                    APTNode::X,
+                    APTNode::Y,
+                    APTNode::Constant(0.5),
+                ]));
+                let mut stack = Vec::with_capacity(sm.stack_depth);
+                stack.set_len(sm.stack_depth);
+                let zero = S::setzero_ps();
+                let mut execute = |x: f32, y: f32| {
+                    sm.execute(
+                        &mut stack,
+                        Arc::new(HashMap::new()),
+                        S::set1_ps(x),
+                        S::set1_ps(y),
+                        zero,
+                        zero,
+                        zero,
+                    )[0]
+                };
+                // simplex noise is 0.0 at the origin, in every octave, that is the crest of a ridge
+                assert_eq!(execute(0.0, 0.0), 1.0);
+                let mut values = Vec::new();
+                for yi in -10..=10 {
+                    for xi in -10..=10 {
+                        values.push(execute(xi as f32 * 0.1, yi as f32 * 0.1));
+                    }
+                }
+                assert!(values.iter().all(|v| (-1.0..=1.0).contains(v)));
+                assert!(values.iter().any(|v| *v < 0.0));
+            }
+        }
+    );
+
+    #[test]
+    fn test_stackmachine_execute_ridgedfbm() {
+        impl_stackmachine_execute_ridgedfbm_runtime_select();
+    }
+
+    simd_runtime_generate!(
+        fn impl_stackmachine_execute_sanitized() {
+            unsafe {
+                let huge = APTNode::Mul(vec![
+                    APTNode::Mul(vec![APTNode::X, APTNode::Constant(1e30)]),
+                    APTNode::Constant(1e30),
+                ]);
+                let zero = S::setzero_ps();
+                let cases = vec![
+                    // inf
+                    (huge.clone(), 1.0),
+                    // -inf
+                    (
+                        APTNode::Sub(vec![APTNode::Constant(0.0), huge.clone()]),
+                        -1.0,
+                    ),
+                    // inf - inf is NaN
+                    (APTNode::Sub(vec![huge.clone(), huge.clone()]), 0.0),
+                    // 0 / 0 is NaN
+                    (
+                        APTNode::Div(vec![
+                            APTNode::Sub(vec![APTNode::X, APTNode::X]),
+                            APTNode::Sub(vec![APTNode::X, APTNode::X]),
+                        ]),
+                        0.0,
+                    ),
+                ];
+                for (node, expected) in cases {
+                    let sm = StackMachine::<S>::build(&node);
+                    let mut stack = Vec::with_capacity(sm.stack_depth);
+                    stack.set_len(sm.stack_depth);
+                    let v = sm.execute(
+                        &mut stack,
+                        Arc::new(HashMap::new()),
+                        S::set1_ps(1.0),
+                        zero,
+                        zero,
+                        zero,
+                        zero,
+                    );
+                    for i in 0..S::VF32_WIDTH {
+                        assert_eq!(v[i], expected, "{}", node.to_lisp());
+                    }
+                }
+            }
+        }
+    );
+
+    #[test]
+    fn test_stackmachine_execute_sanitized() {
+        impl_stackmachine_execute_sanitized_runtime_select();
+    }
+
+    simd_runtime_generate!(
+        fn impl_stackmachine_sample_picture() {
+            unsafe {
+                // a 2x1 picture, black on the left and white on the right
+                let picture = ActualPicture::new_from_bytes(
+                    &[0, 0, 0, 255, 255, 255, 255, 255],
+                    "halves",
+                    2,
+                    1,
+                )
+                .unwrap();
+                let zero = S::setzero_ps();
+
+                // at the pixel centers both samplers agree
+                let left =
+                    StackMachine::<S>::sample_bilinear(&picture, S::set1_ps(0.25), S::set1_ps(0.5));
+                assert_eq!(left[0], -1.0);
+                let right =
+                    StackMachine::<S>::sample_bilinear(&picture, S::set1_ps(0.75), S::set1_ps(0.5));
+                assert_eq!(right[0], 1.0);
+
+                // in between bilinear blends, nearest picks a side
+                let between =
+                    StackMachine::<S>::sample_bilinear(&picture, S::set1_ps(0.5), S::set1_ps(0.5));
+                assert_eq!(between[0], 0.0);
+                let between = StackMachine::<S>::sample_nearest(
+                    &picture,
+                    zero,
+                    S::set1_ps(0.5),
+                    S::set1_ps(0.5),
+                );
+                assert_eq!(between[0], 1.0);
+
+                // a quarter of the way from the left center to the right center
+                let quarter = StackMachine::<S>::sample_bilinear(
+                    &picture,
+                    S::set1_ps(0.375),
+                    S::set1_ps(0.5),
+                );
+                assert_eq!(quarter[0], -0.5);
+            }
+        }
+    );
+
+    #[test]
+    fn test_stackmachine_sample_picture() {
+        impl_stackmachine_sample_picture_runtime_select();
+    }
+
+    simd_runtime_generate!(
+        fn impl_stackmachine_execute_time() {
+            unsafe {
+                let tsin = StackMachine::<S>::build(&APTNode::TSin);
+                let tcos = StackMachine::<S>::build(&APTNode::TCos);
+                let mut stack = vec![S::setzero_ps(); 1];
+                let zero = S::setzero_ps();
+                let mut execute = |sm: &StackMachine<S>, t: f32| {
+                    sm.execute(
+                        &mut stack,
+                        Arc::new(HashMap::new()),
+                        zero,
+                        zero,
+                        S::set1_ps(t),
+                        zero,
+                        zero,
+                    )[0]
+                };
+                for t in [-1.0, -0.25, 0.0, 0.5, 0.75, 1.5, 3.25] {
+                    assert!((execute(&tsin, t) - time_sin(t)).abs() < 0.01, "{}", t);
+                    assert!((execute(&tcos, t) - time_cos(t)).abs() < 0.01, "{}", t);
+                }
+                // a loop ends exactly where it starts
+                assert_eq!(execute(&tsin, 0.0), execute(&tsin, 2.0));
+                assert_eq!(execute(&tcos, 0.0), execute(&tcos, 2.0));
+            }
+        }
+    );
+
+    #[test]
+    fn test_stackmachine_execute_time() {
+        impl_stackmachine_execute_time_runtime_select();
+    }
+
     simd_runtime_generate!(
         fn impl_stackmachine_deal_with_nan() {
             unsafe {